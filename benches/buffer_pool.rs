@@ -0,0 +1,213 @@
+/*
+* Benchmarks for the buffer pool and slotted pages, the baseline replacer and latching changes get measured against.
+*
+* cargo bench runs everything, cargo bench --bench buffer_pool -- <filter> only the benchmarks whose id contains
+* filter (e.g. -- fetch_page/zipf or -- slotted_page). Criterion keeps the results under target/criterion and
+* compares every run with the one before it, so run it once without the change and once with it.
+*
+* Groups:
+* - fetch_page: read fetches of random pages out of DB_PAGES, with pools of POOL_SIZES frames and pages picked
+*   uniformly or Zipfian (a few hot pages, see ZIPF_THETA). The hit ratio of every setup is printed before it runs
+* - eviction: writes to random pages with a pool much smaller than the database, once per replacer, so most fetches
+*   have to evict (and write back) a dirty frame first
+* - slotted_page: filling an empty page with records of a few sizes, and scanning a full one. No pool involved
+*
+* Data files go to the system temp dir and are deleted again once their setup is done.
+*/
+
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use GGDB::paging::buffer_pool_manager::BufferPoolManager;
+use GGDB::paging::config::{BufferPoolConfig, ReplacerKind};
+use GGDB::paging::page::{Page, PageType};
+use GGDB::paging::page_constants::PageId;
+use GGDB::paging::slotted_page::SlottedPage;
+
+const DB_PAGES: usize = 1024;
+const POOL_SIZES: [usize; 3] = [32, 128, 512];
+// pool for the eviction group, small enough that nearly every fetch misses
+const PRESSURE_POOL_SIZE: usize = 16;
+// skew of the Zipfian workloads, the usual YCSB value: about 1% of the pages get a third of the fetches
+const ZIPF_THETA: f64 = 0.99;
+// fetches per measured iteration
+const OPS: usize = 1000;
+const RECORD_SIZES: [usize; 3] = [16, 64, 256];
+
+// xorshift64*, fixed seed so every run fetches the same pages in the same order
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Skew {
+    Uniform,
+    Zipf,
+}
+
+impl Skew {
+    fn name(self) -> &'static str {
+        match self {
+            Skew::Uniform => "uniform",
+            Skew::Zipf => "zipf",
+        }
+    }
+}
+
+// Picks which of n pages to touch next. Under Zipf page i comes up with probability proportional to 1 / (i + 1)^theta
+struct Workload {
+    rng: Rng,
+    n: usize,
+    cdf: Option<Vec<f64>>, // running sum of the probabilities, None for uniform
+}
+
+impl Workload {
+    fn new(skew: Skew, n: usize) -> Self {
+        let cdf = match skew {
+            Skew::Uniform => None,
+            Skew::Zipf => {
+                let weights: Vec<f64> = (1..=n).map(|i| 1.0 / (i as f64).powf(ZIPF_THETA)).collect();
+                let total: f64 = weights.iter().sum();
+                let mut sum = 0.0;
+                Some(weights.iter().map(|w| { sum += w / total; sum }).collect())
+            }
+        };
+        Self { rng: Rng(0x9E37_79B9_7F4A_7C15), n, cdf }
+    }
+
+    // index of the next page, in 0..n
+    fn next(&mut self) -> usize {
+        match &self.cdf {
+            None => (self.rng.next_u64() % self.n as u64) as usize,
+            Some(cdf) => {
+                let u = self.rng.next_f64();
+                cdf.partition_point(|&p| p < u).min(self.n - 1)
+            }
+        }
+    }
+}
+
+// A pool over a fresh data file of DB_PAGES pages, all of them flushed so misses really go to disk
+struct TempDb {
+    bpm: BufferPoolManager,
+    pages: Vec<PageId>,
+    path: PathBuf,
+}
+
+impl TempDb {
+    fn new(name: &str, pool_size: usize, replacer: ReplacerKind) -> Self {
+        let path = std::env::temp_dir().join(format!("ggdb_bench_{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config = BufferPoolConfig { pool_size, replacer, ..BufferPoolConfig::new(&path) };
+        let bpm = BufferPoolManager::with_config(config).expect("can't open the bench data file");
+        let pages = (0..DB_PAGES).map(|_| bpm.allocate_page().expect("can't allocate").page_id).collect();
+        bpm.flush_all().expect("can't flush");
+        Self { bpm, pages, path }
+    }
+
+    fn fetch(&self, workload: &mut Workload) -> u64 {
+        let page = self.bpm.fetch_page(self.pages[workload.next()]).expect("fetch failed");
+        page.get_lsn()
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn fetch_page(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fetch_page");
+    group.throughput(Throughput::Elements(OPS as u64));
+    for skew in [Skew::Uniform, Skew::Zipf] {
+        for pool_size in POOL_SIZES {
+            let db = TempDb::new("fetch", pool_size, ReplacerKind::Clock);
+            let mut workload = Workload::new(skew, DB_PAGES);
+
+            // warm the pool up and see what share of fetches this setup serves from RAM
+            let before = db.bpm.stats();
+            for _ in 0..10 * OPS {
+                db.fetch(&mut workload);
+            }
+            let after = db.bpm.stats();
+            let (hits, misses) = (after.hits - before.hits, after.misses - before.misses);
+            println!("fetch_page/{}/{}: hit ratio {:.3}", skew.name(), pool_size, hits as f64 / (hits + misses) as f64);
+
+            group.bench_with_input(BenchmarkId::new(skew.name(), pool_size), &pool_size, |b, _| {
+                b.iter(|| {
+                    for _ in 0..OPS {
+                        black_box(db.fetch(&mut workload));
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn eviction(c: &mut Criterion) {
+    let mut group = c.benchmark_group("eviction");
+    group.throughput(Throughput::Elements(OPS as u64));
+    let replacers = [
+        ("clock", ReplacerKind::Clock),
+        ("gclock", ReplacerKind::GClock { max_count: 4 }),
+        ("lru2", ReplacerKind::LruK { k: 2 }),
+    ];
+    for skew in [Skew::Uniform, Skew::Zipf] {
+        for (name, replacer) in replacers {
+            let db = TempDb::new("evict", PRESSURE_POOL_SIZE, replacer);
+            let mut workload = Workload::new(skew, DB_PAGES);
+            group.bench_function(BenchmarkId::new(name, skew.name()), |b| {
+                b.iter(|| {
+                    for i in 0..OPS {
+                        let mut page = db.bpm.fetch_page_write(db.pages[workload.next()]).expect("fetch failed");
+                        page.write_u64_at(128, i as u64).unwrap();
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn slotted_page(c: &mut Criterion) {
+    let mut group = c.benchmark_group("slotted_page");
+    for size in RECORD_SIZES {
+        let record = vec![0xAB; size];
+        let mut full = Page::new(1, PageType::NodeStore);
+        let mut page = SlottedPage::init(&mut full);
+        while page.insert_record(&record).is_some() {}
+        let per_page = page.record_count() as u64;
+        group.throughput(Throughput::Elements(per_page));
+
+        group.bench_with_input(BenchmarkId::new("insert", size), &record, |b, record| {
+            b.iter(|| {
+                let mut empty = Page::new(1, PageType::NodeStore);
+                let mut page = SlottedPage::init(&mut empty);
+                while page.insert_record(black_box(record)).is_some() {}
+                black_box(page.record_count())
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("scan", size), &full, |b, full| {
+            b.iter(|| SlottedPage::new(full).iter().map(|(_, record)| record[0] as u64).sum::<u64>())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fetch_page, eviction, slotted_page);
+criterion_main!(benches);
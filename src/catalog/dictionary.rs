@@ -0,0 +1,228 @@
+/*
+* String dictionary for the u32 ids used by node labels, relationship types and property keys.
+* intern hands out ids in order starting at 0 and keeps handing back the same id for the same name, resolve goes
+* the other way. Names only ever get added, so the ids stay valid for as long as the database does.
+*
+* Everything lives in a chain of Dictionary pages starting at the root, which whoever owns the dictionary has to
+* keep (see root_page_id). Opening reads the whole chain into memory, lookups never touch the pool after that.
+* The n-th name in the chain has id n.
+*
+* Dictionary page layout:
+* | PageHeader | next_page: u64 | name_len: u16 | name ... | name_len: u16 | name ... | ...
+* next_page is NO_PAGE on the last page, item_count is how many names the page holds and free_space_pointer
+* where the next one goes. Names are UTF-8.
+*/
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use crate::paging::buffer_pool_manager::{BufferError, BufferPoolManager};
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::{PageId, HEADER_SIZE, PAGE_SIZE};
+
+const NO_PAGE: u64 = u64::MAX;
+
+const NEXT: usize = HEADER_SIZE;
+const NAMES: usize = HEADER_SIZE + 8;
+const LEN_SIZE: usize = 2;
+
+// longest name that fits in a page of its own
+pub const MAX_NAME_LEN: usize = PAGE_SIZE - NAMES - LEN_SIZE;
+
+pub struct Dictionary {
+    bpm: Arc<BufferPoolManager>,
+    root: PageId,
+    last: PageId, // where new names get appended
+    names: Vec<String>,
+    ids: HashMap<String, u32>,
+}
+
+impl Dictionary {
+    // starts an empty dictionary on a new root page
+    pub fn create(bpm: Arc<BufferPoolManager>) -> Result<Self, BufferError> {
+        let root = new_page(&bpm)?;
+        Ok(Self { bpm, root, last: root, names: Vec::new(), ids: HashMap::new() })
+    }
+
+    // reopens a dictionary from its root page, see root_page_id
+    pub fn open(bpm: Arc<BufferPoolManager>, root: PageId) -> Result<Self, BufferError> {
+        let mut names = Vec::new();
+        let mut page_id = root;
+        let mut last = root;
+        while page_id != NO_PAGE {
+            let page = bpm.fetch_page(page_id)?;
+            if page.page_type() != Ok(PageType::Dictionary) {
+                return Err(corrupt(page_id));
+            }
+            let data = page.get_data();
+            let mut at = NAMES;
+            for _ in 0..page.get_item_count() {
+                let len = data.get(at..at + LEN_SIZE).ok_or_else(|| corrupt(page_id))?;
+                let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+                let name = data.get(at + LEN_SIZE..at + LEN_SIZE + len).ok_or_else(|| corrupt(page_id))?;
+                names.push(String::from_utf8(name.to_vec()).map_err(|_| corrupt(page_id))?);
+                at += LEN_SIZE + len;
+            }
+            last = page_id;
+            page_id = u64::from_le_bytes(data[NEXT..NEXT + 8].try_into().unwrap());
+        }
+
+        let ids = names.iter().enumerate().map(|(id, name)| (name.clone(), id as u32)).collect();
+        Ok(Self { bpm, root, last, names, ids })
+    }
+
+    // where the chain starts, this is what has to be persisted to open the dictionary again
+    pub fn root_page_id(&self) -> PageId {
+        self.root
+    }
+
+    // id of name, adding it if it isn't in the dictionary yet
+    // fails with InvalidInput if name is longer than MAX_NAME_LEN bytes
+    pub fn intern(&mut self, name: &str) -> Result<u32, BufferError> {
+        if let Some(&id) = self.ids.get(name) {
+            return Ok(id);
+        }
+        if name.len() > MAX_NAME_LEN {
+            return Err(BufferError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("dictionary names can be at most {} bytes", MAX_NAME_LEN),
+            )));
+        }
+
+        let mut page = self.bpm.fetch_page_write(self.last)?;
+        if page.get_free_space_pointer() as usize + LEN_SIZE + name.len() > PAGE_SIZE {
+            // last page is full, chain a new one on
+            let next = new_page(&self.bpm)?;
+            page.get_data_mut()[NEXT..NEXT + 8].copy_from_slice(&next.to_le_bytes());
+            page.set_dirty(true);
+            drop(page);
+            self.last = next;
+            page = self.bpm.fetch_page_write(next)?;
+        }
+        append_name(&mut page, name);
+        drop(page);
+
+        let id = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        Ok(id)
+    }
+
+    // id of name if it was interned, never adds it
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn resolve(&self, id: u32) -> Option<&str> {
+        self.names.get(id as usize).map(String::as_str)
+    }
+
+    // number of names, also the id the next new name gets
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+fn new_page(bpm: &BufferPoolManager) -> Result<PageId, BufferError> {
+    let mut page = bpm.allocate_page_of_type(PageType::Dictionary)?;
+    page.get_data_mut()[NEXT..NEXT + 8].copy_from_slice(&NO_PAGE.to_le_bytes());
+    page.set_free_space_pointer(NAMES as u32);
+    page.set_dirty(true);
+    Ok(page.page_id)
+}
+
+fn append_name(page: &mut Page, name: &str) {
+    let at = page.get_free_space_pointer() as usize;
+    let data = page.get_data_mut();
+    data[at..at + LEN_SIZE].copy_from_slice(&(name.len() as u16).to_le_bytes());
+    data[at + LEN_SIZE..at + LEN_SIZE + name.len()].copy_from_slice(name.as_bytes());
+    page.set_free_space_pointer((at + LEN_SIZE + name.len()) as u32);
+    let count = page.get_item_count();
+    page.set_item_count(count + 1);
+    page.set_dirty(true);
+}
+
+fn corrupt(page_id: PageId) -> BufferError {
+    BufferError::Io(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("page {} is not a valid dictionary page", page_id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+
+    fn pool(disk: &Arc<MockDiskManager>) -> Arc<BufferPoolManager> {
+        Arc::new(BufferPoolManager::new(8, disk.clone()).unwrap())
+    }
+
+    #[test]
+    fn interning_a_name_twice_gives_the_same_id() {
+        let mut dict = Dictionary::create(pool(&Arc::new(MockDiskManager::new()))).unwrap();
+        let person = dict.intern("Person").unwrap();
+        let knows = dict.intern("KNOWS").unwrap();
+        assert_eq!((person, knows), (0, 1));
+        assert_eq!(dict.intern("Person").unwrap(), person);
+        assert_eq!(dict.len(), 2);
+        assert_eq!(dict.lookup("KNOWS"), Some(knows));
+        assert_eq!(dict.lookup("name"), None);
+    }
+
+    #[test]
+    fn resolve_gives_back_the_interned_name() {
+        let mut dict = Dictionary::create(pool(&Arc::new(MockDiskManager::new()))).unwrap();
+        assert!(dict.is_empty());
+        for name in ["", "Person", "née", "KNOWS"] {
+            let id = dict.intern(name).unwrap();
+            assert_eq!(dict.resolve(id), Some(name));
+        }
+        assert_eq!(dict.resolve(4), None);
+    }
+
+    #[test]
+    fn names_spanning_several_pages_survive_a_reopen() {
+        let disk = Arc::new(MockDiskManager::new());
+        let names: Vec<String> = (0..PAGE_SIZE / 8).map(|i| format!("label_{i}")).collect();
+        let root = {
+            let bpm = pool(&disk);
+            let mut dict = Dictionary::create(bpm.clone()).unwrap();
+            for name in &names {
+                dict.intern(name).unwrap();
+            }
+            bpm.flush_all().unwrap();
+            dict.root_page_id()
+        };
+
+        let mut dict = Dictionary::open(pool(&disk), root).unwrap();
+        assert_ne!(dict.last, root);
+        assert_eq!(dict.len(), names.len());
+        for (id, name) in names.iter().enumerate() {
+            assert_eq!(dict.resolve(id as u32), Some(name.as_str()));
+        }
+        assert_eq!(dict.intern("label_3").unwrap(), 3);
+        assert_eq!(dict.intern("new").unwrap(), names.len() as u32);
+    }
+
+    #[test]
+    fn overlong_names_are_refused() {
+        let mut dict = Dictionary::create(pool(&Arc::new(MockDiskManager::new()))).unwrap();
+        let name = "x".repeat(MAX_NAME_LEN + 1);
+        assert!(matches!(dict.intern(&name), Err(BufferError::Io(e)) if e.kind() == io::ErrorKind::InvalidInput));
+        assert!(dict.is_empty());
+        assert_eq!(dict.intern(&name[1..]).unwrap(), 0);
+    }
+
+    #[test]
+    fn opening_a_page_that_isnt_a_dictionary_fails() {
+        let bpm = pool(&Arc::new(MockDiskManager::new()));
+        let other = bpm.allocate_page().unwrap().page_id;
+        assert!(matches!(Dictionary::open(bpm, other), Err(BufferError::Io(e)) if e.kind() == io::ErrorKind::InvalidData));
+    }
+}
@@ -0,0 +1,251 @@
+/*
+* The superblock: page 0 of every database file, where everything that has to be found again after a restart
+* keeps its root page id (the dictionary, indexes, the free page list).
+* BufferPoolManager writes it when it creates a new file and never hands page 0 out, go through
+* BufferPoolManager::superblock / superblock_mut to read or change it. next_page_id and free_list_head belong to
+* the pool, it saves them on every flush_all.
+* Files from before the superblock existed don't have one, page 0 is a normal page there and superblock() fails.
+*
+* Superblock page layout:
+* | PageHeader | magic: u32 | pad: u32 | next_page_id: u64 | free_list_head: u64 | dictionary_root: u64 |
+* | index_roots: u64 * MAX_INDEX_ROOTS | free_space_root: u64 | free_space_current: u64 |
+* Unset roots are NO_PAGE. Superblocks from before free_space_root have 0 there, which reads as unset too (page 0
+* is always the superblock).
+*
+* free_space_root is the head of the overflow chain the free space maps were last saved to, free_space_current is 1
+* while that copy matches the rest of the file. Only the copy of the superblock on disk ever has it set, see
+* BufferPoolManager::flush_all.
+*
+* Free pages: deallocate_page turns the page into a Free page pointing at the previous head of the list, so the
+* whole list is a chain through the freed pages themselves and only its head has to live here.
+* | PageHeader | next_free: u64 |
+* The head only gets saved on flush_all, after a crash it can point at a page that was handed out again since.
+* With a write ahead log the AllocPage / FreePage records put the list back the way it was (see
+* LogManager::redo_free_list). Without one, walking the chain stops at the first page that isn't a Free page
+* anymore, so that leaks the rest of the list instead of handing out a page that's in use.
+*/
+
+use std::ops::{Deref, DerefMut};
+
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::{PageId, HEADER_SIZE};
+
+pub const SUPERBLOCK_PAGE: PageId = 0;
+// how many index roots the superblock has room for, the slot number is up to whoever creates the index
+pub const MAX_INDEX_ROOTS: usize = 64;
+
+const NO_PAGE: u64 = u64::MAX;
+const MAGIC: u32 = u32::from_le_bytes(*b"GGDB");
+
+const MAGIC_AT: usize = HEADER_SIZE;
+const NEXT_PAGE_ID: usize = HEADER_SIZE + 8;
+const FREE_LIST_HEAD: usize = HEADER_SIZE + 16;
+const DICTIONARY_ROOT: usize = HEADER_SIZE + 24;
+const INDEX_ROOTS: usize = HEADER_SIZE + 32;
+const FREE_SPACE_ROOT: usize = INDEX_ROOTS + MAX_INDEX_ROOTS * 8;
+const FREE_SPACE_CURRENT: usize = FREE_SPACE_ROOT + 8;
+
+const NEXT_FREE: usize = HEADER_SIZE;
+
+// Typed view over the superblock page, works over anything that derefs to a Page like SlottedPage does
+pub struct Superblock<P> {
+    page: P,
+}
+
+impl<P: Deref<Target = Page>> Superblock<P> {
+    // None if page isn't a superblock
+    pub fn new(page: P) -> Option<Self> {
+        let sb = Self { page };
+        sb.is_valid().then_some(sb)
+    }
+
+    pub fn into_inner(self) -> P {
+        self.page
+    }
+
+    fn is_valid(&self) -> bool {
+        self.page.page_type() == Ok(PageType::Superblock) && self.read_u64(MAGIC_AT) as u32 == MAGIC
+    }
+
+    // one past the highest page id allocated as of the last flush_all
+    pub fn next_page_id(&self) -> PageId {
+        self.read_u64(NEXT_PAGE_ID)
+    }
+
+    pub fn free_list_head(&self) -> Option<PageId> {
+        self.read_root(FREE_LIST_HEAD)
+    }
+
+    pub fn dictionary_root(&self) -> Option<PageId> {
+        self.read_root(DICTIONARY_ROOT)
+    }
+
+    // None for an unused slot, panics if slot is MAX_INDEX_ROOTS or more
+    pub fn index_root(&self, slot: usize) -> Option<PageId> {
+        assert!(slot < MAX_INDEX_ROOTS, "index root slot {} out of range", slot);
+        self.read_root(INDEX_ROOTS + slot * 8)
+    }
+
+    pub(crate) fn free_space_root(&self) -> Option<PageId> {
+        self.read_root(FREE_SPACE_ROOT).filter(|&root| root != SUPERBLOCK_PAGE)
+    }
+
+    // whether the chain at free_space_root is still what the free space maps look like
+    pub(crate) fn free_space_current(&self) -> bool {
+        self.read_u64(FREE_SPACE_CURRENT) == 1
+    }
+
+    fn read_root(&self, at: usize) -> Option<PageId> {
+        let root = self.read_u64(at);
+        (root != NO_PAGE).then_some(root)
+    }
+
+    fn read_u64(&self, at: usize) -> u64 {
+        u64::from_le_bytes(self.page.get_data()[at..at + 8].try_into().unwrap())
+    }
+}
+
+impl<P: DerefMut<Target = Page>> Superblock<P> {
+    // Formats page (a new Superblock type page) as an empty superblock, every root unset
+    pub(crate) fn init(page: P, next_page_id: PageId) -> Self {
+        let mut sb = Self { page };
+        sb.write_u64(MAGIC_AT, MAGIC as u64);
+        sb.write_u64(NEXT_PAGE_ID, next_page_id);
+        sb.write_u64(FREE_LIST_HEAD, NO_PAGE);
+        sb.write_u64(DICTIONARY_ROOT, NO_PAGE);
+        for slot in 0..MAX_INDEX_ROOTS {
+            sb.write_u64(INDEX_ROOTS + slot * 8, NO_PAGE);
+        }
+        sb.write_u64(FREE_SPACE_ROOT, NO_PAGE);
+        sb.write_u64(FREE_SPACE_CURRENT, 0);
+        sb
+    }
+
+    pub(crate) fn set_next_page_id(&mut self, next_page_id: PageId) {
+        self.write_u64(NEXT_PAGE_ID, next_page_id);
+    }
+
+    pub(crate) fn set_free_list_head(&mut self, head: Option<PageId>) {
+        self.write_u64(FREE_LIST_HEAD, head.unwrap_or(NO_PAGE));
+    }
+
+    pub fn set_dictionary_root(&mut self, root: Option<PageId>) {
+        self.write_u64(DICTIONARY_ROOT, root.unwrap_or(NO_PAGE));
+    }
+
+    pub fn set_index_root(&mut self, slot: usize, root: Option<PageId>) {
+        assert!(slot < MAX_INDEX_ROOTS, "index root slot {} out of range", slot);
+        self.write_u64(INDEX_ROOTS + slot * 8, root.unwrap_or(NO_PAGE));
+    }
+
+    pub(crate) fn set_free_space_root(&mut self, root: Option<PageId>) {
+        self.write_u64(FREE_SPACE_ROOT, root.unwrap_or(NO_PAGE));
+    }
+
+    pub(crate) fn set_free_space_current(&mut self, current: bool) {
+        self.write_u64(FREE_SPACE_CURRENT, current as u64);
+    }
+
+    fn write_u64(&mut self, at: usize, value: u64) {
+        self.page.get_data_mut()[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        self.page.set_dirty(true);
+    }
+}
+
+// page_id as a Free page linked to next
+pub(crate) fn free_page(page_id: PageId, next: Option<PageId>) -> Page {
+    let mut page = Page::new(page_id, PageType::Free);
+    page.get_data_mut()[NEXT_FREE..NEXT_FREE + 8].copy_from_slice(&next.unwrap_or(NO_PAGE).to_le_bytes());
+    page
+}
+
+// the link out of a Free page, None if page isn't one (anymore)
+pub(crate) fn next_free(page: &Page) -> Option<Option<PageId>> {
+    if page.page_type() != Ok(PageType::Free) {
+        return None;
+    }
+    let next = u64::from_le_bytes(page.get_data()[NEXT_FREE..NEXT_FREE + 8].try_into().unwrap());
+    Some((next != NO_PAGE).then_some(next))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::buffer_pool_manager::BufferPoolManager;
+
+    fn superblock_page() -> Page {
+        let mut page = Page::new(SUPERBLOCK_PAGE, PageType::Superblock);
+        Superblock::init(&mut page, 1);
+        page
+    }
+
+    #[test]
+    fn a_new_superblock_has_every_root_unset() {
+        let page = superblock_page();
+        let sb = Superblock::new(&page).unwrap();
+        assert_eq!(sb.next_page_id(), 1);
+        assert_eq!(sb.free_list_head(), None);
+        assert_eq!(sb.dictionary_root(), None);
+        assert!((0..MAX_INDEX_ROOTS).all(|slot| sb.index_root(slot).is_none()));
+    }
+
+    #[test]
+    fn only_superblock_pages_with_the_magic_are_taken() {
+        assert!(Superblock::new(&Page::new(SUPERBLOCK_PAGE, PageType::NodeStore)).is_none());
+        assert!(Superblock::new(&Page::new(SUPERBLOCK_PAGE, PageType::Superblock)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn index_root_slots_past_the_end_panic() {
+        let page = superblock_page();
+        Superblock::new(&page).unwrap().index_root(MAX_INDEX_ROOTS);
+    }
+
+    #[test]
+    fn roots_set_before_a_flush_are_there_after_reopening() {
+        let disk = Arc::new(MockDiskManager::new());
+        {
+            let bpm = BufferPoolManager::new(4, disk.clone()).unwrap();
+            let mut sb = bpm.superblock_mut().unwrap();
+            sb.set_dictionary_root(Some(7));
+            sb.set_index_root(3, Some(42));
+            drop(sb);
+            bpm.flush_all().unwrap();
+        }
+
+        let bpm = BufferPoolManager::new(4, disk).unwrap();
+        let sb = bpm.superblock().unwrap();
+        assert_eq!(sb.dictionary_root(), Some(7));
+        assert_eq!(sb.index_root(3), Some(42));
+        assert_eq!(sb.index_root(2), None);
+    }
+
+    #[test]
+    fn page_zero_is_never_handed_out() {
+        let bpm = BufferPoolManager::new(4, MockDiskManager::new()).unwrap();
+        let ids: Vec<PageId> = (0..8).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        assert!(!ids.contains(&SUPERBLOCK_PAGE));
+        assert!(bpm.superblock().is_ok());
+    }
+
+    #[test]
+    fn free_pages_link_to_the_next_one() {
+        let page = free_page(5, Some(9));
+        assert_eq!(next_free(&page), Some(Some(9)));
+        assert_eq!(next_free(&free_page(9, None)), Some(None));
+        assert_eq!(next_free(&Page::new(5, PageType::NodeStore)), None);
+    }
+
+    #[test]
+    fn a_superblock_from_before_the_free_space_root_has_none() {
+        let mut page = superblock_page();
+        // zeroed, the way files from before the field have it
+        page.get_data_mut()[FREE_SPACE_ROOT..FREE_SPACE_CURRENT + 8].fill(0);
+        let sb = Superblock::new(&page).unwrap();
+        assert_eq!(sb.free_space_root(), None);
+        assert!(!sb.free_space_current());
+    }
+}
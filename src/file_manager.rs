@@ -0,0 +1,3 @@
+pub mod disk_manager;
+pub mod mock_disk_manager;
+pub mod segment_manager;
@@ -1 +1,340 @@
-// manages files in the disk
+/*
+* Where the buffer pool reads and writes pages.
+* DiskManager is the interface, the pool holds a Box<dyn DiskManager> the same way it holds its Replacer.
+* FileDiskManager is the real one backed by the database file, MockDiskManager (see mock_disk_manager) keeps the
+* pages in memory and can be told to fail or tear writes, for testing recovery and eviction.
+* Recovery writes through the pool, so the write ahead log's redo ends up going through whichever one the pool has.
+* The log itself is on a LogDevice of its own (see wal::log_device), which MockDiskManager also is.
+*
+* FileDiskManager:
+* Pages are laid out back to back, so page N lives at byte offset N * PAGE_SIZE (see page_offset). With segments
+* (BufferPoolConfig::segments) the same goes for each segment file, SegmentManager works out which one.
+* PAGE_SIZE is fixed at compile time, a file written with one page size can't be read back with another.
+* By default every page gets its checksum stamped on the way out and verified on the way back in, see ChecksumMode
+* for the cheaper options.
+* The buffer pool is the only thing that should be talking to this directly.
+*/
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::segment_manager::SegmentManager;
+
+use crate::paging::config::ChecksumMode;
+use crate::paging::page::{stamp_checksum, verify_checksum_of};
+use crate::paging::page_constants::{PageId, PAGE_SIZE};
+
+pub trait DiskManager: Send + Sync {
+    // Reads page_id into buf. Pages that were never written come back zeroed, that includes page ids at or past
+    // num_pages, so reading a page allocate_page just handed out works before it's first flushed
+    // Fails with InvalidData if the stored checksum doesn't match the contents (when the checksum mode verifies)
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> io::Result<()>;
+
+    // Stores buf as page_id, stamping its checksum unless that's disabled
+    fn write_page(&self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> io::Result<()>;
+
+    // Durability barrier: blocks until every page written so far is on stable storage
+    fn sync(&self) -> io::Result<()>;
+
+    // One past the highest page that has storage behind it
+    fn num_pages(&self) -> io::Result<u64>;
+
+    fn is_read_only(&self) -> bool;
+}
+
+// so a test can hand the pool a disk and still look at it afterwards
+impl<D: DiskManager + ?Sized> DiskManager for Arc<D> {
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        (**self).read_page(page_id, buf)
+    }
+
+    fn write_page(&self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        (**self).write_page(page_id, buf)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        (**self).sync()
+    }
+
+    fn num_pages(&self) -> io::Result<u64> {
+        (**self).num_pages()
+    }
+
+    fn is_read_only(&self) -> bool {
+        (**self).is_read_only()
+    }
+}
+
+// checksums on top of a SegmentManager, which owns the file (or files) and where each page goes in them
+pub struct FileDiskManager {
+    segments: SegmentManager,
+    checksum_mode: ChecksumMode,
+}
+
+impl FileDiskManager {
+    // Opens the database file at path, creating it if it doesn't exist yet
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_checksum_mode(path, ChecksumMode::Always)
+    }
+
+    // same as new but with control over when checksums are stamped/verified
+    pub fn with_checksum_mode<P: AsRef<Path>>(path: P, checksum_mode: ChecksumMode) -> io::Result<Self> {
+        Ok(Self::with_segments(SegmentManager::single_file(path.as_ref(), false)?, checksum_mode))
+    }
+
+    // opens an existing file without write permission, so other processes can share it and nothing here can
+    // change it by accident. write_page fails with PermissionDenied
+    pub fn open_read_only<P: AsRef<Path>>(path: P, checksum_mode: ChecksumMode) -> io::Result<Self> {
+        Ok(Self::with_segments(SegmentManager::single_file(path.as_ref(), true)?, checksum_mode))
+    }
+
+    // a database spread over the segment files of segments, see SegmentManager
+    pub fn with_segments(segments: SegmentManager, checksum_mode: ChecksumMode) -> Self {
+        Self { segments, checksum_mode }
+    }
+
+    pub fn segments(&self) -> &SegmentManager {
+        &self.segments
+    }
+
+    // where page_id starts in the file of a single file database
+    pub fn page_offset(page_id: PageId) -> u64 {
+        page_id * PAGE_SIZE as u64
+    }
+}
+
+impl DiskManager for FileDiskManager {
+    fn is_read_only(&self) -> bool {
+        self.segments.is_read_only()
+    }
+
+    // Pages past the end of the file have never been written, so they come back zeroed. A partial last page is
+    // InvalidData, see SegmentManager::read_page
+    // the checksum is only checked in ChecksumMode::Always
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        self.segments.read_page(page_id, buf)?;
+        verify_read(page_id, buf, self.checksum_mode)
+    }
+
+    // Writes buf to page_id's slot in the file, growing the file if needed
+    fn write_page(&self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "data file was opened read only"));
+        }
+        self.segments.write_page(page_id, &prepare_write(buf, self.checksum_mode))
+    }
+
+    // write_page alone only hands the bytes to the OS. Nothing to do for a read only file
+    fn sync(&self) -> io::Result<()> {
+        self.segments.sync()
+    }
+
+    // rounded up if the last page is partial
+    fn num_pages(&self) -> io::Result<u64> {
+        self.segments.num_pages()
+    }
+}
+
+// what a page looks like on its way to storage, the checksum stamped in unless checksum_mode is Disabled
+pub(crate) fn prepare_write(buf: &[u8; PAGE_SIZE], checksum_mode: ChecksumMode) -> [u8; PAGE_SIZE] {
+    let mut out = *buf;
+    if checksum_mode != ChecksumMode::Disabled {
+        stamp_checksum(&mut out);
+    }
+    out
+}
+
+// checks a page that just came in from storage, only ChecksumMode::Always verifies anything
+pub(crate) fn verify_read(page_id: PageId, buf: &[u8; PAGE_SIZE], checksum_mode: ChecksumMode) -> io::Result<()> {
+    if checksum_mode != ChecksumMode::Always {
+        return Ok(());
+    }
+
+    // an all zero page is a hole in the file that was never written, nothing to verify
+    if buf.iter().any(|&b| b != 0) && !verify_checksum_of(buf) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("checksum mismatch on page {}", page_id),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::buffer_pool_manager::BufferPoolManager;
+    use crate::paging::page::{Page, PageType};
+    use crate::paging::page_constants::HEADER_SIZE;
+    use crate::test_util::TempPath;
+
+    #[test]
+    fn pages_read_back_what_was_written() {
+        let temp = TempPath::new("disk_roundtrip");
+        let disk = FileDiskManager::new(temp.path()).unwrap();
+        let mut page = [0u8; PAGE_SIZE];
+        page[HEADER_SIZE..HEADER_SIZE + 5].copy_from_slice(b"hello");
+        disk.write_page(3, &page).unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        disk.read_page(3, &mut buf).unwrap();
+        assert_eq!(&buf[HEADER_SIZE..HEADER_SIZE + 5], b"hello");
+        assert_eq!(disk.num_pages().unwrap(), 4);
+    }
+
+    #[test]
+    fn unwritten_pages_read_as_zeroes() {
+        let temp = TempPath::new("disk_holes");
+        let disk = FileDiskManager::new(temp.path()).unwrap();
+        disk.write_page(2, &[7; PAGE_SIZE]).unwrap();
+
+        let mut buf = [1u8; PAGE_SIZE];
+        disk.read_page(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+        disk.read_page(10, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn pages_survive_dropping_and_reopening_the_pool() {
+        let temp = TempPath::new("disk_reopen");
+        let page_id = {
+            let bpm = BufferPoolManager::new(4, FileDiskManager::new(temp.path()).unwrap()).unwrap();
+            let mut page = bpm.allocate_page_of_type(PageType::Overflow).unwrap();
+            page.write_u64_at(HEADER_SIZE as u32, 0xdead_beef).unwrap();
+            page.page_id
+        };
+
+        let bpm = BufferPoolManager::new(4, FileDiskManager::new(temp.path()).unwrap()).unwrap();
+        let page = bpm.fetch_page(page_id).unwrap();
+        assert_eq!(page.read_u64_at(HEADER_SIZE as u32).unwrap(), 0xdead_beef);
+        assert_eq!(page.page_type(), Ok(PageType::Overflow));
+    }
+
+    #[test]
+    fn written_pages_carry_a_valid_checksum() {
+        let temp = TempPath::new("disk_checksum");
+        let disk = FileDiskManager::new(temp.path()).unwrap();
+        disk.write_page(1, &Page::new(1, PageType::NodeStore).to_owned_bytes()).unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        disk.read_page(1, &mut buf).unwrap();
+        assert!(verify_checksum_of(&buf));
+    }
+
+    // flips a byte in the middle of page_id in the file at path
+    fn corrupt(path: &Path, page_id: PageId) {
+        use std::io::{Seek, SeekFrom, Write};
+
+        let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(FileDiskManager::page_offset(page_id) + 100)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+    }
+
+    #[test]
+    fn a_flipped_byte_on_disk_fails_the_read() {
+        let temp = TempPath::new("disk_corrupt");
+        let disk = FileDiskManager::new(temp.path()).unwrap();
+        disk.write_page(1, &Page::new(1, PageType::NodeStore).to_owned_bytes()).unwrap();
+        corrupt(temp.path(), 1);
+
+        let mut buf = [0u8; PAGE_SIZE];
+        let err = disk.read_page(1, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn on_flush_only_stamps_but_doesnt_verify() {
+        let temp = TempPath::new("disk_flush_only");
+        let disk = FileDiskManager::with_checksum_mode(temp.path(), ChecksumMode::OnFlushOnly).unwrap();
+        disk.write_page(1, &Page::new(1, PageType::NodeStore).to_owned_bytes()).unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        disk.read_page(1, &mut buf).unwrap();
+        assert!(verify_checksum_of(&buf));
+
+        corrupt(temp.path(), 1);
+        disk.read_page(1, &mut buf).unwrap();
+        assert!(!verify_checksum_of(&buf));
+    }
+
+    #[test]
+    fn disabled_leaves_the_checksum_alone() {
+        let temp = TempPath::new("disk_no_checksum");
+        let disk = FileDiskManager::with_checksum_mode(temp.path(), ChecksumMode::Disabled).unwrap();
+        disk.write_page(1, &Page::new(1, PageType::NodeStore).to_owned_bytes()).unwrap();
+
+        let mut buf = [0u8; PAGE_SIZE];
+        disk.read_page(1, &mut buf).unwrap();
+        assert_eq!(Page::from_bytes(buf).unwrap().get_checksum(), 0);
+
+        // reopened with verification it's caught
+        let verifying = FileDiskManager::new(temp.path()).unwrap();
+        assert!(verifying.read_page(1, &mut buf).is_err());
+    }
+
+    #[test]
+    fn page_size_follows_the_feature() {
+        let expected = if cfg!(feature = "page-4k") {
+            4096
+        } else if cfg!(feature = "page-16k") {
+            16384
+        } else {
+            8192
+        };
+        assert_eq!(PAGE_SIZE, expected);
+    }
+
+    #[test]
+    fn pages_sit_at_multiples_of_the_page_size() {
+        assert_eq!(FileDiskManager::page_offset(0), 0);
+        assert_eq!(FileDiskManager::page_offset(5), 5 * PAGE_SIZE as u64);
+
+        let temp = TempPath::new("disk_offsets");
+        let disk = FileDiskManager::new(temp.path()).unwrap();
+        let mut page = [0u8; PAGE_SIZE];
+        page[PAGE_SIZE - 1] = 0xee;
+        disk.write_page(2, &page).unwrap();
+        let bytes = std::fs::read(temp.path()).unwrap();
+        assert_eq!(bytes.len(), 3 * PAGE_SIZE);
+        assert_eq!(bytes[3 * PAGE_SIZE - 1], 0xee);
+    }
+
+    #[test]
+    fn the_page_one_past_the_end_reads_as_a_fresh_page() {
+        let temp = TempPath::new("disk_one_past_end");
+        let disk = FileDiskManager::new(temp.path()).unwrap();
+        for page_id in 0..3 {
+            disk.write_page(page_id, &Page::new(page_id, PageType::NodeStore).to_owned_bytes()).unwrap();
+        }
+        let end = disk.num_pages().unwrap();
+        assert_eq!(end, 3);
+
+        let mut buf = [1u8; PAGE_SIZE];
+        disk.read_page(end, &mut buf).unwrap();
+        assert_eq!(buf, [0; PAGE_SIZE]);
+        assert_eq!(disk.num_pages().unwrap(), end);
+        assert!(Page::from_bytes(buf).is_ok());
+    }
+
+    #[test]
+    fn a_partial_last_page_is_invalid_data() {
+        let temp = TempPath::new("disk_partial_page");
+        let disk = FileDiskManager::with_checksum_mode(temp.path(), ChecksumMode::Disabled).unwrap();
+        disk.write_page(0, &[1; PAGE_SIZE]).unwrap();
+        disk.write_page(1, &[2; PAGE_SIZE]).unwrap();
+        std::fs::OpenOptions::new().write(true).open(temp.path()).unwrap().set_len(PAGE_SIZE as u64 + 100).unwrap();
+
+        let mut buf = [3u8; PAGE_SIZE];
+        let err = disk.read_page(1, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(buf, [0; PAGE_SIZE]);
+        // the pages around it are fine
+        assert_eq!(disk.num_pages().unwrap(), 2);
+        disk.read_page(0, &mut buf).unwrap();
+        assert_eq!(buf, [1; PAGE_SIZE]);
+        disk.read_page(2, &mut buf).unwrap();
+        assert_eq!(buf, [0; PAGE_SIZE]);
+    }
+}
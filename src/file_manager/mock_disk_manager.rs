@@ -0,0 +1,305 @@
+/*
+* In memory DiskManager for tests that need the disk to misbehave on cue.
+* Pages live in a HashMap, one that was never written reads back zeroed like a hole in the file. Checksums are
+* stamped and verified exactly like FileDiskManager does it, so a torn page gets caught the same way.
+*
+* Faults:
+* - fail_reads / fail_writes: every read / write fails with an io error until turned off again
+* - fail_next_writes(n): only the next n writes fail
+* - tear_next_write(len): the next write only gets its first len bytes stored and the rest of the page keeps
+*   whatever was there before, like losing power halfway through a write. The write itself still reports success
+* Every call gets counted, see reads / writes / syncs.
+*
+* It's a LogDevice as well, the log is a byte vector kept next to the pages. The faults and counters cover its
+* appends (a torn one only stores its first len bytes) and reads the same way, so one mock can be the data disk and
+* another the log, or a single one can be both.
+*/
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::disk_manager::{prepare_write, verify_read, DiskManager};
+use crate::paging::config::ChecksumMode;
+use crate::paging::page_constants::{PageId, PAGE_SIZE};
+use crate::wal::log_device::LogDevice;
+
+#[derive(Default)]
+struct Faults {
+    failing_writes: usize,     // how many of the next writes fail
+    torn_write: Option<usize>, // bytes the next write gets to store
+}
+
+pub struct MockDiskManager {
+    pages: Mutex<HashMap<PageId, [u8; PAGE_SIZE]>>,
+    log: Mutex<Vec<u8>>,
+    faults: Mutex<Faults>,
+    fail_reads: AtomicBool,
+    fail_writes: AtomicBool,
+    checksum_mode: ChecksumMode,
+    read_only: bool,
+    reads: AtomicU64,
+    writes: AtomicU64,
+    syncs: AtomicU64,
+}
+
+impl MockDiskManager {
+    // an empty disk verifying checksums like ChecksumMode::Always
+    pub fn new() -> Self {
+        Self::with_checksum_mode(ChecksumMode::Always)
+    }
+
+    pub fn with_checksum_mode(checksum_mode: ChecksumMode) -> Self {
+        Self {
+            pages: Mutex::new(HashMap::new()),
+            log: Mutex::new(Vec::new()),
+            faults: Mutex::new(Faults::default()),
+            fail_reads: AtomicBool::new(false),
+            fail_writes: AtomicBool::new(false),
+            checksum_mode,
+            read_only: false,
+            reads: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+            syncs: AtomicU64::new(0),
+        }
+    }
+
+    // a read only disk holding the same pages as other, writes fail with PermissionDenied
+    pub fn read_only_copy(other: &MockDiskManager) -> Self {
+        let mut disk = Self::with_checksum_mode(other.checksum_mode);
+        disk.pages = Mutex::new(other.pages.lock().unwrap().clone());
+        disk.read_only = true;
+        disk
+    }
+
+    pub fn fail_reads(&self, fail: bool) {
+        self.fail_reads.store(fail, Ordering::SeqCst);
+    }
+
+    pub fn fail_writes(&self, fail: bool) {
+        self.fail_writes.store(fail, Ordering::SeqCst);
+    }
+
+    pub fn fail_next_writes(&self, n: usize) {
+        self.faults.lock().unwrap().failing_writes = n;
+    }
+
+    // len is clamped to PAGE_SIZE, which makes it a normal write (a log append longer than that is still torn)
+    pub fn tear_next_write(&self, len: usize) {
+        self.faults.lock().unwrap().torn_write = Some(len.min(PAGE_SIZE));
+    }
+
+    // the stored bytes of page_id, checksum and all. None if it was never written
+    pub fn raw_page(&self, page_id: PageId) -> Option<[u8; PAGE_SIZE]> {
+        self.pages.lock().unwrap().get(&page_id).copied()
+    }
+
+    // replaces the stored bytes of page_id as is, nothing gets stamped or counted
+    pub fn set_raw_page(&self, page_id: PageId, data: [u8; PAGE_SIZE]) {
+        self.pages.lock().unwrap().insert(page_id, data);
+    }
+
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    pub fn syncs(&self) -> u64 {
+        self.syncs.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for MockDiskManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DiskManager for MockDiskManager {
+    fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        if self.fail_reads.load(Ordering::SeqCst) {
+            return Err(injected("read", page_id));
+        }
+        match self.pages.lock().unwrap().get(&page_id) {
+            Some(data) => *buf = *data,
+            None => buf.fill(0),
+        }
+        verify_read(page_id, buf, self.checksum_mode)
+    }
+
+    fn write_page(&self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "mock disk is read only"));
+        }
+        let mut faults = self.faults.lock().unwrap();
+        if self.fail_writes.load(Ordering::SeqCst) || faults.failing_writes > 0 {
+            faults.failing_writes = faults.failing_writes.saturating_sub(1);
+            return Err(injected("write", page_id));
+        }
+        let out = prepare_write(buf, self.checksum_mode);
+        let mut pages = self.pages.lock().unwrap();
+        let stored = pages.entry(page_id).or_insert([0; PAGE_SIZE]);
+        let len = faults.torn_write.take().unwrap_or(PAGE_SIZE);
+        stored[..len].copy_from_slice(&out[..len]);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn num_pages(&self) -> io::Result<u64> {
+        Ok(self.pages.lock().unwrap().keys().max().map_or(0, |&last| last + 1))
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+}
+
+impl LogDevice for MockDiskManager {
+    fn read_all(&self) -> io::Result<Vec<u8>> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        if self.fail_reads.load(Ordering::SeqCst) {
+            return Err(io::Error::other("injected read failure on the log"));
+        }
+        Ok(self.log.lock().unwrap().clone())
+    }
+
+    fn append(&self, bytes: &[u8]) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "mock disk is read only"));
+        }
+        let mut faults = self.faults.lock().unwrap();
+        if self.fail_writes.load(Ordering::SeqCst) || faults.failing_writes > 0 {
+            faults.failing_writes = faults.failing_writes.saturating_sub(1);
+            return Err(io::Error::other("injected write failure on the log"));
+        }
+        let len = faults.torn_write.take().unwrap_or(bytes.len()).min(bytes.len());
+        self.log.lock().unwrap().extend_from_slice(&bytes[..len]);
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        DiskManager::sync(self)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.log.lock().unwrap().truncate(len as usize);
+        Ok(())
+    }
+
+    fn replace(&self, bytes: &[u8]) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        *self.log.lock().unwrap() = bytes.to_vec();
+        Ok(())
+    }
+}
+
+fn injected(op: &str, page_id: PageId) -> io::Error {
+    io::Error::other(format!("injected {} failure on page {}", op, page_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::page::{Page, PageType};
+    use crate::paging::page_constants::HEADER_SIZE;
+
+    fn page_of(page_id: PageId, fill: u8) -> [u8; PAGE_SIZE] {
+        let mut data = Page::new(page_id, PageType::Overflow).to_owned_bytes();
+        data[HEADER_SIZE..].fill(fill);
+        data
+    }
+
+    #[test]
+    fn written_pages_read_back_and_holes_read_as_zeros() {
+        let disk = MockDiskManager::new();
+        disk.write_page(3, &page_of(3, 7)).unwrap();
+        let mut buf = [1; PAGE_SIZE];
+        disk.read_page(3, &mut buf).unwrap();
+        assert_eq!(buf[HEADER_SIZE..], page_of(3, 7)[HEADER_SIZE..]);
+        disk.read_page(1, &mut buf).unwrap();
+        assert_eq!(buf, [0; PAGE_SIZE]);
+        assert_eq!(disk.num_pages().unwrap(), 4);
+        assert_eq!((disk.reads(), disk.writes()), (2, 1));
+    }
+
+    #[test]
+    fn a_torn_write_is_caught_by_the_checksum() {
+        let disk = MockDiskManager::new();
+        disk.write_page(1, &page_of(1, 1)).unwrap();
+        disk.tear_next_write(PAGE_SIZE / 2);
+        disk.write_page(1, &page_of(1, 2)).unwrap();
+
+        let mut buf = [0; PAGE_SIZE];
+        let err = disk.read_page(1, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // the next full write repairs it
+        disk.write_page(1, &page_of(1, 2)).unwrap();
+        disk.read_page(1, &mut buf).unwrap();
+    }
+
+    #[test]
+    fn a_torn_write_goes_unnoticed_without_checksums() {
+        let disk = MockDiskManager::with_checksum_mode(ChecksumMode::Disabled);
+        disk.write_page(1, &page_of(1, 1)).unwrap();
+        disk.tear_next_write(PAGE_SIZE / 2);
+        disk.write_page(1, &page_of(1, 2)).unwrap();
+
+        let mut buf = [0; PAGE_SIZE];
+        disk.read_page(1, &mut buf).unwrap();
+        assert_eq!(buf[PAGE_SIZE / 2 - 1], 2);
+        assert_eq!(buf[PAGE_SIZE / 2], 1);
+    }
+
+    #[test]
+    fn injected_failures_fail_until_turned_off() {
+        let disk = MockDiskManager::new();
+        let mut buf = [0; PAGE_SIZE];
+        disk.fail_writes(true);
+        assert!(disk.write_page(1, &page_of(1, 1)).is_err());
+        disk.fail_writes(false);
+        disk.fail_next_writes(2);
+        assert!(disk.write_page(1, &page_of(1, 1)).is_err());
+        assert!(disk.write_page(1, &page_of(1, 1)).is_err());
+        disk.write_page(1, &page_of(1, 1)).unwrap();
+
+        disk.fail_reads(true);
+        assert!(disk.read_page(1, &mut buf).is_err());
+        disk.fail_reads(false);
+        disk.read_page(1, &mut buf).unwrap();
+        assert_eq!(disk.writes(), 4);
+    }
+
+    #[test]
+    fn a_read_only_copy_has_the_pages_but_refuses_writes() {
+        let disk = MockDiskManager::new();
+        disk.write_page(2, &page_of(2, 5)).unwrap();
+        let copy = MockDiskManager::read_only_copy(&disk);
+        assert!(copy.is_read_only());
+        assert_eq!(copy.raw_page(2), disk.raw_page(2));
+        assert_eq!(copy.write_page(2, &page_of(2, 6)).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn the_log_side_keeps_appends_and_tears_them_too() {
+        let disk = MockDiskManager::new();
+        disk.append(b"first").unwrap();
+        disk.tear_next_write(3);
+        disk.append(b"second").unwrap();
+        assert_eq!(disk.read_all().unwrap(), b"firstsec");
+        disk.truncate(5).unwrap();
+        assert_eq!(disk.read_all().unwrap(), b"first");
+        disk.replace(b"new").unwrap();
+        assert_eq!(disk.read_all().unwrap(), b"new");
+    }
+}
@@ -0,0 +1,339 @@
+/*
+* Spreads the pages of one database over several files (segments) of a fixed maximum size.
+* Page ids stay global, page N lives in segment N / segment_pages at local page N % segment_pages (see locate),
+* so the buffer pool never knows how many files there are. Segments are created the first time a page in them
+* gets written and each one has its own lock, I/O on different segments doesn't wait on each other.
+*
+* Segment files are named segment_00000.db, segment_00001.db, ... inside the segment directory. The segment size
+* isn't stored anywhere, reopening a directory with a smaller size than it was written with is caught (a segment
+* is bigger than allowed) but a bigger one would scramble the page ids, always open with the same size.
+*
+* A plain single file database is the same thing with one segment that never fills up, which is what
+* FileDiskManager::new uses.
+*/
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+use crate::paging::page_constants::{PageId, PAGE_SIZE};
+
+enum Layout {
+    Single(PathBuf), // the whole database in this one file
+    Dir(PathBuf),    // segment files inside this directory
+}
+
+pub struct SegmentManager {
+    layout: Layout,
+    segment_pages: u64, // pages per segment, u64::MAX for a single file
+    // index is the segment number, a segment that doesn't exist yet is None. Seek + read/write has to happen as
+    // one step, so every file handle is behind its own lock
+    files: RwLock<Vec<Option<Mutex<File>>>>,
+    read_only: bool,
+}
+
+impl SegmentManager {
+    // Opens (creating if needed) the segments in dir, each one at most segment_size bytes
+    // segment_size has to be a non zero multiple of PAGE_SIZE
+    pub fn open<P: AsRef<Path>>(dir: P, segment_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        Self::open_dir(dir.as_ref(), segment_size, false)
+    }
+
+    // same as open without write permission, the directory has to exist already
+    pub fn open_read_only<P: AsRef<Path>>(dir: P, segment_size: u64) -> io::Result<Self> {
+        Self::open_dir(dir.as_ref(), segment_size, true)
+    }
+
+    // one file holding every page, created if it doesn't exist unless read_only
+    pub(crate) fn single_file(path: &Path, read_only: bool) -> io::Result<Self> {
+        let file = open_file(path, read_only, true)?;
+        Ok(Self {
+            layout: Layout::Single(path.to_path_buf()),
+            segment_pages: u64::MAX,
+            files: RwLock::new(vec![Some(Mutex::new(file))]),
+            read_only,
+        })
+    }
+
+    fn open_dir(dir: &Path, segment_size: u64, read_only: bool) -> io::Result<Self> {
+        if segment_size == 0 || !segment_size.is_multiple_of(PAGE_SIZE as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("segment size has to be a non zero multiple of {} bytes", PAGE_SIZE),
+            ));
+        }
+        if !dir.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a directory", dir.display())));
+        }
+
+        let mut segments = Self {
+            layout: Layout::Dir(dir.to_path_buf()),
+            segment_pages: segment_size / PAGE_SIZE as u64,
+            files: RwLock::new(Vec::new()),
+            read_only,
+        };
+        // pick up every segment that's there, gaps (never written segments) included
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            let Some(index) = name.to_str().and_then(parse_segment_name) else { continue };
+            if files.len() <= index {
+                files.resize_with(index + 1, || None);
+            }
+            let path = segments.segment_path(index);
+            let file = open_file(&path, read_only, false)?;
+            if file.metadata()?.len() > segment_size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is bigger than the segment size of {} bytes", path.display(), segment_size),
+                ));
+            }
+            files[index] = Some(Mutex::new(file));
+        }
+        segments.files = RwLock::new(files);
+        Ok(segments)
+    }
+
+    // which segment page_id lives in and its page number inside that segment
+    pub fn locate(&self, page_id: PageId) -> (usize, u64) {
+        ((page_id / self.segment_pages) as usize, page_id % self.segment_pages)
+    }
+
+    // how many pages fit in one segment
+    pub fn segment_pages(&self) -> u64 {
+        self.segment_pages
+    }
+
+    // segments that exist on disk
+    pub fn segment_count(&self) -> usize {
+        self.files.read().unwrap().iter().flatten().count()
+    }
+
+    // file segment index is (or would be) stored in
+    pub fn segment_path(&self, index: usize) -> PathBuf {
+        match &self.layout {
+            Layout::Single(path) => path.clone(),
+            Layout::Dir(dir) => dir.join(format!("segment_{:05}.db", index)),
+        }
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // Reads page_id into buf, zeroed if its segment or the page itself was never written. A page that starts at
+    // or past the end of the file isn't an error, it comes back zeroed, which is how a page that was allocated but
+    // never written looks. A page the end of the file cuts through is: a write to it never finished, so it's
+    // InvalidData like any other torn page and buf is left zeroed
+    pub fn read_page(&self, page_id: PageId, buf: &mut [u8; PAGE_SIZE]) -> io::Result<()> {
+        let (index, local) = self.locate(page_id);
+        let files = self.files.read().unwrap();
+        let Some(Some(file)) = files.get(index) else {
+            buf.fill(0);
+            return Ok(());
+        };
+        let mut file = file.lock().unwrap();
+        let offset = local * PAGE_SIZE as u64;
+        let len = file.metadata()?.len();
+        if offset >= len {
+            buf.fill(0);
+            return Ok(());
+        }
+        // the file can end inside the page when a write to it never finished
+        if len - offset < PAGE_SIZE as u64 {
+            buf.fill(0);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {} is cut short by the end of its file ({} of {} bytes)", page_id, len - offset, PAGE_SIZE),
+            ));
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(buf)
+    }
+
+    // Writes buf as page_id, creating its segment if this is the first page written to it
+    pub fn write_page(&self, page_id: PageId, buf: &[u8; PAGE_SIZE]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "data file was opened read only"));
+        }
+        let (index, local) = self.locate(page_id);
+        let files = self.files.read().unwrap();
+        let files = match files.get(index) {
+            Some(Some(_)) => files,
+            _ => {
+                drop(files);
+                self.create_segment(index)?;
+                self.files.read().unwrap()
+            }
+        };
+        let mut file = files[index].as_ref().unwrap().lock().unwrap();
+        file.seek(SeekFrom::Start(local * PAGE_SIZE as u64))?;
+        file.write_all(buf)?;
+        file.flush()
+    }
+
+    fn create_segment(&self, index: usize) -> io::Result<()> {
+        let mut files = self.files.write().unwrap();
+        if files.len() <= index {
+            files.resize_with(index + 1, || None);
+        }
+        if files[index].is_none() {
+            files[index] = Some(Mutex::new(open_file(&self.segment_path(index), false, true)?));
+        }
+        Ok(())
+    }
+
+    // Durability barrier over every segment, nothing to do when read only
+    pub fn sync(&self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        for file in self.files.read().unwrap().iter().flatten() {
+            file.lock().unwrap().sync_data()?;
+        }
+        Ok(())
+    }
+
+    // One past the highest page backed by a segment (rounded up if the last page is partial, reading that one fails)
+    pub fn num_pages(&self) -> io::Result<u64> {
+        let files = self.files.read().unwrap();
+        for (index, file) in files.iter().enumerate().rev() {
+            let Some(file) = file else { continue };
+            let len = file.lock().unwrap().metadata()?.len();
+            if len > 0 {
+                return Ok(index as u64 * self.segment_pages + len.div_ceil(PAGE_SIZE as u64));
+            }
+        }
+        Ok(0)
+    }
+}
+
+fn open_file(path: &Path, read_only: bool, create: bool) -> io::Result<File> {
+    if read_only {
+        return OpenOptions::new().read(true).open(path);
+    }
+    OpenOptions::new().read(true).write(true).create(create).truncate(false).open(path)
+}
+
+// segment_00042.db -> 42
+fn parse_segment_name(name: &str) -> Option<usize> {
+    name.strip_prefix("segment_")?.strip_suffix(".db")?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+
+    const SEGMENT_PAGES: u64 = 4;
+
+    fn page_of(fill: u8) -> [u8; PAGE_SIZE] {
+        [fill; PAGE_SIZE]
+    }
+
+    fn open(dir: &TempPath) -> SegmentManager {
+        SegmentManager::open(dir.path(), SEGMENT_PAGES * PAGE_SIZE as u64).unwrap()
+    }
+
+    #[test]
+    fn page_ids_map_to_segment_and_local_page() {
+        let dir = TempPath::new("segment_locate");
+        let segments = open(&dir);
+        assert_eq!(segments.locate(0), (0, 0));
+        assert_eq!(segments.locate(3), (0, 3));
+        assert_eq!(segments.locate(4), (1, 0));
+        assert_eq!(segments.locate(9), (2, 1));
+    }
+
+    #[test]
+    fn writes_roll_over_into_a_second_segment_and_read_across_the_boundary() {
+        let dir = TempPath::new("segment_rollover");
+        let segments = open(&dir);
+        for page_id in 0..6 {
+            segments.write_page(page_id, &page_of(page_id as u8 + 1)).unwrap();
+        }
+        assert_eq!(segments.segment_count(), 2);
+        assert!(segments.segment_path(1).exists());
+        assert_eq!(fs::metadata(segments.segment_path(0)).unwrap().len(), SEGMENT_PAGES * PAGE_SIZE as u64);
+        assert_eq!(segments.num_pages().unwrap(), 6);
+
+        let mut buf = [0; PAGE_SIZE];
+        for page_id in 0..6 {
+            segments.read_page(page_id, &mut buf).unwrap();
+            assert_eq!(buf, page_of(page_id as u8 + 1));
+        }
+    }
+
+    #[test]
+    fn reopening_picks_up_every_segment_gaps_included() {
+        let dir = TempPath::new("segment_reopen");
+        {
+            let segments = open(&dir);
+            segments.write_page(1, &page_of(1)).unwrap();
+            segments.write_page(9, &page_of(9)).unwrap();
+            segments.sync().unwrap();
+        }
+        let segments = open(&dir);
+        assert_eq!(segments.segment_count(), 2);
+        assert_eq!(segments.num_pages().unwrap(), 10);
+        let mut buf = [1; PAGE_SIZE];
+        segments.read_page(9, &mut buf).unwrap();
+        assert_eq!(buf, page_of(9));
+        // the segment in between was never written
+        segments.read_page(5, &mut buf).unwrap();
+        assert_eq!(buf, page_of(0));
+    }
+
+    #[test]
+    fn a_segment_size_that_isnt_whole_pages_is_refused() {
+        let dir = TempPath::new("segment_bad_size");
+        for size in [0, PAGE_SIZE as u64 + 1] {
+            let err = SegmentManager::open(dir.path(), size).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn reopening_with_a_smaller_segment_size_is_refused() {
+        let dir = TempPath::new("segment_shrunk");
+        open(&dir).write_page(3, &page_of(3)).unwrap();
+        let err = SegmentManager::open(dir.path(), 2 * PAGE_SIZE as u64).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn a_read_only_manager_reads_but_refuses_writes() {
+        let dir = TempPath::new("segment_read_only");
+        open(&dir).write_page(0, &page_of(7)).unwrap();
+        let segments = SegmentManager::open_read_only(dir.path(), SEGMENT_PAGES * PAGE_SIZE as u64).unwrap();
+        let mut buf = [0; PAGE_SIZE];
+        segments.read_page(0, &mut buf).unwrap();
+        assert_eq!(buf, page_of(7));
+        assert_eq!(segments.write_page(0, &buf).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        // and never creates segments
+        assert_eq!(segments.write_page(8, &buf).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(segments.segment_count(), 1);
+    }
+
+    #[test]
+    fn a_segment_cut_short_inside_a_page_fails_that_page() {
+        let dir = TempPath::new("segment_partial_page");
+        let segments = open(&dir);
+        for page_id in 4..6 {
+            segments.write_page(page_id, &page_of(page_id as u8)).unwrap();
+        }
+        let path = segments.segment_path(1);
+        drop(segments);
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(PAGE_SIZE as u64 + 1).unwrap();
+
+        let segments = open(&dir);
+        let mut buf = [0; PAGE_SIZE];
+        assert_eq!(segments.read_page(5, &mut buf).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        segments.read_page(4, &mut buf).unwrap();
+        assert_eq!(buf, page_of(4));
+        segments.read_page(6, &mut buf).unwrap();
+        assert_eq!(buf, page_of(0));
+    }
+}
@@ -0,0 +1,2 @@
+pub mod bplus_tree;
+pub mod hash_index;
@@ -0,0 +1,418 @@
+/*
+* Disk backed B+ tree from u64 keys to NodeIds, for looking nodes up by a property value.
+* Every tree node is one BTreeNode page in the buffer pool. Keys are unique, inserting an existing key
+* replaces its value. Leaves are linked left to right so range scans just walk the leaf level.
+*
+* Tree node page layout:
+* | PageHeader | kind: u8 | pad: u8 | count: u16 | pad: u32 | next: u64 | entry 0 | entry 1 | ... |
+* each entry is (key: u64, value: u64)
+* leaf: value is the NodeId, next is the right sibling (NO_PAGE for the last leaf)
+* internal: value is the child holding keys >= key, next is the leftmost child (keys < entry 0's key)
+*
+* Writers are serialized by the root lock, readers share it. A node is read into memory, changed, and written back
+* as a whole, splits allocate the new right half through the buffer pool. An insert latches every page it's going
+* to change and allocates every page it needs before it writes any of them.
+*/
+
+use std::sync::{Arc, RwLock};
+
+use crate::paging::buffer_pool_manager::{BufferError, BufferPoolManager, WritePageGuard};
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::{PageId, HEADER_SIZE, PAGE_SIZE};
+use crate::store::node_store::NodeId;
+
+const NO_PAGE: u64 = u64::MAX;
+
+const KIND: usize = HEADER_SIZE;
+const COUNT: usize = HEADER_SIZE + 2;
+const NEXT: usize = HEADER_SIZE + 8;
+const ENTRIES: usize = HEADER_SIZE + 16;
+const ENTRY_SIZE: usize = 16;
+
+const KIND_LEAF: u8 = 0;
+const KIND_INTERNAL: u8 = 1;
+
+// most entries a node page can hold, one more than this and it splits
+pub const MAX_ENTRIES: usize = (PAGE_SIZE - ENTRIES) / ENTRY_SIZE;
+
+// a tree node pulled out of its page
+struct TreeNode {
+    leaf: bool,
+    next: u64,
+    keys: Vec<u64>,
+    values: Vec<u64>,
+}
+
+impl TreeNode {
+    fn read(page: &Page) -> Option<Self> {
+        let data = page.get_data();
+        let leaf = match data[KIND] {
+            KIND_LEAF => true,
+            KIND_INTERNAL => false,
+            _ => return None,
+        };
+        let count = u16::from_le_bytes([data[COUNT], data[COUNT + 1]]) as usize;
+        if count > MAX_ENTRIES {
+            return None;
+        }
+        let next = page.read_u64_at(NEXT as u32).ok()?;
+        let mut keys = Vec::with_capacity(count + 1);
+        let mut values = Vec::with_capacity(count + 1);
+        for i in 0..count {
+            let at = (ENTRIES + i * ENTRY_SIZE) as u32;
+            keys.push(page.read_u64_at(at).ok()?);
+            values.push(page.read_u64_at(at + 8).ok()?);
+        }
+        Some(Self { leaf, next, keys, values })
+    }
+
+    fn write(&self, page: &mut Page) {
+        let data = page.get_data_mut();
+        data[KIND] = if self.leaf { KIND_LEAF } else { KIND_INTERNAL };
+        data[COUNT..COUNT + 2].copy_from_slice(&(self.keys.len() as u16).to_le_bytes());
+        data[NEXT..NEXT + 8].copy_from_slice(&self.next.to_le_bytes());
+        for (i, (key, value)) in self.keys.iter().zip(&self.values).enumerate() {
+            let at = ENTRIES + i * ENTRY_SIZE;
+            data[at..at + 8].copy_from_slice(&key.to_le_bytes());
+            data[at + 8..at + 16].copy_from_slice(&value.to_le_bytes());
+        }
+        page.set_dirty(true);
+    }
+
+    // child of an internal node that covers key
+    fn child_for(&self, key: u64) -> PageId {
+        match self.keys.partition_point(|&k| k <= key) {
+            0 => self.next,
+            i => self.values[i - 1],
+        }
+    }
+
+    // moves the upper half into a new node, returns (separator key, right half)
+    fn split(&mut self) -> (u64, TreeNode) {
+        let mid = self.keys.len() / 2;
+        if self.leaf {
+            let right = TreeNode {
+                leaf: true,
+                next: self.next,
+                keys: self.keys.split_off(mid),
+                values: self.values.split_off(mid),
+            };
+            (right.keys[0], right)
+        } else {
+            // the middle key moves up, its child becomes the right node's leftmost child
+            let keys = self.keys.split_off(mid + 1);
+            let values = self.values.split_off(mid + 1);
+            let separator = self.keys.pop().unwrap();
+            let leftmost = self.values.pop().unwrap();
+            (separator, TreeNode { leaf: false, next: leftmost, keys, values })
+        }
+    }
+}
+
+pub struct BPlusTree {
+    bpm: Arc<BufferPoolManager>,
+    root: RwLock<PageId>,
+}
+
+impl BPlusTree {
+    // starts an empty tree with a single leaf as its root
+    pub fn create(bpm: Arc<BufferPoolManager>) -> Result<Self, BufferError> {
+        let empty = TreeNode { leaf: true, next: NO_PAGE, keys: Vec::new(), values: Vec::new() };
+        let root = Self::new_node(&bpm, &empty)?;
+        Ok(Self { bpm, root: RwLock::new(root) })
+    }
+
+    // reopens a tree from its root page, see root_page_id
+    pub fn open(bpm: Arc<BufferPoolManager>, root: PageId) -> Self {
+        Self { bpm, root: RwLock::new(root) }
+    }
+
+    // the root moves when it splits, whoever persists the tree has to save this after inserting
+    pub fn root_page_id(&self) -> PageId {
+        *self.root.read().unwrap()
+    }
+
+    // Maps key to value, replacing whatever key mapped to before
+    // every page the insert changes is latched and every page its splits need is allocated before anything gets
+    // written, so an error (PoolExhausted, most likely) leaves the tree as it was
+    pub fn insert(&self, key: u64, value: NodeId) -> Result<(), BufferError> {
+        let mut root = self.root.write().unwrap();
+        // nothing else writes while we hold the root lock, so these stay what's on the pages
+        let mut path = vec![(*root, self.load(*root)?)];
+        while !path.last().unwrap().1.leaf {
+            let child = path.last().unwrap().1.child_for(key);
+            path.push((child, self.load(child)?));
+        }
+
+        let leaf = &mut path.last_mut().unwrap().1;
+        match leaf.keys.binary_search(&key) {
+            Ok(i) => leaf.values[i] = value.0,
+            Err(i) => {
+                leaf.keys.insert(i, key);
+                leaf.values.insert(i, value.0);
+            }
+        }
+        // A node that splits hands one more key to its parent. The ones that split come off the bottom of the path,
+        // the one above them only takes a key, and if they all split the tree grows a new root
+        let mut splits = 0;
+        let mut extra = 0;
+        for (_, node) in path.iter().rev() {
+            if node.keys.len() + extra <= MAX_ENTRIES {
+                break;
+            }
+            splits += 1;
+            extra = 1;
+        }
+        let changed = path.len().saturating_sub(splits + 1);
+        let new_pages = splits + usize::from(splits == path.len());
+
+        let mut guards = Vec::with_capacity(path.len() - changed);
+        for &(page_id, _) in &path[changed..] {
+            guards.push(self.bpm.fetch_page_write(page_id)?);
+        }
+        let mut fresh = Vec::with_capacity(new_pages);
+        for _ in 0..new_pages {
+            match self.bpm.allocate_page_of_type(PageType::BTreeNode) {
+                Ok(page) => fresh.push(page),
+                Err(e) => {
+                    self.free_unused(fresh);
+                    return Err(e);
+                }
+            }
+        }
+
+        // from here on nothing can fail, bottom up: each split's right half goes to a fresh page and its
+        // separator into the node above
+        let mut carry: Option<(u64, PageId)> = None;
+        for ((_, mut node), guard) in path.drain(changed..).zip(&mut guards).rev() {
+            if let Some((separator, right)) = carry.take() {
+                let i = node.keys.partition_point(|&k| k < separator);
+                node.keys.insert(i, separator);
+                node.values.insert(i, right);
+            }
+            if node.keys.len() > MAX_ENTRIES {
+                let (separator, right) = node.split();
+                let mut page = fresh.pop().unwrap();
+                if node.leaf {
+                    // the new leaf slots in between node and its old right sibling
+                    node.next = page.page_id;
+                }
+                right.write(&mut page);
+                carry = Some((separator, page.page_id));
+            }
+            node.write(guard);
+        }
+        if let Some((separator, right)) = carry {
+            // the root split, grow the tree by one level
+            let new_root = TreeNode { leaf: false, next: *root, keys: vec![separator], values: vec![right] };
+            let mut page = fresh.pop().unwrap();
+            new_root.write(&mut page);
+            *root = page.page_id;
+        }
+        Ok(())
+    }
+
+    pub fn search(&self, key: u64) -> Option<NodeId> {
+        let root = self.root.read().unwrap();
+        let leaf = self.find_leaf(*root, key)?;
+        let i = leaf.keys.binary_search(&key).ok()?;
+        Some(NodeId(leaf.values[i]))
+    }
+
+    // every (key, value) with lo <= key <= hi, in key order
+    // not a snapshot, inserts happening during the scan may or may not show up
+    pub fn range(&self, lo: u64, hi: u64) -> BPlusRange<'_> {
+        let leaf = {
+            let root = self.root.read().unwrap();
+            self.find_leaf(*root, lo)
+        };
+        let (entries, next) = match leaf {
+            Some(leaf) => (leaf.keys.into_iter().zip(leaf.values).collect(), leaf.next),
+            None => (Vec::new(), NO_PAGE),
+        };
+        BPlusRange { tree: self, entries, pos: 0, next, lo, hi, last: None }
+    }
+
+    // walks down from page_id to the leaf that would hold key
+    fn find_leaf(&self, mut page_id: PageId, key: u64) -> Option<TreeNode> {
+        loop {
+            let node = self.load(page_id).ok()?;
+            if node.leaf {
+                return Some(node);
+            }
+            page_id = node.child_for(key);
+        }
+    }
+
+    fn load(&self, page_id: PageId) -> Result<TreeNode, BufferError> {
+        let page = self.bpm.fetch_page(page_id)?;
+        // a child or next pointer into some other kind of page, decoding it anyway would make up a node
+        page.expect_type(PageType::BTreeNode)?;
+        TreeNode::read(&page).ok_or_else(|| corrupt(page_id))
+    }
+
+    // hands back pages a failed insert allocated but never used, a page that can't be freed is only leaked
+    fn free_unused(&self, pages: Vec<WritePageGuard<'_>>) {
+        let ids: Vec<PageId> = pages.iter().map(|page| page.page_id).collect();
+        drop(pages);
+        for page_id in ids {
+            if let Err(e) = self.bpm.deallocate_page(page_id) {
+                self.bpm.defer_error(e);
+            }
+        }
+    }
+
+    fn new_node(bpm: &BufferPoolManager, node: &TreeNode) -> Result<PageId, BufferError> {
+        let mut page = bpm.allocate_page_of_type(PageType::BTreeNode)?;
+        node.write(&mut page);
+        Ok(page.page_id)
+    }
+}
+
+pub struct BPlusRange<'a> {
+    tree: &'a BPlusTree,
+    entries: Vec<(u64, u64)>, // current leaf
+    pos: usize,
+    next: u64, // leaf after the current one
+    lo: u64,
+    hi: u64,
+    // leaves can split while we're between them, anything at or below the last key we returned is a repeat
+    last: Option<u64>,
+}
+
+impl<'a> Iterator for BPlusRange<'a> {
+    type Item = (u64, NodeId);
+
+    fn next(&mut self) -> Option<(u64, NodeId)> {
+        loop {
+            while let Some(&(key, value)) = self.entries.get(self.pos) {
+                self.pos += 1;
+                if key > self.hi {
+                    self.next = NO_PAGE;
+                    self.entries.clear();
+                    return None;
+                }
+                if key < self.lo || self.last.is_some_and(|last| key <= last) {
+                    continue;
+                }
+                self.last = Some(key);
+                return Some((key, NodeId(value)));
+            }
+
+            if self.next == NO_PAGE {
+                return None;
+            }
+            let leaf = self.tree.load(self.next).ok()?;
+            self.next = leaf.next;
+            self.entries = leaf.keys.into_iter().zip(leaf.values).collect();
+            self.pos = 0;
+        }
+    }
+}
+
+fn corrupt(page_id: PageId) -> BufferError {
+    BufferError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("page {} is not a valid B+ tree node", page_id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+
+    fn tree(frames: usize) -> BPlusTree {
+        let bpm = Arc::new(BufferPoolManager::new(frames, MockDiskManager::new()).unwrap());
+        BPlusTree::create(bpm).unwrap()
+    }
+
+    // levels from the root down to the leaves
+    fn depth(tree: &BPlusTree) -> usize {
+        let mut node = tree.load(tree.root_page_id()).unwrap();
+        let mut depth = 1;
+        while !node.leaf {
+            node = tree.load(node.next).unwrap();
+            depth += 1;
+        }
+        depth
+    }
+
+    #[test]
+    fn search_finds_what_was_inserted() {
+        let tree = tree(8);
+        for key in [5, 1, 9, 3] {
+            tree.insert(key, NodeId(key * 10)).unwrap();
+        }
+        assert_eq!(tree.search(9), Some(NodeId(90)));
+        assert_eq!(tree.search(4), None);
+        tree.insert(9, NodeId(1)).unwrap();
+        assert_eq!(tree.search(9), Some(NodeId(1)));
+        assert_eq!(tree.range(0, u64::MAX).count(), 4);
+    }
+
+    #[test]
+    fn range_is_inclusive_on_both_ends() {
+        let tree = tree(8);
+        for key in 0..100 {
+            tree.insert(key * 2, NodeId(key)).unwrap();
+        }
+        let keys: Vec<u64> = tree.range(10, 20).map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![10, 12, 14, 16, 18, 20]);
+        assert_eq!(tree.range(11, 11).count(), 0);
+        assert_eq!(tree.range(500, 600).count(), 0);
+    }
+
+    #[test]
+    fn three_levels_of_splits_keep_keys_sorted_and_findable() {
+        // more pages than frames, the splits go through eviction too
+        let tree = tree(64);
+        // a permutation of 0..MAX_ENTRIES^2, so splits happen all over the tree and not just at its right edge.
+        // That's more keys than two levels can hold however full the leaves are
+        let n = (MAX_ENTRIES * MAX_ENTRIES) as u64;
+        let mut inserted = Vec::new();
+        for i in 0..n {
+            let key = i * 7919 % n;
+            tree.insert(key, NodeId(key + 1)).unwrap();
+            inserted.push(key);
+            if i % 1000 == 0 && depth(&tree) == 3 {
+                break;
+            }
+        }
+        assert_eq!(depth(&tree), 3);
+
+        inserted.sort_unstable();
+        let scanned: Vec<(u64, NodeId)> = tree.range(0, u64::MAX).collect();
+        assert!(scanned.iter().map(|&(key, _)| key).eq(inserted.iter().copied()));
+        assert!(scanned.iter().all(|&(key, value)| value == NodeId(key + 1)));
+        for &key in inserted.iter().step_by(97) {
+            assert_eq!(tree.search(key), Some(NodeId(key + 1)));
+        }
+
+        let (lo, hi) = (inserted[inserted.len() / 3], inserted[inserted.len() / 2]);
+        let middle: Vec<u64> = tree.range(lo, hi).map(|(key, _)| key).collect();
+        let expected: Vec<u64> = inserted.iter().copied().filter(|key| (lo..=hi).contains(key)).collect();
+        assert_eq!(middle, expected);
+    }
+
+    #[test]
+    fn a_reopened_tree_sees_the_same_keys() {
+        let tree = tree(8);
+        for key in 0..(MAX_ENTRIES as u64 * 3) {
+            tree.insert(key, NodeId(key)).unwrap();
+        }
+        let reopened = BPlusTree::open(tree.bpm.clone(), tree.root_page_id());
+        assert_eq!(depth(&reopened), 2);
+        assert_eq!(reopened.range(0, u64::MAX).count(), MAX_ENTRIES * 3);
+        assert_eq!(reopened.search(7), Some(NodeId(7)));
+    }
+
+    #[test]
+    fn a_root_pointing_at_another_page_type_is_an_error() {
+        let tree = tree(8);
+        let other = tree.bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        let broken = BPlusTree::open(tree.bpm.clone(), other);
+        assert_eq!(broken.search(1), None);
+        assert!(broken.insert(1, NodeId(1)).is_err());
+    }
+}
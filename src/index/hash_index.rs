@@ -0,0 +1,401 @@
+/*
+* Linear hash index for equality lookups, from a key hash to every NodeId stored under it.
+* Callers hash the property value themselves, the index only ever sees the u64 and keeps duplicates,
+* so colliding keys just pile up in the same bucket and lookup hands back all of them.
+*
+* Buckets are chains of HashBucket pages: a primary page plus overflow pages linked through next once it fills up.
+* Every time an insert has to add an overflow page the bucket under the split pointer gets split in two
+* (classic linear hashing), so the table grows one bucket at a time instead of doubling all at once.
+* With level L and split pointer s there are INITIAL_BUCKETS * 2^L + s buckets, a hash goes to h mod (INITIAL_BUCKETS * 2^L),
+* or h mod (INITIAL_BUCKETS * 2^(L+1)) if that bucket was already split this round.
+*
+* Meta page layout (HashBucket page too, told apart by kind):
+* | PageHeader | kind: u8 | pad: u8 | bucket_count: u16 | level: u32 | split: u64 | bucket page ids: u64 ... |
+* Bucket page layout, same shape as a B+ tree node:
+* | PageHeader | kind: u8 | pad: u8 | count: u16 | pad: u32 | next: u64 | entry 0 | entry 1 | ... |
+* each entry is (key_hash: u64, value: u64)
+* The directory is bounded by what fits in the meta page, past MAX_BUCKETS buckets stop splitting and only chain.
+*/
+
+use std::sync::{Arc, RwLock};
+
+use crate::paging::buffer_pool_manager::{BufferError, BufferPoolManager};
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::{PageId, HEADER_SIZE, PAGE_SIZE};
+use crate::store::node_store::NodeId;
+
+const NO_PAGE: u64 = u64::MAX;
+const INITIAL_BUCKETS: usize = 4;
+
+const KIND: usize = HEADER_SIZE;
+const COUNT: usize = HEADER_SIZE + 2;
+const LEVEL: usize = HEADER_SIZE + 4;
+const NEXT: usize = HEADER_SIZE + 8; // split pointer on the meta page
+const ENTRIES: usize = HEADER_SIZE + 16; // bucket ids on the meta page
+const ENTRY_SIZE: usize = 16;
+
+const KIND_META: u8 = 0;
+const KIND_BUCKET: u8 = 1;
+
+// entries per bucket page
+pub const BUCKET_CAPACITY: usize = (PAGE_SIZE - ENTRIES) / ENTRY_SIZE;
+// most buckets the meta page has room for
+pub const MAX_BUCKETS: usize = (PAGE_SIZE - ENTRIES) / 8;
+
+struct HashState {
+    level: u32,
+    split: usize,
+    buckets: Vec<PageId>, // primary page of each bucket
+}
+
+impl HashState {
+    fn bucket_for(&self, key_hash: u64) -> usize {
+        let round = (INITIAL_BUCKETS << self.level) as u64;
+        let bucket = (key_hash % round) as usize;
+        if bucket < self.split { (key_hash % (round * 2)) as usize } else { bucket }
+    }
+}
+
+pub struct HashIndex {
+    bpm: Arc<BufferPoolManager>,
+    meta_page: PageId,
+    // writers take this exclusively, one insert at a time keeps splits simple
+    state: RwLock<HashState>,
+}
+
+impl HashIndex {
+    // makes an empty index, allocating its meta page and the first INITIAL_BUCKETS buckets
+    pub fn create(bpm: Arc<BufferPoolManager>) -> Result<Self, BufferError> {
+        let meta_page = bpm.allocate_page_of_type(PageType::HashBucket)?.page_id;
+        let buckets = (0..INITIAL_BUCKETS).map(|_| new_bucket(&bpm)).collect::<Result<_, _>>()?;
+        let index = Self { bpm, meta_page, state: RwLock::new(HashState { level: 0, split: 0, buckets }) };
+        index.save_meta(&index.state.read().unwrap())?;
+        Ok(index)
+    }
+
+    // reopens an index from its meta page, see meta_page_id
+    pub fn open(bpm: Arc<BufferPoolManager>, meta_page: PageId) -> Result<Self, BufferError> {
+        let state = {
+            let page = bpm.fetch_page(meta_page)?;
+            page.expect_type(PageType::HashBucket)?;
+            let data = page.get_data();
+            if data[KIND] != KIND_META {
+                return Err(corrupt(meta_page));
+            }
+            let count = u16::from_le_bytes([data[COUNT], data[COUNT + 1]]) as usize;
+            if count > MAX_BUCKETS {
+                return Err(corrupt(meta_page));
+            }
+            let level = page.read_u32_at(LEVEL as u32).map_err(|_| corrupt(meta_page))?;
+            let split = page.read_u64_at(NEXT as u32).map_err(|_| corrupt(meta_page))? as usize;
+            let buckets = (0..count)
+                .map(|i| page.read_u64_at((ENTRIES + i * 8) as u32).map_err(|_| corrupt(meta_page)))
+                .collect::<Result<_, _>>()?;
+            HashState { level, split, buckets }
+        };
+        Ok(Self { bpm, meta_page, state: RwLock::new(state) })
+    }
+
+    pub fn meta_page_id(&self) -> PageId {
+        self.meta_page
+    }
+
+    // adds value under key_hash, duplicates are kept
+    pub fn insert(&self, key_hash: u64, value: NodeId) -> Result<(), BufferError> {
+        let mut state = self.state.write().unwrap();
+        let head = state.buckets[state.bucket_for(key_hash)];
+        let overflowed = self.append(head, key_hash, value.0)?;
+        // the entry is in either way and a failed split leaves the index as it was, so there's only the
+        // error to keep, see BufferPoolManager::take_deferred_errors
+        if overflowed
+            && state.buckets.len() < MAX_BUCKETS
+            && let Err(e) = self.split(&mut state)
+        {
+            self.bpm.defer_error(e);
+        }
+        Ok(())
+    }
+
+    // every value stored under key_hash, in no particular order
+    pub fn lookup(&self, key_hash: u64) -> Vec<NodeId> {
+        let state = self.state.read().unwrap();
+        let mut found = Vec::new();
+        let mut next = state.buckets[state.bucket_for(key_hash)];
+        while next != NO_PAGE {
+            let Ok(bucket) = self.load(next) else { break };
+            found.extend(bucket.entries.iter().filter(|(k, _)| *k == key_hash).map(|&(_, v)| NodeId(v)));
+            next = bucket.next;
+        }
+        found
+    }
+
+    // adds the entry to the first page in the chain with room, returns true if an overflow page had to be added
+    fn append(&self, head: PageId, key_hash: u64, value: u64) -> Result<bool, BufferError> {
+        let mut page_id = head;
+        loop {
+            let mut bucket = self.load(page_id)?;
+            if bucket.entries.len() < BUCKET_CAPACITY {
+                bucket.entries.push((key_hash, value));
+                self.store(page_id, &bucket)?;
+                return Ok(false);
+            }
+            if bucket.next == NO_PAGE {
+                let overflow = Bucket { next: NO_PAGE, entries: vec![(key_hash, value)] };
+                let overflow_id = new_page(&self.bpm, &overflow)?;
+                bucket.next = overflow_id;
+                self.store(page_id, &bucket)?;
+                return Ok(true);
+            }
+            page_id = bucket.next;
+        }
+    }
+
+    // Splits the bucket under the split pointer, its entries get spread over it and a new bucket at the end.
+    // Both halves are written to new chains and the meta page saved before state changes, the old chain is only
+    // freed after that, so an error on the way leaves everything (on the pages and in state) as it was
+    fn split(&self, state: &mut HashState) -> Result<(), BufferError> {
+        let old = state.split;
+        let mut entries = Vec::new();
+        let mut chain = Vec::new();
+        let mut next = state.buckets[old];
+        while next != NO_PAGE {
+            let bucket = self.load(next)?;
+            entries.extend(bucket.entries);
+            chain.push(next);
+            next = bucket.next;
+        }
+
+        let mut split = HashState { level: state.level, split: state.split + 1, buckets: state.buckets.clone() };
+        if split.split == INITIAL_BUCKETS << split.level {
+            split.level += 1;
+            split.split = 0;
+        }
+        // whatever doesn't stay goes to the bucket about to be pushed
+        let (kept, moved): (Vec<_>, Vec<_>) =
+            entries.into_iter().partition(|&(key_hash, _)| split.bucket_for(key_hash) == old);
+        let kept_head = self.write_chain(&kept)?;
+        let moved_head = match self.write_chain(&moved) {
+            Ok(head) => head,
+            Err(e) => {
+                self.free_chain(kept_head);
+                return Err(e);
+            }
+        };
+        split.buckets[old] = kept_head;
+        split.buckets.push(moved_head);
+        if let Err(e) = self.save_meta(&split) {
+            self.free_chain(kept_head);
+            self.free_chain(moved_head);
+            return Err(e);
+        }
+        *state = split;
+        for page_id in chain {
+            self.free_page(page_id);
+        }
+        Ok(())
+    }
+
+    // entries as a new chain of bucket pages (one empty page if there are none), returns its head
+    // whatever got written is freed again if a page can't be allocated
+    fn write_chain(&self, entries: &[(u64, u64)]) -> Result<PageId, BufferError> {
+        let mut pages: Vec<&[(u64, u64)]> = entries.chunks(BUCKET_CAPACITY).collect();
+        if pages.is_empty() {
+            pages.push(&[]);
+        }
+        // back to front so each page can point at the one after it
+        let mut next = NO_PAGE;
+        for entries in pages.into_iter().rev() {
+            match new_page(&self.bpm, &Bucket { next, entries: entries.to_vec() }) {
+                Ok(page_id) => next = page_id,
+                Err(e) => {
+                    self.free_chain(next);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(next)
+    }
+
+    // hands back every page of a chain nobody points at, the rest of it leaks if one can't be read
+    fn free_chain(&self, mut next: PageId) {
+        while next != NO_PAGE {
+            let Ok(bucket) = self.load(next) else { return };
+            self.free_page(next);
+            next = bucket.next;
+        }
+    }
+
+    // nowhere to return the error to, see BufferPoolManager::take_deferred_errors
+    fn free_page(&self, page_id: PageId) {
+        if let Err(e) = self.bpm.deallocate_page(page_id) {
+            self.bpm.defer_error(e);
+        }
+    }
+
+    fn save_meta(&self, state: &HashState) -> Result<(), BufferError> {
+        let mut page = self.bpm.fetch_page_write(self.meta_page)?;
+        let data = page.get_data_mut();
+        data[KIND] = KIND_META;
+        data[COUNT..COUNT + 2].copy_from_slice(&(state.buckets.len() as u16).to_le_bytes());
+        data[LEVEL..LEVEL + 4].copy_from_slice(&state.level.to_le_bytes());
+        data[NEXT..NEXT + 8].copy_from_slice(&(state.split as u64).to_le_bytes());
+        for (i, bucket) in state.buckets.iter().enumerate() {
+            let at = ENTRIES + i * 8;
+            data[at..at + 8].copy_from_slice(&bucket.to_le_bytes());
+        }
+        page.set_dirty(true);
+        Ok(())
+    }
+
+    fn load(&self, page_id: PageId) -> Result<Bucket, BufferError> {
+        let page = self.bpm.fetch_page(page_id)?;
+        page.expect_type(PageType::HashBucket)?;
+        Bucket::read(&page).ok_or_else(|| corrupt(page_id))
+    }
+
+    fn store(&self, page_id: PageId, bucket: &Bucket) -> Result<(), BufferError> {
+        let mut page = self.bpm.fetch_page_write(page_id)?;
+        bucket.write(&mut page);
+        Ok(())
+    }
+}
+
+// one page of a bucket chain pulled out of its page
+struct Bucket {
+    next: u64,
+    entries: Vec<(u64, u64)>,
+}
+
+impl Bucket {
+    fn read(page: &Page) -> Option<Self> {
+        let data = page.get_data();
+        if data[KIND] != KIND_BUCKET {
+            return None;
+        }
+        let count = u16::from_le_bytes([data[COUNT], data[COUNT + 1]]) as usize;
+        if count > BUCKET_CAPACITY {
+            return None;
+        }
+        let next = page.read_u64_at(NEXT as u32).ok()?;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let at = (ENTRIES + i * ENTRY_SIZE) as u32;
+            entries.push((page.read_u64_at(at).ok()?, page.read_u64_at(at + 8).ok()?));
+        }
+        Some(Self { next, entries })
+    }
+
+    fn write(&self, page: &mut Page) {
+        let data = page.get_data_mut();
+        data[KIND] = KIND_BUCKET;
+        data[COUNT..COUNT + 2].copy_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        data[NEXT..NEXT + 8].copy_from_slice(&self.next.to_le_bytes());
+        for (i, (key_hash, value)) in self.entries.iter().enumerate() {
+            let at = ENTRIES + i * ENTRY_SIZE;
+            data[at..at + 8].copy_from_slice(&key_hash.to_le_bytes());
+            data[at + 8..at + 16].copy_from_slice(&value.to_le_bytes());
+        }
+        page.set_dirty(true);
+    }
+}
+
+fn new_page(bpm: &BufferPoolManager, bucket: &Bucket) -> Result<PageId, BufferError> {
+    let mut page = bpm.allocate_page_of_type(PageType::HashBucket)?;
+    bucket.write(&mut page);
+    Ok(page.page_id)
+}
+
+fn new_bucket(bpm: &BufferPoolManager) -> Result<PageId, BufferError> {
+    new_page(bpm, &Bucket { next: NO_PAGE, entries: Vec::new() })
+}
+
+fn corrupt(page_id: PageId) -> BufferError {
+    BufferError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("page {} is not a valid hash index page", page_id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+
+    fn index(frames: usize) -> HashIndex {
+        let bpm = Arc::new(BufferPoolManager::new(frames, MockDiskManager::new()).unwrap());
+        HashIndex::create(bpm).unwrap()
+    }
+
+    fn sorted(mut ids: Vec<NodeId>) -> Vec<NodeId> {
+        ids.sort_unstable();
+        ids
+    }
+
+    // pages in the chain of the bucket key_hash goes to
+    fn chain_len(index: &HashIndex, key_hash: u64) -> usize {
+        let state = index.state.read().unwrap();
+        let mut next = state.buckets[state.bucket_for(key_hash)];
+        let mut pages = 0;
+        while next != NO_PAGE {
+            next = index.load(next).unwrap().next;
+            pages += 1;
+        }
+        pages
+    }
+
+    #[test]
+    fn lookup_returns_every_value_under_a_key() {
+        let index = index(8);
+        index.insert(7, NodeId(1)).unwrap();
+        index.insert(7, NodeId(2)).unwrap();
+        index.insert(11, NodeId(3)).unwrap();
+        assert_eq!(sorted(index.lookup(7)), vec![NodeId(1), NodeId(2)]);
+        assert_eq!(index.lookup(11), vec![NodeId(3)]);
+        assert!(index.lookup(8).is_empty());
+    }
+
+    #[test]
+    fn colliding_keys_spill_into_overflow_pages() {
+        let index = index(8);
+        let n = BUCKET_CAPACITY * 3 + 5;
+        for i in 0..n {
+            index.insert(42, NodeId(i as u64)).unwrap();
+        }
+        // splitting can't separate entries with the same hash, they stay chained
+        assert_eq!(chain_len(&index, 42), 4);
+        let expected: Vec<NodeId> = (0..n as u64).map(NodeId).collect();
+        assert_eq!(sorted(index.lookup(42)), expected);
+    }
+
+    #[test]
+    fn splits_keep_every_entry_reachable() {
+        let index = index(16);
+        let n = BUCKET_CAPACITY as u64 * 12;
+        for key in 0..n {
+            index.insert(key * 31, NodeId(key)).unwrap();
+        }
+        assert!(index.state.read().unwrap().buckets.len() > INITIAL_BUCKETS);
+        assert!(index.bpm.take_deferred_errors().is_empty());
+        for key in 0..n {
+            assert_eq!(index.lookup(key * 31), vec![NodeId(key)]);
+        }
+    }
+
+    #[test]
+    fn a_reopened_index_finds_the_same_values() {
+        let index = index(8);
+        for key in 0..(BUCKET_CAPACITY as u64 * 6) {
+            index.insert(key, NodeId(key + 100)).unwrap();
+        }
+        let reopened = HashIndex::open(index.bpm.clone(), index.meta_page_id()).unwrap();
+        assert_eq!(reopened.state.read().unwrap().buckets, index.state.read().unwrap().buckets);
+        assert_eq!(reopened.lookup(5), vec![NodeId(105)]);
+    }
+
+    #[test]
+    fn opening_a_bucket_page_as_the_meta_page_fails() {
+        let index = index(8);
+        let bucket = index.state.read().unwrap().buckets[0];
+        assert!(HashIndex::open(index.bpm.clone(), bucket).is_err());
+    }
+}
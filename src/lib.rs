@@ -1,4 +1,12 @@
+#![allow(non_snake_case)] // crate name is GGDB
+
 pub mod paging;
 pub mod store;
 pub mod file_manager;
-pub mod wal;
\ No newline at end of file
+pub mod wal;
+pub mod index;
+pub mod query;
+pub mod catalog;
+
+#[cfg(test)]
+mod test_util;
@@ -2,4 +2,13 @@ pub mod page;
 pub mod buffer_pool_manager;
 pub mod page_constants;
 pub mod replacement;
-
+pub mod checksum;
+pub mod slotted_page;
+pub mod config;
+pub mod free_space_map;
+pub mod stats;
+pub mod record;
+pub mod frames;
+pub mod flusher;
+pub mod bulk_loader;
+pub mod dump;
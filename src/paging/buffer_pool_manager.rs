@@ -5,49 +5,314 @@
 */
 
 
-use std::sync::{Arc, Mutex};
-use std::collections::{HashMap, VecDeque};
-use super::page_constants::{PageId, PAGE_SIZE, FrameId, BUFFER_SIZE};
-use super::page::Page;
-use super::replacement::ClockReplacer;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use super::page_constants::{PageId, FrameId, HEADER_SIZE, PAGE_SIZE};
+use super::page::{Page, PageError, PageType, PageTypeMismatch, UnsupportedVersion};
+use super::frames::Frames;
+use super::free_space_map::FreeSpaceMap;
+use super::slotted_page::SlottedPage;
+use super::flusher::BackgroundFlusher;
+use super::replacement::{ClockReplacer, GClockReplacer, LruKReplacer, Replacer};
+use super::config::{
+    BufferPoolConfig, FlushErrorPolicy, LogGranularity, OnCorruption, RecoveryPolicy, ReplacerKind, SyncMode,
+};
+use super::stats::{BufferPoolStats, BufferPoolStatsSnapshot};
+use crate::catalog::superblock::{free_page, next_free, Superblock, SUPERBLOCK_PAGE};
+use crate::store::overflow::OverflowChain;
+use crate::file_manager::disk_manager::{DiskManager, FileDiskManager};
+use crate::file_manager::segment_manager::SegmentManager;
+use crate::wal::log_manager::{DeltaRun, LogManager, Lsn, TxnId, NO_TXN};
+use crate::wal::transaction::Transaction;
+
+// what pin_or_claim did for a page: it was already in a frame, or it got a frame that still needs reading in
+enum Pinned<'a> {
+    Resident(FrameId),
+    Claimed(FrameId, RwLockWriteGuard<'a, Page>),
+}
+
+// Why the pool couldn't hand out a page
+#[derive(Debug)]
+pub enum BufferError {
+    PoolExhausted,        // every frame is pinned, retrying after some guards drop can work
+    PageNotFound(PageId), // the page was never allocated or has been deallocated
+    ReadOnly,             // the pool was opened read only and the call would change a page
+    Conflict(PageId),     // an optimistic transaction found the page changed by somebody else, abort and retry it
+    Io(io::Error),
+}
+
+impl fmt::Display for BufferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BufferError::PoolExhausted => write!(f, "all frames in the buffer pool are pinned"),
+            BufferError::PageNotFound(page_id) => write!(f, "page {} does not exist", page_id),
+            BufferError::ReadOnly => write!(f, "the buffer pool is read only"),
+            BufferError::Conflict(page_id) => write!(f, "page {} was changed by another transaction", page_id),
+            BufferError::Io(e) => write!(f, "disk error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BufferError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BufferError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+// Why an unpin was refused, instead of letting a pin count underflow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PinError {
+    NotResident(PageId), // the page isn't in any frame, so nobody can be holding a pin on it
+    NotPinned(PageId),   // the page is in a frame but its pin count is already 0
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::NotResident(page_id) => write!(f, "page {} is not in the buffer pool", page_id),
+            PinError::NotPinned(page_id) => write!(f, "page {} is not pinned", page_id),
+        }
+    }
+}
+
+impl std::error::Error for PinError {}
+
+impl From<io::Error> for BufferError {
+    fn from(e: io::Error) -> Self {
+        BufferError::Io(e)
+    }
+}
+
+impl From<UnsupportedVersion> for BufferError {
+    fn from(e: UnsupportedVersion) -> Self {
+        BufferError::Io(io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl From<PageError> for BufferError {
+    fn from(e: PageError) -> Self {
+        BufferError::Io(io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl From<PageTypeMismatch> for BufferError {
+    fn from(e: PageTypeMismatch) -> Self {
+        BufferError::Io(io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
 
 /*
 * Page guard is simply a structure to prevent race conditions with RAII.
+* It holds the frame's latch (shared) for as long as it lives, so the Page it derefs to can't change underneath it.
 * Will automatically unpin when the PageFrameRef goes out of scope.
-* We may need a WriteGuard in the future too to ensure the dirty bit is guarded.
+* PageFrameRef is read only, use WritePageGuard if you need to modify the page.
 */
 pub struct PageFrameRef<'a> {
     pub bpm: &'a BufferPoolManager, // based on lifetime of the bpm
     pub page_id: PageId,
     pub frame_index: FrameId,
+    latch: Option<RwLockReadGuard<'a, Page>>, // only None while dropping
 }
 
 // When you implement Deref, users can treat this Guard like a Page
 // 'a simply means lifetime impl<'a> here means it will live as long as bufferpoolmanager
 //this method tells what do we do upon dereferencing a pageframeref
-//TODO: research some rust and see how safe these functions actually are
 impl<'a> std::ops::Deref for PageFrameRef<'a> {
     type Target = Page;
     fn deref(&self) -> &Self::Target {
-        // The lock should be automatically dropped after deref
-        let state = self.bpm.state.lock().unwrap();
-        unsafe { &*(&state.frames[self.frame_index] as *const Page) }
+        self.latch.as_ref().unwrap()
     }
 }
 //this ensures we never forget to unpin a page
 impl<'a> Drop for PageFrameRef<'a> {
     fn drop(&mut self) {
+        // release the latch before unpinning, an unpinned frame must never have anyone holding its latch
+        self.latch.take();
         // Auto-Unpin when this variable goes out of scope
-        self.bpm.unpin_page(self.page_id, false); 
-        // Note: We default dirty=false here. 
-        // TODO: WriteGuard that sets dirty=true.
+        self.bpm.unpin_frame(self.frame_index, false); 
+        // Note: read only guard so dirty=false here, WritePageGuard handles the dirty case
+    }
+}
+
+/*
+* PageFrameRef for code that holds the pool in an Arc and needs the guard to outlive a borrow of it, e.g. to hand
+* it to another thread or task. See BufferPoolManager::fetch_page_arc.
+* It only keeps the pin, not the latch: std's lock guards can't be sent to another thread (some platforms need the
+* unlock to happen on the locking thread), so read() takes the shared latch for as long as its guard lives.
+* The page can't be evicted or swapped out while this exists, but writers can get in between two read() calls.
+*/
+pub struct OwnedPageFrameRef {
+    bpm: Arc<BufferPoolManager>,
+    pub page_id: PageId,
+    pub frame_index: FrameId,
+}
+
+impl OwnedPageFrameRef {
+    // shared access to the page, blocks while someone has it latched for writing
+    pub fn read(&self) -> RwLockReadGuard<'_, Page> {
+        self.bpm.frames[self.frame_index].read().unwrap()
+    }
+
+    pub fn bpm(&self) -> &Arc<BufferPoolManager> {
+        &self.bpm
+    }
+}
+
+impl Drop for OwnedPageFrameRef {
+    fn drop(&mut self) {
+        self.bpm.unpin_frame(self.frame_index, false);
+    }
+}
+
+// guards and the pool get moved and shared between threads, keep it that way
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<BufferPoolManager>();
+    assert_send_sync::<OwnedPageFrameRef>();
+};
+
+/*
+* Same idea as PageFrameRef but holds the frame latch exclusively and hands out a mutable Page.
+* Dropping it unpins with dirty=true so the changes make it to disk on eviction.
+* With a write ahead log the guard keeps a copy of the page from when it was taken, and on drop logs whatever
+* changed and stamps the record's LSN into the page.
+*/
+pub struct WritePageGuard<'a> {
+    pub bpm: &'a BufferPoolManager,
+    pub page_id: PageId,
+    pub frame_index: FrameId,
+    latch: Option<RwLockWriteGuard<'a, Page>>, // only None while dropping
+    before: Option<Box<[u8; PAGE_SIZE]>>,      // page contents when the guard was taken, only kept with a log
+    txn_id: TxnId,                             // transaction the changes get logged under
+}
+
+impl<'a> std::ops::Deref for WritePageGuard<'a> {
+    type Target = Page;
+    fn deref(&self) -> &Self::Target {
+        self.latch.as_ref().unwrap()
+    }
+}
+
+impl<'a> std::ops::DerefMut for WritePageGuard<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.latch.as_mut().unwrap()
+    }
+}
+
+impl<'a> Drop for WritePageGuard<'a> {
+    fn drop(&mut self) {
+        let mut latch = self.latch.take();
+        if let (Some(page), Some(before)) = (latch.as_mut(), self.before.take()) {
+            self.bpm.log_changes(self.txn_id, self.page_id, &before, page);
+        }
+        // we can't tell if the page was actually modified, so assume it was. The page gets marked too before the
+        // latch goes, readers getting in ahead of the unpin shouldn't take it for clean
+        if let Some(page) = latch.as_mut() {
+            page.set_dirty(true);
+        }
+        drop(latch);
+        self.bpm.unpin_frame(self.frame_index, true);
+    }
+}
+
+/*
+* Bookkeeping the pool keeps for every frame, separate from the Page itself.
+* This lives under the state mutex rather than behind the frame latch so pinning/unpinning and picking
+* victims never has to wait on somebody who is reading or writing the page.
+* The pin count and ref bit are atomics so they can be bumped through a shared reference, today every caller still
+* holds state but pinning an already resident frame shouldn't need the mutex forever.
+*/
+#[derive(Debug, Default)]
+pub struct FrameMeta {
+    pub page_id: Option<PageId>, // page currently loaded in the frame, None if the frame is empty
+    pin_count: AtomicU32,
+    ref_bit: AtomicBool,
+    pub is_dirty: bool, // set by unpin_page, the Page has its own is_dirty for changes made through it
+}
+
+impl FrameMeta {
+    pub fn pin_count(&self) -> u32 {
+        self.pin_count.load(Ordering::Acquire)
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pin_count() > 0
+    }
+
+    // adds a pin and marks the frame recently used, returns the new pin count
+    pub fn pin(&self) -> u32 {
+        self.ref_bit.store(true, Ordering::Release);
+        self.pin_count.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    // drops one pin and returns how many are left, refuses rather than going below 0
+    pub fn unpin(&self) -> Result<u32, PinError> {
+        self.pin_count
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |pins| pins.checked_sub(1))
+            .map(|pins| pins - 1)
+            .map_err(|_| PinError::NotPinned(self.page_id.unwrap_or(PageId::MAX)))
     }
+
+    pub fn ref_bit(&self) -> bool {
+        self.ref_bit.load(Ordering::Acquire)
+    }
+
+    // clears the ref bit, returns whether it was set
+    pub fn take_ref_bit(&self) -> bool {
+        self.ref_bit.swap(false, Ordering::AcqRel)
+    }
+
+    // for a frame that was just claimed for a page, nobody else can see it yet
+    fn set_pin_count(&self, pins: u32) {
+        self.pin_count.store(pins, Ordering::Release);
+    }
+}
+
+// One row of BufferPoolManager::frame_table, plain values copied out of the frame
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub frame_id: FrameId,
+    pub page_id: Option<PageId>, // None for an empty frame
+    pub pin_count: u32,
+    pub is_dirty: bool,
+    pub ref_bit: bool,
+    // None for an empty frame, or if a write guard held the frame's latch when the snapshot was taken
+    pub page_type: Option<PageType>,
+}
+
+// What BufferPoolManager::pages_changed_since found, both in page id order
+#[derive(Debug, Default)]
+pub struct ChangedPages {
+    pub changed: Vec<PageId>,
+    // pages that couldn't be read or failed their checksum or header check, so there's no telling whether they
+    // changed. A backup can't copy them as they are, the caller decides what to do about them
+    pub unreadable: Vec<(PageId, io::Error)>,
+}
+
+// called with a page id by the hooks set through set_eviction_hook / set_load_hook
+pub type PageHook = Box<dyn Fn(PageId) + Send + Sync>;
+
+#[derive(Default)]
+struct Hooks {
+    on_evict: Option<PageHook>,
+    on_load: Option<PageHook>,
 }
 
 // RAM state and manager
 pub struct BufferPoolState {
-    // physical frames
-    frames: Vec<Page>,
+    // metadata for each physical frame, the frames themselves are in BufferPoolManager::frames
+    frames: Vec<FrameMeta>,
+
+    // frames in use, the ones past it were retired by resize. Retired frames are never handed out again
+    // and only stay mapped while somebody still has them pinned
+    pool_size: usize,
     
     // Maps pagid to frameid TODO: We probably need a more robust way to map page to frame
     page_mapping: HashMap<PageId, FrameId>,
@@ -56,96 +321,3158 @@ pub struct BufferPoolState {
     free_list: VecDeque<FrameId>,
     
     // The Eviction Algo
-    replacer: ClockReplacer,
+    replacer: Box<dyn Replacer>,
     
-    // Helper to read/write disk TODO: implement the disk manager
-    // disk_manager: DiskManager, 
+    // Page ids that were deallocated and can be handed out again
+    free_pages: Vec<PageId>,
+
+    // Next never-used page id, one past the end of the file
+    next_page_id: PageId,
+
+    // under NoStealForce, pages each live transaction has written. Each one carries an extra pin
+    // on behalf of the transaction until it finishes, that's what keeps eviction away from them
+    txn_pages: HashMap<TxnId, Vec<PageId>>,
 }
 
+impl BufferPoolState {
+    // frames retired by resize are outside the replacer, it's only told about the ones in use
+    fn set_evictable(&mut self, frame_id: FrameId, evictable: bool) {
+        if frame_id < self.pool_size {
+            self.replacer.set_evictable(frame_id, evictable);
+        }
+    }
+}
+
+/*
+* Locking rules:
+* - state (mapping, free list, replacer, frame metadata) is only ever held briefly, and never while waiting on a frame latch
+* - a frame is pinned (under state) before anyone takes its latch, and the latch is released before it is unpinned,
+*   so a frame with pin_count 0 has no latch holders and the pool can latch it without blocking
+* - the order is always state -> latch of an unpinned frame, nobody takes state while waiting on a latch or vice versa,
+*   so eviction can't deadlock with readers/writers
+* - reading a page in from disk happens with only that frame's latch held, other pages stay accessible meanwhile.
+*   Writing back a dirty victim still happens under state: until it's on disk nobody may read the old page from the file
+* - between page latches the order is ascending page id. Anything that holds more than one write guard at a time
+*   has to take them through lock_pages_ordered, two threads latching the same pair of pages the other way around
+*   deadlock otherwise. Holding one guard at a time (like the stores do) needs nothing
+*/
 pub struct BufferPoolManager {
-    state: Mutex<BufferPoolState>, // We are currently wrapping the entire page table in a mutex, not very good performance,
-                                   // we probably need to lock each structure individually later
+    state: Mutex<BufferPoolState>,
+    // physical frames, each one behind its own latch
+    frames: Frames,
+    // Helper to read/write disk, has its own lock on the file (FileDiskManager unless a test swapped in a mock)
+    disk_manager: Box<dyn DiskManager>,
+    log: Option<LogManager>,
+    on_drop_flush_error: FlushErrorPolicy,
+    closed: bool, // close() already flushed, nothing left for Drop to do
+    stats: BufferPoolStats,
+    read_only: bool, // taken from the disk manager, no write guards, no allocation, no write back
+    recovery_policy: RecoveryPolicy,
+    sync_mode: SyncMode,
+    eviction_batch: usize, // see BufferPoolConfig::eviction_batch
+    log_granularity: LogGranularity,
+    on_corruption: OnCorruption,
+    quarantined: Mutex<BTreeSet<PageId>>, // pages OnCorruption::Quarantine replaced
+    vacuum_on_flush: Option<f64>,         // see BufferPoolConfig::vacuum_on_flush
+    flusher: Mutex<Option<BackgroundFlusher>>, // see start_background_flusher
+    hooks: RwLock<Hooks>,
+    // pages that left RAM under state and still have to go through the eviction hook, only kept while there is one
+    evicted: Mutex<Vec<PageId>>,
+    has_eviction_hook: AtomicBool,
+    // errors from work nobody was around to return them to, see take_deferred_errors
+    deferred_errors: Mutex<Vec<BufferError>>,
+    free_space_maps: Mutex<HashMap<PageType, Arc<FreeSpaceMap>>>, // see free_space_map
+    // page 0 as it has to be back on disk before any other page is written, while the disk's copy says the saved
+    // free space maps are current. See flush_all
+    free_space_current: Mutex<Option<Box<[u8; PAGE_SIZE]>>>,
 }
 
 impl BufferPoolManager {
-    //initiates buffer pool to size of pool_size
-    pub fn new(pool_size: usize) -> Self {
-        let mut frames = Vec::with_capacity(pool_size);
-        let mut free_list = VecDeque::new();
+    //initiates buffer pool to size of pool_size, backed by the file behind disk_manager
+    pub fn new(pool_size: usize, disk_manager: impl DiskManager + 'static) -> io::Result<Self> {
+        Self::with_replacer(pool_size, disk_manager, Box::new(ClockReplacer::new(pool_size)))
+    }
+
+    // builds the pool described by config, opening (or creating) its data file
+    pub fn with_config(config: BufferPoolConfig) -> io::Result<Self> {
+        let replacer: Box<dyn Replacer> = match config.replacer {
+            ReplacerKind::Clock => Box::new(ClockReplacer::new(config.pool_size)),
+            ReplacerKind::GClock { max_count } => Box::new(GClockReplacer::new(config.pool_size, max_count)),
+            ReplacerKind::LruK { k: 0 } => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "LRU-K needs k of at least 1"));
+            }
+            ReplacerKind::LruK { k } => Box::new(LruKReplacer::new(config.pool_size, k)),
+        };
+        if config.eviction_batch == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "eviction_batch must be at least 1"));
+        }
+        if config.vacuum_on_flush.is_some_and(|ratio| !(0.0..=1.0).contains(&ratio)) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "vacuum_on_flush must be between 0 and 1"));
+        }
+        if config.read_only && config.log_file.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a read only pool can't have a write ahead log"));
+        }
+        let disk_manager = open_disk_manager(&config)?;
+        let mut bpm = Self::build(config.pool_size, Box::new(disk_manager), replacer)?;
+        bpm.on_drop_flush_error = config.on_drop_flush_error.clone();
+        bpm.recovery_policy = config.recovery_policy;
+        bpm.sync_mode = config.sync_mode;
+        bpm.eviction_batch = config.eviction_batch;
+        bpm.log_granularity = config.log_granularity;
+        bpm.on_corruption = config.on_corruption;
+        bpm.vacuum_on_flush = config.vacuum_on_flush;
+        let log = config.log_file.as_ref().map(LogManager::open).transpose()?;
+        bpm.start(log)?;
+        Ok(bpm)
+    }
+
+    // same as new but with a custom eviction policy
+    pub fn with_replacer(
+        pool_size: usize,
+        disk_manager: impl DiskManager + 'static,
+        replacer: Box<dyn Replacer>,
+    ) -> io::Result<Self> {
+        let mut bpm = Self::build(pool_size, Box::new(disk_manager), replacer)?;
+        bpm.start(None)?;
+        Ok(bpm)
+    }
 
-        for i in 0..pool_size {
-            frames.push(Page::default()); //use default pages (page types shouldn't matter)
-            free_list.push_back(i);
+    // same as new but logging to log (recovering from it first), for a log on something other than a file
+    // like a MockDiskManager. with_config is the way to get a pool with a log file
+    pub fn with_log(pool_size: usize, disk_manager: impl DiskManager + 'static, log: LogManager) -> io::Result<Self> {
+        if disk_manager.is_read_only() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "a read only pool can't have a write ahead log"));
         }
+        let mut bpm = Self::build(pool_size, Box::new(disk_manager), Box::new(ClockReplacer::new(pool_size)))?;
+        bpm.start(Some(log))?;
+        Ok(bpm)
+    }
+
+    // loads the free page list, bringing the data file up to date with whatever made it into log before the last
+    // shutdown/crash first
+    fn start(&mut self, log: Option<LogManager>) -> io::Result<()> {
+        // the chain has to be read before redo, afterwards pages taken off the list can look in use already
+        let mut free_pages = self.read_free_list();
+        self.log = log;
+        if let Some(log) = &self.log {
+            log.recover(self)?;
+            log.redo_free_list(self, &mut free_pages)?;
+        }
+        self.install_free_list(free_pages);
+        self.load_free_space_maps();
+        Ok(())
+    }
+
+    // the pool without its free page list, recovery has to be able to reach freed pages before that gets loaded
+    fn build(pool_size: usize, disk_manager: Box<dyn DiskManager>, replacer: Box<dyn Replacer>) -> io::Result<Self> {
+        if pool_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "pool_size must be at least 1"));
+        }
+
+        let frames = Frames::new(pool_size);
+        let free_list = (0..pool_size).collect();
+        let next_page_id = open_superblock(disk_manager.as_ref())?;
 
         let state = BufferPoolState {
-            frames,
+            frames: (0..pool_size).map(|_| FrameMeta::default()).collect(),
+            pool_size,
             page_mapping: HashMap::new(),
             free_list,
-            replacer: ClockReplacer::new(pool_size),
-            // disk_manager: DiskManager::new("data.db"), implement later
+            replacer,
+            free_pages: Vec::new(),
+            next_page_id,
+            txn_pages: HashMap::new(),
         };
+        let disk_manager_read_only = disk_manager.is_read_only();
 
-        Self { state: Mutex::new(state) }
+        Ok(Self {
+            state: Mutex::new(state),
+            frames,
+            disk_manager,
+            log: None,
+            on_drop_flush_error: FlushErrorPolicy::Ignore,
+            closed: false,
+            stats: BufferPoolStats::new(),
+            read_only: disk_manager_read_only,
+            recovery_policy: RecoveryPolicy::StealNoForce,
+            sync_mode: SyncMode::PerCommit,
+            eviction_batch: 1,
+            log_granularity: LogGranularity::Delta,
+            on_corruption: OnCorruption::Fail,
+            quarantined: Mutex::new(BTreeSet::new()),
+            vacuum_on_flush: None,
+            flusher: Mutex::new(None),
+            hooks: RwLock::new(Hooks::default()),
+            evicted: Mutex::new(Vec::new()),
+            has_eviction_hook: AtomicBool::new(false),
+            deferred_errors: Mutex::new(Vec::new()),
+            free_space_maps: Mutex::new(HashMap::new()),
+            free_space_current: Mutex::new(None),
+        })
     }
 
     // fetches a page frame RAM if present, if not add it in and evict if needed
-    pub fn fetch_page(&self, page_id: PageId) -> Option<PageFrameRef> {
+    // the guard holds the frame's latch shared, so any number of readers of one page go at the same time and a
+    // fetch_page_write of it waits until they've all dropped
+    pub fn fetch_page(&self, page_id: PageId) -> Result<PageFrameRef<'_>, BufferError> {
+        let frame_id = self.pin_frame(page_id)?;
+        self.read_guard(page_id, frame_id)
+    }
+
+    // fetch_page under the name that says what it's for, next to fetch_page_write
+    pub fn fetch_page_read(&self, page_id: PageId) -> Result<PageFrameRef<'_>, BufferError> {
+        self.fetch_page(page_id)
+    }
+
+    // fetch_page that rides out a full pool: while every frame is pinned it sleeps a little (backing off up to a
+    // few ms) and tries again, PoolExhausted only comes back once timeout has passed. Other errors return right away
+    pub fn fetch_page_timeout(&self, page_id: PageId, timeout: Duration) -> Result<PageFrameRef<'_>, BufferError> {
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_micros(50);
+        loop {
+            match self.pin_frame(page_id) {
+                Ok(frame_id) => return self.read_guard(page_id, frame_id),
+                Err(BufferError::PoolExhausted) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(BufferError::PoolExhausted);
+                    }
+                    std::thread::sleep(backoff.min(deadline - now));
+                    backoff = (backoff * 2).min(Duration::from_millis(5));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // same as fetch_page but the page can be modified, it gets marked dirty when the guard drops
+    // holds the latch exclusively: waits for every reader of the page to drop and keeps everyone else out until then
+    pub fn fetch_page_write(&self, page_id: PageId) -> Result<WritePageGuard<'_>, BufferError> {
+        self.check_writable()?;
+        let frame_id = self.pin_frame(page_id)?;
+        self.write_guard(page_id, frame_id, Some(NO_TXN))
+    }
+
+    // same as fetch_page but the guard holds on to the pool through the Arc instead of borrowing it, so it can
+    // be moved to other threads. See OwnedPageFrameRef
+    pub fn fetch_page_arc(self: &Arc<Self>, page_id: PageId) -> Result<OwnedPageFrameRef, BufferError> {
+        let frame_id = self.pin_frame(page_id)?;
+        // same check read_guard does, a failed load leaves the frame without our page in it
+        if self.frames[frame_id].read().unwrap().page_id != Some(page_id) {
+            self.unpin_frame(frame_id, false);
+            return Err(load_failed(page_id));
+        }
+        Ok(OwnedPageFrameRef { bpm: Arc::clone(self), page_id, frame_index: frame_id })
+    }
+
+    // Copy of page_id's bytes, the page is only pinned and latched for as long as the copy takes
+    // for readers that don't need a live view and would otherwise sit on a latch (and block eviction) for a while
+    pub fn read_page_copy(&self, page_id: PageId) -> Result<[u8; PAGE_SIZE], BufferError> {
+        Ok(self.fetch_page(page_id)?.to_owned_bytes())
+    }
+
+    // Loads page_ids into the pool ahead of time without pinning them, so fetching them afterwards is a hit
+    // it's only a hint: resident pages and pages that can't be read are skipped, and it stops once every frame
+    // is pinned. Never loads more pages than the pool holds so it doesn't evict its own earlier prefetches
+    // the loads themselves show up as misses in stats()
+    pub fn prefetch(&self, page_ids: &[PageId]) {
+        for &page_id in page_ids.iter().take(self.pool_size()) {
+            if self.state.lock().unwrap().page_mapping.contains_key(&page_id) {
+                continue;
+            }
+            match self.pin_frame(page_id) {
+                Ok(frame_id) => self.unpin_frame(frame_id, false),
+                Err(BufferError::PoolExhausted) => break,
+                Err(_) => continue,
+            }
+        }
+    }
+
+    // write access with the changes logged as part of txn_id, see Transaction
+    pub(crate) fn fetch_page_write_in(&self, page_id: PageId, txn_id: TxnId) -> Result<WritePageGuard<'_>, BufferError> {
+        self.check_writable()?;
+        let frame_id = self.pin_frame(page_id)?;
+        if self.recovery_policy == RecoveryPolicy::NoStealForce {
+            let mut state = self.state.lock().unwrap();
+            let pages = state.txn_pages.entry(txn_id).or_default();
+            if !pages.contains(&page_id) {
+                pages.push(page_id);
+                state.frames[frame_id].pin(); // the transaction's own pin, see txn_pages
+            }
+        }
+        self.write_guard(page_id, frame_id, Some(txn_id))
+    }
+
+    // Called once txn_id committed (force = true) or rolled back. Under NoStealForce this is the force half:
+    // every page the transaction wrote goes to the data file before its pin is released. Pages are still
+    // released (and the first error returned) if a write fails, they stay dirty and get written back later
+    pub(crate) fn end_txn(&self, txn_id: TxnId, force: bool) -> io::Result<()> {
+        let Some(pages) = self.state.lock().unwrap().txn_pages.remove(&txn_id) else { return Ok(()) };
+        let mut result = Ok(());
+        for page_id in pages {
+            if force && result.is_ok() {
+                result = self.flush_page(page_id);
+            }
+            let _ = self.unpin_page(page_id, false);
+        }
+        result
+    }
+
+    // Starts a transaction, its page changes can be undone with abort. Needs a write ahead log
+    pub fn begin_txn(&self) -> io::Result<Transaction<'_>> {
+        self.begin(false)
+    }
+
+    // Same as begin_txn but the transaction checks for conflicts with others instead of assuming there are none,
+    // see Transaction::validate
+    pub fn begin_optimistic_txn(&self) -> io::Result<Transaction<'_>> {
+        self.begin(true)
+    }
+
+    fn begin(&self, optimistic: bool) -> io::Result<Transaction<'_>> {
+        match &self.log {
+            Some(log) => Ok(Transaction::new(self, log, optimistic)),
+            None => Err(io::Error::new(io::ErrorKind::Unsupported, "transactions need a write ahead log")),
+        }
+    }
+
+    // write access that bypasses the log, for recovery replaying changes that are already in it
+    pub(crate) fn fetch_page_write_unlogged(&self, page_id: PageId) -> Result<WritePageGuard<'_>, BufferError> {
+        self.check_writable()?;
+        let frame_id = self.pin_frame(page_id)?;
+        self.write_guard(page_id, frame_id, None)
+    }
+
+    // makes page ids up to page_id valid even if the file doesn't reach that far yet
+    // recovery needs this for pages that were logged but never flushed before a crash
+    pub(crate) fn extend_to(&self, page_id: PageId) {
+        let mut state = self.state.lock().unwrap();
+        state.next_page_id = state.next_page_id.max(page_id + 1);
+    }
+
+    // latches an already pinned frame, state must not be held here since this can block
+    // errors if the frame was still being loaded and the read failed, the pin is given back in that case
+    fn read_guard(&self, page_id: PageId, frame_id: FrameId) -> Result<PageFrameRef<'_>, BufferError> {
+        let latch = self.frames[frame_id].read().unwrap();
+        if latch.page_id != Some(page_id) {
+            drop(latch);
+            self.unpin_frame(frame_id, false);
+            return Err(load_failed(page_id));
+        }
+        #[cfg(feature = "paranoid")]
+        latch.verify_clean();
+        Ok(PageFrameRef { bpm: self, page_id, frame_index: frame_id, latch: Some(latch) })
+    }
+
+    // log_as is the transaction to log changes under, None skips logging
+    fn write_guard(&self, page_id: PageId, frame_id: FrameId, log_as: Option<TxnId>) -> Result<WritePageGuard<'_>, BufferError> {
+        let latch = self.frames[frame_id].write().unwrap();
+        if latch.page_id != Some(page_id) {
+            drop(latch);
+            self.unpin_frame(frame_id, false);
+            return Err(load_failed(page_id));
+        }
+        #[cfg(feature = "paranoid")]
+        latch.verify_clean();
+        let before = self.log.as_ref().filter(|_| log_as.is_some()).map(|_| Box::new(*latch.get_data()));
+        let txn_id = log_as.unwrap_or(NO_TXN);
+        Ok(WritePageGuard { bpm: self, page_id, frame_index: frame_id, latch: Some(latch), before, txn_id })
+    }
+
+    // logs the bytes that differ between before and page as one record, and stamps its LSN into the page
+    // a single changed span is an Update, several are a Delta, and too much change (or FullPage) the whole page
+    fn log_changes(&self, txn_id: TxnId, page_id: PageId, before: &[u8; PAGE_SIZE], page: &mut Page) {
+        let Some(log) = &self.log else { return };
+        let after = page.get_data();
+        let runs = changed_runs(before, after);
+        if runs.is_empty() {
+            return;
+        }
+        let changed: usize = runs.iter().map(|r| r.len()).sum();
+        let lsn = if self.log_granularity == LogGranularity::FullPage || changed > MAX_DELTA_BYTES {
+            log.append(txn_id, page_id, 0, before, after)
+        } else if let [run] = runs.as_slice() {
+            log.append(txn_id, page_id, run.start as u32, &before[run.clone()], &after[run.clone()])
+        } else {
+            let runs = runs
+                .into_iter()
+                .map(|r| DeltaRun { offset: r.start as u32, before: before[r.clone()].to_vec(), after: after[r].to_vec() })
+                .collect();
+            log.append_delta(txn_id, page_id, runs)
+        };
+        page.set_lsn(lsn);
+    }
+
+    // writes page to disk, flushing the log up to the page's LSN first (the WAL rule)
+    fn write_back(&self, page_id: PageId, page: &Page) -> io::Result<()> {
+        // a quarantine stand in only lives in the pool, the bad copy on disk has to stay there for whoever looks
+        // at it later. Any write guard marks it dirty (recovery takes one on it) so this is the one place to stop it
+        if page.page_type() == Ok(PageType::Quarantined) {
+            return Ok(());
+        }
+        if let Some(log) = &self.log {
+            log.flush_log_up_to(page.get_lsn())?;
+        }
+        self.clear_free_space_current()?;
+        self.disk_manager.write_page(page_id, page.get_data())?;
+        self.stats.record_dirty_flush();
+        Ok(())
+    }
+
+    // the write ahead log, if the pool was set up with one
+    pub fn log_manager(&self) -> Option<&LogManager> {
+        self.log.as_ref()
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn check_writable(&self) -> Result<(), BufferError> {
+        if self.read_only { Err(BufferError::ReadOnly) } else { Ok(()) }
+    }
+
+    // hit/miss/eviction counters since the pool was created
+    pub fn stats(&self) -> BufferPoolStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    // number of frames the pool is using, see resize
+    pub fn pool_size(&self) -> usize {
+        self.state.lock().unwrap().pool_size
+    }
+
+    /*
+    * Changes how many frames the pool uses without restarting it.
+    * Growing adds empty frames to the free list. Shrinking evicts the frames past new_size, writing back dirty
+    * ones, and fails with PoolExhausted if more than new_size frames are pinned right now. Pinned frames past
+    * new_size can't move, they keep their page until the last pin goes and are evicted then, so until those
+    * guards drop the pool holds a few more pages than new_size.
+    * Frame memory is never given back before the pool is dropped, shrinking and growing again reuses it.
+    */
+    pub fn resize(&self, new_size: usize) -> Result<(), BufferError> {
+        let result = self.resize_frames(new_size);
+        self.run_eviction_hook();
+        result
+    }
+
+    fn resize_frames(&self, new_size: usize) -> Result<(), BufferError> {
+        if new_size == 0 {
+            return Err(BufferError::Io(io::Error::new(io::ErrorKind::InvalidInput, "pool_size must be at least 1")));
+        }
         let mut state = self.state.lock().unwrap();
+        let old_size = state.pool_size;
+
+        if new_size > old_size {
+            if !self.frames.reserve(new_size) {
+                return Err(BufferError::Io(io::Error::new(io::ErrorKind::OutOfMemory, "buffer pool can't grow that far")));
+            }
+            while state.frames.len() < new_size {
+                state.frames.push(FrameMeta::default());
+            }
+            state.replacer.resize(new_size);
+            state.pool_size = new_size;
+            // frames retired by an earlier shrink can still be holding a pinned page, those just come back into use
+            for frame_id in old_size..new_size {
+                if state.frames[frame_id].page_id.is_none() {
+                    state.free_list.push_back(frame_id);
+                } else {
+                    let evictable = !state.frames[frame_id].is_pinned();
+                    state.replacer.set_evictable(frame_id, evictable);
+                }
+            }
+            return Ok(());
+        }
+
+        let pinned = state.frames.iter().filter(|meta| meta.is_pinned()).count();
+        if pinned > new_size {
+            return Err(BufferError::PoolExhausted);
+        }
+        // write back everything that's leaving before touching the bookkeeping, a failed write leaves the pool as it was
+        for frame_id in new_size..old_size {
+            let meta = &state.frames[frame_id];
+            let Some(page_id) = meta.page_id else { continue };
+            if meta.is_pinned() || self.read_only {
+                continue;
+            }
+            // unpinned so the latch is free, unless a guard was leaked, retire_frame skips those too
+            let Ok(mut page) = self.frames[frame_id].try_write() else { continue };
+            if meta.is_dirty || page.is_dirty() {
+                self.write_back(page_id, &page)?;
+                page.set_dirty(false);
+                drop(page);
+                state.frames[frame_id].is_dirty = false;
+            }
+        }
+
+        state.free_list.retain(|&frame_id| frame_id < new_size);
+        for frame_id in new_size..old_size {
+            state.set_evictable(frame_id, false);
+            if !state.frames[frame_id].is_pinned() {
+                self.retire_frame(&mut state, frame_id);
+            }
+        }
+        state.replacer.resize(new_size);
+        state.pool_size = new_size;
+        Ok(())
+    }
+
+    // drops whatever page a frame past pool_size still holds, the frame has to be unpinned
+    // anything dirty gets written back first, if that fails (or the latch is still held by a leaked guard)
+    // the page just stays mapped in the retired frame and gets another go the next time it's unpinned
+    fn retire_frame(&self, state: &mut BufferPoolState, frame_id: FrameId) {
+        let Some(page_id) = state.frames[frame_id].page_id else { return };
+        let Ok(mut page) = self.frames[frame_id].try_write() else { return };
+        let dirty = !self.read_only && (state.frames[frame_id].is_dirty || page.is_dirty());
+        if dirty && self.write_back(page_id, &page).is_err() {
+            return;
+        }
+        state.page_mapping.remove(&page_id);
+        state.frames[frame_id] = FrameMeta::default();
+        page.page_id = None;
+        page.set_dirty(false);
+        self.stats.record_eviction();
+        self.note_eviction(page_id);
+    }
+
+    // pins page_id in a frame (loading it from disk if needed) and returns the frame it lives in
+    fn pin_frame(&self, page_id: PageId) -> Result<FrameId, BufferError> {
+        let mut guard = self.state.lock().unwrap();
+        let pinned = self.pin_or_claim(&mut guard, page_id);
+        drop(guard);
+        self.run_eviction_hook();
+
+        match pinned? {
+            Pinned::Resident(frame_id) => Ok(frame_id),
+            Pinned::Claimed(frame_id, page) => {
+                self.load_claimed(page_id, frame_id, page)?;
+                self.run_load_hook(page_id);
+                Ok(frame_id)
+            }
+        }
+    }
 
+    // Pins page_id if it's in RAM, otherwise claims a frame for it and hands back the frame's latch so the caller
+    // can read the page in once state is released
+    fn pin_or_claim(&self, state: &mut BufferPoolState, page_id: PageId) -> Result<Pinned<'_>, BufferError> {
         // check if page in RAM
         if let Some(&frame_id) = state.page_mapping.get(&page_id) {
-            state.frames[frame_id].pin_count += 1;
-            state.frames[frame_id].ref_bit = true; 
-            return Some(PageFrameRef { bpm: self, page_id, frame_index: frame_id });
+            state.frames[frame_id].pin();
+            if frame_id < state.pool_size {
+                state.replacer.record_access(frame_id);
+            }
+            state.set_evictable(frame_id, false);
+            self.stats.record_hit();
+            return Ok(Pinned::Resident(frame_id));
+        }
+
+        // pages past the end of the file or on the free list hold nothing worth reading
+        if page_id >= state.next_page_id || state.free_pages.contains(&page_id) {
+            return Err(BufferError::PageNotFound(page_id));
         }
 
         // Not in RAM. Find a frame to use.
-        let frame_id= self.find_free_frame(&mut state)?;
+        self.stats.record_miss();
+        let frame_id= self.find_free_frame(state)?;
+
+        // Claim the frame for page_id before letting go of state. Nobody else can be latching an unpinned frame
+        // so this won't block, and anyone else fetching page_id meanwhile pins it and waits on the latch
+        let page = self.frames[frame_id].write().unwrap();
+        let meta = &mut state.frames[frame_id];
+        meta.page_id = Some(page_id);
+        meta.set_pin_count(1);
+        meta.is_dirty = false;
+        state.page_mapping.insert(page_id, frame_id);
+        state.replacer.record_access(frame_id);
+        state.replacer.set_evictable(frame_id, false);
+        Ok(Pinned::Claimed(frame_id, page))
+    }
+
+    // Reads page_id into a frame claimed by pin_or_claim, state must not be held. Only this frame is tied up
+    // while we wait on the disk. If the read fails (or the page has a format version we can't migrate, or a corrupt
+    // header that on_corruption couldn't deal with) the frame is given back and the pin dropped
+    fn load_claimed(&self, page_id: PageId, frame_id: FrameId, mut page: RwLockWriteGuard<'_, Page>) -> Result<(), BufferError> {
+        // validate before migrate, see Page::try_from_bytes
+        let checked = match self.disk_manager.read_page(page_id, page.get_data_mut()) {
+            Ok(()) => page.validate().map(|()| false).map_err(BufferError::from),
+            Err(e) => Err(e.into()),
+        };
+        // checksum mismatches and bad headers both come back as InvalidData
+        let loaded = match checked {
+            Err(BufferError::Io(e)) if e.kind() == io::ErrorKind::InvalidData => self.repair(page_id, &mut page, e),
+            checked => checked,
+        };
+        let loaded = loaded.and_then(|repaired| page.migrate().map(|()| repaired).map_err(BufferError::from));
+        let repaired = match loaded {
+            Ok(repaired) => repaired,
+            Err(e) => {
+                // page_id None tells whoever is waiting on the latch that the load failed
+                page.page_id = None;
+                drop(page);
+                let mut state = self.state.lock().unwrap();
+                state.page_mapping.remove(&page_id);
+                state.frames[frame_id].page_id = None;
+                drop(state);
+                // the frame is empty, once the waiters are gone too the replacer can hand it out again
+                self.unpin_frame(frame_id, false);
+                return Err(e);
+            }
+        };
+        page.page_id = Some(page_id);
+        // a page rebuilt from the log has to make it back over the bad copy on disk
+        page.set_dirty(repaired);
+        Ok(())
+    }
+
+    // what on_corruption says to do with page_id, which just failed its checksum or header check with error
+    // Ok(true) if page now holds a rebuilt copy that should be written back
+    fn repair(&self, page_id: PageId, page: &mut Page, error: io::Error) -> Result<bool, BufferError> {
+        match self.on_corruption {
+            OnCorruption::Fail => Err(error.into()),
+            OnCorruption::Quarantine => {
+                // the fetch goes through, so the only place left to say what happened is the deferred errors
+                let error = io::Error::new(error.kind(), format!("page {} is corrupt ({}), quarantined", page_id, error));
+                self.defer_error(error.into());
+                *page = Page::new(page_id, PageType::Quarantined);
+                self.quarantined.lock().unwrap().insert(page_id);
+                Ok(false)
+            }
+            OnCorruption::AttemptWalRedo => {
+                let Some(log) = &self.log else { return Err(error.into()) };
+                let Some(rebuilt) = log.rebuild_page(page_id)? else { return Err(error.into()) };
+                *page = rebuilt;
+                Ok(true)
+            }
+        }
+    }
+
+    // pages OnCorruption::Quarantine swapped out for an empty one since the pool was opened, in page id order
+    pub fn quarantined_pages(&self) -> Vec<PageId> {
+        self.quarantined.lock().unwrap().iter().copied().collect()
+    }
+
+    // Errors that came up where there was no caller to hand them to (a transaction rolled back by its Drop, a
+    // background flush pass, freeing a deleted record's overflow pages, a page OnCorruption::Quarantine swapped
+    // out), oldest first. Each one is only handed out once
+    pub fn take_deferred_errors(&self) -> Vec<BufferError> {
+        std::mem::take(&mut *self.deferred_errors.lock().unwrap())
+    }
+
+    pub(crate) fn defer_error(&self, error: BufferError) {
+        self.deferred_errors.lock().unwrap().push(error);
+    }
+
+    // Write guards on every page in page_ids, latched in ascending page id order whatever order they're asked in
+    // (see the locking rules above). Comes back sorted that way with duplicates dropped. If any page can't be had
+    // the ones already latched are released again and the error returned
+    pub fn lock_pages_ordered(&self, page_ids: &[PageId]) -> Result<Vec<WritePageGuard<'_>>, BufferError> {
+        let mut page_ids = page_ids.to_vec();
+        page_ids.sort_unstable();
+        page_ids.dedup();
+        page_ids.into_iter().map(|page_id| self.fetch_page_write(page_id)).collect()
+    }
+
+    // Fetches a batch of pages taking the state lock once for all of them instead of once per page
+    // results line up with page_ids. Everything in the batch stays pinned until its guard drops, so a batch
+    // bigger than the pool runs out of frames partway and the rest come back PoolExhausted
+    // asking for the same page twice is fine, both guards share the frame
+    pub fn fetch_pages(&self, page_ids: &[PageId]) -> Vec<Result<PageFrameRef<'_>, BufferError>> {
+        let mut guard = self.state.lock().unwrap();
+        let pinned: Vec<Result<Pinned<'_>, BufferError>> =
+            page_ids.iter().map(|&page_id| self.pin_or_claim(&mut guard, page_id)).collect();
+        drop(guard);
+        self.run_eviction_hook();
+
+        // do the disk reads with state released, then latch everything for the caller
+        let frames: Vec<Result<FrameId, BufferError>> = page_ids
+            .iter()
+            .zip(pinned)
+            .map(|(&page_id, pinned)| match pinned? {
+                Pinned::Resident(frame_id) => Ok(frame_id),
+                Pinned::Claimed(frame_id, page) => {
+                    self.load_claimed(page_id, frame_id, page)?;
+                    self.run_load_hook(page_id);
+                    Ok(frame_id)
+                }
+            })
+            .collect();
+        page_ids.iter().zip(frames).map(|(&page_id, frame_id)| self.read_guard(page_id, frame_id?)).collect()
+    }
+
+    // creates a brand new page, reusing a deallocated page id if there is one
+    // the page starts out dirty so it makes it to disk even if nobody writes to it
+    // new pages are tagged NodeStore and slot formatted, use allocate_page_of_type for anything else
+    pub fn allocate_page(&self) -> Result<PageFrameRef<'_>, BufferError> {
+        let allocated = self.allocate_frame(PageType::NodeStore);
+        self.run_eviction_hook();
+        let (page_id, frame_id) = allocated?;
+        self.read_guard(page_id, frame_id)
+    }
 
-        // if the victim frame is dirty, write it to disk, TODO: need to implement disk writing
-        let old_page_id = state.frames[frame_id].page_id;
-        if let Some(old_pid) = old_page_id {
-            if state.frames[frame_id].is_dirty {
-                // state.disk_manager.write_page(old_pid, &state.frames[frame_id].data);
+    // same as allocate_page but the header starts out with page_type, and the page comes back ready to be written
+    pub fn allocate_page_of_type(&self, page_type: PageType) -> Result<WritePageGuard<'_>, BufferError> {
+        let allocated = self.allocate_frame(page_type);
+        self.run_eviction_hook();
+        let (page_id, frame_id) = allocated?;
+        self.write_guard(page_id, frame_id, Some(NO_TXN))
+    }
+
+    // picks the page id for a new page and pins a freshly initialized frame for it
+    fn allocate_frame(&self, page_type: PageType) -> Result<(PageId, FrameId), BufferError> {
+        self.check_writable()?;
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+
+        let frame_id = self.find_free_frame(state)?;
+        let page_id = match state.free_pages.pop() {
+            Some(pid) => pid,
+            None => {
+                state.next_page_id += 1;
+                state.next_page_id - 1
             }
-            state.page_mapping.remove(&old_pid);
+        };
+
+        let mut page = Page::new(page_id, page_type);
+        // record store pages come back with an empty slot directory, so a NodeStore page from allocate_page is
+        // something the stores and the free space map can use rather than a page they'd have to skip
+        if page_type.is_slotted() {
+            SlottedPage::init(&mut page);
+        }
+        page.set_dirty(true);
+        // log the fresh header too, otherwise redoing later changes on a page that never got flushed
+        // would leave it without one
+        if let Some(log) = &self.log {
+            log.log_alloc(page_id, page_type);
+            let lsn = log.append(NO_TXN, page_id, 0, &[0; HEADER_SIZE], &page.get_data()[..HEADER_SIZE]);
+            page.set_lsn(lsn);
         }
+        *self.frames[frame_id].write().unwrap() = page;
 
-        // Read new page from disk, TODO
-        // state.disk_manager.read_page(page_id, &mut state.frames[frame_id].data);
-        
-        //Update Metadata
-        state.frames[frame_id].page_id = Some(page_id);
-        state.frames[frame_id].pin_count = 1;
-        state.frames[frame_id].is_dirty = false;
+        let meta = &mut state.frames[frame_id];
+        meta.page_id = Some(page_id);
+        meta.set_pin_count(1);
+        meta.is_dirty = true;
         state.page_mapping.insert(page_id, frame_id);
+        state.replacer.record_access(frame_id);
+        state.replacer.set_evictable(frame_id, false);
 
-        Some(PageFrameRef { bpm: self, page_id, frame_index: frame_id })
+        Ok((page_id, frame_id))
     }
 
+    // Hands out n pages with consecutive ids and returns the first one, for structures that want sequential I/O
+    // (bulk loads, B+ tree builds). A run of n pages on the free list is taken if there is one, otherwise the run
+    // goes past the end of the file. Each page is a fresh, slot formatted NodeStore page like allocate_page's and
+    // gets written straight to the file instead of taking up frames, fetch_page_write them to set the type and fill
+    // them in.
+    // None if n is 0 or there aren't n page ids left. If writing a page out fails the error comes back and the
+    // ids of the run are lost
+    pub fn allocate_contiguous(&self, n: usize) -> Result<Option<PageId>, BufferError> {
+        self.check_writable()?;
+        if n == 0 {
+            return Ok(None);
+        }
+        let mut state = self.state.lock().unwrap();
+        let first = match free_run(&state.free_pages, n) {
+            Some(first) => {
+                self.take_free_run(&mut state, first..first + n as PageId);
+                first
+            }
+            None => {
+                let first = state.next_page_id;
+                let Some(next) = first.checked_add(n as PageId) else { return Ok(None) };
+                state.next_page_id = next;
+                first
+            }
+        };
 
-    // Helper to find a free frame or evict one
-    fn find_free_frame(&self, state: &mut BufferPoolState) -> Option<FrameId> {
-        // Try free list first
-        if let Some(fid) = state.free_list.pop_front() {
-            return Some(fid);
+        // under state like deallocate_page, so nobody reads one of them in before it's written
+        for page_id in first..first + n as PageId {
+            let mut page = Page::new(page_id, PageType::NodeStore);
+            // same as allocate_frame, a node store scan has to find an empty slotted page here and not a raw one
+            SlottedPage::init(&mut page);
+            if let Some(log) = &self.log {
+                log.log_alloc(page_id, PageType::NodeStore);
+                let lsn = log.append(NO_TXN, page_id, 0, &[0; HEADER_SIZE], &page.get_data()[..HEADER_SIZE]);
+                page.set_lsn(lsn);
+            }
+            self.write_back(page_id, &page)?;
         }
-        // Run clock algo to find and return victim
-        state.replacer.victim(&mut state.frames)
+        Ok(Some(first))
     }
 
-    // Called by the PageGuard when it drops
-    pub fn unpin_page(&self, page_id: PageId, is_dirty: bool) {
-        let mut state = self.state.lock().unwrap(); // heard unwrap caused cloudflare outage, might not be so safe
-        if let Some(&frame_id) = state.page_mapping.get(&page_id) {
-            let frame = &mut state.frames[frame_id];
-            if frame.pin_count > 0 {
-                frame.pin_count -= 1;
+    // takes the pages in run off the free list and relinks the ones that pointed into it, see install_free_list
+    fn take_free_run(&self, state: &mut BufferPoolState, run: std::ops::Range<PageId>) {
+        let old = std::mem::take(&mut state.free_pages);
+        let mut installed = Vec::with_capacity(old.len());
+        let mut old_below = None;
+        for page_id in old {
+            if run.contains(&page_id) {
+                old_below = Some(page_id);
+                continue;
             }
-            if is_dirty {
-                frame.is_dirty = true;
+            let below = installed.last().copied();
+            if below != old_below && !self.relink_free_page(state, page_id, below) {
+                break;
             }
+            installed.push(page_id);
+            old_below = Some(page_id);
         }
+        state.free_pages = installed;
     }
-}
\ No newline at end of file
+
+    // Hands out the next page id past the end of the file without putting anything in the pool, see BulkLoader
+    pub(crate) fn reserve_page_id(&self) -> Result<PageId, BufferError> {
+        self.check_writable()?;
+        let mut state = self.state.lock().unwrap();
+        state.next_page_id += 1;
+        Ok(state.next_page_id - 1)
+    }
+
+    // writes page straight to the data file, no pool, no log. Only for pages nobody else can be using
+    pub(crate) fn write_unbuffered(&self, page: &Page) -> io::Result<()> {
+        let page_id = page.page_id.expect("page needs an id to be written");
+        self.clear_free_space_current()?;
+        self.disk_manager.write_page(page_id, page.get_data())
+    }
+
+    // drops page_id from RAM and puts it on the free page list so allocate_page can hand it out again
+    // the list is kept on disk (see catalog/superblock.rs), freed pages get reused after a restart too
+    // Ok(false) if the page is still pinned by someone, the pool is read only or it's the superblock. If writing
+    // the free list link into it fails the error comes back and the page is gone from RAM but isn't on the
+    // list, it's leaked until the list gets rebuilt
+    pub fn deallocate_page(&self, page_id: PageId) -> Result<bool, BufferError> {
+        if self.read_only || page_id == SUPERBLOCK_PAGE {
+            return Ok(false);
+        }
+        let mut state = self.state.lock().unwrap();
+        if page_id >= state.next_page_id || state.free_pages.contains(&page_id) {
+            return Ok(true);
+        }
+
+        if !self.discard_frame(&mut state, page_id) {
+            return Ok(false);
+        }
+
+        // link it to the current head, under state like a victim write back so nobody reads it in meanwhile
+        let mut page = free_page(page_id, state.free_pages.last().copied());
+        if let Some(log) = &self.log {
+            log.log_free(page_id);
+            let lsn = log.append(NO_TXN, page_id, 0, &[0; HEADER_SIZE + 8], &page.get_data()[..HEADER_SIZE + 8]);
+            page.set_lsn(lsn);
+        }
+        self.write_back(page_id, &page)?;
+        state.free_pages.push(page_id);
+        drop(state);
+        // nothing can go into it until it's handed out again, as whatever type that is
+        for map in self.free_space_maps.lock().unwrap().values() {
+            map.remove(page_id);
+        }
+        Ok(true)
+    }
+
+    // forgets the pool's copy of page_id without writing it back, false if someone has it pinned
+    fn discard_frame(&self, state: &mut BufferPoolState, page_id: PageId) -> bool {
+        let Some(&frame_id) = state.page_mapping.get(&page_id) else { return true };
+        if state.frames[frame_id].is_pinned() {
+            return false;
+        }
+        state.page_mapping.remove(&page_id);
+        state.frames[frame_id] = FrameMeta::default();
+        self.frames[frame_id].write().unwrap().page_id = None;
+        if frame_id < state.pool_size {
+            state.free_list.push_back(frame_id);
+        }
+        state.set_evictable(frame_id, false);
+        true
+    }
+
+    // the free page list as the chain on disk has it, top of the stack last. Read when the pool opens, before redo
+    // pages are read around the pool so none of them take up a frame (or look allocated) afterwards
+    fn read_free_list(&self) -> Vec<PageId> {
+        let Ok(superblock) = self.read_around_pool(SUPERBLOCK_PAGE) else { return Vec::new() };
+        let Some(Some(head)) = Superblock::new(&superblock).map(|sb| sb.free_list_head()) else { return Vec::new() };
+        let limit = self.page_limit();
+        let mut chain = Vec::new();
+        let mut next = Some(head);
+        while let Some(page_id) = next {
+            if page_id == SUPERBLOCK_PAGE || page_id >= limit || chain.contains(&page_id) {
+                break;
+            }
+            let Ok(page) = self.read_around_pool(page_id) else { break };
+            let Some(link) = next_free(&page) else { break };
+            chain.push(page_id);
+            next = link;
+        }
+        // the head is the top of the stack
+        chain.reverse();
+        chain
+    }
+
+    // Makes free_pages the pool's free page list. After redo_free_list the chain on disk might not match it
+    // anymore, so every page whose link (or type) is off gets rewritten as a Free page pointing at the one below it
+    // and dropped from the pool if redo pulled it in. A page that can't be written ends the list there, the
+    // pages under it leak instead of getting handed out with a wrong link
+    fn install_free_list(&self, free_pages: Vec<PageId>) {
+        let mut state = self.state.lock().unwrap();
+        let mut installed = Vec::with_capacity(free_pages.len());
+        for page_id in free_pages {
+            let below = installed.last().copied();
+            if !self.read_only && !self.relink_free_page(&mut state, page_id, below) {
+                break;
+            }
+            installed.push(page_id);
+        }
+        state.free_pages = installed;
+    }
+
+    // see install_free_list, state is held so nobody can fetch the page while it's rewritten
+    fn relink_free_page(&self, state: &mut BufferPoolState, page_id: PageId, below: Option<PageId>) -> bool {
+        if !self.discard_frame(state, page_id) {
+            return false;
+        }
+        let mut data = [0; PAGE_SIZE];
+        if self.disk_manager.read_page(page_id, &mut data).is_err() {
+            return false;
+        }
+        // a copy whose header is corrupt gets rewritten like any other wrong link, its LSN can't be trusted either
+        let on_disk = Page::from_bytes(data).ok();
+        if on_disk.as_ref().and_then(next_free) == Some(below) {
+            return true;
+        }
+        let mut page = free_page(page_id, below);
+        page.set_lsn(on_disk.map_or(0, |on_disk| on_disk.get_lsn()));
+        self.write_back(page_id, &page).is_ok()
+    }
+
+    // Writes page_id to disk if it's dirty and clears its dirty bit, the page stays in the pool (pinned or not)
+    // pages that aren't in the pool have nothing to flush
+    pub fn flush_page(&self, page_id: PageId) -> io::Result<()> {
+        // read only pages can't have changed, anything marked dirty through unpin_page is dropped
+        if self.read_only {
+            return Ok(());
+        }
+        if let Some(threshold) = self.vacuum_on_flush {
+            self.vacuum_before_flush(page_id, threshold);
+        }
+        // pin so the frame can't be evicted while we wait for the latch, and take the dirty bit now so a writer
+        // unpinning after this point marks it dirty again instead of us clearing their change
+        let (frame_id, was_dirty) = {
+            let mut state = self.state.lock().unwrap();
+            let Some(&frame_id) = state.page_mapping.get(&page_id) else { return Ok(()) };
+            let meta = &mut state.frames[frame_id];
+            meta.pin();
+            let was_dirty = std::mem::take(&mut meta.is_dirty);
+            state.set_evictable(frame_id, false);
+            (frame_id, was_dirty)
+        };
+
+        // a read latch is enough to keep writers out while the page goes to disk, and lets the caller flush a page
+        // they're holding a PageFrameRef to
+        let page = self.frames[frame_id].read().unwrap();
+        let result = if was_dirty || page.is_dirty() {
+            self.write_back(page_id, &page)
+        } else {
+            Ok(())
+        };
+        if result.is_ok() {
+            page.mark_clean();
+        }
+        drop(page);
+
+        // if the write failed the page is still dirty
+        self.unpin_frame(frame_id, result.is_err());
+        result
+    }
+
+    // Vacuums page_id if it's a resident slotted page with more than threshold of its slots tombstoned, logged like
+    // any other change, and tells the free space map of its type about the room it got back. Live records keep
+    // their slot (see SlottedPage::vacuum), so ids and forwards pointing into the page stay valid.
+    // Skipped while anyone holds the page's latch, flush_page has to work for a caller holding a guard on it, and
+    // while a running transaction has changed the page, since rolling it back writes its before images over the
+    // moved records
+    fn vacuum_before_flush(&self, page_id: PageId, threshold: f64) {
+        let frame_id = {
+            let mut state = self.state.lock().unwrap();
+            let Some(&frame_id) = state.page_mapping.get(&page_id) else { return };
+            state.frames[frame_id].pin();
+            state.set_evictable(frame_id, false);
+            frame_id
+        };
+        let mut vacuumed = false;
+        if let Ok(mut page) = self.frames[frame_id].try_write()
+            && page.page_id == Some(page_id)
+            && let Ok(page_type) = page.page_type()
+            && page_type.is_slotted()
+            && SlottedPage::new(&*page).tombstone_ratio() > threshold
+            // under the latch, a transaction has to get it before it can change the page
+            && !self.log.as_ref().is_some_and(|log| log.has_uncommitted_changes(page_id))
+        {
+            let before = Box::new(*page.get_data());
+            SlottedPage::new(&mut *page).vacuum();
+            self.log_changes(NO_TXN, page_id, &before, &mut page);
+            // only a map that's already there, building one would read pages of this type while holding the latch
+            if let Some(map) = self.free_space_maps.lock().unwrap().get(&page_type) {
+                map.update(page_id, SlottedPage::new(&*page).available_space());
+            }
+            vacuumed = true;
+        }
+        self.unpin_frame(frame_id, vacuumed);
+    }
+
+    /*
+    * Spawns a thread that wakes up every interval and, whenever more than high_watermark (0.0 to 1.0) of the
+    * pool's frames are dirty, writes back unpinned dirty pages oldest LSN first until the ratio is back down
+    * to the watermark. Replaces a flusher that was already running. It stops when stop_background_flusher is
+    * called or the pool is dropped, and never keeps the pool alive by itself
+    */
+    pub fn start_background_flusher(self: &Arc<Self>, interval: Duration, high_watermark: f64) {
+        let flusher = BackgroundFlusher::spawn(Arc::downgrade(self), interval, high_watermark);
+        // the old one is stopped (and waited for) outside the lock
+        let old = self.flusher.lock().unwrap().replace(flusher);
+        drop(old);
+    }
+
+    pub fn stop_background_flusher(&self) {
+        let old = self.flusher.lock().unwrap().take();
+        drop(old);
+    }
+
+    // one pass of the background flusher, returns how many pages it wrote back
+    // pinned frames are left alone, whoever holds them may still be writing
+    pub(crate) fn flush_to_watermark(&self, high_watermark: f64) -> io::Result<usize> {
+        let candidates = {
+            let state = self.state.lock().unwrap();
+            let allowed = (state.pool_size as f64 * high_watermark.clamp(0.0, 1.0)) as usize;
+            let mut dirty = Vec::new();
+            let mut dirty_count = 0;
+            for (frame_id, meta) in state.frames.iter().enumerate() {
+                let Some(page_id) = meta.page_id else { continue };
+                if meta.is_pinned() {
+                    dirty_count += meta.is_dirty as usize;
+                    continue;
+                }
+                // unpinned so nobody holds the latch, unless a guard was leaked
+                let Ok(page) = self.frames[frame_id].try_read() else { continue };
+                if meta.is_dirty || page.is_dirty() {
+                    dirty_count += 1;
+                    dirty.push((page.get_lsn(), page_id));
+                }
+            }
+            if dirty_count <= allowed {
+                return Ok(0);
+            }
+            dirty.sort_unstable();
+            dirty.truncate(dirty_count - allowed);
+            dirty
+        };
+
+        for &(_, page_id) in &candidates {
+            self.flush_page(page_id)?;
+        }
+        Ok(candidates.len())
+    }
+
+    /*
+    * Flushes every dirty page in the pool, stops at the first write that fails.
+    * The free space maps get saved along with them: into a new overflow chain if they changed since the last
+    * time, then once every page is out the superblock's free_space_current is set in a write around the pool.
+    * Only that copy of page 0 on disk ever has it set, the pool's own has it cleared, and write_back puts that back
+    * before writing anything else. So the flag is only still set at the next open if nothing was written since.
+    */
+    pub fn flush_all(&self) -> io::Result<()> {
+        let saved = self.save_free_space_maps();
+        self.save_superblock();
+        let page_ids: Vec<PageId> = self.state.lock().unwrap().page_mapping.keys().copied().collect();
+        for page_id in page_ids {
+            self.flush_page(page_id)?;
+        }
+        self.mark_free_space_current(&saved)
+    }
+
+    // writes the free space maps to a new chain and points the superblock at it, freeing the old one, unless they
+    // haven't changed since that was written. Returns each map with the changes the chain has of it, empty if
+    // there's no chain to mark current
+    fn save_free_space_maps(&self) -> Vec<(Arc<FreeSpaceMap>, u64)> {
+        let mut maps: Vec<(PageType, Arc<FreeSpaceMap>)> =
+            self.free_space_maps.lock().unwrap().iter().map(|(&page_type, map)| (page_type, Arc::clone(map))).collect();
+        if self.read_only || maps.is_empty() {
+            return Vec::new();
+        }
+        let Some(old) = self.superblock().map(|sb| sb.free_space_root()).ok() else { return Vec::new() };
+        if old.is_some() && maps.iter().all(|(_, map)| map.is_saved()) {
+            return maps.into_iter().map(|(_, map)| (Arc::clone(&map), map.changes())).collect();
+        }
+
+        maps.sort_by_key(|&(page_type, _)| page_type as u16);
+        let mut blob = Vec::new();
+        let saved: Vec<(Arc<FreeSpaceMap>, u64)> =
+            maps.into_iter().map(|(page_type, map)| (Arc::clone(&map), map.encode(page_type, &mut blob))).collect();
+        let chain = OverflowChain::new(self);
+        let Ok(head) = chain.write_blob(&blob) else { return Vec::new() };
+        let Ok(mut sb) = self.superblock_mut() else {
+            let _ = chain.free_chain(head);
+            return Vec::new();
+        };
+        sb.set_free_space_root(Some(head));
+        drop(sb);
+        // leaked if it can't be freed, the new chain is the one that counts either way
+        if let Some(old) = old {
+            let _ = chain.free_chain(old);
+        }
+        saved.iter().for_each(|(map, changes)| map.mark_saved(*changes));
+        saved
+    }
+
+    // sets free_space_current on disk, unless a map changed since saved was taken (its pages might have gone out
+    // after the change)
+    fn mark_free_space_current(&self, saved: &[(Arc<FreeSpaceMap>, u64)]) -> io::Result<()> {
+        if saved.is_empty() || saved.iter().any(|(map, changes)| map.changes() != *changes) {
+            return Ok(());
+        }
+        // read latched so page 0 can't change (or be evicted) between taking the copy and writing it
+        let Ok(guard) = self.fetch_page(SUPERBLOCK_PAGE) else { return Ok(()) };
+        let unmarked = guard.to_owned_bytes();
+        let Ok(mut marked) = Page::from_bytes(unmarked) else { return Ok(()) };
+        let Some(mut sb) = Superblock::new(&mut marked) else { return Ok(()) };
+        sb.set_free_space_current(true);
+
+        let mut current = self.free_space_current.lock().unwrap();
+        self.disk_manager.write_page(SUPERBLOCK_PAGE, marked.get_data())?;
+        *current = Some(Box::new(unmarked));
+        Ok(())
+    }
+
+    // puts page 0 back on disk without free_space_current if it's set there, write_back does it before every write
+    fn clear_free_space_current(&self) -> io::Result<()> {
+        let mut current = self.free_space_current.lock().unwrap();
+        if let Some(unmarked) = current.as_deref() {
+            self.disk_manager.write_page(SUPERBLOCK_PAGE, unmarked)?;
+            *current = None;
+        }
+        Ok(())
+    }
+
+    // Loads the free space maps saved by the last flush_all, if the superblock says they're still current and
+    // recovery didn't have to redo anything. Otherwise free_space_map rebuilds them when they're asked for
+    // the flag is taken off the pool's copy of page 0 either way, the copy on disk stays as it is until the first
+    // page is written
+    fn load_free_space_maps(&self) {
+        let redone = !self.dirty_page_ids().is_empty();
+        // around the pool like read_free_list, it only has to come in if the flag needs taking off
+        let Ok(superblock) = self.read_around_pool(SUPERBLOCK_PAGE) else { return };
+        let Some((root, current)) = Superblock::new(&superblock).map(|sb| (sb.free_space_root(), sb.free_space_current()))
+        else {
+            return;
+        };
+
+        if current && !self.read_only {
+            let Ok(mut sb) = self.superblock_mut() else { return };
+            sb.set_free_space_current(false);
+            drop(sb);
+            if let Ok(unmarked) = self.read_page_copy(SUPERBLOCK_PAGE) {
+                *self.free_space_current.lock().unwrap() = Some(Box::new(unmarked));
+            }
+        }
+        let Some(root) = root.filter(|_| current && !redone) else { return };
+        let Some(maps) = OverflowChain::new(self).read_blob(root).ok().and_then(|blob| FreeSpaceMap::decode(&blob)) else {
+            return;
+        };
+        let mut loaded = self.free_space_maps.lock().unwrap();
+        for (page_type, map) in maps {
+            loaded.insert(page_type, Arc::new(map));
+        }
+    }
+
+    /*
+    * Hooks for embedders keeping caches of their own on top of the pool.
+    * The eviction hook gets the id of every page that leaves RAM to make room for another one (or because resize
+    * shrank the pool), the load hook every page read in from disk. Deallocated pages and freshly allocated ones
+    * don't count. Both run on the thread that caused them, with none of the pool's locks held, right after the
+    * fetch/allocate/unpin that caused them, so they may call back into the pool, just not set hooks from inside
+    * one. Setting a hook replaces the old one, None removes it.
+    */
+    pub fn set_eviction_hook(&self, hook: Option<PageHook>) {
+        let mut hooks = self.hooks.write().unwrap();
+        self.has_eviction_hook.store(hook.is_some(), Ordering::Release);
+        hooks.on_evict = hook;
+    }
+
+    pub fn set_load_hook(&self, hook: Option<PageHook>) {
+        self.hooks.write().unwrap().on_load = hook;
+    }
+
+    // under state, page_id just left its frame. Only remembered while there's a hook to tell
+    fn note_eviction(&self, page_id: PageId) {
+        if self.has_eviction_hook.load(Ordering::Acquire) {
+            self.evicted.lock().unwrap().push(page_id);
+        }
+    }
+
+    // passes the evictions noted so far to the hook, state must not be held
+    fn run_eviction_hook(&self) {
+        if !self.has_eviction_hook.load(Ordering::Acquire) {
+            return;
+        }
+        let evicted = std::mem::take(&mut *self.evicted.lock().unwrap());
+        if evicted.is_empty() {
+            return;
+        }
+        if let Some(hook) = &self.hooks.read().unwrap().on_evict {
+            evicted.into_iter().for_each(hook);
+        }
+    }
+
+    fn run_load_hook(&self, page_id: PageId) {
+        if let Some(hook) = &self.hooks.read().unwrap().on_load {
+            hook(page_id);
+        }
+    }
+
+    // Snapshot of every frame for monitoring, taken under one short hold of state. Pages are only looked at with
+    // try_read, so a frame somebody is writing to shows is_dirty true and no page_type instead of blocking
+    pub fn frame_table(&self) -> Vec<FrameInfo> {
+        let state = self.state.lock().unwrap();
+        state
+            .frames
+            .iter()
+            .enumerate()
+            .map(|(frame_id, meta)| {
+                let page = self.frames[frame_id].try_read().ok();
+                let loaded = page.as_ref().filter(|_| meta.page_id.is_some());
+                FrameInfo {
+                    frame_id,
+                    page_id: meta.page_id,
+                    pin_count: meta.pin_count(),
+                    is_dirty: meta.page_id.is_some() && (meta.is_dirty || loaded.is_none_or(|page| page.is_dirty())),
+                    ref_bit: meta.ref_bit(),
+                    page_type: loaded.and_then(|page| page.page_type().ok()),
+                }
+            })
+            .collect()
+    }
+
+    // The top_n resident pages with the most pins right now and their pin counts, for finding contention. Pages
+    // with the same pin count go by how often the replacer saw them accessed lately (Replacer::recent_accesses),
+    // so with nothing pinned this ranks by recent access alone
+    pub fn hot_pages(&self, top_n: usize) -> Vec<(PageId, u32)> {
+        let state = self.state.lock().unwrap();
+        let mut pages: Vec<(PageId, u32, u32)> = state
+            .frames
+            .iter()
+            .enumerate()
+            .filter_map(|(frame_id, meta)| {
+                // retired frames are outside the replacer
+                let accesses = if frame_id < state.pool_size { state.replacer.recent_accesses(frame_id) } else { 0 };
+                Some((meta.page_id?, meta.pin_count(), accesses))
+            })
+            .collect();
+        drop(state);
+        pages.sort_by(|a, b| (b.1, b.2, a.0).cmp(&(a.1, a.2, b.0)));
+        pages.into_iter().take(top_n).map(|(page_id, pins, _)| (page_id, pins)).collect()
+    }
+
+    // Resident pages with changes that haven't been written back yet, in page id order
+    // a page somebody holds a write guard on counts as dirty, it will be once the guard drops
+    pub fn dirty_page_ids(&self) -> Vec<PageId> {
+        let state = self.state.lock().unwrap();
+        let mut dirty: Vec<PageId> = state
+            .frames
+            .iter()
+            .enumerate()
+            .filter_map(|(frame_id, meta)| {
+                let page_id = meta.page_id?;
+                let dirty = meta.is_dirty || self.frames[frame_id].try_read().map_or(true, |page| page.is_dirty());
+                dirty.then_some(page_id)
+            })
+            .collect();
+        dirty.sort_unstable();
+        dirty
+    }
+
+    // Pages whose copy in the data file has an LSN newer than since, in page id order. Reads the file directly,
+    // so changes still sitting in the pool don't show up, dirty_page_ids covers those. Page LSNs are only
+    // stamped with a write ahead log, without one every page says 0 and nothing is ever newer.
+    // A page that can't be read goes in unreadable and the scan carries on, only not knowing how many pages the
+    // file has fails the whole thing
+    pub fn pages_changed_since(&self, since: Lsn) -> io::Result<ChangedPages> {
+        let mut pages = ChangedPages::default();
+        let mut data = [0; PAGE_SIZE];
+        for page_id in 0..self.disk_manager.num_pages()? {
+            let page = self.disk_manager.read_page(page_id, &mut data).and_then(|()| Page::try_from_bytes(data));
+            match page {
+                Ok(page) if page.get_lsn() > since => pages.changed.push(page_id),
+                Ok(_) => {}
+                Err(e) => pages.unreadable.push((page_id, e)),
+            }
+        }
+        Ok(pages)
+    }
+
+    // The superblock (page 0) of a file this pool created, see catalog/superblock.rs
+    // fails with InvalidData on files from before there was one
+    pub fn superblock(&self) -> Result<Superblock<PageFrameRef<'_>>, BufferError> {
+        Superblock::new(self.fetch_page(SUPERBLOCK_PAGE)?).ok_or_else(no_superblock)
+    }
+
+    // same as superblock, for changing the roots in it
+    pub fn superblock_mut(&self) -> Result<Superblock<WritePageGuard<'_>>, BufferError> {
+        Superblock::new(self.fetch_page_write(SUPERBLOCK_PAGE)?).ok_or_else(no_superblock)
+    }
+
+    // latest version of page_id without loading it into the pool: the resident copy if there is one, else the file's
+    fn read_around_pool(&self, page_id: PageId) -> Result<Page, BufferError> {
+        if self.state.lock().unwrap().page_mapping.contains_key(&page_id) {
+            return Ok(Page::from_bytes(self.read_page_copy(page_id)?)?);
+        }
+        let mut data = [0; PAGE_SIZE];
+        self.disk_manager.read_page(page_id, &mut data)?;
+        Ok(Page::try_from_bytes(data)?)
+    }
+
+    // page_id's bytes for copying it somewhere else: the resident copy if there is one, else the file's as
+    // stored, which also works for free pages and comes back zeroed for holes
+    pub(crate) fn raw_page_copy(&self, page_id: PageId) -> Result<[u8; PAGE_SIZE], BufferError> {
+        if self.state.lock().unwrap().page_mapping.contains_key(&page_id) {
+            return self.read_page_copy(page_id);
+        }
+        let mut data = [0; PAGE_SIZE];
+        self.disk_manager.read_page(page_id, &mut data)?;
+        Ok(data)
+    }
+
+    // copies next_page_id and the free list head into the superblock, so pages that were allocated but never
+    // written and pages that were freed aren't forgotten. Skipped when the superblock can't be had, the file
+    // length still covers the first and the second only leaks pages until the next save
+    pub(crate) fn save_superblock(&self) {
+        if self.read_only {
+            return;
+        }
+        let (next_page_id, free_head) = {
+            let state = self.state.lock().unwrap();
+            (state.next_page_id, state.free_pages.last().copied())
+        };
+        if let Ok(mut sb) = self.superblock_mut() {
+            if sb.next_page_id() != next_page_id {
+                sb.set_next_page_id(next_page_id);
+            }
+            if sb.free_list_head() != free_head {
+                sb.set_free_list_head(free_head);
+            }
+        }
+    }
+
+    // flush_all followed by an fsync of the data file, once this returns every change made before the call
+    // survives a crash even without the log
+    pub fn flush_all_and_sync(&self) -> io::Result<()> {
+        self.flush_all()?;
+        self.sync()
+    }
+
+    // fsyncs the data file, pages that were written back but never synced could still be lost in a crash
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.disk_manager.sync()?;
+        self.stats.record_sync();
+        Ok(())
+    }
+
+    pub fn sync_mode(&self) -> SyncMode {
+        self.sync_mode
+    }
+
+    // Sequential scan over every page of page_type, in page id order
+    // only one page is pinned at a time and the pool lock is only taken per page, so other threads keep going
+    // pages allocated during the scan show up if the scan hasn't passed them yet
+    pub fn iter_pages_of_type(&self, page_type: PageType) -> PageTypeIter<'_> {
+        PageTypeIter { bpm: self, page_type, next: 0 }
+    }
+
+    // The free space map every store keeping page_type pages shares, so pages from before a restart get filled up
+    // again. The one the last flush_all saved if opening found it still current, otherwise built from the pages
+    // themselves the first time it's asked for. Don't ask while holding a latch on a page_type page, building it
+    // reads them all
+    pub fn free_space_map(&self, page_type: PageType) -> Arc<FreeSpaceMap> {
+        if let Some(map) = self.free_space_maps.lock().unwrap().get(&page_type) {
+            return Arc::clone(map);
+        }
+        // scanned without the lock, if someone else got there first theirs is the one that counts
+        let map = Arc::new(FreeSpaceMap::of_pages(self, page_type));
+        Arc::clone(self.free_space_maps.lock().unwrap().entry(page_type).or_insert(map))
+    }
+
+    // COUNT(*) over every page of page_type: sums up the item counts in the headers instead of decoding records,
+    // so it's one page read per page. Stops early (undercounting) on the same errors iter_pages_of_type does
+    pub fn count_items_of_type(&self, page_type: PageType) -> u64 {
+        self.iter_pages_of_type(page_type).map(|page| page.get_item_count() as u64).sum()
+    }
+
+    // one past the highest page id handed out so far
+    pub(crate) fn page_limit(&self) -> PageId {
+        self.state.lock().unwrap().next_page_id
+    }
+
+    // Flushes everything and shuts the pool down, same as dropping it except you get to see the error
+    pub fn close(mut self) -> io::Result<()> {
+        self.closed = true;
+        self.flush_all_and_sync()
+    }
+
+    /*
+    * Cross checks the pool's bookkeeping, for tests and debugging after tricky sequences. Err names the first
+    * thing that's off. Checked:
+    * - every page_mapping entry points at a real frame whose metadata says it holds that page
+    * - every frame whose metadata holds a page is the one page_mapping has for it, so no page sits in two frames
+    * - free list frames are unmapped, unpinned and only listed once
+    * - no mapped page is past next_page_id or on the free page list
+    * - latched frames that aren't busy hold the page their metadata says (busy ones are skipped, this never waits)
+    * - the replacer considers exactly the unpinned frames outside the free list evictable
+    * - frames retired by resize aren't on the free list
+    * Pin counts are unsigned so they can't go negative, FrameMeta::unpin refuses to take them below 0.
+    * Takes the state lock, so don't call it while holding a guard that's being loaded
+    */
+    #[cfg(debug_assertions)]
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let state = self.state.lock().unwrap();
+
+        for (&page_id, &frame_id) in &state.page_mapping {
+            let Some(meta) = state.frames.get(frame_id) else {
+                return Err(format!("page {} is mapped to frame {} which doesn't exist", page_id, frame_id));
+            };
+            if meta.page_id != Some(page_id) {
+                return Err(format!("page {} is mapped to frame {} but that frame holds {:?}", page_id, frame_id, meta.page_id));
+            }
+            if page_id >= state.next_page_id {
+                return Err(format!("page {} is mapped but next_page_id is {}", page_id, state.next_page_id));
+            }
+            if state.free_pages.contains(&page_id) {
+                return Err(format!("page {} is mapped but also on the free page list", page_id));
+            }
+        }
+
+        for (frame_id, meta) in state.frames.iter().enumerate() {
+            if let Some(page_id) = meta.page_id
+                && state.page_mapping.get(&page_id) != Some(&frame_id)
+            {
+                return Err(format!("frame {} holds page {} but page_mapping doesn't point at it", frame_id, page_id));
+            }
+            if let Ok(page) = self.frames[frame_id].try_read()
+                && meta.page_id.is_some()
+                && page.page_id != meta.page_id
+            {
+                return Err(format!("frame {} should hold page {:?} but holds {:?}", frame_id, meta.page_id, page.page_id));
+            }
+        }
+
+        let mut seen = vec![false; state.frames.len()];
+        for &frame_id in &state.free_list {
+            let Some(meta) = state.frames.get(frame_id) else {
+                return Err(format!("free list has frame {} which doesn't exist", frame_id));
+            };
+            if std::mem::replace(&mut seen[frame_id], true) {
+                return Err(format!("frame {} is on the free list twice", frame_id));
+            }
+            if frame_id >= state.pool_size {
+                return Err(format!("frame {} is on the free list but was retired, pool_size is {}", frame_id, state.pool_size));
+            }
+            if meta.page_id.is_some() || meta.is_pinned() {
+                return Err(format!("frame {} is on the free list but holds {:?} with {} pins", frame_id, meta.page_id, meta.pin_count()));
+            }
+        }
+
+        for (frame_id, meta) in state.frames.iter().enumerate().take(state.pool_size) {
+            if seen[frame_id] {
+                continue;
+            }
+            let expected = !meta.is_pinned();
+            if state.replacer.is_evictable(frame_id, &state.frames) != expected {
+                return Err(format!(
+                    "frame {} has {} pins but the replacer thinks it {} be evicted",
+                    frame_id,
+                    meta.pin_count(),
+                    if expected { "can't" } else { "can" }
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Panics listing every page that still has pins, for the end of a test once all guards should be gone
+    // catches guards that were mem::forget'd and unpin_page calls that don't balance out
+    #[cfg(debug_assertions)]
+    pub fn assert_all_unpinned(&self) {
+        // collect first so the state lock isn't poisoned by the panic
+        let mut pinned: Vec<(PageId, u32)> = self
+            .state
+            .lock()
+            .unwrap()
+            .frames
+            .iter()
+            .filter(|meta| meta.is_pinned())
+            .filter_map(|meta| Some((meta.page_id?, meta.pin_count())))
+            .collect();
+        if pinned.is_empty() {
+            return;
+        }
+        pinned.sort_unstable();
+        let list: Vec<String> = pinned.iter().map(|(page_id, pins)| format!("{} ({} pins)", page_id, pins)).collect();
+        panic!("pages still pinned: {}", list.join(", "));
+    }
+
+    // Helper to find a free frame or evict one
+    // if we have to evict, the victim gets written back (if dirty) and unmapped, so the frame is ready to be reused
+    fn find_free_frame(&self, state: &mut BufferPoolState) -> Result<FrameId, BufferError> {
+        // Try free list first, refilling it from the replacer when it's empty
+        if state.free_list.is_empty() {
+            self.evict_batch(state, self.eviction_batch)?;
+        }
+        Ok(state.free_list.pop_front().expect("evict_batch freed at least one frame"))
+    }
+
+    /*
+    * Evicts up to n frames in one pass over the replacer and puts them on the free list, writing back the dirty
+    * ones first. Stops early once the replacer has nothing left to give, so it only ever takes unpinned frames
+    * and never more than are evictable. Fails with PoolExhausted if it couldn't free a single frame, or with the
+    * write back error if the first victim couldn't be written (a later one just ends the batch, it stays put).
+    * Victims stay pinned until the batch is done so the replacer can't hand the same frame out twice.
+    */
+    fn evict_batch(&self, state: &mut BufferPoolState, n: usize) -> Result<usize, BufferError> {
+        self.stats.record_replacer_pass();
+        let pool_size = state.pool_size;
+        let mut evicted = Vec::with_capacity(n);
+        let mut failed = None;
+        while evicted.len() < n {
+            let Some(frame_id) = state.replacer.victim(&mut state.frames[..pool_size]) else { break };
+            state.frames[frame_id].pin();
+
+            // the victim is unpinned so its latch is free
+            let mut page = self.frames[frame_id].write().unwrap();
+            if let Some(old_pid) = state.frames[frame_id].page_id {
+                let dirty = !self.read_only && (state.frames[frame_id].is_dirty || page.is_dirty());
+                if dirty && let Err(e) = self.write_back(old_pid, &page) {
+                    // the victim is still valid in RAM, so just leave it there and stop
+                    let _ = state.frames[frame_id].unpin();
+                    state.replacer.set_evictable(frame_id, true);
+                    failed = Some(e);
+                    break;
+                }
+                state.page_mapping.remove(&old_pid);
+                self.stats.record_eviction();
+                self.note_eviction(old_pid);
+            }
+            page.page_id = None;
+            page.set_dirty(false);
+            evicted.push(frame_id);
+        }
+
+        for &frame_id in &evicted {
+            state.frames[frame_id] = FrameMeta::default();
+            state.free_list.push_back(frame_id);
+        }
+        match failed {
+            Some(e) if evicted.is_empty() => Err(e.into()),
+            _ if evicted.is_empty() => {
+                self.stats.record_pool_exhausted();
+                Err(BufferError::PoolExhausted)
+            }
+            _ => Ok(evicted.len()),
+        }
+    }
+
+    // Drops a pin taken by hand, Err instead of a panic if the page isn't resident or has no pins to drop
+    // (a double unpin), the pool's bookkeeping is left alone in that case
+    pub fn unpin_page(&self, page_id: PageId, is_dirty: bool) -> Result<(), PinError> {
+        let mut state = self.state.lock().unwrap();
+        let frame_id = *state.page_mapping.get(&page_id).ok_or(PinError::NotResident(page_id))?;
+        let released = self.release_pin(&mut state, frame_id, is_dirty);
+        drop(state);
+        self.run_eviction_hook();
+        released
+    }
+
+    // Called by the PageGuard when it drops
+    // goes by frame rather than page id since a frame whose load failed isn't in page_mapping anymore
+    // only for pins the pool handed out itself, those always have a pin to drop so there's no error to report
+    fn unpin_frame(&self, frame_id: FrameId, is_dirty: bool) {
+        let mut state = self.state.lock().unwrap(); // heard unwrap caused cloudflare outage, might not be so safe
+        let _ = self.release_pin(&mut state, frame_id, is_dirty);
+        drop(state);
+        self.run_eviction_hook();
+    }
+
+    fn release_pin(&self, state: &mut BufferPoolState, frame_id: FrameId, is_dirty: bool) -> Result<(), PinError> {
+        let frame = &mut state.frames[frame_id];
+        let left = frame.unpin()?;
+        if is_dirty {
+            frame.is_dirty = true;
+        }
+        if left == 0 {
+            if frame_id < state.pool_size {
+                state.replacer.set_evictable(frame_id, true);
+            } else {
+                // shrunk away while pinned, now it can finally go
+                self.retire_frame(state, frame_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/*
+* Iterator returned by iter_pages_of_type, yields a pinned guard per page and unpins it when the caller moves on
+* (or drops it, the caller can also hold on to it).
+* Stops early if a page can't be brought in (pool exhausted or a disk error), deallocated pages are skipped.
+*/
+pub struct PageTypeIter<'a> {
+    bpm: &'a BufferPoolManager,
+    page_type: PageType,
+    next: PageId,
+}
+
+impl<'a> Iterator for PageTypeIter<'a> {
+    type Item = PageFrameRef<'a>;
+
+    fn next(&mut self) -> Option<PageFrameRef<'a>> {
+        while self.next < self.bpm.page_limit() {
+            let page_id = self.next;
+            self.next += 1;
+            match self.bpm.fetch_page(page_id) {
+                // header page id doesn't match on pages that were never written (holes in the file)
+                Ok(page) if page.get_header().page_id == page_id && page.page_type() == Ok(self.page_type) => {
+                    return Some(page);
+                }
+                Ok(_) | Err(BufferError::PageNotFound(_)) => continue,
+                Err(_) => return None,
+            }
+        }
+        None
+    }
+}
+
+// the data file (or segments) config describes, opened the way with_config needs it
+pub(crate) fn open_disk_manager(config: &BufferPoolConfig) -> io::Result<FileDiskManager> {
+    match (&config.segments, config.read_only) {
+        (Some(segments), read_only) => {
+            let segments = if read_only {
+                SegmentManager::open_read_only(&segments.dir, segments.segment_size)?
+            } else {
+                SegmentManager::open(&segments.dir, segments.segment_size)?
+            };
+            Ok(FileDiskManager::with_segments(segments, config.checksum_mode))
+        }
+        (None, true) => FileDiskManager::open_read_only(&config.data_file, config.checksum_mode),
+        (None, false) => FileDiskManager::with_checksum_mode(&config.data_file, config.checksum_mode),
+    }
+}
+
+// Writes the superblock if the file is brand new, returns the first page id that's free to allocate
+// page 0 is reserved either way, old files without a superblock just never get it handed out
+fn open_superblock(disk_manager: &dyn DiskManager) -> io::Result<PageId> {
+    let num_pages = disk_manager.num_pages()?;
+    if num_pages == 0 {
+        if disk_manager.is_read_only() {
+            return Ok(0);
+        }
+        let mut page = Page::new(SUPERBLOCK_PAGE, PageType::Superblock);
+        Superblock::init(&mut page, SUPERBLOCK_PAGE + 1);
+        disk_manager.write_page(SUPERBLOCK_PAGE, page.get_data())?;
+        return Ok(SUPERBLOCK_PAGE + 1);
+    }
+
+    let mut data = [0; PAGE_SIZE];
+    disk_manager.read_page(SUPERBLOCK_PAGE, &mut data)?;
+    let page = Page::try_from_bytes(data)?;
+    let recorded = Superblock::new(&page).map_or(0, |sb| sb.next_page_id());
+    Ok(num_pages.max(recorded))
+}
+
+fn no_superblock() -> BufferError {
+    BufferError::Io(io::Error::new(io::ErrorKind::InvalidData, "page 0 is not a superblock"))
+}
+
+// dirty pages would be lost otherwise, the file handle closes right after this
+impl Drop for BufferPoolManager {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Err(e) = self.flush_all_and_sync() {
+            match &self.on_drop_flush_error {
+                FlushErrorPolicy::Ignore => {}
+                FlushErrorPolicy::Report(errors) => {
+                    let _ = errors.send(e.into());
+                }
+                // don't panic while already unwinding, that would abort
+                FlushErrorPolicy::Panic if !std::thread::panicking() => {
+                    panic!("failed to flush buffer pool on drop: {e}");
+                }
+                FlushErrorPolicy::Panic => {}
+            }
+        }
+    }
+}
+
+// somebody else was loading the page and their read failed, they got the real io error
+fn load_failed(page_id: PageId) -> BufferError {
+    BufferError::Io(io::Error::other(format!("loading page {} failed", page_id)))
+}
+
+// a Delta run costs its offset and length on top of the bytes, runs closer together than that are cheaper merged
+const DELTA_RUN_OVERHEAD: usize = 8;
+// past this many changed bytes a guard logs the whole page, see LogGranularity
+const MAX_DELTA_BYTES: usize = PAGE_SIZE / 4;
+
+// the spans where before and after differ, with gaps of up to DELTA_RUN_OVERHEAD unchanged bytes folded in
+fn changed_runs(before: &[u8; PAGE_SIZE], after: &[u8; PAGE_SIZE]) -> Vec<std::ops::Range<usize>> {
+    let mut runs: Vec<std::ops::Range<usize>> = Vec::new();
+    let mut i = 0;
+    while i < PAGE_SIZE {
+        if before[i] == after[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < PAGE_SIZE && before[i] != after[i] {
+            i += 1;
+        }
+        match runs.last_mut() {
+            Some(last) if start - last.end <= DELTA_RUN_OVERHEAD => last.end = i,
+            _ => runs.push(start..i),
+        }
+    }
+    runs
+}
+
+// lowest first id of n consecutive page ids that are all on the free list
+fn free_run(free_pages: &[PageId], n: usize) -> Option<PageId> {
+    let mut sorted = free_pages.to_vec();
+    sorted.sort_unstable();
+    sorted.windows(n).find(|w| w[n - 1] - w[0] == n as PageId - 1).map(|w| w[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::page::{stamp_checksum, PageHeader, PAGE_FORMAT_VERSION};
+    use crate::wal::log_manager::LogRecord;
+
+    // a pool over a mock disk the test keeps a handle to
+    fn pool(size: usize) -> (Arc<MockDiskManager>, BufferPoolManager) {
+        let disk = Arc::new(MockDiskManager::new());
+        let bpm = BufferPoolManager::new(size, disk.clone()).unwrap();
+        (disk, bpm)
+    }
+
+    #[test]
+    fn allocate_page_hands_out_new_ids_after_the_superblock() {
+        let (_, bpm) = pool(4);
+        let first = bpm.allocate_page().unwrap().page_id;
+        let second = bpm.allocate_page().unwrap().page_id;
+        assert_eq!(first, SUPERBLOCK_PAGE + 1);
+        assert_eq!(second, first + 1);
+    }
+
+    #[test]
+    fn allocated_pages_reach_disk_without_being_written() {
+        let (disk, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.flush_all().unwrap();
+
+        let page = Page::from_bytes(disk.raw_page(page_id).unwrap()).unwrap();
+        assert_eq!(page.page_id, Some(page_id));
+        assert_eq!(page.page_type(), Ok(PageType::NodeStore));
+    }
+    #[test]
+    fn write_guard_marks_the_page_dirty() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.flush_all().unwrap();
+        assert!(bpm.dirty_page_ids().is_empty());
+
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 42).unwrap();
+        assert_eq!(bpm.dirty_page_ids(), vec![page_id]);
+    }
+
+    #[test]
+    fn changes_through_a_write_guard_are_written_back_on_eviction() {
+        let (disk, bpm) = pool(1);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.flush_all().unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 42).unwrap();
+
+        // the only frame goes to the new page, page_id has to be written out to make room
+        drop(bpm.allocate_page().unwrap());
+        let on_disk = Page::from_bytes(disk.raw_page(page_id).unwrap()).unwrap();
+        assert_eq!(on_disk.read_u32_at(HEADER_SIZE as u32), Ok(42));
+    }
+
+    #[test]
+    fn pool_size_comes_from_the_config() {
+        let temp = crate::test_util::TempPath::new("pool_size");
+        let bpm = BufferPoolManager::with_config(BufferPoolConfig { pool_size: 3, ..BufferPoolConfig::new(temp.path()) })
+            .unwrap();
+        assert_eq!(bpm.pool_size(), 3);
+
+        let pinned: Vec<_> = (0..3).map(|_| bpm.allocate_page().unwrap()).collect();
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::PoolExhausted)));
+        drop(pinned);
+    }
+
+    #[test]
+    fn a_pool_needs_at_least_one_frame() {
+        let err = BufferPoolManager::new(0, MockDiskManager::new()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn readers_never_see_a_half_done_write() {
+        let (_, bpm) = pool(4);
+        let bpm = Arc::new(bpm);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let (a, b) = (HEADER_SIZE as u32, PAGE_SIZE as u32 - 8);
+
+        let writer = {
+            let bpm = bpm.clone();
+            std::thread::spawn(move || {
+                for i in 0..2000u64 {
+                    let mut page = bpm.fetch_page_write(page_id).unwrap();
+                    page.write_u64_at(a, i).unwrap();
+                    page.write_u64_at(b, i).unwrap();
+                }
+            })
+        };
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let bpm = bpm.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..2000 {
+                        let page = bpm.fetch_page(page_id).unwrap();
+                        assert_eq!(page.read_u64_at(a), page.read_u64_at(b));
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        readers.into_iter().for_each(|r| r.join().unwrap());
+    }
+
+    #[test]
+    fn readers_of_one_page_share_its_latch() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let first = bpm.fetch_page(page_id).unwrap();
+        let second = bpm.fetch_page(page_id).unwrap();
+        assert_eq!(first.frame_index, second.frame_index);
+    }
+
+    #[test]
+    fn a_latched_page_doesnt_hold_up_other_pages() {
+        let (_, bpm) = pool(4);
+        let bpm = Arc::new(bpm);
+        let a = bpm.allocate_page().unwrap().page_id;
+        let b = bpm.allocate_page().unwrap().page_id;
+
+        let _held = bpm.fetch_page_write(a).unwrap();
+        let (done, finished) = std::sync::mpsc::channel();
+        let other = bpm.clone();
+        std::thread::spawn(move || {
+            other.fetch_page_write(b).unwrap().write_u32_at(HEADER_SIZE as u32, 1).unwrap();
+            done.send(()).unwrap();
+        });
+        finished.recv_timeout(Duration::from_secs(5)).expect("writing b waited on a's latch");
+    }
+
+    #[test]
+    fn flush_page_writes_only_that_page_and_only_once() {
+        let (disk, bpm) = pool(4);
+        let a = bpm.allocate_page().unwrap().page_id;
+        let b = bpm.allocate_page().unwrap().page_id;
+        let writes = disk.writes();
+
+        bpm.flush_page(a).unwrap();
+        assert_eq!(disk.writes(), writes + 1);
+        assert!(disk.raw_page(a).is_some());
+        assert!(disk.raw_page(b).is_none());
+        assert_eq!(bpm.dirty_page_ids(), vec![b]);
+
+        // clean now, nothing left to write
+        bpm.flush_page(a).unwrap();
+        assert_eq!(disk.writes(), writes + 1);
+    }
+
+    #[test]
+    fn flush_all_writes_every_dirty_page() {
+        let (disk, bpm) = pool(4);
+        let ids: Vec<PageId> = (0..3).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.flush_all().unwrap();
+        assert!(ids.iter().all(|&id| disk.raw_page(id).is_some()));
+        assert!(bpm.dirty_page_ids().is_empty());
+    }
+
+    #[test]
+    fn a_failed_flush_leaves_the_page_dirty() {
+        let (disk, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        disk.fail_writes(true);
+        assert!(bpm.flush_page(page_id).is_err());
+        assert_eq!(bpm.dirty_page_ids(), vec![page_id]);
+
+        disk.fail_writes(false);
+        bpm.flush_page(page_id).unwrap();
+        assert!(bpm.dirty_page_ids().is_empty());
+    }
+
+    #[test]
+    fn dropping_the_pool_flushes_and_syncs() {
+        let (disk, bpm) = pool(4);
+        let page_id = {
+            let mut page = bpm.allocate_page_of_type(PageType::Overflow).unwrap();
+            page.write_u32_at(HEADER_SIZE as u32, 7).unwrap();
+            page.page_id
+        };
+        let syncs = disk.syncs();
+        drop(bpm);
+
+        let on_disk = Page::from_bytes(disk.raw_page(page_id).unwrap()).unwrap();
+        assert_eq!(on_disk.read_u32_at(HEADER_SIZE as u32), Ok(7));
+        assert!(disk.syncs() > syncs);
+    }
+
+    #[test]
+    fn close_reports_what_drop_would_swallow() {
+        let (disk, bpm) = pool(4);
+        drop(bpm.allocate_page().unwrap());
+        disk.fail_writes(true);
+        assert!(bpm.close().is_err());
+    }
+
+    #[test]
+    fn fetching_with_every_frame_pinned_fails_instead_of_evicting() {
+        let (_, bpm) = pool(2);
+        let outside = bpm.allocate_page().unwrap().page_id;
+        let a = bpm.allocate_page().unwrap();
+        let b = bpm.allocate_page().unwrap();
+
+        assert!(matches!(bpm.fetch_page(outside), Err(BufferError::PoolExhausted)));
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::PoolExhausted)));
+        assert_eq!(bpm.stats().pool_exhausted_events, 2);
+        // resident pages don't need a frame
+        assert!(bpm.fetch_page(a.page_id).is_ok());
+
+        drop(b);
+        assert!(bpm.fetch_page(outside).is_ok());
+        drop(a);
+    }
+
+    #[test]
+    fn iter_pages_of_type_finds_only_that_type_in_id_order() {
+        let (_, bpm) = pool(2);
+        let mut dictionaries = Vec::new();
+        for i in 0..6 {
+            let page_type = if i % 2 == 0 { PageType::Dictionary } else { PageType::Overflow };
+            let page_id = bpm.allocate_page_of_type(page_type).unwrap().page_id;
+            if page_type == PageType::Dictionary {
+                dictionaries.push(page_id);
+            }
+        }
+
+        // more pages than frames, the scan has to go through eviction
+        let found: Vec<PageId> = bpm.iter_pages_of_type(PageType::Dictionary).map(|page| page.page_id).collect();
+        assert_eq!(found, dictionaries);
+        assert_eq!(bpm.iter_pages_of_type(PageType::HashBucket).count(), 0);
+    }
+
+    #[test]
+    fn iter_pages_of_type_skips_holes() {
+        let (_, bpm) = pool(4);
+        let before = bpm.reserve_page_id().unwrap();
+        let page_id = bpm.allocate_page_of_type(PageType::Dictionary).unwrap().page_id;
+        assert!(page_id > before);
+        let found: Vec<PageId> = bpm.iter_pages_of_type(PageType::Dictionary).map(|page| page.page_id).collect();
+        assert_eq!(found, vec![page_id]);
+    }
+
+    #[test]
+    fn stats_count_hits_misses_and_evictions() {
+        let (_, bpm) = pool(1);
+        let a = bpm.allocate_page().unwrap().page_id;
+        let b = bpm.allocate_page().unwrap().page_id;
+        let start = bpm.stats();
+        assert_eq!((start.evictions, start.dirty_flushes), (1, 1));
+
+        drop(bpm.fetch_page(b).unwrap());
+        drop(bpm.fetch_page(a).unwrap());
+        drop(bpm.fetch_page(a).unwrap());
+        let stats = bpm.stats();
+        assert_eq!(stats.hits - start.hits, 2);
+        assert_eq!(stats.misses - start.misses, 1);
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.dirty_flushes, 2);
+        assert!(stats.hit_ratio() > 0.0 && stats.hit_ratio() < 1.0);
+    }
+
+    #[test]
+    fn hit_ratio_of_an_unused_pool_is_zero() {
+        let (_, bpm) = pool(2);
+        assert_eq!(bpm.stats().hit_ratio(), 0.0);
+    }
+
+    // page ids of n pages that are on disk but not in the pool
+    fn pages_on_disk(bpm: &BufferPoolManager, n: usize) -> Vec<PageId> {
+        let ids = (0..n).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.flush_all().unwrap();
+        let cached: Vec<PageId> = bpm.state.lock().unwrap().page_mapping.keys().copied().collect();
+        for page_id in cached {
+            assert!(bpm.discard_frame(&mut bpm.state.lock().unwrap(), page_id));
+        }
+        ids
+    }
+
+    #[test]
+    fn prefetched_pages_are_hits_and_stay_unpinned() {
+        let (_, bpm) = pool(4);
+        let ids = pages_on_disk(&bpm, 3);
+        let before = bpm.stats().misses;
+        bpm.prefetch(&ids);
+        let misses = bpm.stats().misses;
+        assert_eq!(misses - before, 3);
+        assert!(bpm.frame_table().iter().all(|f| f.pin_count == 0));
+
+        for &page_id in &ids {
+            drop(bpm.fetch_page(page_id).unwrap());
+        }
+        assert_eq!(bpm.stats().misses, misses);
+    }
+
+    #[test]
+    fn prefetch_never_loads_more_than_the_pool_holds() {
+        let (_, bpm) = pool(2);
+        let ids = pages_on_disk(&bpm, 5);
+        let before = bpm.stats();
+        bpm.prefetch(&ids);
+        let after = bpm.stats();
+        assert_eq!(after.misses - before.misses, 2);
+        assert_eq!(after.evictions, before.evictions);
+    }
+
+    #[test]
+    fn every_page_type_survives_a_flush_and_reload() {
+        let types = [
+            PageType::NodeStore,
+            PageType::Relationship,
+            PageType::PropertyStore,
+            PageType::BTreeNode,
+            PageType::HashBucket,
+            PageType::Overflow,
+            PageType::Dictionary,
+        ];
+        let (disk, bpm) = pool(4);
+        let ids: Vec<PageId> = types.iter().map(|&t| bpm.allocate_page_of_type(t).unwrap().page_id).collect();
+        bpm.close().unwrap();
+
+        let bpm = BufferPoolManager::new(4, disk).unwrap();
+        for (&page_id, &page_type) in ids.iter().zip(&types) {
+            let page = bpm.fetch_page(page_id).unwrap();
+            assert_eq!(page.get_header().page_type(), Ok(page_type));
+            assert_eq!(SlottedPage::new(&*page).is_formatted(), page_type.is_slotted());
+        }
+    }
+
+    #[test]
+    fn allocate_page_hands_out_a_formatted_node_store_page() {
+        let (_, bpm) = pool(4);
+        let page = bpm.allocate_page().unwrap();
+        assert_eq!(page.get_header().page_type(), Ok(PageType::NodeStore));
+        let slotted = SlottedPage::new(&*page);
+        assert_eq!(slotted.slot_count(), 0);
+        assert!(slotted.available_space() > 0);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn invariants_hold_through_eviction_deallocation_and_resize() {
+        let (_, bpm) = pool(3);
+        let ids: Vec<PageId> = (0..8).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.check_invariants().unwrap();
+        let held = bpm.fetch_page(ids[0]).unwrap();
+        bpm.deallocate_page(ids[3]).unwrap();
+        bpm.check_invariants().unwrap();
+        bpm.resize(5).unwrap();
+        for &page_id in &ids[4..] {
+            drop(bpm.fetch_page_write(page_id).unwrap());
+        }
+        bpm.check_invariants().unwrap();
+        bpm.resize(2).unwrap();
+        bpm.check_invariants().unwrap();
+        drop(held);
+        bpm.flush_all().unwrap();
+        bpm.check_invariants().unwrap();
+        bpm.assert_all_unpinned();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn invariants_catch_a_mapping_without_its_frame() {
+        let (_, bpm) = pool(3);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.state.lock().unwrap().page_mapping.insert(page_id + 100, 2);
+        let err = bpm.check_invariants().unwrap_err();
+        assert!(err.contains(&format!("page {}", page_id + 100)), "{}", err);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn invariants_catch_a_mapped_frame_on_the_free_list() {
+        let (_, bpm) = pool(3);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let mut state = bpm.state.lock().unwrap();
+        let frame_id = state.page_mapping[&page_id];
+        state.free_list.push_back(frame_id);
+        drop(state);
+        let err = bpm.check_invariants().unwrap_err();
+        assert!(err.contains("free list"), "{}", err);
+    }
+
+    fn pins_of(bpm: &BufferPoolManager, page_id: PageId) -> u32 {
+        bpm.frame_table().iter().find(|f| f.page_id == Some(page_id)).map_or(0, |f| f.pin_count)
+    }
+
+    #[test]
+    fn fetch_pages_lines_results_up_with_a_mixed_batch() {
+        let (_, bpm) = pool(4);
+        let on_disk = pages_on_disk(&bpm, 2);
+        let resident = bpm.allocate_page().unwrap().page_id;
+        let batch = [on_disk[0], resident, on_disk[1], resident];
+
+        let pages = bpm.fetch_pages(&batch);
+        let got: Vec<PageId> = pages.iter().map(|page| page.as_ref().unwrap().page_id).collect();
+        assert_eq!(got, batch);
+        assert_eq!(pins_of(&bpm, resident), 2);
+        assert_eq!(pins_of(&bpm, on_disk[0]), 1);
+        assert_eq!(pins_of(&bpm, on_disk[1]), 1);
+
+        drop(pages);
+        assert!(batch.iter().all(|&page_id| pins_of(&bpm, page_id) == 0));
+    }
+
+    #[test]
+    fn fetch_pages_past_the_pool_size_runs_out_of_frames() {
+        let (_, bpm) = pool(2);
+        let ids = pages_on_disk(&bpm, 3);
+        let pages = bpm.fetch_pages(&ids);
+        assert!(pages[0].is_ok() && pages[1].is_ok());
+        assert!(matches!(pages[2], Err(BufferError::PoolExhausted)));
+    }
+
+    #[test]
+    fn a_read_only_pool_reads_but_refuses_changes() {
+        let temp = crate::test_util::TempPath::new("bpm_read_only");
+        let config = BufferPoolConfig { pool_size: 2, ..BufferPoolConfig::new(temp.path()) };
+        let bpm = BufferPoolManager::with_config(config.clone()).unwrap();
+        let ids: Vec<PageId> = (0..4)
+            .map(|i| {
+                let mut page = bpm.allocate_page_of_type(PageType::Overflow).unwrap();
+                page.write_u32_at(HEADER_SIZE as u32, i).unwrap();
+                page.page_id
+            })
+            .collect();
+        bpm.close().unwrap();
+        let on_disk = std::fs::read(temp.path()).unwrap();
+
+        let bpm = BufferPoolManager::with_config(BufferPoolConfig { read_only: true, ..config }).unwrap();
+        assert!(bpm.is_read_only());
+        // more pages than frames, so reading them all evicts
+        for (i, &page_id) in ids.iter().enumerate() {
+            assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(i as u32));
+        }
+        assert!(matches!(bpm.fetch_page_write(ids[0]), Err(BufferError::ReadOnly)));
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::ReadOnly)));
+        assert!(matches!(bpm.deallocate_page(ids[1]), Ok(false)));
+        bpm.close().unwrap();
+        assert_eq!(std::fs::read(temp.path()).unwrap(), on_disk);
+    }
+
+    #[test]
+    fn read_only_and_a_log_file_dont_go_together() {
+        let temp = crate::test_util::TempPath::new("bpm_read_only_log");
+        let config = BufferPoolConfig {
+            read_only: true,
+            log_file: Some(temp.with_extension("log")),
+            ..BufferPoolConfig::new(temp.path())
+        };
+        let err = BufferPoolManager::with_config(config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn read_page_copy_matches_the_page_and_leaves_it_unpinned() {
+        let (_, bpm) = pool(4);
+        let page_id = {
+            let mut page = bpm.allocate_page_of_type(PageType::Overflow).unwrap();
+            assert!(page.write_at(HEADER_SIZE as u32, b"snapshot"));
+            page.page_id
+        };
+        let copy = bpm.read_page_copy(page_id).unwrap();
+        assert_eq!(pins_of(&bpm, page_id), 0);
+        assert_eq!(&copy, bpm.fetch_page(page_id).unwrap().get_data());
+
+        // the copy doesn't follow later changes
+        assert!(bpm.fetch_page_write(page_id).unwrap().write_at(HEADER_SIZE as u32, b"changed!"));
+        assert_eq!(&copy[HEADER_SIZE..HEADER_SIZE + 8], b"snapshot");
+    }
+
+    #[test]
+    fn read_page_copy_of_a_missing_page_is_an_error() {
+        let (_, bpm) = pool(4);
+        assert!(bpm.read_page_copy(1000).is_err());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "pages still pinned: 1 (2 pins)")]
+    fn assert_all_unpinned_names_a_forgotten_guard() {
+        let (_, bpm) = pool(3);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        std::mem::forget(bpm.fetch_page(page_id).unwrap());
+        std::mem::forget(bpm.fetch_page(page_id).unwrap());
+        drop(bpm.allocate_page().unwrap());
+        bpm.assert_all_unpinned();
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn assert_all_unpinned_passes_once_pins_balance_out() {
+        let (_, bpm) = pool(3);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        std::mem::forget(bpm.fetch_page(page_id).unwrap());
+        bpm.unpin_page(page_id, false).unwrap();
+        bpm.assert_all_unpinned();
+    }
+
+    #[test]
+    fn a_double_unpin_is_an_error_not_a_panic() {
+        let (_, bpm) = pool(3);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        std::mem::forget(bpm.fetch_page(page_id).unwrap());
+        assert_eq!(bpm.unpin_page(page_id, true), Ok(()));
+        assert_eq!(bpm.unpin_page(page_id, true), Err(PinError::NotPinned(page_id)));
+        assert_eq!(bpm.unpin_page(page_id + 50, false), Err(PinError::NotResident(page_id + 50)));
+        assert_eq!(pins_of(&bpm, page_id), 0);
+    }
+
+    #[test]
+    fn frame_meta_refuses_to_unpin_below_zero() {
+        let meta = FrameMeta { page_id: Some(4), ..FrameMeta::default() };
+        assert_eq!(meta.pin(), 1);
+        assert_eq!(meta.unpin(), Ok(0));
+        assert_eq!(meta.unpin(), Err(PinError::NotPinned(4)));
+        assert_eq!(meta.pin_count(), 0);
+    }
+
+    #[test]
+    fn concurrent_pins_and_unpins_on_one_frame_balance_out() {
+        let meta = Arc::new(FrameMeta::default());
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let meta = meta.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        meta.pin();
+                        meta.unpin().unwrap();
+                    }
+                    meta.pin();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(meta.pin_count(), 8);
+        assert!(meta.take_ref_bit());
+        assert!(!meta.ref_bit());
+    }
+
+    #[test]
+    fn concurrent_fetches_of_one_page_leave_it_unpinned() {
+        let (_, bpm) = pool(4);
+        let bpm = Arc::new(bpm);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let bpm = bpm.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..2_000 {
+                        drop(bpm.fetch_page(page_id).unwrap());
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(pins_of(&bpm, page_id), 0);
+    }
+
+    #[test]
+    fn a_grown_pool_uses_its_new_frames() {
+        let (_, bpm) = pool(2);
+        let held: Vec<_> = (0..2).map(|_| bpm.allocate_page().unwrap()).collect();
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::PoolExhausted)));
+
+        bpm.resize(5).unwrap();
+        assert_eq!(bpm.pool_size(), 5);
+        let more: Vec<_> = (0..3).map(|_| bpm.allocate_page().unwrap()).collect();
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::PoolExhausted)));
+        drop((held, more));
+    }
+
+    #[test]
+    fn shrinking_writes_back_what_it_evicts() {
+        let (disk, bpm) = pool(4);
+        let ids: Vec<PageId> = (0..4)
+            .map(|i| {
+                let mut page = bpm.allocate_page_of_type(PageType::Overflow).unwrap();
+                page.write_u32_at(HEADER_SIZE as u32, i).unwrap();
+                page.page_id
+            })
+            .collect();
+        bpm.resize(1).unwrap();
+        assert_eq!(bpm.pool_size(), 1);
+        assert!(bpm.frame_table().iter().filter(|f| f.page_id.is_some()).count() <= 1);
+        for (i, &page_id) in ids.iter().enumerate() {
+            assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(i as u32));
+        }
+        drop(bpm);
+        assert!(ids.iter().all(|&page_id| disk.raw_page(page_id).is_some()));
+    }
+
+    #[test]
+    fn shrinking_below_the_pinned_frames_fails_and_changes_nothing() {
+        let (_, bpm) = pool(4);
+        let held: Vec<_> = (0..3).map(|_| bpm.allocate_page().unwrap()).collect();
+        assert!(matches!(bpm.resize(2), Err(BufferError::PoolExhausted)));
+        assert!(matches!(bpm.resize(0), Err(BufferError::Io(_))));
+        assert_eq!(bpm.pool_size(), 4);
+        bpm.resize(3).unwrap();
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::PoolExhausted)));
+        drop(held);
+        drop(bpm.allocate_page().unwrap());
+    }
+
+    fn no_steal_force_pool(temp: &crate::test_util::TempPath, pool_size: usize) -> BufferPoolManager {
+        BufferPoolManager::with_config(BufferPoolConfig {
+            pool_size,
+            recovery_policy: RecoveryPolicy::NoStealForce,
+            log_file: Some(temp.with_extension("log")),
+            ..BufferPoolConfig::new(temp.path())
+        })
+        .unwrap()
+    }
+
+    fn is_resident(bpm: &BufferPoolManager, page_id: PageId) -> bool {
+        bpm.frame_table().iter().any(|f| f.page_id == Some(page_id))
+    }
+
+    #[test]
+    fn no_steal_keeps_uncommitted_pages_out_of_eviction() {
+        let temp = crate::test_util::TempPath::new("bpm_no_steal");
+        let bpm = no_steal_force_pool(&temp, 3);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        bpm.flush_all().unwrap();
+
+        let txn = bpm.begin_txn().unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 7).unwrap();
+        for _ in 0..10 {
+            drop(bpm.allocate_page().unwrap());
+            assert!(is_resident(&bpm, page_id));
+        }
+        let on_disk = || {
+            let at = page_id as usize * PAGE_SIZE + HEADER_SIZE;
+            std::fs::read(temp.path()).unwrap()[at..at + 4].to_vec()
+        };
+        assert_eq!(on_disk(), [0; 4]);
+
+        // force: commit writes the page out
+        txn.commit().unwrap();
+        assert_eq!(on_disk(), 7u32.to_le_bytes());
+        for _ in 0..3 {
+            drop(bpm.allocate_page().unwrap());
+        }
+        assert!(!is_resident(&bpm, page_id));
+    }
+
+    #[test]
+    fn no_steal_runs_out_of_frames_instead_of_stealing() {
+        let temp = crate::test_util::TempPath::new("bpm_no_steal_full");
+        let bpm = no_steal_force_pool(&temp, 2);
+        let ids: Vec<PageId> = (0..3).map(|_| bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id).collect();
+        let txn = bpm.begin_txn().unwrap();
+        drop(txn.fetch_page_write(ids[0]).unwrap());
+        drop(txn.fetch_page_write(ids[1]).unwrap());
+        assert!(matches!(txn.fetch_page_write(ids[2]), Err(BufferError::PoolExhausted)));
+        txn.abort().unwrap();
+        drop(bpm.fetch_page_write(ids[2]).unwrap());
+    }
+
+    // data file and log on separate mocks so their syncs can be told apart
+    fn synced_setup(mode: SyncMode) -> (Arc<MockDiskManager>, Arc<MockDiskManager>, BufferPoolManager) {
+        let disk = Arc::new(MockDiskManager::new());
+        let log_device = Arc::new(MockDiskManager::new());
+        let mut bpm = BufferPoolManager::with_log(4, disk.clone(), LogManager::with_device(log_device.clone()).unwrap()).unwrap();
+        bpm.sync_mode = mode;
+        (disk, log_device, bpm)
+    }
+
+    fn commit_one_change(bpm: &BufferPoolManager) {
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        let txn = bpm.begin_txn().unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 9).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn per_commit_syncs_the_data_file_on_every_commit() {
+        let (disk, log_device, bpm) = synced_setup(SyncMode::PerCommit);
+        let (data_syncs, log_syncs) = (disk.syncs(), log_device.syncs());
+        commit_one_change(&bpm);
+        commit_one_change(&bpm);
+        assert_eq!(disk.syncs() - data_syncs, 2);
+        assert!(log_device.syncs() - log_syncs >= 2);
+        assert_eq!(bpm.stats().syncs, 2);
+    }
+
+    #[test]
+    fn group_commit_leaves_the_data_file_sync_to_checkpoints() {
+        let (disk, log_device, bpm) = synced_setup(SyncMode::GroupCommit);
+        let (data_syncs, log_syncs) = (disk.syncs(), log_device.syncs());
+        commit_one_change(&bpm);
+        assert_eq!(disk.syncs(), data_syncs);
+        assert!(log_device.syncs() > log_syncs);
+
+        bpm.log_manager().unwrap().checkpoint(&bpm).unwrap();
+        assert_eq!(disk.syncs(), data_syncs + 1);
+    }
+
+    #[test]
+    fn flush_all_and_sync_writes_then_syncs() {
+        let (disk, _, bpm) = synced_setup(SyncMode::GroupCommit);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let syncs = disk.syncs();
+        bpm.flush_all_and_sync().unwrap();
+        assert!(disk.raw_page(page_id).is_some());
+        assert_eq!(disk.syncs(), syncs + 1);
+    }
+
+    #[test]
+    fn freed_pages_are_handed_out_again_newest_first() {
+        let (_, bpm) = pool(4);
+        let ids: Vec<PageId> = (0..4).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        assert!(bpm.deallocate_page(ids[1]).unwrap());
+        assert!(bpm.deallocate_page(ids[3]).unwrap());
+        assert!(bpm.deallocate_page(ids[3]).unwrap());
+        assert!(!bpm.deallocate_page(SUPERBLOCK_PAGE).unwrap());
+
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[3]);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[1]);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[3] + 1);
+    }
+
+    #[test]
+    fn a_pinned_page_cant_be_freed() {
+        let (_, bpm) = pool(4);
+        let guard = bpm.allocate_page().unwrap();
+        assert!(!bpm.deallocate_page(guard.page_id).unwrap());
+        let page_id = guard.page_id;
+        drop(guard);
+        assert!(bpm.deallocate_page(page_id).unwrap());
+    }
+
+    #[test]
+    fn the_free_list_survives_a_reopen() {
+        let (disk, bpm) = pool(4);
+        let ids: Vec<PageId> = (0..5).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        for &page_id in &ids[..3] {
+            assert!(bpm.deallocate_page(page_id).unwrap());
+        }
+        bpm.flush_all().unwrap();
+        assert_eq!(bpm.superblock().unwrap().free_list_head(), Some(ids[2]));
+        drop(bpm);
+
+        let bpm = BufferPoolManager::new(4, disk).unwrap();
+        let reused: Vec<PageId> = (0..4).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        assert_eq!(reused, vec![ids[2], ids[1], ids[0], ids[4] + 1]);
+    }
+
+    #[test]
+    fn a_chain_through_a_page_that_was_reused_stops_there() {
+        let (disk, bpm) = pool(4);
+        let ids: Vec<PageId> = (0..3).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        for &page_id in &ids {
+            assert!(bpm.deallocate_page(page_id).unwrap());
+        }
+        bpm.flush_all().unwrap();
+        drop(bpm);
+
+        // the middle page got used for something else after the last flush_all (a crash without a log)
+        let mut data = *Page::new(ids[1], PageType::NodeStore).get_data();
+        stamp_checksum(&mut data);
+        disk.set_raw_page(ids[1], data);
+
+        let bpm = BufferPoolManager::new(4, disk).unwrap();
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[2]);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[2] + 1);
+    }
+
+    #[test]
+    fn an_owned_guard_can_be_moved_to_another_thread() {
+        let bpm = Arc::new(BufferPoolManager::new(4, MockDiskManager::new()).unwrap());
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        assert!(bpm.fetch_page_write(page_id).unwrap().write_at(HEADER_SIZE as u32, b"moved"));
+
+        let guard = bpm.fetch_page_arc(page_id).unwrap();
+        assert_eq!(pins_of(&bpm, page_id), 1);
+        let read = std::thread::spawn(move || guard.read().get_data()[HEADER_SIZE..HEADER_SIZE + 5].to_vec());
+        assert_eq!(read.join().unwrap(), b"moved");
+        assert_eq!(pins_of(&bpm, page_id), 0);
+    }
+
+    #[test]
+    fn an_owned_guard_keeps_the_pool_alive() {
+        let bpm = Arc::new(BufferPoolManager::new(4, MockDiskManager::new()).unwrap());
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let guard = bpm.fetch_page_arc(page_id).unwrap();
+        drop(bpm);
+        assert_eq!(guard.read().page_id, Some(page_id));
+        assert_eq!(Arc::strong_count(guard.bpm()), 1);
+    }
+
+    #[test]
+    fn fetching_a_page_of_an_unknown_version_fails_and_frees_the_frame() {
+        let (disk, bpm) = pool(2);
+        let page_id = pages_on_disk(&bpm, 1)[0];
+        let mut data = disk.raw_page(page_id).unwrap();
+        let at = std::mem::offset_of!(PageHeader, version);
+        data[at..at + 2].copy_from_slice(&(PAGE_FORMAT_VERSION + 1).to_le_bytes());
+        stamp_checksum(&mut data);
+        disk.set_raw_page(page_id, data);
+
+        assert!(bpm.fetch_page(page_id).is_err());
+        assert_eq!(pins_of(&bpm, page_id), 0);
+        assert!(bpm.frame_table().iter().all(|f| f.page_id != Some(page_id)));
+    }
+
+    fn batched_pool(size: usize, batch: usize) -> BufferPoolManager {
+        let (_, mut bpm) = pool(size);
+        bpm.eviction_batch = batch;
+        bpm
+    }
+
+    #[test]
+    fn batched_eviction_takes_fewer_replacer_passes_than_evictions() {
+        let bpm = batched_pool(8, 4);
+        for _ in 0..64 {
+            bpm.allocate_page().unwrap();
+        }
+        let stats = bpm.stats();
+        assert!(stats.evictions >= 56);
+        assert!(stats.replacer_passes * 3 < stats.evictions, "{stats:?}");
+    }
+
+    #[test]
+    fn a_batch_never_takes_pinned_frames() {
+        let bpm = batched_pool(4, 4);
+        let pinned: Vec<_> = (0..3).map(|_| bpm.allocate_page().unwrap()).collect();
+        let ids: Vec<PageId> = pinned.iter().map(|guard| guard.page_id).collect();
+        for _ in 0..8 {
+            bpm.allocate_page().unwrap();
+        }
+        // only one frame was ever free to go round, every pass found just that one
+        let stats = bpm.stats();
+        assert_eq!(stats.replacer_passes, stats.evictions);
+        assert!(ids.iter().all(|&page_id| pins_of(&bpm, page_id) == 1));
+
+        let _last = bpm.allocate_page().unwrap();
+        assert!(matches!(bpm.allocate_page(), Err(BufferError::PoolExhausted)));
+        drop(pinned);
+    }
+
+    #[test]
+    fn an_eviction_batch_of_zero_is_refused() {
+        let temp = crate::test_util::TempPath::new("eviction_batch_zero");
+        let config = BufferPoolConfig { eviction_batch: 0, ..BufferPoolConfig::new(temp.path()) };
+        let err = BufferPoolManager::with_config(config).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn fetching_a_page_with_an_oversized_free_space_pointer_fails_cleanly() {
+        let (disk, bpm) = pool(2);
+        let page_id = pages_on_disk(&bpm, 1)[0];
+        let mut data = disk.raw_page(page_id).unwrap();
+        let at = std::mem::offset_of!(PageHeader, free_space_pointer);
+        data[at..at + 4].copy_from_slice(&(PAGE_SIZE as u32 * 2).to_le_bytes());
+        stamp_checksum(&mut data);
+        disk.set_raw_page(page_id, data);
+
+        let Err(BufferError::Io(err)) = bpm.fetch_page(page_id) else { panic!("corrupt page was loaded") };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(bpm.frame_table().iter().all(|f| f.page_id != Some(page_id)));
+    }
+
+    #[test]
+    fn concurrent_readers_all_get_in_at_once() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let barrier = std::sync::Barrier::new(4);
+        // every reader holds its guard until all four have one, that only finishes if the latch is shared
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let guard = bpm.fetch_page_read(page_id).unwrap();
+                    barrier.wait();
+                    assert_eq!(guard.page_id, page_id);
+                });
+            }
+        });
+        assert_eq!(pins_of(&bpm, page_id), 0);
+    }
+
+    #[test]
+    fn a_writer_waits_until_the_readers_let_go() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let readers = [bpm.fetch_page_read(page_id).unwrap(), bpm.fetch_page_read(page_id).unwrap()];
+        let (wrote, written) = std::sync::mpsc::channel();
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut guard = bpm.fetch_page_write(page_id).unwrap();
+                guard.write_u32_at(HEADER_SIZE as u32, 9).unwrap();
+                wrote.send(()).unwrap();
+            });
+            assert!(written.recv_timeout(Duration::from_millis(100)).is_err(), "the writer got in past two readers");
+            assert!(readers.iter().all(|r| r.read_u32_at(HEADER_SIZE as u32) == Ok(0)));
+            drop(readers);
+            written.recv_timeout(Duration::from_secs(5)).expect("the writer never got the latch");
+        });
+        assert_eq!(bpm.fetch_page_read(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(9));
+    }
+
+    #[test]
+    fn a_reader_waits_for_the_writer() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        let mut writer = bpm.fetch_page_write(page_id).unwrap();
+        let (read, value) = std::sync::mpsc::channel();
+
+        std::thread::scope(|s| {
+            s.spawn(|| read.send(bpm.fetch_page_read(page_id).unwrap().read_u32_at(HEADER_SIZE as u32)).unwrap());
+            assert!(value.recv_timeout(Duration::from_millis(100)).is_err(), "the reader got in past the writer");
+            writer.write_u32_at(HEADER_SIZE as u32, 3).unwrap();
+            drop(writer);
+            assert_eq!(value.recv_timeout(Duration::from_secs(5)).unwrap(), Ok(3));
+        });
+    }
+
+    #[test]
+    fn lock_pages_ordered_sorts_and_drops_duplicates() {
+        let (_, bpm) = pool(4);
+        let ids: Vec<PageId> = (0..3).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        let guards = bpm.lock_pages_ordered(&[ids[2], ids[0], ids[2], ids[1]]).unwrap();
+        assert_eq!(guards.iter().map(|g| g.page_id).collect::<Vec<_>>(), ids);
+        assert!(ids.iter().all(|&page_id| pins_of(&bpm, page_id) == 1));
+    }
+
+    #[test]
+    fn lock_pages_ordered_lets_go_of_everything_if_one_page_fails() {
+        let (_, bpm) = pool(2);
+        let a = bpm.allocate_page().unwrap().page_id;
+        let b = bpm.allocate_page().unwrap().page_id;
+        let c = bpm.allocate_page().unwrap().page_id;
+        // two frames can't hold three pages
+        assert!(matches!(bpm.lock_pages_ordered(&[c, a, b]), Err(BufferError::PoolExhausted)));
+        assert!([a, b, c].iter().all(|&page_id| pins_of(&bpm, page_id) == 0));
+        assert_eq!(bpm.lock_pages_ordered(&[b, a]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn locking_the_same_pages_in_opposite_orders_doesnt_deadlock() {
+        let (_, bpm) = pool(4);
+        let a = bpm.allocate_page().unwrap().page_id;
+        let b = bpm.allocate_page().unwrap().page_id;
+        let (done, finished) = std::sync::mpsc::channel();
+
+        std::thread::scope(|s| {
+            for order in [[a, b], [b, a]] {
+                let (bpm, done) = (&bpm, done.clone());
+                s.spawn(move || {
+                    for i in 0..1000u32 {
+                        let mut guards = bpm.lock_pages_ordered(&order).unwrap();
+                        for guard in &mut guards {
+                            guard.write_u32_at(HEADER_SIZE as u32, i).unwrap();
+                        }
+                    }
+                    done.send(()).unwrap();
+                });
+            }
+            for _ in 0..2 {
+                finished.recv_timeout(Duration::from_secs(10)).expect("deadlocked");
+            }
+        });
+    }
+
+    // the records the log has for page_id
+    fn logged_for(bpm: &BufferPoolManager, page_id: PageId) -> Vec<LogRecord> {
+        let log = bpm.log_manager().unwrap();
+        log.flush_log_up_to(Lsn::MAX).unwrap();
+        let records = log.records().unwrap().into_iter();
+        records
+            .filter(|r| match r {
+                LogRecord::Update { page_id: id, .. } | LogRecord::Delta { page_id: id, .. } => *id == page_id,
+                _ => false,
+            })
+            .collect()
+    }
+
+    fn logged_pool_with(granularity: LogGranularity) -> (Arc<MockDiskManager>, Arc<MockDiskManager>, BufferPoolManager) {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let mut bpm = BufferPoolManager::with_log(4, disk.clone(), LogManager::with_device(log.clone()).unwrap()).unwrap();
+        bpm.log_granularity = granularity;
+        (disk, log, bpm)
+    }
+
+    #[test]
+    fn small_changes_are_logged_as_deltas() {
+        let (_, _, bpm) = logged_pool_with(LogGranularity::Delta);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        let logged = logged_for(&bpm, page_id).len();
+
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(200, 7).unwrap();
+        {
+            let mut guard = bpm.fetch_page_write(page_id).unwrap();
+            guard.write_u32_at(300, 8).unwrap();
+            guard.write_u32_at(900, 9).unwrap();
+        }
+        let records = logged_for(&bpm, page_id);
+        assert_eq!(records.len(), logged + 2);
+        assert!(matches!(&records[logged], LogRecord::Update { offset: 200, after_image, .. } if after_image.len() == 1));
+        assert!(matches!(&records[logged + 1], LogRecord::Delta { runs, .. } if runs.len() == 2));
+    }
+
+    #[test]
+    fn big_changes_and_full_page_logging_log_the_whole_page() {
+        let (_, _, bpm) = logged_pool_with(LogGranularity::Delta);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        assert!(bpm.fetch_page_write(page_id).unwrap().write_at(HEADER_SIZE as u32, &[1; PAGE_SIZE / 2]));
+        let last = logged_for(&bpm, page_id).pop().unwrap();
+        assert!(matches!(last, LogRecord::Update { offset: 0, after_image, .. } if after_image.len() == PAGE_SIZE));
+
+        let (_, _, bpm) = logged_pool_with(LogGranularity::FullPage);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(200, 7).unwrap();
+        let last = logged_for(&bpm, page_id).pop().unwrap();
+        assert!(matches!(last, LogRecord::Update { offset: 0, after_image, .. } if after_image.len() == PAGE_SIZE));
+    }
+
+    #[test]
+    fn both_granularities_recover_the_same_page() {
+        let recovered = [LogGranularity::Delta, LogGranularity::FullPage].map(|granularity| {
+            let (disk, log, bpm) = logged_pool_with(granularity);
+            let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+            bpm.flush_all().unwrap();
+            for i in 0..20u32 {
+                let mut guard = bpm.fetch_page_write(page_id).unwrap();
+                guard.write_u32_at(HEADER_SIZE as u32 + i * 40, i).unwrap();
+                guard.write_u32_at(PAGE_SIZE as u32 - 4 - i * 4, !i).unwrap();
+            }
+            assert!(bpm.fetch_page_write(page_id).unwrap().write_at(1000, &[3; PAGE_SIZE / 3]));
+            bpm.log_manager().unwrap().flush_log_up_to(Lsn::MAX).unwrap();
+            let expected = bpm.read_page_copy(page_id).unwrap();
+            std::mem::forget(bpm);
+
+            let bpm = BufferPoolManager::with_log(4, disk, LogManager::with_device(log).unwrap()).unwrap();
+            let page = bpm.read_page_copy(page_id).unwrap();
+            assert_eq!(page[HEADER_SIZE..], expected[HEADER_SIZE..]);
+            page
+        });
+        assert_eq!(recovered[0][HEADER_SIZE..], recovered[1][HEADER_SIZE..]);
+    }
+
+    #[test]
+    fn a_timed_fetch_gets_a_frame_once_another_thread_lets_go() {
+        let (_, bpm) = pool(2);
+        let pages = pages_on_disk(&bpm, 3);
+        let pinned = std::sync::Barrier::new(2);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let guards = [bpm.fetch_page(pages[0]).unwrap(), bpm.fetch_page(pages[1]).unwrap()];
+                pinned.wait();
+                std::thread::sleep(Duration::from_millis(50));
+                drop(guards);
+            });
+            pinned.wait();
+            assert!(matches!(bpm.fetch_page(pages[2]), Err(BufferError::PoolExhausted)));
+            let page = bpm.fetch_page_timeout(pages[2], Duration::from_secs(5)).unwrap();
+            assert_eq!(page.page_id, pages[2]);
+        });
+    }
+
+    #[test]
+    fn a_timed_fetch_gives_up_at_the_deadline() {
+        let (_, bpm) = pool(2);
+        let pages = pages_on_disk(&bpm, 3);
+        let _guards = [bpm.fetch_page(pages[0]).unwrap(), bpm.fetch_page(pages[1]).unwrap()];
+        let start = Instant::now();
+        let result = bpm.fetch_page_timeout(pages[2], Duration::from_millis(20));
+        assert!(matches!(result, Err(BufferError::PoolExhausted)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn only_modified_pages_are_in_the_dirty_set() {
+        let (_, bpm) = pool(8);
+        let pages: Vec<PageId> = (0..5).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.flush_all().unwrap();
+        assert!(bpm.dirty_page_ids().is_empty());
+
+        for &page_id in [pages[3], pages[1]].iter() {
+            bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 7).unwrap();
+        }
+        assert_eq!(bpm.dirty_page_ids(), vec![pages[1], pages[3]]);
+        bpm.flush_page(pages[1]).unwrap();
+        assert_eq!(bpm.dirty_page_ids(), vec![pages[3]]);
+    }
+
+    #[test]
+    fn pages_changed_since_an_lsn_are_the_ones_written_after_it() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = BufferPoolManager::with_log(8, disk, LogManager::with_device(log).unwrap()).unwrap();
+        let pages: Vec<PageId> = (0..5).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.flush_all().unwrap();
+        let since = bpm.log_manager().unwrap().next_lsn() - 1;
+
+        for &page_id in [pages[0], pages[4]].iter() {
+            bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 7).unwrap();
+        }
+        // still in the pool, the data file doesn't know yet
+        assert!(bpm.pages_changed_since(since).unwrap().changed.is_empty());
+        bpm.flush_all().unwrap();
+        let changed = bpm.pages_changed_since(since).unwrap();
+        assert_eq!(changed.changed, vec![pages[0], pages[4]]);
+        assert!(changed.unreadable.is_empty());
+    }
+
+    #[test]
+    fn a_corrupt_page_is_reported_instead_of_failing_the_scan() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = BufferPoolManager::with_log(8, disk.clone(), LogManager::with_device(log).unwrap()).unwrap();
+        let pages: Vec<PageId> = (0..3).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.flush_all().unwrap();
+        let mut data = disk.raw_page(pages[1]).unwrap();
+        data[PAGE_SIZE - 1] ^= 0xff;
+        disk.set_raw_page(pages[1], data);
+
+        let changed = bpm.pages_changed_since(0).unwrap();
+        assert_eq!(changed.unreadable.len(), 1);
+        assert_eq!(changed.unreadable[0].0, pages[1]);
+        assert!(changed.changed.contains(&pages[2]));
+    }
+
+    // a logged pool with one page holding 42 whose copy on disk has been damaged since, and isn't in the pool
+    fn corrupted_page(on_corruption: OnCorruption) -> (Arc<MockDiskManager>, BufferPoolManager, PageId) {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let mut bpm = BufferPoolManager::with_log(4, disk.clone(), LogManager::with_device(log).unwrap()).unwrap();
+        bpm.on_corruption = on_corruption;
+        let page_id = pages_on_disk(&bpm, 1)[0];
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 42).unwrap();
+        bpm.flush_all().unwrap();
+        assert!(bpm.discard_frame(&mut bpm.state.lock().unwrap(), page_id));
+
+        let mut data = disk.raw_page(page_id).unwrap();
+        data[PAGE_SIZE - 1] ^= 0xff;
+        disk.set_raw_page(page_id, data);
+        (disk, bpm, page_id)
+    }
+
+    #[test]
+    fn a_corrupt_page_fails_the_fetch_by_default() {
+        let (_, bpm, page_id) = corrupted_page(OnCorruption::Fail);
+        let Err(BufferError::Io(err)) = bpm.fetch_page(page_id) else { panic!("expected a read error") };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(bpm.quarantined_pages().is_empty());
+        // the frame went back, the pool still works
+        assert_eq!(bpm.frame_table().iter().filter(|f| f.page_id == Some(page_id)).count(), 0);
+    }
+
+    #[test]
+    fn a_quarantined_page_is_replaced_in_the_pool_only() {
+        let (disk, bpm, page_id) = corrupted_page(OnCorruption::Quarantine);
+        let damaged = disk.raw_page(page_id).unwrap();
+        assert_eq!(bpm.fetch_page(page_id).unwrap().page_type(), Ok(PageType::Quarantined));
+        assert_eq!(bpm.quarantined_pages(), vec![page_id]);
+        assert_eq!(bpm.take_deferred_errors().len(), 1);
+
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 1).unwrap();
+        bpm.flush_all().unwrap();
+        assert_eq!(disk.raw_page(page_id).unwrap(), damaged);
+    }
+
+    #[test]
+    fn wal_redo_rebuilds_a_corrupt_page_and_writes_it_back() {
+        let (disk, bpm, page_id) = corrupted_page(OnCorruption::AttemptWalRedo);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(42));
+        assert!(bpm.quarantined_pages().is_empty());
+        bpm.flush_all().unwrap();
+        let mut data = [0; PAGE_SIZE];
+        disk.read_page(page_id, &mut data).unwrap();
+        assert_eq!(Page::from_bytes(data).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(42));
+    }
+
+    #[test]
+    fn wal_redo_without_a_log_falls_back_to_failing() {
+        let (disk, mut bpm) = pool(4);
+        bpm.on_corruption = OnCorruption::AttemptWalRedo;
+        let page_id = pages_on_disk(&bpm, 1)[0];
+        let mut data = disk.raw_page(page_id).unwrap();
+        data[PAGE_SIZE - 1] ^= 0xff;
+        disk.set_raw_page(page_id, data);
+        assert!(matches!(bpm.fetch_page(page_id), Err(BufferError::Io(_))));
+    }
+
+    #[test]
+    fn a_contiguous_run_of_8_has_consecutive_ids() {
+        let (disk, bpm) = pool(4);
+        let before = bpm.allocate_page().unwrap().page_id;
+        let first = bpm.allocate_contiguous(8).unwrap().unwrap();
+        assert_eq!(first, before + 1);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, first + 8);
+        // written straight to disk, none of them took a frame
+        for page_id in first..first + 8 {
+            assert!(disk.raw_page(page_id).is_some());
+            assert_eq!(pins_of(&bpm, page_id), 0);
+        }
+        assert_eq!(bpm.allocate_contiguous(0).unwrap(), None);
+    }
+
+    #[test]
+    fn contiguous_pages_are_empty_slotted_pages() {
+        let (_, bpm) = pool(4);
+        let first = bpm.allocate_contiguous(3).unwrap().unwrap();
+        for page_id in first..first + 3 {
+            let page = bpm.fetch_page(page_id).unwrap();
+            assert_eq!(page.page_type(), Ok(PageType::NodeStore));
+            assert_eq!(page.get_free_space_pointer(), PAGE_SIZE as u32);
+            assert_eq!(SlottedPage::new(&*page).slot_count(), 0);
+        }
+        assert_eq!(bpm.count_items_of_type(PageType::NodeStore), 0);
+        let mut page = bpm.fetch_page_write(first + 1).unwrap();
+        assert_eq!(SlottedPage::new(&mut *page).insert_record(b"record"), Some(0));
+    }
+
+    #[test]
+    fn a_contiguous_run_comes_off_the_free_list_when_it_has_one() {
+        let (_, bpm) = pool(8);
+        let pages: Vec<PageId> = (0..6).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        for &page_id in [pages[0], pages[2], pages[3], pages[4]].iter() {
+            assert!(bpm.deallocate_page(page_id).unwrap());
+        }
+        assert_eq!(bpm.allocate_contiguous(3).unwrap(), Some(pages[2]));
+        // what's left of the list still hands out the page outside the run, then new ones
+        assert_eq!(bpm.allocate_page().unwrap().page_id, pages[0]);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, pages[5] + 1);
+    }
+
+    #[test]
+    fn the_frame_table_reflects_a_just_fetched_page() {
+        let (_, bpm) = pool(4);
+        let page_id = pages_on_disk(&bpm, 1)[0];
+        assert!(bpm.frame_table().iter().all(|f| f.page_id.is_none()));
+
+        let page = bpm.fetch_page(page_id).unwrap();
+        let table = bpm.frame_table();
+        assert_eq!(table.len(), 4);
+        let frame = table.iter().find(|f| f.page_id == Some(page_id)).unwrap();
+        assert_eq!(frame.pin_count, 1);
+        assert!(!frame.is_dirty);
+        assert_eq!(frame.page_type, Some(PageType::NodeStore));
+        assert_eq!(table.iter().filter(|f| f.page_id.is_some()).count(), 1);
+        drop(page);
+        assert_eq!(pins_of(&bpm, page_id), 0);
+    }
+
+    #[test]
+    fn the_frame_table_shows_a_page_being_written_as_dirty() {
+        let (_, bpm) = pool(4);
+        let page_id = pages_on_disk(&bpm, 1)[0];
+        let guard = bpm.fetch_page_write(page_id).unwrap();
+        let frame = bpm.frame_table().into_iter().find(|f| f.page_id == Some(page_id)).unwrap();
+        assert!(frame.is_dirty);
+        // the latch is taken, the snapshot doesn't wait for it
+        assert_eq!(frame.page_type, None);
+        drop(guard);
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    #[should_panic(expected = "changed in memory while clean")]
+    fn a_wild_write_to_a_clean_resident_page_is_caught_on_fetch() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.flush_all().unwrap();
+        let frame_id = bpm.state.lock().unwrap().page_mapping[&page_id];
+        // behind the pool's back, nothing marks the page dirty
+        bpm.frames[frame_id].write().unwrap().get_data_mut()[HEADER_SIZE] ^= 0xff;
+        let _ = bpm.fetch_page(page_id);
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    fn changes_through_the_pool_pass_the_clean_check() {
+        let (_, bpm) = pool(4);
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.flush_all().unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 1).unwrap();
+        bpm.flush_all().unwrap();
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(1));
+    }
+
+    // a hook that records the page ids it's called with
+    fn recording_hook() -> (Arc<Mutex<Vec<PageId>>>, PageHook) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = seen.clone();
+        (seen, Box::new(move |page_id| hook_seen.lock().unwrap().push(page_id)))
+    }
+
+    #[test]
+    fn the_eviction_hook_fires_with_the_evicted_page() {
+        let (_, bpm) = pool(1);
+        let (evicted, hook) = recording_hook();
+        bpm.set_eviction_hook(Some(hook));
+        let first = bpm.allocate_page().unwrap().page_id;
+        assert!(evicted.lock().unwrap().is_empty());
+        let second = bpm.allocate_page().unwrap().page_id;
+        assert_eq!(*evicted.lock().unwrap(), vec![first]);
+        drop(bpm.fetch_page(first).unwrap());
+        assert_eq!(*evicted.lock().unwrap(), vec![first, second]);
+
+        bpm.set_eviction_hook(None);
+        drop(bpm.fetch_page(second).unwrap());
+        assert_eq!(evicted.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn the_load_hook_fires_for_reads_from_disk_only() {
+        let (_, bpm) = pool(2);
+        let pages = pages_on_disk(&bpm, 2);
+        let (loaded, hook) = recording_hook();
+        bpm.set_load_hook(Some(hook));
+        drop(bpm.fetch_page(pages[1]).unwrap());
+        drop(bpm.fetch_page(pages[1]).unwrap());
+        drop(bpm.allocate_page().unwrap());
+        assert_eq!(*loaded.lock().unwrap(), vec![pages[1]]);
+    }
+
+    #[test]
+    fn a_hook_can_call_back_into_the_pool() {
+        let bpm = Arc::new(BufferPoolManager::new(1, MockDiskManager::new()).unwrap());
+        let (seen, record) = recording_hook();
+        let pool = Arc::downgrade(&bpm);
+        bpm.set_eviction_hook(Some(Box::new(move |page_id| {
+            // the flush on drop can still evict (to write the superblock), the pool is gone by then
+            let Some(bpm) = pool.upgrade() else { return };
+            // the pool's locks are all free here
+            assert!(bpm.frame_table().iter().all(|f| f.page_id != Some(page_id)));
+            record(page_id);
+        })));
+        let first = bpm.allocate_page().unwrap().page_id;
+        drop(bpm.allocate_page().unwrap());
+        assert_eq!(*seen.lock().unwrap(), vec![first]);
+    }
+
+    const TOMBSTONED_RECORD: usize = PAGE_SIZE / 32;
+
+    // a resident slotted page with 20 records of TOMBSTONED_RECORD bytes, every other one but the last deleted, and
+    // slot 19 turned into a forward to page 77 slot 3
+    fn tombstoned_page(bpm: &BufferPoolManager) -> PageId {
+        let mut page = bpm.allocate_page_of_type(PageType::NodeStore).unwrap();
+        let mut slotted = SlottedPage::init(&mut *page);
+        for i in 0..20 {
+            slotted.insert_record(&[i; TOMBSTONED_RECORD]).unwrap();
+        }
+        for slot in (0..18).step_by(2) {
+            assert!(slotted.delete_record(slot));
+        }
+        assert!(slotted.set_forward(19, 77, 3));
+        page.page_id
+    }
+
+    #[test]
+    fn a_tombstoned_page_is_vacuumed_on_flush_and_keeps_its_slot_ids() {
+        let (disk, mut bpm) = pool(4);
+        bpm.vacuum_on_flush = Some(0.3);
+        let page_id = tombstoned_page(&bpm);
+        let free_before = SlottedPage::new(&*bpm.fetch_page(page_id).unwrap()).free_space();
+        let map = bpm.free_space_map(PageType::NodeStore);
+        bpm.flush_page(page_id).unwrap();
+
+        let page = bpm.fetch_page(page_id).unwrap();
+        let slotted = SlottedPage::new(&*page);
+        assert!(slotted.free_space() >= free_before + 9 * TOMBSTONED_RECORD);
+        assert_eq!(slotted.record_count(), 10);
+        for slot in (1..19).step_by(2).chain([18]) {
+            assert_eq!(slotted.get_record(slot), Some(&[slot as u8; TOMBSTONED_RECORD][..]));
+        }
+        assert!(slotted.is_tombstone(0));
+        assert_eq!(slotted.resolve_forward(19), Some((77, 3)));
+        assert_eq!(map.find_page_with_room(free_before + 4 * TOMBSTONED_RECORD), Some(page_id));
+        // what went to disk is the vacuumed page
+        assert_eq!(disk.raw_page(page_id).unwrap()[HEADER_SIZE..], page.get_data()[HEADER_SIZE..]);
+    }
+
+    #[test]
+    fn pages_under_the_threshold_or_latched_are_flushed_as_they_are() {
+        let (_, mut bpm) = pool(4);
+        bpm.vacuum_on_flush = Some(0.6);
+        let page_id = tombstoned_page(&bpm);
+        let free_before = SlottedPage::new(&*bpm.fetch_page(page_id).unwrap()).free_space();
+        bpm.flush_page(page_id).unwrap();
+        assert_eq!(SlottedPage::new(&*bpm.fetch_page(page_id).unwrap()).free_space(), free_before);
+
+        bpm.vacuum_on_flush = Some(0.1);
+        let guard = bpm.fetch_page(page_id).unwrap();
+        bpm.flush_page(page_id).unwrap();
+        assert_eq!(SlottedPage::new(&*guard).free_space(), free_before);
+        drop(guard);
+        bpm.flush_page(page_id).unwrap();
+        assert!(SlottedPage::new(&*bpm.fetch_page(page_id).unwrap()).free_space() > free_before);
+    }
+
+    #[test]
+    fn a_heavily_pinned_page_ranks_first() {
+        let (_, bpm) = pool(4);
+        let pages = pages_on_disk(&bpm, 3);
+        let heavy: Vec<PageFrameRef<'_>> = (0..5).map(|_| bpm.fetch_page(pages[1]).unwrap()).collect();
+        let light = bpm.fetch_page(pages[2]).unwrap();
+        drop(bpm.fetch_page(pages[0]).unwrap());
+
+        assert_eq!(bpm.hot_pages(2), vec![(pages[1], 5), (pages[2], 1)]);
+        assert_eq!(bpm.hot_pages(10).len(), 3);
+        drop((heavy, light));
+        assert!(bpm.hot_pages(3).iter().all(|&(_, pins)| pins == 0));
+    }
+
+    #[test]
+    fn with_nothing_pinned_hot_pages_ranks_by_recent_access() {
+        let (_, bpm) = pool(4);
+        let pages = pages_on_disk(&bpm, 3);
+        for (page_id, times) in [(pages[0], 1), (pages[1], 2), (pages[2], 4)] {
+            for _ in 0..times {
+                drop(bpm.fetch_page(page_id).unwrap());
+            }
+        }
+        let ranked: Vec<PageId> = bpm.hot_pages(3).into_iter().map(|(page_id, _)| page_id).collect();
+        assert_eq!(ranked, vec![pages[2], pages[1], pages[0]]);
+    }
+}
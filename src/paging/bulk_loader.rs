@@ -0,0 +1,130 @@
+/*
+* Fast path for the initial load of a big dataset: fully built pages go straight to the end of the data file,
+* one after another, without passing through the buffer pool or the write ahead log. Loading through the pool
+* would evict (and write back) a page for every page loaded once the pool is full.
+*
+* NOT crash safe until finish() returns: nothing is logged, so a crash halfway leaves whatever pages made it to
+* disk and recovery knows nothing about them. finish() syncs the data file, after that the pages are as durable
+* as anything flushed normally. Only meant for loading into pages nobody is using yet, which is all it can do
+* anyway since every page gets a brand new id past the end of the file.
+*/
+
+use std::io;
+
+use super::buffer_pool_manager::{BufferError, BufferPoolManager};
+use super::page::{Page, PageType};
+use super::page_constants::PageId;
+use super::slotted_page::SlottedPage;
+
+pub struct BulkLoader<'a> {
+    bpm: &'a BufferPoolManager,
+    loaded: Vec<PageId>,
+    // the slotted ones among them with their type and free space, for registering on finish
+    free_space: Vec<(PageId, PageType, usize)>,
+}
+
+impl<'a> BulkLoader<'a> {
+    pub fn new(bpm: &'a BufferPoolManager) -> Self {
+        Self { bpm, loaded: Vec::new(), free_space: Vec::new() }
+    }
+
+    // Writes page to the file under the next page id (whatever id it had is replaced) and returns that id
+    pub fn append_page(&mut self, mut page: Page) -> Result<PageId, BufferError> {
+        let page_id = self.bpm.reserve_page_id()?;
+        page.set_page_id(page_id);
+        self.bpm.write_unbuffered(&page)?;
+
+        if let Ok(page_type) = page.page_type()
+            && page_type.is_slotted()
+        {
+            self.free_space.push((page_id, page_type, SlottedPage::new(&page).available_space()));
+        }
+        self.loaded.push(page_id);
+        Ok(page_id)
+    }
+
+    // Syncs the data file and puts the loaded slotted pages' free space on the pool's free space maps (see
+    // BufferPoolManager::free_space_map), so the stores' normal inserts fill them up after. Returns their ids in
+    // load order
+    pub fn finish(self) -> io::Result<Vec<PageId>> {
+        self.bpm.sync()?;
+        for &(page_id, page_type, free) in self.free_space.iter().filter(|(_, _, free)| *free > 0) {
+            self.bpm.free_space_map(page_type).update(page_id, free);
+        }
+        Ok(self.loaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::slotted_page::SlotId;
+
+    fn record(i: u32) -> [u8; 16] {
+        let mut record = [0; 16];
+        record[..4].copy_from_slice(&i.to_le_bytes());
+        record[4..8].copy_from_slice(&(!i).to_le_bytes());
+        record
+    }
+
+    fn empty_page() -> Page {
+        let mut page = Page::new(0, PageType::NodeStore);
+        SlottedPage::init(&mut page);
+        page
+    }
+
+    // loads count records, returns where each one ended up
+    fn load(bpm: &BufferPoolManager, count: u32) -> (Vec<PageId>, Vec<(usize, SlotId)>) {
+        let mut loader = BulkLoader::new(bpm);
+        let mut placed = Vec::new();
+        let mut pages = 0;
+        let mut page = empty_page();
+        for i in 0..count {
+            let slot = match SlottedPage::new(&mut page).insert_record(&record(i)) {
+                Some(slot) => slot,
+                None => {
+                    loader.append_page(std::mem::replace(&mut page, empty_page())).unwrap();
+                    pages += 1;
+                    SlottedPage::new(&mut page).insert_record(&record(i)).unwrap()
+                }
+            };
+            placed.push((pages, slot));
+        }
+        loader.append_page(page).unwrap();
+        (loader.finish().unwrap(), placed)
+    }
+
+    #[test]
+    fn bulk_loaded_records_read_back_through_the_pool() {
+        let disk = Arc::new(MockDiskManager::new());
+        let bpm = BufferPoolManager::new(4, disk.clone()).unwrap();
+        let writes = disk.writes();
+        let (pages, placed) = load(&bpm, 10_000);
+        assert!(pages.len() > 4);
+        assert!(pages.windows(2).all(|w| w[1] == w[0] + 1));
+        // each page written once, nothing written back out of the pool
+        assert_eq!(disk.writes() - writes, pages.len() as u64);
+        assert!(disk.syncs() >= 1);
+
+        for i in (0..10_000).step_by(997) {
+            let (page, slot) = placed[i as usize];
+            let guard = bpm.fetch_page(pages[page]).unwrap();
+            assert_eq!(guard.page_id, pages[page]);
+            assert_eq!(SlottedPage::new(&*guard).get_record(slot), Some(&record(i)[..]));
+        }
+        let total: u32 = pages.iter().map(|&p| bpm.fetch_page(p).unwrap().get_item_count()).sum();
+        assert_eq!(total, 10_000);
+    }
+
+    #[test]
+    fn loaded_pages_with_room_go_on_the_free_space_map() {
+        let bpm = BufferPoolManager::new(4, MockDiskManager::new()).unwrap();
+        let (pages, _) = load(&bpm, 10);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(bpm.free_space_map(PageType::NodeStore).find_page_with_room(64), Some(pages[0]));
+        // the next normal page comes after the loaded ones
+        assert_eq!(bpm.allocate_page().unwrap().page_id, pages[0] + 1);
+    }
+}
@@ -0,0 +1,179 @@
+/*
+* Knobs for building a BufferPoolManager.
+* Start from BufferPoolConfig::new(path) (or default()) and override whatever fields you need.
+*/
+
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+use super::buffer_pool_manager::BufferError;
+use super::page_constants::{BUFFER_SIZE, PAGE_SIZE};
+
+// Which eviction policy the pool should use
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReplacerKind {
+    Clock,
+    GClock { max_count: u8 }, // clock with a usage counter per frame, see GClockReplacer
+    LruK { k: usize },
+}
+
+/*
+* When page checksums get computed and checked.
+* Always: stamped on every write to disk and verified on every read, torn or corrupted pages are caught on load.
+* OnFlushOnly: still stamped on write but reads skip the check, so pages on disk stay verifiable
+*   (Page::verify_checksum, or reopening with Always) while loads stay cheap. Corruption goes unnoticed until then.
+* Disabled: no CRC at all, the header checksum is written as is (zero unless somebody set it) and reads never check.
+*   Fastest for bulk loads but nothing protects against torn writes, and reopening those pages with
+*   Always/OnFlushOnly verification will report them as corrupt unless they get rewritten first.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChecksumMode {
+    Always,
+    OnFlushOnly,
+    Disabled,
+}
+
+// What dropping the pool does when the final flush fails, Drop has no way to return the error
+// call BufferPoolManager::close instead if you want to handle it yourself
+#[derive(Debug, Clone)]
+pub enum FlushErrorPolicy {
+    Ignore,
+    Report(Sender<BufferError>), // the error gets sent here, nothing happens if the receiver is gone already
+    Panic,                     // unless the thread is already panicking, the error is dropped then
+}
+
+/*
+* How transactions' dirty pages are allowed to reach the data file.
+* StealNoForce: the usual WAL policy. Eviction can write out uncommitted changes (steal) and commit only flushes
+*   the log (no force), redo from the log brings back whatever committed changes the data file is missing.
+* NoStealForce: pages a live transaction has written stay in the pool until it finishes (no steal), and commit
+*   writes them all to the data file (force). Uncommitted changes never reach disk through eviction. A transaction
+*   can't touch more pages than the pool has frames, past that writes fail with PoolExhausted. Explicit
+*   flush_page / flush_all still write whatever is dirty.
+* Neither one makes a crash roll back a transaction that was still running: recovery is redo only and the log has
+* no commit records to tell those apart (see log_manager.rs). Under StealNoForce anything a steal wrote out stays,
+* and under both, redo replays a running transaction's records once something else (another commit, a page write
+* back) made them durable. Only abort, or a transaction dropped before the crash, undoes its changes.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    StealNoForce,
+    NoStealForce,
+}
+
+/*
+* How often commits wait for the data file to be fsynced.
+* PerCommit: every commit ends with a sync of the data file as well as the log, so whatever reached the file
+*   before commit returns (the forced pages under NoStealForce, earlier write backs) is on stable storage.
+* GroupCommit: commits only wait for the log, which is enough for redo, and the data file gets synced at
+*   checkpoints and flush_all_and_sync. Much cheaper when lots of small transactions commit at once.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncMode {
+    PerCommit,
+    GroupCommit,
+}
+
+/*
+* What the write ahead log records for a change made through a write guard (only matters with a log_file).
+* Delta: just the byte runs that changed, each with its offset, so a guard that touched a couple of fields in two
+*   places of the page logs two short runs instead of everything in between. Once the changes add up to more than
+*   a quarter of the page it logs the whole page instead, past that the run headers stop paying for themselves.
+* FullPage: every change logs a before and after image of the whole page. Much bigger log, but a record never
+*   depends on what was on the page before it, handy for checking delta logging against.
+*/
+/*
+* What loading a page does when it fails its checksum or its header doesn't make sense.
+* Fail: the fetch fails with InvalidData, same as a read error.
+* Quarantine: the page is replaced, in the pool only, with an empty page of type Quarantined, so scans skip it and
+*   the rest of the database stays usable. The bad bytes on disk stay as they are until something writes over the
+*   page. BufferPoolManager::quarantined_pages lists what got replaced, and what was wrong with each one is kept
+*   for take_deferred_errors.
+* AttemptWalRedo: the page gets rebuilt from the write ahead log by replaying its records from its last AllocPage
+*   (or full page image) on, and the rebuilt page is written back over the bad one. Only works while the log still
+*   goes back that far, checkpoints cut it short. Falls back to Fail when it doesn't, or without a log_file.
+*/
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OnCorruption {
+    Fail,
+    Quarantine,
+    AttemptWalRedo,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LogGranularity {
+    Delta,
+    FullPage,
+}
+
+// Where a database spread over several files keeps them, see SegmentManager
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentConfig {
+    pub dir: PathBuf,
+    pub segment_size: u64, // bytes per segment file, a non zero multiple of PAGE_SIZE
+}
+
+impl SegmentConfig {
+    // segments of DEFAULT_SEGMENT_SIZE in dir
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into(), segment_size: DEFAULT_SEGMENT_SIZE }
+    }
+}
+
+pub const DEFAULT_SEGMENT_SIZE: u64 = 1 << 30;
+
+// biggest record the stores put in a slotted page as is, bigger ones keep their variable length part in an
+// overflow chain and only a pointer to it inline (see node_store.rs). Keeps a few records on every page whatever
+// the page size
+pub const MAX_INLINE_RECORD: usize = PAGE_SIZE / 4;
+
+#[derive(Debug, Clone)]
+pub struct BufferPoolConfig {
+    pub pool_size: usize, // number of frames, must be at least 1
+    pub replacer: ReplacerKind,
+    pub data_file: PathBuf,
+    pub segments: Option<SegmentConfig>, // split the database over segment files instead, data_file is unused then
+    pub on_drop_flush_error: FlushErrorPolicy,
+    pub checksum_mode: ChecksumMode,
+    pub log_file: Option<PathBuf>, // write ahead log, None runs without one (changes are only durable once flushed)
+    // open the data file without write permission, any attempt to change a page fails with BufferError::ReadOnly
+    // and nothing is ever written back. Can't be combined with log_file since recovery has to write
+    pub read_only: bool,
+    pub recovery_policy: RecoveryPolicy, // only matters for transactions, see RecoveryPolicy
+    pub sync_mode: SyncMode,             // same, see SyncMode
+    // how many frames get evicted in one go once the free list runs dry, the rest wait on the free list for the
+    // next misses. 1 evicts one frame per miss. Must be at least 1
+    pub eviction_batch: usize,
+    pub log_granularity: LogGranularity, // see LogGranularity
+    pub on_corruption: OnCorruption,     // see OnCorruption
+    // flush_page vacuums slotted pages (SlottedPage::vacuum) with more than this share of their slots tombstoned
+    // before writing them, None never does. Between 0.0 and 1.0
+    pub vacuum_on_flush: Option<f64>,
+}
+
+impl BufferPoolConfig {
+    pub fn new<P: Into<PathBuf>>(data_file: P) -> Self {
+        Self { data_file: data_file.into(), ..Self::default() }
+    }
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        Self {
+            pool_size: BUFFER_SIZE,
+            replacer: ReplacerKind::Clock,
+            data_file: PathBuf::from("data.db"),
+            segments: None,
+            on_drop_flush_error: FlushErrorPolicy::Ignore,
+            checksum_mode: ChecksumMode::Always,
+            log_file: None,
+            read_only: false,
+            recovery_policy: RecoveryPolicy::StealNoForce,
+            sync_mode: SyncMode::PerCommit,
+            eviction_batch: 1,
+            log_granularity: LogGranularity::Delta,
+            on_corruption: OnCorruption::Fail,
+            vacuum_on_flush: None,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+/*
+* Background thread that writes dirty pages out ahead of eviction, so a write heavy workload doesn't pay for
+* the flushes all at once when the pool fills up. Started with BufferPoolManager::start_background_flusher.
+* The thread only holds a Weak to the pool and upgrades it for each pass, so it never keeps the pool alive.
+* Dropping the handle (which the pool does when it goes away) wakes the thread up and waits for it to stop.
+* A pass that fails is left in the pool's take_deferred_errors.
+*/
+
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::buffer_pool_manager::BufferPoolManager;
+
+pub(crate) struct BackgroundFlusher {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl BackgroundFlusher {
+    pub(crate) fn spawn(bpm: Weak<BufferPoolManager>, interval: Duration, high_watermark: f64) -> Self {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = stop.clone();
+        let thread = thread::spawn(move || {
+            let (stopped, wake) = &*signal;
+            loop {
+                let guard = stopped.lock().unwrap();
+                let (guard, _) = wake.wait_timeout_while(guard, interval, |stopped| !*stopped).unwrap();
+                if *guard {
+                    return;
+                }
+                drop(guard);
+
+                let Some(bpm) = bpm.upgrade() else { return };
+                // nobody is waiting on this pass, the pool keeps the error until someone asks for it
+                if let Err(e) = bpm.flush_to_watermark(high_watermark) {
+                    bpm.defer_error(e.into());
+                }
+            }
+        });
+        Self { stop, thread: Some(thread) }
+    }
+}
+
+impl Drop for BackgroundFlusher {
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stop;
+        *stopped.lock().unwrap() = true;
+        wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // the flusher itself can end up dropping the pool if it held the last Arc, it can't wait on itself
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+
+    // a pool of size frames, (nearly) all of them holding a dirty page
+    fn dirty_pool(size: usize) -> Arc<BufferPoolManager> {
+        let bpm = Arc::new(BufferPoolManager::new(size, MockDiskManager::new()).unwrap());
+        bpm.flush_all().unwrap();
+        for _ in 0..size {
+            drop(bpm.allocate_page().unwrap());
+        }
+        assert!(bpm.dirty_page_ids().len() >= size - 1);
+        bpm
+    }
+
+    #[test]
+    fn the_flusher_brings_the_dirty_count_down_to_the_watermark() {
+        let bpm = dirty_pool(8);
+        bpm.start_background_flusher(Duration::from_millis(5), 0.25);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while bpm.dirty_page_ids().len() > 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(bpm.dirty_page_ids().len(), 2);
+        bpm.stop_background_flusher();
+        assert!(bpm.take_deferred_errors().is_empty());
+    }
+
+    #[test]
+    fn a_pass_flushes_down_to_the_watermark_and_skips_pinned_pages() {
+        let bpm = dirty_pool(4);
+        let dirty = bpm.dirty_page_ids();
+        let pinned = bpm.fetch_page(dirty[0]).unwrap();
+        assert_eq!(bpm.flush_to_watermark(0.0).unwrap(), dirty.len() - 1);
+        assert_eq!(bpm.dirty_page_ids(), vec![dirty[0]]);
+        assert_eq!(bpm.flush_to_watermark(0.5).unwrap(), 0);
+        drop(pinned);
+    }
+
+    #[test]
+    fn the_flusher_doesnt_keep_the_pool_alive() {
+        let bpm = dirty_pool(2);
+        let weak = Arc::downgrade(&bpm);
+        bpm.start_background_flusher(Duration::from_millis(1), 0.5);
+        drop(bpm);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,87 @@
+/*
+* The buffer pool's physical frames.
+* Guards borrow a frame's latch for as long as the pool lives, so a frame can never move or go away once it exists.
+* Growing the pool adds a new chunk next to the old ones instead of reallocating one big Vec, and nothing is
+* ever freed before the pool itself: shrinking only retires frames (see BufferPoolManager::resize).
+* Chunks double like a Vec would, chunk 0 is the initial pool size and chunk k >= 1 holds base << (k - 1) frames,
+* so frame k's chunk is found with a little arithmetic and MAX_CHUNKS is more growth than anyone will need.
+*/
+
+use std::ops::Index;
+use std::sync::{OnceLock, RwLock};
+
+use super::page::Page;
+use super::page_constants::FrameId;
+
+const MAX_CHUNKS: usize = 48;
+
+pub(crate) struct Frames {
+    base: usize, // size of chunk 0
+    chunks: [OnceLock<Box<[RwLock<Page>]>>; MAX_CHUNKS],
+}
+
+impl Frames {
+    pub(crate) fn new(size: usize) -> Self {
+        let frames = Self { base: size.max(1), chunks: std::array::from_fn(|_| OnceLock::new()) };
+        frames.chunks[0].get_or_init(|| empty_frames(frames.base));
+        frames
+    }
+
+    // frames allocated so far, the pool may be using fewer
+    pub(crate) fn capacity(&self) -> usize {
+        (0..MAX_CHUNKS).take_while(|&k| self.chunks[k].get().is_some()).map(|k| self.chunk_len(k)).sum()
+    }
+
+    // allocates chunks until there are at least size frames, false if that's past what MAX_CHUNKS can hold
+    pub(crate) fn reserve(&self, size: usize) -> bool {
+        let mut capacity = self.capacity();
+        let mut k = self.chunk_count();
+        while capacity < size {
+            if k == MAX_CHUNKS {
+                return false;
+            }
+            let len = self.chunk_len(k);
+            self.chunks[k].get_or_init(|| empty_frames(len));
+            capacity += len;
+            k += 1;
+        }
+        true
+    }
+
+    fn chunk_count(&self) -> usize {
+        (0..MAX_CHUNKS).take_while(|&k| self.chunks[k].get().is_some()).count()
+    }
+
+    fn chunk_len(&self, k: usize) -> usize {
+        if k == 0 { self.base } else { self.base << (k - 1) }
+    }
+
+    // (chunk, index in chunk) of frame_id
+    fn locate(&self, frame_id: FrameId) -> (usize, usize) {
+        if frame_id < self.base {
+            return (0, frame_id);
+        }
+        // chunk k >= 1 covers [base << (k - 1), base << k)
+        let k = (frame_id / self.base).ilog2() as usize + 1;
+        (k, frame_id - (self.base << (k - 1)))
+    }
+}
+
+impl Index<FrameId> for Frames {
+    type Output = RwLock<Page>;
+
+    fn index(&self, frame_id: FrameId) -> &RwLock<Page> {
+        let (chunk, at) = self.locate(frame_id);
+        &self.chunks[chunk].get().expect("frame was never allocated")[at]
+    }
+}
+
+fn empty_frames(len: usize) -> Box<[RwLock<Page>]> {
+    (0..len)
+        .map(|_| {
+            let mut frame = Page::default(); //use default pages (page types shouldn't matter)
+            frame.page_id = None; // empty frame, otherwise it looks like it's holding page 0
+            RwLock::new(frame)
+        })
+        .collect()
+}
@@ -0,0 +1,313 @@
+/*
+* Free space map: remembers roughly how much room each page has so inserts can go straight to a page that fits
+* instead of scanning or allocating a new page every time.
+* Free space is kept in coarse buckets of BUCKET_BYTES, a page in bucket b has at least b * BUCKET_BYTES free.
+* Lookups only hand out pages whose bucket guarantees the room, so the answer can be a bit pessimistic but
+* never points at a page that's too full (as long as whoever changes a page calls update).
+* Lives in memory, one per page type in the buffer pool (BufferPoolManager::free_space_map), and gets saved on every
+* flush_all (so checkpoints too) into an overflow chain the superblock points at. Opening loads it back from there
+* as long as no page has been written since it was saved, otherwise (or for a page type it has no map of) the map
+* is rebuilt from the pages' headers and slot directories the first time it's needed.
+*
+* Saved maps, one after another:
+* | page_type: u16 | pages: u64 | (page_id: u64, bucket: u8) * pages |
+*/
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use super::buffer_pool_manager::BufferPoolManager;
+use super::page::PageType;
+use super::page_constants::{PageId, PAGE_SIZE};
+use super::slotted_page::SlottedPage;
+
+const BUCKETS: usize = 64;
+const BUCKET_BYTES: usize = PAGE_SIZE / BUCKETS;
+
+struct FreeSpaceState {
+    bucket_of: HashMap<PageId, usize>,
+    buckets: Vec<BTreeSet<PageId>>, // lowest page ids first so inserts pack into the front of the file
+    changes: u64,                   // how often a page changed buckets, counts up from 0
+    saved: Option<u64>,             // changes as of the copy that was last saved, None if there isn't one
+}
+
+pub struct FreeSpaceMap {
+    state: Mutex<FreeSpaceState>,
+}
+
+impl FreeSpaceMap {
+    pub fn new() -> Self {
+        let state = FreeSpaceState {
+            bucket_of: HashMap::new(),
+            buckets: vec![BTreeSet::new(); BUCKETS],
+            changes: 0,
+            saved: None,
+        };
+        Self { state: Mutex::new(state) }
+    }
+
+    // the room every page_type page has right now, they're all read in once
+    // pages of the type that were never formatted as slotted pages are left out, nothing could be inserted into them
+    pub(crate) fn of_pages(bpm: &BufferPoolManager, page_type: PageType) -> Self {
+        let map = Self::new();
+        for guard in bpm.iter_pages_of_type(page_type) {
+            let page = SlottedPage::new(&*guard);
+            if page.is_formatted() {
+                map.update(guard.page_id, page.available_space());
+            }
+        }
+        map
+    }
+
+    // records that page_id now has free_bytes of room
+    pub fn update(&self, page_id: PageId, free_bytes: usize) {
+        let bucket = (free_bytes / BUCKET_BYTES).min(BUCKETS - 1);
+        let mut state = self.state.lock().unwrap();
+        let old = state.bucket_of.insert(page_id, bucket);
+        if old == Some(bucket) {
+            return;
+        }
+        if let Some(old) = old {
+            state.buckets[old].remove(&page_id);
+        }
+        state.buckets[bucket].insert(page_id);
+        state.changes += 1;
+    }
+
+    // forget about page_id, e.g. after it has been deallocated
+    pub fn remove(&self, page_id: PageId) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.bucket_of.remove(&page_id) {
+            state.buckets[old].remove(&page_id);
+            state.changes += 1;
+        }
+    }
+
+    // some page with at least bytes free, None if no known page is guaranteed to have that much
+    pub fn find_page_with_room(&self, bytes: usize) -> Option<PageId> {
+        let first = bytes.div_ceil(BUCKET_BYTES);
+        let state = self.state.lock().unwrap();
+        state.buckets.get(first..)?.iter().find_map(|bucket| bucket.first().copied())
+    }
+
+    // how many changes the map has had, compare with a later call to see if it changed in between
+    pub(crate) fn changes(&self) -> u64 {
+        self.state.lock().unwrap().changes
+    }
+
+    // Appends the map to out the way the top of the file has it, as the map of page_type. Returns changes as of
+    // what was written
+    pub(crate) fn encode(&self, page_type: PageType, out: &mut Vec<u8>) -> u64 {
+        let state = self.state.lock().unwrap();
+        out.extend_from_slice(&(page_type as u16).to_le_bytes());
+        out.extend_from_slice(&(state.bucket_of.len() as u64).to_le_bytes());
+        for (bucket, pages) in state.buckets.iter().enumerate() {
+            for page_id in pages {
+                out.extend_from_slice(&page_id.to_le_bytes());
+                out.push(bucket as u8);
+            }
+        }
+        state.changes
+    }
+
+    // the maps encode wrote into blob, None unless it's nothing but whole maps. They count as saved
+    pub(crate) fn decode(mut blob: &[u8]) -> Option<Vec<(PageType, FreeSpaceMap)>> {
+        let mut maps = Vec::new();
+        while !blob.is_empty() {
+            let page_type = PageType::try_from(u16::from_le_bytes(blob.get(..2)?.try_into().unwrap())).ok()?;
+            let pages = u64::from_le_bytes(blob.get(2..10)?.try_into().unwrap()) as usize;
+            let end = pages.checked_mul(9)?.checked_add(10)?;
+            let entries = blob.get(10..end)?;
+            let map = Self::new();
+            for entry in entries.chunks(9) {
+                let bucket = entry[8] as usize;
+                if bucket >= BUCKETS {
+                    return None;
+                }
+                map.update(u64::from_le_bytes(entry[..8].try_into().unwrap()), bucket * BUCKET_BYTES);
+            }
+            map.mark_saved(map.changes());
+            maps.push((page_type, map));
+            blob = &blob[end..];
+        }
+        Some(maps)
+    }
+
+    // the copy encode returned changes for is the saved one now
+    pub(crate) fn mark_saved(&self, changes: u64) {
+        self.state.lock().unwrap().saved = Some(changes);
+    }
+
+    // whether the saved copy is the map as it is, false if there is none
+    pub(crate) fn is_saved(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.saved == Some(state.changes)
+    }
+}
+
+impl Default for FreeSpaceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::catalog::superblock::{Superblock, SUPERBLOCK_PAGE};
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::page::Page;
+    use crate::paging::page_constants::HEADER_SIZE;
+    use crate::store::node_store::NodeStore;
+
+    #[test]
+    fn only_pages_whose_bucket_guarantees_the_room_are_handed_out() {
+        let map = FreeSpaceMap::new();
+        map.update(1, BUCKET_BYTES - 1);
+        map.update(2, 3 * BUCKET_BYTES);
+        assert_eq!(map.find_page_with_room(1), Some(2));
+        assert_eq!(map.find_page_with_room(3 * BUCKET_BYTES), Some(2));
+        assert_eq!(map.find_page_with_room(3 * BUCKET_BYTES + 1), None);
+    }
+
+    #[test]
+    fn updates_move_pages_between_buckets() {
+        let map = FreeSpaceMap::new();
+        map.update(1, PAGE_SIZE);
+        map.update(2, PAGE_SIZE);
+        assert_eq!(map.find_page_with_room(100), Some(1));
+        map.update(1, 0);
+        assert_eq!(map.find_page_with_room(100), Some(2));
+        map.remove(2);
+        assert_eq!(map.find_page_with_room(100), None);
+    }
+
+    // a NodeStore page the way a file from before allocation formatted them has it, free space pointer at the header
+    fn unformatted(bpm: &BufferPoolManager) -> PageId {
+        let mut page = bpm.allocate_page_of_type(PageType::NodeStore).unwrap();
+        page.set_free_space_pointer(HEADER_SIZE as u32);
+        page.page_id
+    }
+
+    #[test]
+    fn rebuilding_skips_pages_that_were_never_formatted() {
+        let bpm = Arc::new(BufferPoolManager::new(8, MockDiskManager::new()).unwrap());
+        let raw = unformatted(&bpm);
+        let formatted = bpm.allocate_page_of_type(PageType::NodeStore).unwrap().page_id;
+
+        let map = FreeSpaceMap::of_pages(&bpm, PageType::NodeStore);
+        assert_eq!(map.find_page_with_room(1), Some(formatted));
+        assert!(!map.state.lock().unwrap().bucket_of.contains_key(&raw));
+    }
+
+    #[test]
+    fn a_store_opens_over_an_unformatted_page_of_its_type() {
+        let bpm = Arc::new(BufferPoolManager::new(8, MockDiskManager::new()).unwrap());
+        let raw = unformatted(&bpm);
+        let nodes = NodeStore::new(bpm);
+        let id = nodes.create_node(&[1]).unwrap();
+        assert_ne!(id.page_id(), raw);
+        assert_eq!(nodes.get_node(id).unwrap().labels, vec![1]);
+    }
+
+    fn buckets(map: &FreeSpaceMap) -> HashMap<PageId, usize> {
+        map.state.lock().unwrap().bucket_of.clone()
+    }
+
+    // whether the superblock on disk says the saved maps match the file
+    fn saved_current(disk: &MockDiskManager) -> bool {
+        let page = Page::from_bytes(disk.raw_page(SUPERBLOCK_PAGE).unwrap()).unwrap();
+        Superblock::new(&page).unwrap().free_space_current()
+    }
+
+    // a pool over disk with a few pages' worth of nodes in it, flushed
+    fn flushed_nodes(disk: &Arc<MockDiskManager>) -> (Arc<BufferPoolManager>, NodeStore) {
+        let bpm = Arc::new(BufferPoolManager::new(8, disk.clone()).unwrap());
+        let nodes = NodeStore::new(bpm.clone());
+        for _ in 0..300 {
+            nodes.create_node(&[1, 2]).unwrap();
+        }
+        bpm.flush_all().unwrap();
+        (bpm, nodes)
+    }
+
+    #[test]
+    fn encoded_maps_decode_to_the_same_buckets() {
+        let (nodes, props) = (FreeSpaceMap::new(), FreeSpaceMap::new());
+        nodes.update(3, PAGE_SIZE);
+        nodes.update(7, BUCKET_BYTES * 2);
+        props.update(4, 0);
+        let mut blob = Vec::new();
+        assert_eq!(nodes.encode(PageType::NodeStore, &mut blob), 2);
+        props.encode(PageType::PropertyStore, &mut blob);
+
+        let decoded = FreeSpaceMap::decode(&blob).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!((decoded[0].0, buckets(&decoded[0].1)), (PageType::NodeStore, buckets(&nodes)));
+        assert_eq!((decoded[1].0, buckets(&decoded[1].1)), (PageType::PropertyStore, buckets(&props)));
+        assert!(decoded.iter().all(|(_, map)| map.is_saved()));
+        // cut short, or not a page type
+        assert!(FreeSpaceMap::decode(&blob[..blob.len() - 1]).is_none());
+        assert!(FreeSpaceMap::decode(&[0xFF, 0xFF, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn a_saved_map_is_loaded_back_instead_of_rebuilt() {
+        let disk = Arc::new(MockDiskManager::new());
+        let (bpm, nodes) = flushed_nodes(&disk);
+        assert!(saved_current(&disk));
+        // only ever set on disk
+        assert!(!bpm.superblock().unwrap().free_space_current());
+        let saved = buckets(&bpm.free_space_map(PageType::NodeStore));
+        drop(nodes);
+        // gone without flushing again, the next open has to make do with what flush_all left
+        std::mem::forget(bpm);
+
+        let bpm = BufferPoolManager::new(8, disk.clone()).unwrap();
+        let reads = disk.reads();
+        let map = bpm.free_space_map(PageType::NodeStore);
+        assert_eq!(disk.reads(), reads);
+        assert_eq!(buckets(&map), saved);
+        assert_eq!(buckets(&FreeSpaceMap::of_pages(&bpm, PageType::NodeStore)), saved);
+    }
+
+    #[test]
+    fn a_page_written_after_the_save_gets_the_map_rebuilt() {
+        let disk = Arc::new(MockDiskManager::new());
+        let (bpm, nodes) = flushed_nodes(&disk);
+        let id = nodes.create_node(&[1]).unwrap();
+        bpm.flush_page(id.page_id()).unwrap();
+        assert!(!saved_current(&disk));
+        drop(nodes);
+        std::mem::forget(bpm);
+
+        let bpm = BufferPoolManager::new(8, disk.clone()).unwrap();
+        let reads = disk.reads();
+        let map = bpm.free_space_map(PageType::NodeStore);
+        assert!(disk.reads() > reads);
+        assert_eq!(buckets(&map), buckets(&FreeSpaceMap::of_pages(&bpm, PageType::NodeStore)));
+    }
+
+    #[test]
+    fn saving_again_replaces_the_chain_only_after_a_change() {
+        let disk = Arc::new(MockDiskManager::new());
+        let (bpm, nodes) = flushed_nodes(&disk);
+        let root = bpm.superblock().unwrap().free_space_root();
+        assert!(root.is_some());
+
+        bpm.flush_all().unwrap();
+        assert_eq!(bpm.superblock().unwrap().free_space_root(), root);
+        assert!(saved_current(&disk));
+
+        // enough to move the page it goes into down a bucket
+        for _ in 0..BUCKET_BYTES / 8 {
+            nodes.create_node(&[1]).unwrap();
+        }
+        bpm.flush_all().unwrap();
+        assert_ne!(bpm.superblock().unwrap().free_space_root(), root);
+        assert!(saved_current(&disk));
+        // the old chain went back to the free list
+        assert_eq!(bpm.iter_pages_of_type(PageType::Overflow).count(), 1);
+    }
+}
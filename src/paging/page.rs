@@ -1,13 +1,143 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(feature = "paranoid")]
+use std::sync::atomic::AtomicU64;
+
 use super::page_constants::{PAGE_SIZE, HEADER_SIZE, PageId};
+use super::checksum::crc32;
+
+// byte offset of PageHeader.checksum inside the page, these 4 bytes are left out when computing the checksum
+const CHECKSUM_OFFSET: usize = std::mem::offset_of!(PageHeader, checksum);
+// clean_crc of a page that's dirty or hasn't been seen clean yet
+#[cfg(feature = "paranoid")]
+const NO_CLEAN_CRC: u64 = u64::MAX;
 
 #[repr(u16)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum PageType {
     NodeStore = 0,
     Relationship = 1,
     PropertyStore = 2,
+    BTreeNode = 3,
+    HashBucket = 4,
+    Overflow = 5,
+    Dictionary = 6,
+    Superblock = 7,
+    Free = 8,
+    // stands in for a page that failed its checksum or header check under OnCorruption::Quarantine
+    Quarantined = 9,
 }
 
+// page_type in a header that isn't one of the PageType values, usually a corrupt or garbage page
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InvalidPageType(pub u16);
+
+impl std::fmt::Display for InvalidPageType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid page type {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidPageType {}
+
+// a page that isn't the type the caller was about to treat it as, see Page::expect_type
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PageTypeMismatch {
+    pub page_id: PageId,
+    pub expected: PageType,
+    pub found: u16, // raw, it might not be a PageType at all
+}
+
+impl std::fmt::Display for PageTypeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match PageType::try_from(self.found) {
+            Ok(found) => write!(f, "page {} is a {:?} page, expected {:?}", self.page_id, found, self.expected),
+            Err(e) => write!(f, "page {} has {}, expected {:?}", self.page_id, e, self.expected),
+        }
+    }
+}
+
+impl std::error::Error for PageTypeMismatch {}
+
+impl PageType {
+    // pages laid out as a SlottedPage (the record stores)
+    pub fn is_slotted(self) -> bool {
+        matches!(self, PageType::NodeStore | PageType::Relationship | PageType::PropertyStore)
+    }
+}
+
+impl TryFrom<u16> for PageType {
+    type Error = InvalidPageType;
+
+    fn try_from(raw: u16) -> Result<Self, Self::Error> {
+        match raw {
+            0 => Ok(PageType::NodeStore),
+            1 => Ok(PageType::Relationship),
+            2 => Ok(PageType::PropertyStore),
+            3 => Ok(PageType::BTreeNode),
+            4 => Ok(PageType::HashBucket),
+            5 => Ok(PageType::Overflow),
+            6 => Ok(PageType::Dictionary),
+            7 => Ok(PageType::Superblock),
+            8 => Ok(PageType::Free),
+            9 => Ok(PageType::Quarantined),
+            _ => Err(InvalidPageType(raw)),
+        }
+    }
+}
+
+// Version of the page layout written into every header, bump it whenever the on-disk format of any page changes and
+// teach migrate_page how to bring the previous one up to date.
+// 0 is what pages from before the field existed have there (it was padding), their layout is the same as 1
+pub const PAGE_FORMAT_VERSION: u16 = 1;
+
+// page whose header says it has a format version we can't read, a newer version than this build knows or garbage
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UnsupportedVersion(pub u16);
+
+impl std::fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unsupported page format version {} (this build reads up to {})", self.0, PAGE_FORMAT_VERSION)
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+// Brings page, written with format old_version, up to PAGE_FORMAT_VERSION. Pages read off disk go through this
+// before anyone sees them. Doesn't dirty the page, the new version goes out the next time it gets written anyway
+pub fn migrate_page(old_version: u16, page: &mut Page) -> Result<(), UnsupportedVersion> {
+    match old_version {
+        PAGE_FORMAT_VERSION => return Ok(()),
+        0 => {} // same layout, only the version needs stamping
+        _ => return Err(UnsupportedVersion(old_version)),
+    }
+    page.get_header_mut().version = PAGE_FORMAT_VERSION;
+    Ok(())
+}
+
+// Errors from reading/writing inside a page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PageError {
+    OutOfBounds { offset: usize, len: usize }, // [offset, offset + len) doesn't fit inside PAGE_SIZE
+    // a header offset outside [HEADER_SIZE, PAGE_SIZE], the page is corrupt
+    BadHeaderOffset { field: &'static str, value: u32 },
+}
+
+impl std::fmt::Display for PageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PageError::OutOfBounds { offset, len } => {
+                write!(f, "access of {} bytes at offset {} is outside the page", len, offset)
+            }
+            PageError::BadHeaderOffset { field, value } => {
+                write!(f, "header {} is {}, outside [{}, {}]", field, value, HEADER_SIZE, PAGE_SIZE)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PageError {}
+
 // Page header containing metadata
 //repr C forces the compiler to not optimize placements of each field as rust compiler will optimize the field placements
 //to condense size and remove padding
@@ -16,11 +146,13 @@ pub enum PageType {
 pub struct PageHeader {
     pub lsn: u64,
     pub page_id: u64,
-    pub checksum: u32, //TODO: add checksum, some kind of encryption to ensure page integrity when loading to and from disk since the OS moves 4k at a time but our page is 8k
+    pub checksum: u32, //CRC32 of the page (minus this field), stamped by the disk manager so torn (partial sector) writes of a page get caught
     pub free_space_pointer: u32,
     pub item_count: u32, //necessary for fast aggregation queries
-    pub page_type: PageType, //for debugging and robustness
-    pub _padding: u16, //ensure 8 byte alignment
+    // for debugging and robustness. Kept as the raw u16 since it comes straight off disk and any value
+    // that isn't a PageType would be UB as the enum, go through page_type() to read it
+    pub page_type: u16,
+    pub version: u16, //PAGE_FORMAT_VERSION the page was written with, also keeps the header 8 byte aligned
 }
 
 impl PageHeader {
@@ -32,10 +164,14 @@ impl PageHeader {
             checksum: 0,
             free_space_pointer: HEADER_SIZE as u32,
             item_count: 0,
-            page_type,
-            _padding: 0,
+            page_type: page_type as u16,
+            version: PAGE_FORMAT_VERSION,
         }
     }
+
+    pub fn page_type(&self) -> Result<PageType, InvalidPageType> {
+        PageType::try_from(self.page_type)
+    }
 }
 
 // A page in the database backed by a contiguous byte array
@@ -46,9 +182,12 @@ pub struct Page {
 
     // Runtime metadata (will not be written to disk, only on RAM)
     pub page_id: Option<PageId>, // included this here so we don't have do fetch header every time we want page_id
-    pub is_dirty: bool,
-    pub pin_count: u32,
-    pub ref_bit: bool,
+    // atomic so the buffer pool can clear it after a flush while only holding a read latch on the frame
+    is_dirty: AtomicBool,
+    // CRC32 of data as of the last time the page went clean (loaded or written out), see verify_clean
+    #[cfg(feature = "paranoid")]
+    clean_crc: AtomicU64,
+    // pin count and ref bit are buffer pool bookkeeping, see FrameMeta in buffer_pool_manager.rs
 }
 
 impl Page {
@@ -57,25 +196,54 @@ impl Page {
         let mut page = Self {
             data: [0; PAGE_SIZE],
             page_id: Some(page_id),
-            is_dirty: false,
-            pin_count: 0,
-            ref_bit: true,
+            is_dirty: AtomicBool::new(false),
+            #[cfg(feature = "paranoid")]
+            clean_crc: AtomicU64::new(NO_CLEAN_CRC),
         };
         page.write_header(PageHeader::new(page_id, page_type));
         page
     }
 
-    // Create a page from raw bytes (e.g., after reading from disk)
-    pub fn from_bytes(data: [u8; PAGE_SIZE]) -> Self {
+    // Create a page from raw bytes (e.g., after reading from disk), fails if the header doesn't pass validate
+    // doesn't migrate, pages coming off disk want try_from_bytes
+    pub fn from_bytes(data: [u8; PAGE_SIZE]) -> Result<Self, PageError> {
         let mut page = Self {
             data,
             page_id: None,
-            is_dirty: false,
-            pin_count: 0,
-            ref_bit: false,
+            is_dirty: AtomicBool::new(false),
+            #[cfg(feature = "paranoid")]
+            clean_crc: AtomicU64::new(NO_CLEAN_CRC),
         };
+        page.validate()?;
         page.page_id = Some(page.get_header().page_id);
-        page
+        Ok(page)
+    }
+
+    // from_bytes for pages coming off disk, migrates older format versions and fails (with InvalidData) on ones we
+    // can't read or whose header doesn't pass validate
+    pub fn try_from_bytes(data: [u8; PAGE_SIZE]) -> io::Result<Self> {
+        // validated first, migrating stamps a version into never written pages which makes them look non blank
+        let mut page = Self::from_bytes(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        page.migrate().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(page)
+    }
+
+    // Checks the header offsets are inside [HEADER_SIZE, PAGE_SIZE], so a corrupt page fails here instead of
+    // underflowing in get_free_space or indexing past the end later. An all zero page (never written) passes
+    pub fn validate(&self) -> Result<(), PageError> {
+        let free_space_pointer = self.get_free_space_pointer();
+        if (HEADER_SIZE..=PAGE_SIZE).contains(&(free_space_pointer as usize)) {
+            return Ok(());
+        }
+        if free_space_pointer == 0 && self.data.iter().all(|&b| b == 0) {
+            return Ok(());
+        }
+        Err(PageError::BadHeaderOffset { field: "free_space_pointer", value: free_space_pointer })
+    }
+
+    // migrate_page from whatever version the header says
+    pub(crate) fn migrate(&mut self) -> Result<(), UnsupportedVersion> {
+        migrate_page(self.version(), self)
     }
 
     // ==================== Header Management ====================
@@ -98,34 +266,59 @@ impl Page {
         self.get_header().page_id
     }
 
+    // errors if the header holds a type we don't know, e.g. the page is corrupt
+    pub fn page_type(&self) -> Result<PageType, InvalidPageType> {
+        self.get_header().page_type()
+    }
+
+    // Err unless the header says expected, for code about to read the page as one of expected's pages
+    pub fn expect_type(&self, expected: PageType) -> Result<(), PageTypeMismatch> {
+        let header = self.get_header();
+        if header.page_type() == Ok(expected) {
+            return Ok(());
+        }
+        Err(PageTypeMismatch { page_id: header.page_id, expected, found: header.page_type })
+    }
+
+    pub fn version(&self) -> u16 {
+        self.get_header().version
+    }
+
     pub fn get_lsn(&self) -> u64 {
         self.get_header().lsn
     }
 
-    pub fn get_free_space_pointer(&self) -> u32 {
-        self.get_header().free_space_pointer
+    pub fn get_checksum(&self) -> u32 {
+        self.get_header().checksum
     }
 
-    pub fn get_pin_count(&self) -> u32 {
-        self.pin_count
+    pub fn get_free_space_pointer(&self) -> u32 {
+        self.get_header().free_space_pointer
     }
 
-    pub fn is_pinned(&self) -> bool {
-        self.pin_count > 0
+    // number of live records on the page, kept up to date by the slotted page layout
+    pub fn get_item_count(&self) -> u32 {
+        self.get_header().item_count
     }
 
     pub fn is_dirty(&self) -> bool {
-        self.is_dirty
+        self.is_dirty.load(Ordering::Acquire)
     }
 
+    // 0 for a corrupt free_space_pointer past the end of the page, see validate
     pub fn get_free_space(&self) -> usize {
-        PAGE_SIZE - self.get_header().free_space_pointer as usize
+        PAGE_SIZE.saturating_sub(self.get_header().free_space_pointer as usize)
     }
 
     pub fn get_data(&self) -> &[u8; PAGE_SIZE] {
         &self.data
     }
 
+    // copy of the raw page, for holding on to the contents after the latch is gone
+    pub fn to_owned_bytes(&self) -> [u8; PAGE_SIZE] {
+        self.data
+    }
+
     pub fn get_data_mut(&mut self) -> &mut [u8; PAGE_SIZE] {
         &mut self.data
     }
@@ -145,32 +338,138 @@ impl Page {
         self.get_header_mut().page_id = page_id;
     }
 
+    pub fn set_page_type(&mut self, page_type: PageType) {
+        self.get_header_mut().page_type = page_type as u16;
+    }
+
     pub fn set_lsn(&mut self, lsn: u64) {
         self.get_header_mut().lsn = lsn;
     }
 
+    pub fn set_checksum(&mut self, checksum: u32) {
+        self.get_header_mut().checksum = checksum;
+    }
+
     pub fn set_free_space_pointer(&mut self, pointer: u32) {
         self.get_header_mut().free_space_pointer = pointer;
     }
 
-    pub fn set_pin_count(&mut self, count: u32) {
-        self.pin_count = count;
+    // SlottedPage already keeps this in sync on insert/delete, only needed for pages with some other layout
+    pub fn set_item_count(&mut self, count: u32) {
+        self.get_header_mut().item_count = count;
     }
 
     pub fn set_dirty(&mut self, dirty: bool) {
-        self.is_dirty = dirty;
+        #[cfg(feature = "paranoid")]
+        self.record_clean_crc(dirty);
+        *self.is_dirty.get_mut() = dirty;
     }
 
-    // ==================== Pin Management ====================
+    // for the buffer pool once the page has been written out, readers may still be looking at it
+    pub(crate) fn mark_clean(&self) {
+        // crc before the dirty bit, so a reader that sees the page clean also sees the crc that goes with it
+        #[cfg(feature = "paranoid")]
+        self.record_clean_crc(false);
+        self.is_dirty.store(false, Ordering::Release);
+    }
 
-    pub fn pin(&mut self) {
-        self.pin_count += 1;
+    #[cfg(feature = "paranoid")]
+    fn record_clean_crc(&self, dirty: bool) {
+        let crc = if dirty { NO_CLEAN_CRC } else { crc32(&[&self.data]) as u64 };
+        self.clean_crc.store(crc, Ordering::Release);
     }
 
-    pub fn unpin(&mut self) -> bool {
-        assert!(self.pin_count > 0, "pin_count must be non negative");
-        self.pin_count -= 1;
-        true
+    // paranoid builds only: panics if a clean page doesn't hold the bytes it had when it went clean. Anything
+    // changing a page through the pool dirties it first, so a mismatch means something wrote to it behind the
+    // pool's back (a wild write). Called on every fetch, which is why release builds leave it out
+    #[cfg(feature = "paranoid")]
+    pub(crate) fn verify_clean(&self) {
+        let expected = self.clean_crc.load(Ordering::Acquire);
+        if self.is_dirty() || expected == NO_CLEAN_CRC {
+            return;
+        }
+        let found = crc32(&[&self.data]) as u64;
+        assert!(found == expected, "page {:?} changed in memory while clean, something wrote to it outside the pool",
+            self.page_id);
+    }
+
+    // ==================== Checksum ====================
+
+    // The bytes the checksum covers: the persisted data array minus the 4 checksum bytes, before them and after
+    // them in that order. page_id and is_dirty live outside data and never reach disk, so they can't be in here
+    // or the checksum would change between writing a page out and loading it back
+    pub fn checksum_region(&self) -> [&[u8]; 2] {
+        checksum_region_of(&self.data)
+    }
+
+    // CRC32 over checksum_region, stays the same across a write and a load
+    pub fn compute_checksum(&self) -> u32 {
+        compute_checksum_of(&self.data)
+    }
+
+    pub fn verify_checksum(&self) -> bool {
+        verify_checksum_of(&self.data)
+    }
+
+    // ==================== Debugging ====================
+
+    // Human readable view of the page: header fields, the slot directory for slotted page types, and a hexdump
+    // of everything after the header (runs of all zero lines are folded into one "*" like hexdump does)
+    // debug builds only, it's a lot of string formatting for a whole page
+    #[cfg(debug_assertions)]
+    pub fn debug_dump(&self) -> String {
+        use std::fmt::Write;
+        use super::slotted_page::{SlottedPage, MAX_SLOTS};
+
+        let header = self.get_header();
+        let mut out = String::new();
+        let page_type = match self.page_type() {
+            Ok(page_type) => format!("{:?}", page_type),
+            Err(e) => e.to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "page_id={} lsn={} checksum={:#010x} free_space_pointer={} item_count={} page_type={} version={}",
+            header.page_id, header.lsn, header.checksum, header.free_space_pointer, header.item_count, page_type,
+            header.version
+        );
+
+        if matches!(self.page_type(), Ok(PageType::NodeStore | PageType::Relationship | PageType::PropertyStore)) {
+            let slotted = SlottedPage::new(self);
+            let count = slotted.slot_count();
+            let _ = writeln!(out, "slots: {}", count);
+            for slot in 0..count.min(MAX_SLOTS as u16) {
+                match slotted.slot(slot) {
+                    Some(_) if slotted.is_tombstone(slot) => { let _ = writeln!(out, "  [{}] tombstone", slot); }
+                    Some(_) if slotted.is_forward(slot) => {
+                        let (page_id, target) = slotted.resolve_forward(slot).unwrap();
+                        let _ = writeln!(out, "  [{}] forward to page {} slot {}", slot, page_id, target);
+                    }
+                    Some((offset, length)) => { let _ = writeln!(out, "  [{}] offset={} len={}", slot, offset, length); }
+                    None => {}
+                }
+            }
+        }
+
+        let _ = writeln!(out, "data:");
+        let mut folded = false;
+        for (i, line) in self.data[HEADER_SIZE..].chunks(16).enumerate() {
+            if line.iter().all(|&b| b == 0) && i > 0 {
+                if !folded {
+                    let _ = writeln!(out, "*");
+                    folded = true;
+                }
+                continue;
+            }
+            folded = false;
+            let _ = write!(out, "{:04x}:", HEADER_SIZE + i * 16);
+            for byte in line {
+                let _ = write!(out, " {:02x}", byte);
+            }
+            let ascii: String = line.iter().map(|&b| if b.is_ascii_graphic() { b as char } else { '.' }).collect();
+            let _ = writeln!(out, "  |{}|", ascii);
+        }
+        out
     }
 
     // ==================== Space Management ====================
@@ -197,10 +496,9 @@ impl Page {
         let page_id = self.get_header().page_id;
         let page_type = self.get_header().page_type;
         self.data = [0; PAGE_SIZE];
-        self.write_header(PageHeader::new(page_id, page_type));
-        self.pin_count = 0;
-        self.is_dirty = false;
-        self.ref_bit = false;
+        self.write_header(PageHeader::new(page_id, PageType::NodeStore));
+        self.get_header_mut().page_type = page_type; // keep it as is, even if it's not a valid type
+        self.set_dirty(false);
         self.page_id = Some(page_id);
     }
 
@@ -213,7 +511,7 @@ impl Page {
         }
 
         self.data[start..end].copy_from_slice(data);
-        self.is_dirty = true;
+        self.set_dirty(true);
         true
     }
 
@@ -227,6 +525,90 @@ impl Page {
 
         Some(&self.data[start..end])
     }
+
+    // ==================== Typed Access ====================
+    // All little endian, all bounds checked against PAGE_SIZE
+
+    fn check_bounds(offset: u32, len: usize) -> Result<std::ops::Range<usize>, PageError> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= PAGE_SIZE => Ok(start..end),
+            _ => Err(PageError::OutOfBounds { offset: start, len }),
+        }
+    }
+
+    pub fn write_u32_at(&mut self, offset: u32, value: u32) -> Result<(), PageError> {
+        let range = Self::check_bounds(offset, 4)?;
+        self.data[range].copy_from_slice(&value.to_le_bytes());
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    pub fn read_u32_at(&self, offset: u32) -> Result<u32, PageError> {
+        let range = Self::check_bounds(offset, 4)?;
+        Ok(u32::from_le_bytes(self.data[range].try_into().unwrap()))
+    }
+
+    pub fn write_u64_at(&mut self, offset: u32, value: u64) -> Result<(), PageError> {
+        let range = Self::check_bounds(offset, 8)?;
+        self.data[range].copy_from_slice(&value.to_le_bytes());
+        self.set_dirty(true);
+        Ok(())
+    }
+
+    pub fn read_u64_at(&self, offset: u32) -> Result<u64, PageError> {
+        let range = Self::check_bounds(offset, 8)?;
+        Ok(u64::from_le_bytes(self.data[range].try_into().unwrap()))
+    }
+
+    // writes a u16 length prefix followed by data, returns the total number of bytes written
+    pub fn write_bytes_with_len(&mut self, offset: u32, data: &[u8]) -> Result<usize, PageError> {
+        let total = data.len() + 2;
+        let range = Self::check_bounds(offset, total)?;
+        if data.len() > u16::MAX as usize {
+            return Err(PageError::OutOfBounds { offset: range.start, len: total });
+        }
+        self.data[range.start..range.start + 2].copy_from_slice(&(data.len() as u16).to_le_bytes());
+        self.data[range.start + 2..range.end].copy_from_slice(data);
+        self.set_dirty(true);
+        Ok(total)
+    }
+
+    // reads back something written by write_bytes_with_len
+    pub fn read_bytes_with_len(&self, offset: u32) -> Result<&[u8], PageError> {
+        let len_range = Self::check_bounds(offset, 2)?;
+        let len = u16::from_le_bytes(self.data[len_range].try_into().unwrap()) as usize;
+        let range = Self::check_bounds(offset + 2, len)?;
+        Ok(&self.data[range])
+    }
+
+    // overwrites offset..offset + bytes.len() with bytes, how redo/undo put a logged Delta run back
+    pub fn apply_delta(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PageError> {
+        let range = Self::check_bounds(offset, bytes.len())?;
+        self.data[range].copy_from_slice(bytes);
+        self.set_dirty(true);
+        Ok(())
+    }
+}
+
+// raw byte versions so the disk manager can checksum buffers without building a Page
+// the order of the two halves is part of the file format, swapping them would fail every page on disk
+pub fn checksum_region_of(data: &[u8; PAGE_SIZE]) -> [&[u8]; 2] {
+    [&data[..CHECKSUM_OFFSET], &data[CHECKSUM_OFFSET + 4..]]
+}
+
+pub fn compute_checksum_of(data: &[u8; PAGE_SIZE]) -> u32 {
+    crc32(&checksum_region_of(data))
+}
+
+pub fn verify_checksum_of(data: &[u8; PAGE_SIZE]) -> bool {
+    let stored = u32::from_ne_bytes(data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].try_into().unwrap());
+    stored == compute_checksum_of(data)
+}
+
+pub fn stamp_checksum(data: &mut [u8; PAGE_SIZE]) {
+    let checksum = compute_checksum_of(data);
+    data[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 4].copy_from_slice(&checksum.to_ne_bytes());
 }
 
 impl Default for Page {
@@ -234,3 +616,264 @@ impl Default for Page {
         Self::new(0, PageType::NodeStore)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_survives_a_trip_through_bytes() {
+        let mut page = Page::new(7, PageType::PropertyStore);
+        page.set_lsn(99);
+        page.set_free_space_pointer(HEADER_SIZE as u32 + 10);
+        page.set_item_count(3);
+        page.set_checksum(0xabcd);
+
+        let copy = Page::from_bytes(page.to_owned_bytes()).unwrap();
+        let header = copy.get_header();
+        assert_eq!(copy.page_id, Some(7));
+        assert_eq!(header.lsn, 99);
+        assert_eq!(header.page_id, 7);
+        assert_eq!(header.checksum, 0xabcd);
+        assert_eq!(header.free_space_pointer, HEADER_SIZE as u32 + 10);
+        assert_eq!(header.item_count, 3);
+        assert_eq!(header.page_type(), Ok(PageType::PropertyStore));
+        assert_eq!(header.version, PAGE_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn header_takes_up_header_size_bytes() {
+        assert_eq!(std::mem::size_of::<PageHeader>(), HEADER_SIZE);
+        let page = Page::new(1, PageType::NodeStore);
+        assert_eq!(page.get_free_space_pointer() as usize, HEADER_SIZE);
+        assert_eq!(page.get_data_segment().len(), PAGE_SIZE - HEADER_SIZE);
+    }
+
+    #[test]
+    fn typed_values_read_back_little_endian() {
+        let mut page = Page::new(1, PageType::NodeStore);
+        page.write_u32_at(40, 0x0102_0304).unwrap();
+        page.write_u64_at(48, u64::MAX - 1).unwrap();
+        assert_eq!(page.read_u32_at(40), Ok(0x0102_0304));
+        assert_eq!(page.read_u64_at(48), Ok(u64::MAX - 1));
+        assert_eq!(&page.get_data()[40..44], &[4, 3, 2, 1]);
+        assert!(page.is_dirty());
+    }
+
+    #[test]
+    fn typed_access_past_the_end_is_an_error() {
+        let mut page = Page::new(1, PageType::NodeStore);
+        let last = (PAGE_SIZE - 4) as u32;
+        assert!(page.write_u32_at(last, 1).is_ok());
+        assert_eq!(page.write_u32_at(last + 1, 1), Err(PageError::OutOfBounds { offset: PAGE_SIZE - 3, len: 4 }));
+        assert!(page.read_u64_at(last).is_err());
+        assert!(page.read_u64_at(u32::MAX).is_err());
+    }
+
+    #[test]
+    fn length_prefixed_bytes_round_trip() {
+        let mut page = Page::new(1, PageType::NodeStore);
+        assert_eq!(page.write_bytes_with_len(64, b"graph"), Ok(7));
+        assert_eq!(page.read_bytes_with_len(64), Ok(&b"graph"[..]));
+        assert!(page.write_bytes_with_len((PAGE_SIZE - 6) as u32, b"graph").is_err());
+    }
+
+    #[test]
+    fn a_bogus_page_type_is_an_error() {
+        let mut bytes = Page::new(3, PageType::NodeStore).to_owned_bytes();
+        let offset = std::mem::offset_of!(PageHeader, page_type);
+        bytes[offset..offset + 2].copy_from_slice(&0x7777u16.to_le_bytes());
+        let page = Page::from_bytes(bytes).unwrap();
+        assert_eq!(page.page_type(), Err(InvalidPageType(0x7777)));
+        assert_eq!(page.get_header().page_type(), Err(InvalidPageType(0x7777)));
+    }
+
+    #[test]
+    fn every_page_type_round_trips_through_its_raw_value() {
+        for raw in 0..=9 {
+            assert_eq!(PageType::try_from(raw).map(|t| t as u16), Ok(raw));
+        }
+        assert_eq!(PageType::try_from(10), Err(InvalidPageType(10)));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn debug_dump_snapshot() {
+        let mut page = Page::new(5, PageType::Overflow);
+        page.set_lsn(12);
+        assert!(page.write_at(HEADER_SIZE as u32, b"hello, page dump!"));
+        let expected = "\
+page_id=5 lsn=12 checksum=0x00000000 free_space_pointer=32 item_count=0 page_type=Overflow version=1
+data:
+0020: 68 65 6c 6c 6f 2c 20 70 61 67 65 20 64 75 6d 70  |hello,.page.dump|
+0030: 21 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  |!...............|
+*
+";
+        assert_eq!(page.debug_dump(), expected);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn debug_dump_lists_the_slot_directory() {
+        use crate::paging::slotted_page::SlottedPage;
+        let mut page = Page::new(2, PageType::NodeStore);
+        let mut sp = SlottedPage::init(&mut page);
+        let gone = sp.insert_record(b"abc").unwrap();
+        sp.insert_record(b"de").unwrap();
+        sp.delete_record(gone);
+
+        let dump = page.debug_dump();
+        assert!(dump.contains("item_count=1 page_type=NodeStore"));
+        assert!(dump.contains(&format!("slots: 2\n  [0] tombstone\n  [1] offset={} len=2\n", PAGE_SIZE - 5)));
+    }
+
+    fn with_version(version: u16) -> [u8; PAGE_SIZE] {
+        let mut page = Page::new(4, PageType::NodeStore);
+        page.get_header_mut().version = version;
+        page.to_owned_bytes()
+    }
+
+    #[test]
+    fn pages_of_the_current_version_load_as_they_are() {
+        let page = Page::try_from_bytes(with_version(PAGE_FORMAT_VERSION)).unwrap();
+        assert_eq!(page.version(), PAGE_FORMAT_VERSION);
+        assert_eq!(page.page_type(), Ok(PageType::NodeStore));
+    }
+
+    #[test]
+    fn pages_of_an_unknown_version_are_rejected() {
+        let bogus = PAGE_FORMAT_VERSION + 1;
+        let Err(err) = Page::try_from_bytes(with_version(bogus)) else { panic!("version {bogus} loaded") };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&bogus.to_string()));
+        assert_eq!(migrate_page(bogus, &mut Page::from_bytes(with_version(bogus)).unwrap()), Err(UnsupportedVersion(bogus)));
+    }
+
+    #[test]
+    fn version_0_pages_get_stamped_with_the_current_version() {
+        let page = Page::try_from_bytes(with_version(0)).unwrap();
+        assert_eq!(page.version(), PAGE_FORMAT_VERSION);
+        assert!(!page.is_dirty());
+        // a page that was never written stays all zeros apart from the version
+        let blank = Page::try_from_bytes([0; PAGE_SIZE]).unwrap();
+        assert_eq!(blank.version(), PAGE_FORMAT_VERSION);
+    }
+
+    fn with_free_space_pointer(value: u32) -> [u8; PAGE_SIZE] {
+        let mut bytes = Page::new(2, PageType::NodeStore).to_owned_bytes();
+        let offset = std::mem::offset_of!(PageHeader, free_space_pointer);
+        bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn an_oversized_free_space_pointer_is_a_clean_error() {
+        let oversized = PAGE_SIZE as u32 + 1;
+        let expected = PageError::BadHeaderOffset { field: "free_space_pointer", value: oversized };
+        assert_eq!(Page::from_bytes(with_free_space_pointer(oversized)).err(), Some(expected.clone()));
+        let message = Page::from_bytes(with_free_space_pointer(u32::MAX)).err().unwrap().to_string();
+        assert_eq!(message, format!("header free_space_pointer is {}, outside [{}, {}]", u32::MAX, HEADER_SIZE, PAGE_SIZE));
+
+        let Err(err) = Page::try_from_bytes(with_free_space_pointer(oversized)) else { panic!("corrupt page loaded") };
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn a_free_space_pointer_inside_the_header_is_an_error_too() {
+        assert!(Page::from_bytes(with_free_space_pointer(HEADER_SIZE as u32 - 1)).is_err());
+        assert!(Page::from_bytes(with_free_space_pointer(0)).is_err());
+    }
+
+    #[test]
+    fn free_space_pointers_at_either_end_are_fine() {
+        let page = Page::from_bytes(with_free_space_pointer(PAGE_SIZE as u32)).unwrap();
+        assert_eq!(page.get_free_space(), 0);
+        let page = Page::from_bytes(with_free_space_pointer(HEADER_SIZE as u32)).unwrap();
+        assert_eq!(page.get_free_space(), PAGE_SIZE - HEADER_SIZE);
+        assert!(Page::from_bytes([0; PAGE_SIZE]).is_ok());
+    }
+
+    #[test]
+    fn apply_delta_overwrites_just_its_range() {
+        let mut page = Page::new(1, PageType::Overflow);
+        page.apply_delta(100, b"delta").unwrap();
+        assert_eq!(&page.get_data()[100..105], b"delta");
+        assert_eq!(page.get_data()[99], 0);
+        assert_eq!(page.get_data()[105], 0);
+        assert!(page.is_dirty());
+
+        page.apply_delta((PAGE_SIZE - 2) as u32, b"ok").unwrap();
+        assert_eq!(
+            page.apply_delta((PAGE_SIZE - 2) as u32, b"far"),
+            Err(PageError::OutOfBounds { offset: PAGE_SIZE - 2, len: 3 })
+        );
+        assert_eq!(&page.get_data()[PAGE_SIZE - 2..], b"ok");
+    }
+
+    #[test]
+    fn a_loaded_page_recomputes_the_checksum_it_was_stored_with() {
+        let mut page = Page::new(5, PageType::NodeStore);
+        page.write_u32_at(HEADER_SIZE as u32, 0xdead_beef).unwrap();
+        let mut bytes = page.to_owned_bytes();
+        stamp_checksum(&mut bytes);
+
+        let loaded = Page::from_bytes(bytes).unwrap();
+        assert_ne!(loaded.get_checksum(), 0);
+        assert_eq!(loaded.compute_checksum(), loaded.get_checksum());
+        assert!(loaded.verify_checksum());
+    }
+
+    #[test]
+    fn the_checksum_skips_runtime_fields_and_its_own_bytes() {
+        let mut page = Page::new(5, PageType::NodeStore);
+        let before = page.compute_checksum();
+        page.set_dirty(true);
+        page.page_id = Some(99);
+        page.set_checksum(0x1234_5678);
+        assert_eq!(page.compute_checksum(), before);
+        assert_eq!(page.checksum_region().iter().map(|part| part.len()).sum::<usize>(), PAGE_SIZE - 4);
+
+        page.write_u32_at(HEADER_SIZE as u32, 1).unwrap();
+        assert_ne!(page.compute_checksum(), before);
+    }
+
+    #[test]
+    fn expect_type_passes_the_right_type_and_names_the_wrong_one() {
+        let page = Page::new(3, PageType::NodeStore);
+        assert_eq!(page.expect_type(PageType::NodeStore), Ok(()));
+        let err = page.expect_type(PageType::Relationship).unwrap_err();
+        assert_eq!(err, PageTypeMismatch { page_id: 3, expected: PageType::Relationship, found: 0 });
+        assert_eq!(err.to_string(), "page 3 is a NodeStore page, expected Relationship");
+    }
+
+    #[test]
+    fn expect_type_reports_a_garbage_type_as_it_is() {
+        let mut page = Page::new(3, PageType::NodeStore);
+        page.get_header_mut().page_type = 77;
+        let err = page.expect_type(PageType::NodeStore).unwrap_err();
+        assert_eq!(err.found, 77);
+        assert_eq!(err.to_string(), "page 3 has invalid page type 77, expected NodeStore");
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    fn verify_clean_lets_dirty_and_untouched_pages_through() {
+        let mut page = Page::new(2, PageType::NodeStore);
+        page.set_dirty(false);
+        page.verify_clean();
+        page.write_u32_at(HEADER_SIZE as u32, 5).unwrap();
+        page.verify_clean();
+        page.mark_clean();
+        page.verify_clean();
+    }
+
+    #[cfg(feature = "paranoid")]
+    #[test]
+    #[should_panic(expected = "changed in memory while clean")]
+    fn verify_clean_catches_a_write_that_skipped_the_dirty_bit() {
+        let mut page = Page::new(2, PageType::NodeStore);
+        page.set_dirty(false);
+        page.get_data_mut()[HEADER_SIZE] ^= 0xff;
+        page.verify_clean();
+    }
+}
@@ -1,8 +1,16 @@
 use super::page::PageHeader;
 
+// picked at compile time with the page-4k / page-16k features, see Cargo.toml
+#[cfg(all(feature = "page-4k", feature = "page-16k"))]
+compile_error!("the page-4k and page-16k features can't both be on");
+#[cfg(feature = "page-4k")]
+pub const PAGE_SIZE: usize = 4096;
+#[cfg(all(feature = "page-16k", not(feature = "page-4k")))]
+pub const PAGE_SIZE: usize = 16384;
+#[cfg(not(any(feature = "page-4k", feature = "page-16k")))]
 pub const PAGE_SIZE: usize = 8192;
 pub const HEADER_SIZE: usize = std::mem::size_of::<PageHeader>();
-pub const BUFFER_SIZE: usize = 128; /* Temporary RAM size of 128 pages just for testing purposes */
+pub const BUFFER_SIZE: usize = 128; /* Default pool size in pages, see BufferPoolConfig to size it for the machine */
 
 pub type PageId = u64; /* Page identifier */
 pub type FrameId = usize; /* ID of a frame in RAM, currently set to 0...127      TODO: extend frames depending on RAM contraints */
\ No newline at end of file
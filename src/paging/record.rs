@@ -0,0 +1,150 @@
+/*
+* Typed records that know how to pack themselves into bytes, so the stores don't each hand roll the same
+* little endian encoding. SlottedPage::insert / SlottedPage::get work with anything that implements Record.
+* Everything is little endian like the rest of the page accessors.
+*/
+
+use std::fmt;
+
+// Why a record couldn't be decoded from its bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    Truncated { needed: usize, got: usize }, // the record is shorter than its fields say it should be
+    Invalid(&'static str),                   // bytes are there but don't make sense (bad tag, bad utf8, ...)
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Truncated { needed, got } => {
+                write!(f, "record truncated, needed {} bytes but only got {}", needed, got)
+            }
+            DecodeError::Invalid(what) => write!(f, "invalid record: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub trait Record: Sized {
+    // exactly how many bytes serialize will append
+    fn serialized_len(&self) -> usize;
+
+    // appends the encoded record to buf
+    fn serialize(&self, buf: &mut Vec<u8>);
+
+    fn deserialize(buf: &[u8]) -> Result<Self, DecodeError>;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.serialized_len());
+        self.serialize(&mut buf);
+        buf
+    }
+}
+
+// Cursor over a record's bytes for writing deserialize, every read is bounds checked
+pub struct RecordReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RecordReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.pos + len;
+        let bytes = self.buf.get(self.pos..end).ok_or(DecodeError::Truncated { needed: end, got: self.buf.len() })?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, DecodeError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, DecodeError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, DecodeError> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    // everything that hasn't been read yet
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.buf[self.pos.min(self.buf.len())..];
+        self.pos = self.buf.len();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::page::{Page, PageType};
+    use crate::paging::slotted_page::SlottedPage;
+
+    #[derive(Debug, PartialEq)]
+    struct Edge {
+        weight: u32,
+        label: String,
+    }
+
+    impl Record for Edge {
+        fn serialized_len(&self) -> usize {
+            4 + 2 + self.label.len()
+        }
+
+        fn serialize(&self, buf: &mut Vec<u8>) {
+            buf.extend_from_slice(&self.weight.to_le_bytes());
+            buf.extend_from_slice(&(self.label.len() as u16).to_le_bytes());
+            buf.extend_from_slice(self.label.as_bytes());
+        }
+
+        fn deserialize(buf: &[u8]) -> Result<Self, DecodeError> {
+            let mut reader = RecordReader::new(buf);
+            let weight = reader.u32()?;
+            let len = reader.u16()? as usize;
+            let label = std::str::from_utf8(reader.bytes(len)?).map_err(|_| DecodeError::Invalid("label"))?;
+            Ok(Edge { weight, label: label.to_string() })
+        }
+    }
+
+    #[test]
+    fn a_record_round_trips_through_a_page() {
+        let mut page = Page::new(1, PageType::Relationship);
+        let mut sp = SlottedPage::init(&mut page);
+        let edge = Edge { weight: 42, label: "knows".to_string() };
+        let slot = sp.insert(&edge).unwrap();
+        assert_eq!(sp.get_record(slot).unwrap().len(), edge.serialized_len());
+        assert_eq!(sp.get::<Edge>(slot), Some(Ok(edge)));
+        assert_eq!(sp.get::<Edge>(slot + 1), None);
+    }
+
+    #[test]
+    fn short_bytes_are_truncated_not_a_panic() {
+        let bytes = Edge { weight: 1, label: "abc".to_string() }.to_bytes();
+        assert_eq!(Edge::deserialize(&bytes[..7]), Err(DecodeError::Truncated { needed: 9, got: 7 }));
+        assert_eq!(Edge::deserialize(&bytes[..2]), Err(DecodeError::Truncated { needed: 4, got: 2 }));
+    }
+
+    #[test]
+    fn the_reader_hands_out_fields_in_order() {
+        let mut reader = RecordReader::new(&[1, 2, 0, 3, 0, 0, 0, 9, 9]);
+        assert_eq!(reader.u8(), Ok(1));
+        assert_eq!(reader.u16(), Ok(2));
+        assert_eq!(reader.u32(), Ok(3));
+        assert_eq!(reader.rest(), &[9, 9]);
+        assert_eq!(reader.rest(), &[] as &[u8]);
+    }
+}
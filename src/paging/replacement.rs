@@ -1,54 +1,518 @@
 /*
-* The current implementation is a simple clock replacement, make sure to change in the future 
+* The default implementation is a simple clock replacement, GCLOCK (clock with usage counters) and LRU-K are also available
+* for workloads where a single ref bit forgets hot pages too quickly or scans flush the pool
 * Database engines can not use the OS's demand paging algo because they need to 
 * optimize demand paging based on query plans and also ensure ACID compliance with the internal page metadata.
 * I separated the replacement with the buffer_pool_manager because we are probably going to change it 
 * and I'm trying to make it modular, we can add back to buffer_pool_manager.rs when its more concrete
 */
 
-use super::page::Page;
+use std::collections::VecDeque;
+use super::buffer_pool_manager::FrameMeta;
 use super::page_constants::FrameId;
 
+/*
+* Anything that can pick eviction victims for the buffer pool.
+* The buffer pool tells the replacer whenever a frame is accessed and whenever its pin status changes,
+* replacers that only look at the frames themselves (like clock) can ignore those callbacks.
+*/
+pub trait Replacer: Send {
+    // Find a victim FrameId to evict, None if nothing can be evicted
+    fn victim(&mut self, frames: &mut [FrameMeta]) -> Option<FrameId>;
+
+    // Called every time the buffer pool pins a frame
+    fn record_access(&mut self, frame: FrameId);
+
+    // Called when a frame becomes pinned (false) or fully unpinned (true)
+    fn set_evictable(&mut self, frame: FrameId, evictable: bool);
+
+    // Called when BufferPoolManager::resize changes the number of frames, victim only ever sees that many
+    // afterwards. New frames start out not evictable (the pool calls set_evictable once they hold a page)
+    // replacers without per frame state can keep the default
+    fn resize(&mut self, _size: usize) {}
+
+    // whether victim could pick frame right now, for BufferPoolManager::check_invariants
+    // replacers that go purely off the frame metadata can keep the default
+    fn is_evictable(&self, frame: FrameId, frames: &[FrameMeta]) -> bool {
+        !frames[frame].is_pinned()
+    }
+
+    // how often frame has been accessed lately, for BufferPoolManager::hot_pages. The replacers here all keep an
+    // AccessCounter for it, the default doesn't know and says 0
+    fn recent_accesses(&self, _frame: FrameId) -> u32 {
+        0
+    }
+}
+
+/*
+* Per frame access counts that fade out, so they say which pages have been busy lately rather than ever.
+* Every DECAY_WINDOW accesses per frame all counts get halved, and a frame's count starts over when it's evicted.
+*/
+#[derive(Debug, Default)]
+pub struct AccessCounter {
+    counts: Vec<u32>,
+    since_decay: usize,
+}
+
+const DECAY_WINDOW: usize = 8;
+
+impl AccessCounter {
+    pub fn new(size: usize) -> Self {
+        Self { counts: vec![0; size], since_decay: 0 }
+    }
+
+    pub fn record(&mut self, frame: FrameId) {
+        self.counts[frame] = self.counts[frame].saturating_add(1);
+        self.since_decay += 1;
+        if self.since_decay >= DECAY_WINDOW * self.counts.len() {
+            self.since_decay = 0;
+            for count in &mut self.counts {
+                *count /= 2;
+            }
+        }
+    }
+
+    pub fn get(&self, frame: FrameId) -> u32 {
+        self.counts.get(frame).copied().unwrap_or(0)
+    }
+
+    // frame got a new page, what the old one did doesn't count for it
+    pub fn reset(&mut self, frame: FrameId) {
+        self.counts[frame] = 0;
+    }
+
+    pub fn resize(&mut self, size: usize) {
+        self.counts.resize(size, 0);
+    }
+}
+
 pub struct ClockReplacer {
     hand: usize,      // clock hand pointer
     size: usize,      // total number of frames
+    accesses: AccessCounter,
 }
 
 impl ClockReplacer {
     pub fn new(size: usize) -> Self {
-        Self { hand: 0, size }
+        Self { hand: 0, size, accesses: AccessCounter::new(size) }
+    }
+
+    fn advance(&mut self) {
+        self.hand = (self.hand + 1) % self.size;
+    }
+
+    // a zero frame clock has nothing to hand out, and a hand that somehow ended up past the end starts over
+    // instead of indexing out of bounds
+    fn can_sweep(&mut self, frames: &[FrameMeta]) -> bool {
+        if self.size == 0 || frames.len() < self.size {
+            return false;
+        }
+        if self.hand >= self.size {
+            self.hand = 0;
+        }
+        true
     }
+}
 
+impl Replacer for ClockReplacer {
     // Find a victim FrameId to evict.
     // Returns None if all pages are pinned (Deadlock, memory is cooked).
-    pub fn victim(&mut self, frames: &mut [Page]) -> Option<FrameId> {
-        let start_hand = self.hand;
+    fn victim(&mut self, frames: &mut [FrameMeta]) -> Option<FrameId> {
+        if !self.can_sweep(frames) {
+            return None;
+        }
+        // counting pinned frames in a row rather than going around once from the start, a frame whose ref bit
+        // we clear on the first lap can still be the victim on the second
+        let mut pinned_in_a_row = 0;
         
         // Rust loop syntax is interesting
         loop {
-            let frame = &mut frames[self.hand];
+            let frame = &frames[self.hand];
 
             // skip pinned pages
-            if frame.pin_count > 0 {
+            if frame.is_pinned() {
                 self.advance();
+                pinned_in_a_row += 1;
                 // if everything is pinned we are cooked
-                if self.hand == start_hand { return None; }
+                if pinned_in_a_row == self.size { return None; }
                 continue;
             }
+            pinned_in_a_row = 0;
             
             // clock algo
-            if frame.ref_bit {
-                frame.ref_bit = false;
+            if frame.take_ref_bit() {
                 self.advance();
             } else {
                 let victim_id = self.hand;
                 self.advance();
+                self.accesses.reset(victim_id);
                 return Some(victim_id);
             }
         }
     }
 
+    // clock itself reads ref_bit and pin_count straight off the frames, this is only counted for recent_accesses
+    fn record_access(&mut self, frame: FrameId) {
+        self.accesses.record(frame);
+    }
+
+    fn set_evictable(&mut self, _frame: FrameId, _evictable: bool) {}
+
+    fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.accesses.resize(size);
+        if self.hand >= size {
+            self.hand = 0;
+        }
+    }
+
+    fn recent_accesses(&self, frame: FrameId) -> u32 {
+        self.accesses.get(frame)
+    }
+}
+
+/*
+* GCLOCK: clock with a small usage counter per frame instead of a single ref bit.
+* Every access bumps the frame's counter (up to max_count), every pass of the hand takes one off,
+* and a frame is only evicted once its counter is down to zero. So a page accessed N times
+* survives N full sweeps of the hand where plain clock would drop it after one.
+* The counters live here, the ref_bit on the frames is ignored.
+*/
+pub struct GClockReplacer {
+    hand: usize,
+    counts: Vec<u8>,
+    max_count: u8,
+    accesses: AccessCounter, // not capped like counts, see recent_accesses
+}
+
+impl GClockReplacer {
+    pub fn new(size: usize, max_count: u8) -> Self {
+        Self { hand: 0, counts: vec![0; size], max_count: max_count.max(1), accesses: AccessCounter::new(size) }
+    }
+
     fn advance(&mut self) {
-        self.hand = (self.hand + 1) % self.size;
+        self.hand = (self.hand + 1) % self.counts.len();
+    }
+
+    // same guards as ClockReplacer::can_sweep
+    fn can_sweep(&mut self, frames: &[FrameMeta]) -> bool {
+        if self.counts.is_empty() || frames.len() < self.counts.len() {
+            return false;
+        }
+        if self.hand >= self.counts.len() {
+            self.hand = 0;
+        }
+        true
+    }
+}
+
+impl Replacer for GClockReplacer {
+    fn victim(&mut self, frames: &mut [FrameMeta]) -> Option<FrameId> {
+        if !self.can_sweep(frames) {
+            return None;
+        }
+        let mut pinned_in_a_row = 0; // same as in ClockReplacer::victim
+
+        loop {
+            if frames[self.hand].is_pinned() {
+                self.advance();
+                pinned_in_a_row += 1;
+                if pinned_in_a_row == self.counts.len() { return None; }
+                continue;
+            }
+            pinned_in_a_row = 0;
+
+            let count = &mut self.counts[self.hand];
+            if *count > 0 {
+                *count -= 1;
+                self.advance();
+            } else {
+                let victim_id = self.hand;
+                self.advance();
+                self.accesses.reset(victim_id);
+                return Some(victim_id);
+            }
+        }
+    }
+
+    fn record_access(&mut self, frame: FrameId) {
+        let count = &mut self.counts[frame];
+        *count = count.saturating_add(1).min(self.max_count);
+        self.accesses.record(frame);
     }
-}
\ No newline at end of file
+
+    fn set_evictable(&mut self, _frame: FrameId, _evictable: bool) {}
+
+    fn resize(&mut self, size: usize) {
+        self.counts.resize(size, 0);
+        self.accesses.resize(size);
+        if self.hand >= size {
+            self.hand = 0;
+        }
+    }
+
+    fn recent_accesses(&self, frame: FrameId) -> u32 {
+        self.accesses.get(frame)
+    }
+}
+
+/*
+* LRU-K: evicts the frame whose K-th most recent access is furthest in the past (largest backward K-distance).
+* Frames with fewer than K accesses have infinite distance and go first, oldest access breaking ties.
+* This makes it scan resistant, a page touched once by a sequential scan can't push out a page used K times.
+*/
+pub struct LruKReplacer {
+    k: usize,
+    current_timestamp: u64,
+    history: Vec<VecDeque<u64>>, // last k access timestamps per frame, oldest at the front
+    evictable: Vec<bool>,
+    accesses: AccessCounter,
+}
+
+impl LruKReplacer {
+    pub fn new(num_frames: usize, k: usize) -> Self {
+        assert!(k > 0, "k must be at least 1");
+        Self {
+            k,
+            current_timestamp: 0,
+            history: vec![VecDeque::with_capacity(k); num_frames],
+            evictable: vec![false; num_frames],
+            accesses: AccessCounter::new(num_frames),
+        }
+    }
+}
+
+impl Replacer for LruKReplacer {
+    fn victim(&mut self, frames: &mut [FrameMeta]) -> Option<FrameId> {
+        // (has infinite distance, timestamp to compare) we want infinite first, then the oldest timestamp
+        let mut best: Option<(FrameId, bool, u64)> = None;
+
+        for (frame_id, history) in self.history.iter().enumerate() {
+            if !self.evictable[frame_id] || frames[frame_id].is_pinned() {
+                continue;
+            }
+            let infinite = history.len() < self.k;
+            // with k accesses the front is the k-th most recent one, otherwise it's the very first access
+            let timestamp = history.front().copied().unwrap_or(0);
+
+            let better = match best {
+                None => true,
+                Some((_, best_inf, best_ts)) => (infinite && !best_inf) || (infinite == best_inf && timestamp < best_ts),
+            };
+            if better {
+                best = Some((frame_id, infinite, timestamp));
+            }
+        }
+
+        let (frame_id, _, _) = best?;
+        self.history[frame_id].clear();
+        self.evictable[frame_id] = false;
+        self.accesses.reset(frame_id);
+        Some(frame_id)
+    }
+
+    fn record_access(&mut self, frame: FrameId) {
+        self.current_timestamp += 1;
+        let history = &mut self.history[frame];
+        if history.len() == self.k {
+            history.pop_front();
+        }
+        history.push_back(self.current_timestamp);
+        self.accesses.record(frame);
+    }
+
+    fn set_evictable(&mut self, frame: FrameId, evictable: bool) {
+        self.evictable[frame] = evictable;
+    }
+
+    fn resize(&mut self, size: usize) {
+        self.history.resize(size, VecDeque::with_capacity(self.k));
+        self.evictable.resize(size, false);
+        self.accesses.resize(size);
+    }
+
+    fn is_evictable(&self, frame: FrameId, frames: &[FrameMeta]) -> bool {
+        self.evictable[frame] && !frames[frame].is_pinned()
+    }
+
+    fn recent_accesses(&self, frame: FrameId) -> u32 {
+        self.accesses.get(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::buffer_pool_manager::BufferPoolManager;
+
+    fn frames(n: usize) -> Vec<FrameMeta> {
+        (0..n).map(|_| FrameMeta::default()).collect()
+    }
+
+    #[test]
+    fn clock_skips_pinned_frames() {
+        let mut frames = frames(3);
+        frames[0].pin();
+        frames[1].pin();
+        let mut clock = ClockReplacer::new(3);
+        assert_eq!(clock.victim(&mut frames), Some(2));
+    }
+
+    #[test]
+    fn clock_has_no_victim_when_everything_is_pinned() {
+        let mut frames = frames(2);
+        frames.iter().for_each(|f| { f.pin(); });
+        assert_eq!(ClockReplacer::new(2).victim(&mut frames), None);
+    }
+
+    // always the lowest unpinned frame, and counts how often the pool asked
+    struct FirstUnpinned(Arc<AtomicUsize>);
+
+    impl Replacer for FirstUnpinned {
+        fn victim(&mut self, frames: &mut [FrameMeta]) -> Option<FrameId> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            frames.iter().position(|f| !f.is_pinned())
+        }
+        fn record_access(&mut self, _frame: FrameId) {}
+        fn set_evictable(&mut self, _frame: FrameId, _evictable: bool) {}
+    }
+
+    #[test]
+    fn the_pool_picks_victims_through_its_replacer() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let replacer = Box::new(FirstUnpinned(calls.clone()));
+        let bpm = BufferPoolManager::with_replacer(2, MockDiskManager::new(), replacer).unwrap();
+        for _ in 0..4 {
+            drop(bpm.allocate_page().unwrap());
+        }
+        // two pages fit in the free frames, the other two needed a victim each
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    fn lru_k(frames: usize, k: usize) -> LruKReplacer {
+        let mut lru = LruKReplacer::new(frames, k);
+        (0..frames).for_each(|f| lru.set_evictable(f, true));
+        lru
+    }
+
+    #[test]
+    fn lru_k_evicts_frames_with_fewer_than_k_accesses_first() {
+        let mut frames = frames(3);
+        let mut lru = lru_k(3, 2);
+        for frame in [0, 0, 1, 2, 2] {
+            lru.record_access(frame);
+        }
+        // frame 1 was only touched once, that's an infinite distance even though 0 was touched before it
+        assert_eq!(lru.victim(&mut frames), Some(1));
+    }
+
+    #[test]
+    fn lru_k_evicts_the_oldest_kth_access() {
+        let mut frames = frames(2);
+        let mut lru = lru_k(2, 2);
+        for frame in [0, 1, 0, 1, 1] {
+            lru.record_access(frame);
+        }
+        // second most recent access of 0 is at time 1, of 1 at time 4
+        assert_eq!(lru.victim(&mut frames), Some(0));
+        assert_eq!(lru.victim(&mut frames), Some(1));
+        assert_eq!(lru.victim(&mut frames), None);
+    }
+
+    #[test]
+    fn lru_k_leaves_frames_that_arent_evictable() {
+        let mut frames = frames(2);
+        let mut lru = lru_k(2, 2);
+        lru.record_access(0);
+        lru.record_access(1);
+        lru.set_evictable(0, false);
+        assert_eq!(lru.victim(&mut frames), Some(1));
+    }
+
+    #[test]
+    fn gclock_keeps_a_frame_one_sweep_per_access() {
+        let mut frames = frames(2);
+        let mut gclock = GClockReplacer::new(2, 8);
+        for _ in 0..3 {
+            gclock.record_access(0);
+        }
+        // each victim call the hand passes frame 0 once on its way to the cold frame 1
+        for _ in 0..3 {
+            assert_eq!(gclock.victim(&mut frames), Some(1));
+        }
+        assert_eq!(gclock.victim(&mut frames), Some(0));
+    }
+
+    #[test]
+    fn gclock_caps_the_counter_at_max_count() {
+        let mut frames = frames(2);
+        let mut gclock = GClockReplacer::new(2, 2);
+        for _ in 0..10 {
+            gclock.record_access(0);
+        }
+        assert_eq!(gclock.victim(&mut frames), Some(1));
+        assert_eq!(gclock.victim(&mut frames), Some(1));
+        assert_eq!(gclock.victim(&mut frames), Some(0));
+    }
+
+    #[test]
+    fn plain_clock_forgets_a_hot_frame_after_one_sweep() {
+        let mut frames = frames(2);
+        let mut clock = ClockReplacer::new(2);
+        frames[0].pin();
+        frames[0].unpin().unwrap(); // leaves the ref bit set
+        assert_eq!(clock.victim(&mut frames), Some(1));
+        assert_eq!(clock.victim(&mut frames), Some(0));
+    }
+
+    #[test]
+    fn a_zero_frame_clock_has_no_victim() {
+        assert_eq!(ClockReplacer::new(0).victim(&mut []), None);
+        assert_eq!(GClockReplacer::new(0, 4).victim(&mut []), None);
+    }
+
+    #[test]
+    fn a_hand_past_the_end_starts_over() {
+        let mut frames = frames(3);
+        let mut clock = ClockReplacer::new(3);
+        clock.hand = 7;
+        assert_eq!(clock.victim(&mut frames), Some(0));
+
+        let mut gclock = GClockReplacer::new(3, 4);
+        gclock.hand = 3;
+        assert_eq!(gclock.victim(&mut frames), Some(0));
+    }
+
+    #[test]
+    fn clock_evicts_once_every_ref_bit_is_cleared() {
+        let mut frames = frames(3);
+        for frame in &frames {
+            frame.pin();
+            frame.unpin().unwrap();
+        }
+        let mut clock = ClockReplacer::new(3);
+        assert_eq!(clock.victim(&mut frames), Some(0));
+        assert!(frames.iter().all(|f| !f.ref_bit()));
+    }
+
+    #[test]
+    fn access_counts_fade_out_and_restart_on_reset() {
+        let mut counter = AccessCounter::new(2);
+        for _ in 0..5 {
+            counter.record(0);
+        }
+        counter.record(1);
+        assert_eq!((counter.get(0), counter.get(1)), (5, 1));
+        // the 16th access (DECAY_WINDOW per frame) halves everything
+        for _ in 0..10 {
+            counter.record(1);
+        }
+        assert_eq!((counter.get(0), counter.get(1)), (2, 5));
+        counter.reset(1);
+        assert_eq!(counter.get(1), 0);
+        assert_eq!(counter.get(7), 0);
+    }
+}
@@ -0,0 +1,869 @@
+/*
+* Slotted page layout for variable length records.
+*
+* | PageHeader | slot_count | slot 0 | slot 1 | ... -> free space <- ... | record 1 | record 0 |
+*
+* The slot directory grows forward from the header and record data grows backward from the end of the page.
+* header.free_space_pointer marks the start of the record data (PAGE_SIZE on an empty page), so for slotted pages
+* use SlottedPage::free_space instead of Page::get_free_space / Page::allocate.
+* Each slot is (offset: u16, length: u16), the top two bits of length say what the slot holds:
+* - 00 a record
+* - 10 a tombstone. Deleting a record sets this with offset 0, its bytes stay dead space until compact() / vacuum()
+*   gives them back. The rest of the length holds a generation instead, the one the next record in the slot gets,
+*   for stores whose ids carry one (see NodeId), 0 otherwise. Pages written before the flag existed marked
+*   tombstones with offset 0 only, which still counts, and ones from before generations kept their offset and
+*   length, those read as generation 0. A tombstoned slot gets reused by the next insert so SlotIds of live records
+*   never move, vacuum() also only ever drops tombstones off the end of the directory, and none with a generation.
+*   Deleting the record at free_space_pointer (usually the last one inserted) skips the wait: its bytes, and those
+*   of any deleted records right below it, go back to the free space at once, and tombstones at the end of the
+*   directory are dropped.
+* - 01 a forward: the record grew too big for this page and moved, the slot keeps | page_id: u64 | slot: u16 | of
+*   where it went so ids handed out for it stay valid (see set_forward / resolve_forward)
+* - 11 a record that was moved here by a forward, it starts with the same 10 bytes pointing back at its home slot
+*   (see home_of). get_record skips them, so it reads like any other record
+* Forwards only ever go one hop: moving a record again rewrites the forward in its home slot.
+*/
+
+use std::ops::{Deref, DerefMut};
+
+use super::page::Page;
+use super::record::{DecodeError, Record};
+use super::page_constants::{PageId, HEADER_SIZE, PAGE_SIZE};
+
+pub type SlotId = u16;
+
+const SLOT_COUNT_OFFSET: usize = HEADER_SIZE;
+const SLOT_DIR_OFFSET: usize = HEADER_SIZE + 2;
+pub const SLOT_SIZE: usize = 4;
+// top two bits of a slot's length, a record always fits in the other 14 even on 16KB pages
+const KIND_BITS: u16 = 0b11 << 14;
+const TOMBSTONE: u16 = 0b10 << 14;
+const FORWARD: u16 = 0b01 << 14;
+const MOVED_IN: u16 = 0b11 << 14;
+// | page_id: u64 | slot: u16 |, the body of a forward and the start of a moved in record
+pub const FORWARD_LEN: usize = 10;
+// biggest generation a tombstone can hold, what's left of the length next to the kind bits
+pub const MAX_GENERATION: u16 = !KIND_BITS;
+// most slots a directory could ever have, anything above this is a corrupt slot count. Only Page::debug_dump
+// needs it so far, which is debug builds only
+#[cfg(debug_assertions)]
+pub(crate) const MAX_SLOTS: usize = (PAGE_SIZE - SLOT_DIR_OFFSET) / SLOT_SIZE;
+
+// What update_record did with the new bytes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UpdateResult {
+    InPlace,                 // same size, or grown and it still fit on this page, SlotId unchanged either way
+    Shrunk { freed: usize }, // written over the old bytes, the leftover tail is dead space until compact()
+    Relocated,               // doesn't fit on this page even after compacting, nothing was changed: the caller has
+                             // to put it on another page and leave a forward behind
+    NotFound,                // no live record in that slot
+}
+
+// What a slot directory entry holds, going by the top two bits of its length (see the layout above)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SlotKind {
+    Record,
+    Tombstone, // old style tombstones (offset 0, no flag) too
+    Forward,
+    MovedIn,
+}
+
+// One slot directory entry as stored, see SlottedPage::raw_slots
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SlotEntry {
+    pub slot: SlotId,
+    pub offset: u16,
+    // kind bits masked off, includes the 10 byte location of forwards and moved in records. The generation for
+    // tombstones
+    pub length: u16,
+    pub kind: SlotKind,
+}
+
+// Works over anything that derefs to a Page: &Page, &mut Page, or one of the buffer pool guards
+pub struct SlottedPage<P> {
+    page: P,
+}
+
+impl<P: Deref<Target = Page>> SlottedPage<P> {
+    // Wraps a page that was already formatted with SlottedPage::init
+    pub fn new(page: P) -> Self {
+        Self { page }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.page
+    }
+
+    pub fn page(&self) -> &Page {
+        &self.page
+    }
+
+    // number of slots in the directory, including tombstones and forwards
+    pub fn slot_count(&self) -> u16 {
+        self.read_u16(SLOT_COUNT_OFFSET)
+    }
+
+    // number of live records, forwards don't count (their record is counted on the page it moved to)
+    pub fn record_count(&self) -> u32 {
+        self.page.get_item_count()
+    }
+
+    // contiguous bytes between the end of the slot directory and the start of the record data
+    // 0 on a page that isn't formatted, see is_formatted
+    pub fn free_space(&self) -> usize {
+        (self.page.get_free_space_pointer() as usize).saturating_sub(self.slot_dir_end())
+    }
+
+    // room insert_record can actually use, counting what compact() would give back
+    // a new record needs its length plus SLOT_SIZE unless it lands in a tombstoned slot. A page that isn't
+    // formatted has none, whatever its header says
+    pub fn available_space(&self) -> usize {
+        if !self.is_formatted() {
+            return 0;
+        }
+        self.free_space() + self.dead_space()
+    }
+
+    // whether the record data starts at or past the end of the slot directory, like it does on every page that went
+    // through init. A page that only got a slotted page type (free_space_pointer still at HEADER_SIZE) doesn't
+    pub fn is_formatted(&self) -> bool {
+        let free_space_pointer = self.page.get_free_space_pointer() as usize;
+        (self.slot_dir_end()..=PAGE_SIZE).contains(&free_space_pointer)
+    }
+
+    // the record in slot, None for tombstones and forwards (see resolve_forward)
+    pub fn get_record(&self, slot: SlotId) -> Option<&[u8]> {
+        let (start, length) = self.record_span(slot)?;
+        Some(&self.page.get_data()[start..start + length])
+    }
+
+    // Every record on the page with its SlotId, in slot order, skipping tombstones and forwards
+    // borrows straight from the page, nothing gets copied
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (SlotId, &[u8])> + '_ {
+        (0..self.slot_count()).filter_map(move |slot| self.get_record(slot).map(|record| (slot, record)))
+    }
+
+    // Every entry of the slot directory in slot order, tombstones and forwards included, for fsck style tools
+    // only reads the directory, record bodies aren't looked at (or checked to be inside the page)
+    pub fn raw_slots(&self) -> impl DoubleEndedIterator<Item = SlotEntry> + '_ {
+        (0..self.slot_count()).map(move |slot| {
+            let (offset, length) = self.raw_slot(slot).unwrap();
+            let kind = match length & KIND_BITS {
+                _ if self.is_tombstone(slot) => SlotKind::Tombstone,
+                FORWARD => SlotKind::Forward,
+                MOVED_IN => SlotKind::MovedIn,
+                _ => SlotKind::Record,
+            };
+            SlotEntry { slot, offset, length: length & !KIND_BITS, kind }
+        })
+    }
+
+    // decodes the record in slot as an R, None if the slot is empty
+    pub fn get<R: Record>(&self, slot: SlotId) -> Option<Result<R, DecodeError>> {
+        self.get_record(slot).map(R::deserialize)
+    }
+
+    fn slot_dir_end(&self) -> usize {
+        SLOT_DIR_OFFSET + self.slot_count() as usize * SLOT_SIZE
+    }
+
+    // (offset, length) of slot without the kind bits, whatever the slot holds. For debug_dump like MAX_SLOTS
+    #[cfg(debug_assertions)]
+    pub(crate) fn slot(&self, slot: SlotId) -> Option<(u16, u16)> {
+        self.raw_slot(slot).map(|(offset, length)| (offset, length & !KIND_BITS))
+    }
+
+    // slot exists but its record was deleted
+    pub fn is_tombstone(&self, slot: SlotId) -> bool {
+        self.raw_slot(slot).is_some_and(|(offset, length)| offset == 0 || length & KIND_BITS == TOMBSTONE)
+    }
+
+    // slot's record moved to another page, see resolve_forward
+    pub fn is_forward(&self, slot: SlotId) -> bool {
+        self.occupied_slot(slot).is_some_and(|(_, _, kind)| kind == FORWARD)
+    }
+
+    // (page, slot) a forward points at, None if slot isn't a forward
+    pub fn resolve_forward(&self, slot: SlotId) -> Option<(PageId, SlotId)> {
+        match self.occupied_slot(slot)? {
+            (offset, _, FORWARD) => Some(self.read_location(offset as usize)),
+            _ => None,
+        }
+    }
+
+    // home (page, slot) of a record that was moved here, None for records that were inserted here
+    // the home slot is where its id points and holds the forward to this one
+    pub fn home_of(&self, slot: SlotId) -> Option<(PageId, SlotId)> {
+        match self.occupied_slot(slot)? {
+            (offset, _, MOVED_IN) => Some(self.read_location(offset as usize)),
+            _ => None,
+        }
+    }
+
+    // (offset, length, kind bits) of anything that takes up bytes: records, forwards and moved in records
+    fn occupied_slot(&self, slot: SlotId) -> Option<(u16, u16, u16)> {
+        if self.is_tombstone(slot) {
+            return None;
+        }
+        self.raw_slot(slot).map(|(offset, length)| (offset, length & !KIND_BITS, length & KIND_BITS))
+    }
+
+    // where the record's own bytes are, skipping the home pointer of moved in records
+    fn record_span(&self, slot: SlotId) -> Option<(usize, usize)> {
+        match self.occupied_slot(slot)? {
+            (_, _, FORWARD) => None,
+            (offset, length, MOVED_IN) => Some((offset as usize + FORWARD_LEN, length as usize - FORWARD_LEN)),
+            (offset, length, _) => Some((offset as usize, length as usize)),
+        }
+    }
+
+    fn raw_slot(&self, slot: SlotId) -> Option<(u16, u16)> {
+        if slot >= self.slot_count() {
+            return None;
+        }
+        let at = SLOT_DIR_OFFSET + slot as usize * SLOT_SIZE;
+        Some((self.read_u16(at), self.read_u16(at + 2)))
+    }
+
+    // generation tombstoned slot hands the next record put in it, see delete_record_with_generation. 0 for
+    // anything that isn't a tombstone
+    pub fn generation(&self, slot: SlotId) -> u16 {
+        match self.raw_slot(slot) {
+            Some((0, length)) if length & KIND_BITS == TOMBSTONE => length & !KIND_BITS,
+            _ => 0,
+        }
+    }
+
+    // generation the next insert_record gets, that of the tombstone it would reuse or 0 for a new slot
+    pub fn next_generation(&self) -> u16 {
+        self.reusable_slot(0).map_or(0, |slot| self.generation(slot))
+    }
+
+    // tombstone the next insert of kind goes into, None if it gets a new slot. Moved in records aren't known by
+    // their slot, they stay out of tombstones with a generation so it's still there for the next real record
+    fn reusable_slot(&self, kind: u16) -> Option<SlotId> {
+        (0..self.slot_count()).find(|&slot| self.is_tombstone(slot) && (kind != MOVED_IN || self.generation(slot) == 0))
+    }
+
+    // share of the slot directory that's tombstones, 0 for an empty one
+    pub fn tombstone_ratio(&self) -> f64 {
+        let slots = self.slot_count();
+        if slots == 0 {
+            return 0.0;
+        }
+        (0..slots).filter(|&slot| self.is_tombstone(slot)).count() as f64 / slots as f64
+    }
+
+    // bytes held by tombstoned records that compact() would give back
+    fn dead_space(&self) -> usize {
+        let used: usize = (0..self.slot_count())
+            .filter_map(|slot| self.occupied_slot(slot))
+            .map(|(_, length, _)| length as usize)
+            .sum();
+        PAGE_SIZE.saturating_sub(self.page.get_free_space_pointer() as usize).saturating_sub(used)
+    }
+
+    fn read_location(&self, at: usize) -> (PageId, SlotId) {
+        let page_id = u64::from_le_bytes(self.page.get_data()[at..at + 8].try_into().unwrap());
+        (page_id, self.read_u16(at + 8))
+    }
+
+    fn read_u16(&self, at: usize) -> u16 {
+        u16::from_le_bytes(self.page.get_data()[at..at + 2].try_into().unwrap())
+    }
+}
+
+impl<'a> SlottedPage<&'a Page> {
+    // iter() for a view over a borrowed page, hands out the records with the page's lifetime instead of the view's
+    // so they can outlive the SlottedPage (never the page, or the guard pinning it)
+    pub fn records(self) -> impl DoubleEndedIterator<Item = (SlotId, &'a [u8])> + 'a {
+        let data = self.page.get_data();
+        (0..self.slot_count()).filter_map(move |slot| {
+            self.record_span(slot).map(|(start, length)| (slot, &data[start..start + length]))
+        })
+    }
+}
+
+impl<P: DerefMut<Target = Page>> SlottedPage<P> {
+    // Formats page as an empty slotted page and wraps it
+    pub fn init(page: P) -> Self {
+        let mut slotted = Self { page };
+        slotted.write_u16(SLOT_COUNT_OFFSET, 0);
+        slotted.page.set_free_space_pointer(PAGE_SIZE as u32);
+        slotted.page.set_item_count(0);
+        slotted.page.set_dirty(true);
+        slotted
+    }
+
+    pub fn page_mut(&mut self) -> &mut Page {
+        &mut self.page
+    }
+
+    // in place access to a record, the length can't change through this
+    pub fn get_record_mut(&mut self, slot: SlotId) -> Option<&mut [u8]> {
+        let (start, length) = self.record_span(slot)?;
+        self.page.set_dirty(true);
+        Some(&mut self.page.get_data_mut()[start..start + length])
+    }
+
+    // Copies data into the page, returns None if it doesn't fit even after compacting
+    pub fn insert_record(&mut self, data: &[u8]) -> Option<SlotId> {
+        self.insert_raw(&[], data, 0)
+    }
+
+    // serializes record and inserts it, same as insert_record otherwise
+    pub fn insert<R: Record>(&mut self, record: &R) -> Option<SlotId> {
+        self.insert_record(&record.to_bytes())
+    }
+
+    // insert_record for a record that's moving here from (home_page, home_slot), which becomes a forward to it
+    // takes FORWARD_LEN bytes more than data
+    pub fn insert_moved(&mut self, home_page: PageId, home_slot: SlotId, data: &[u8]) -> Option<SlotId> {
+        self.insert_raw(&encode_location(home_page, home_slot), data, MOVED_IN)
+    }
+
+    fn insert_raw(&mut self, prefix: &[u8], data: &[u8], kind: u16) -> Option<SlotId> {
+        let len = prefix.len() + data.len();
+        let reuse = self.reusable_slot(kind);
+        let needed = len + if reuse.is_some() { 0 } else { SLOT_SIZE };
+
+        if self.free_space() < needed {
+            if self.available_space() < needed {
+                return None;
+            }
+            self.compact();
+        }
+
+        let slot = match reuse {
+            Some(slot) => slot,
+            None => {
+                let slot = self.slot_count();
+                self.write_u16(SLOT_COUNT_OFFSET, slot + 1);
+                slot
+            }
+        };
+
+        let offset = self.page.get_free_space_pointer() as usize - len;
+        self.write_bytes(offset, prefix, data);
+        self.page.set_free_space_pointer(offset as u32);
+        self.set_slot(slot, offset as u16, len as u16 | kind);
+
+        let count = self.record_count();
+        self.page.set_item_count(count + 1);
+        self.page.set_dirty(true);
+        Some(slot)
+    }
+
+    // Replaces the record in slot with new_data, keeping the SlotId
+    // a record that grows moves to the free space (compacting if that's what it takes), see UpdateResult
+    pub fn update_record(&mut self, slot: SlotId, new_data: &[u8]) -> UpdateResult {
+        match self.occupied_slot(slot) {
+            Some((_, _, 0)) => self.rewrite(slot, &[], new_data, 0),
+            Some((offset, _, MOVED_IN)) => {
+                let home: [u8; FORWARD_LEN] = self.page.get_data()[offset as usize..][..FORWARD_LEN].try_into().unwrap();
+                self.rewrite(slot, &home, new_data, MOVED_IN)
+            }
+            _ => UpdateResult::NotFound,
+        }
+    }
+
+    // Turns slot into a forward to (page_id, target), for when its record had to move to another page
+    // works on records, moved in records and forwards (repointing them). false if there's nothing in slot, or
+    // a record shorter than FORWARD_LEN sits in a page too full to grow it
+    pub fn set_forward(&mut self, slot: SlotId, page_id: PageId, target: SlotId) -> bool {
+        let Some((_, _, kind)) = self.occupied_slot(slot) else { return false };
+        let result = self.rewrite(slot, &encode_location(page_id, target), &[], FORWARD);
+        if matches!(result, UpdateResult::Relocated | UpdateResult::NotFound) {
+            return false;
+        }
+        if kind != FORWARD {
+            let count = self.record_count();
+            self.page.set_item_count(count - 1);
+        }
+        true
+    }
+
+    // writes prefix + data over slot's bytes, or into the free space if that's bigger than what's there
+    fn rewrite(&mut self, slot: SlotId, prefix: &[u8], data: &[u8], kind: u16) -> UpdateResult {
+        let Some((offset, length, _)) = self.occupied_slot(slot) else { return UpdateResult::NotFound };
+        let (start, old_len, new_len) = (offset as usize, length as usize, prefix.len() + data.len());
+
+        if new_len <= old_len {
+            self.write_bytes(start, prefix, data);
+            self.set_slot(slot, offset, new_len as u16 | kind);
+            self.page.set_dirty(true);
+            return if new_len == old_len {
+                UpdateResult::InPlace
+            } else {
+                UpdateResult::Shrunk { freed: old_len - new_len }
+            };
+        }
+
+        if self.free_space() < new_len {
+            // the old bytes count as dead once the record moves
+            if self.available_space() + old_len < new_len {
+                return UpdateResult::Relocated;
+            }
+            self.set_slot(slot, offset, length | TOMBSTONE);
+            self.compact();
+            // compact left the slot as an empty tombstone, it gets its real value below
+        }
+        let at = self.page.get_free_space_pointer() as usize - new_len;
+        self.write_bytes(at, prefix, data);
+        self.page.set_free_space_pointer(at as u32);
+        self.set_slot(slot, at as u16, new_len as u16 | kind);
+        self.page.set_dirty(true);
+        UpdateResult::InPlace
+    }
+
+    // Tombstones slot (a record or a forward), returns false if there was nothing there
+    // bytes right at the start of the record data go straight back to the free space, see reclaim_frontier
+    pub fn delete_record(&mut self, slot: SlotId) -> bool {
+        self.delete_record_with_generation(slot, 0)
+    }
+
+    // delete_record leaving generation (cut to MAX_GENERATION) in the tombstone for the next record in slot
+    pub fn delete_record_with_generation(&mut self, slot: SlotId, generation: u16) -> bool {
+        match self.occupied_slot(slot) {
+            Some((_, _, kind)) => {
+                self.set_slot(slot, 0, TOMBSTONE | (generation & MAX_GENERATION));
+                if kind != FORWARD {
+                    let count = self.record_count();
+                    self.page.set_item_count(count - 1);
+                }
+                self.reclaim_frontier();
+                self.page.set_dirty(true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Moves free_space_pointer up to the lowest record still on the page, which gives back the bytes of every
+    // deleted record in between, and trims the slot directory. Interior dead space waits for compact() like before
+    fn reclaim_frontier(&mut self) {
+        let lowest = (0..self.slot_count()).filter_map(|slot| self.occupied_slot(slot)).map(|(offset, _, _)| offset).min();
+        self.page.set_free_space_pointer(lowest.map_or(PAGE_SIZE as u32, u32::from));
+        self.trim_slots();
+    }
+
+    // drops tombstones off the end of the slot directory, except ones holding a generation
+    fn trim_slots(&mut self) {
+        let mut count = self.slot_count();
+        while count > 0 && self.is_tombstone(count - 1) && self.generation(count - 1) == 0 {
+            count -= 1;
+        }
+        self.write_u16(SLOT_COUNT_OFFSET, count);
+    }
+
+    // Copies the record in slot into dest and tombstones it here, returns its slot in dest
+    // None with both pages left as they were if slot holds no record or dest has no room for it
+    // a record that was moved in keeps pointing back at its home, so only the forward there has to be repointed.
+    // Anything else that refers to (this page, slot) doesn't follow the record, that's up to the caller
+    pub fn move_record_to<Q: DerefMut<Target = Page>>(&mut self, slot: SlotId, dest: &mut SlottedPage<Q>) -> Option<SlotId> {
+        let data = self.get_record(slot)?.to_vec();
+        let moved = match self.home_of(slot) {
+            Some((home_page, home_slot)) => dest.insert_moved(home_page, home_slot, &data)?,
+            None => dest.insert_record(&data)?,
+        };
+        self.delete_record(slot);
+        Some(moved)
+    }
+
+    // Slides everything that isn't a tombstone up against the end of the page so the space held by tombstones
+    // becomes free again. SlotIds don't change, only the offsets stored in the slots do
+    pub fn compact(&mut self) {
+        let old = *self.page.get_data();
+        let mut end = PAGE_SIZE;
+
+        for slot in 0..self.slot_count() {
+            let Some((offset, length, kind)) = self.occupied_slot(slot) else {
+                let generation = self.generation(slot);
+                self.set_slot(slot, 0, TOMBSTONE | generation);
+                continue;
+            };
+            let (start, len) = (offset as usize, length as usize);
+            end -= len;
+            self.page.get_data_mut()[end..end + len].copy_from_slice(&old[start..start + len]);
+            self.set_slot(slot, end as u16, length | kind);
+        }
+
+        self.page.set_free_space_pointer(end as u32);
+        self.page.set_dirty(true);
+    }
+
+    // compact() plus dropping tombstones off the end of the slot directory (see trim_slots), and putting item_count back in line
+    // with the live records. Returns how many bytes of contiguous free space that gained
+    // tombstones in the middle of the directory stay, so SlotIds of live records stay valid
+    pub fn vacuum(&mut self) -> usize {
+        let before = self.free_space();
+        self.compact();
+        self.trim_slots();
+
+        let live = (0..self.slot_count()).filter(|&slot| self.record_span(slot).is_some()).count();
+        self.page.set_item_count(live as u32);
+        self.page.set_dirty(true);
+        self.free_space() - before
+    }
+
+    fn set_slot(&mut self, slot: SlotId, offset: u16, length: u16) {
+        let at = SLOT_DIR_OFFSET + slot as usize * SLOT_SIZE;
+        self.write_u16(at, offset);
+        self.write_u16(at + 2, length);
+    }
+
+    fn write_bytes(&mut self, at: usize, prefix: &[u8], data: &[u8]) {
+        let bytes = self.page.get_data_mut();
+        bytes[at..at + prefix.len()].copy_from_slice(prefix);
+        bytes[at + prefix.len()..at + prefix.len() + data.len()].copy_from_slice(data);
+    }
+
+    fn write_u16(&mut self, at: usize, value: u16) {
+        self.page.get_data_mut()[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn encode_location(page_id: PageId, slot: SlotId) -> [u8; FORWARD_LEN] {
+    let mut out = [0; FORWARD_LEN];
+    out[..8].copy_from_slice(&page_id.to_le_bytes());
+    out[8..].copy_from_slice(&slot.to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paging::page::PageType;
+
+    fn empty_page() -> Page {
+        let mut page = Page::new(1, PageType::NodeStore);
+        SlottedPage::init(&mut page);
+        page
+    }
+
+    #[test]
+    fn records_of_different_lengths_read_back() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let a = sp.insert_record(b"a").unwrap();
+        let b = sp.insert_record(&[7; 300]).unwrap();
+        let c = sp.insert_record(b"").unwrap();
+        assert_eq!((a, b, c), (0, 1, 2));
+        assert_eq!(sp.get_record(a), Some(&b"a"[..]));
+        assert_eq!(sp.get_record(b), Some(&[7; 300][..]));
+        assert_eq!(sp.get_record(c), Some(&b""[..]));
+        assert_eq!(sp.get_record(3), None);
+        assert_eq!(sp.record_count(), 3);
+    }
+
+    #[test]
+    fn each_insert_takes_its_length_plus_a_slot() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let before = sp.free_space();
+        sp.insert_record(&[1; 100]).unwrap();
+        assert_eq!(sp.free_space(), before - 100 - SLOT_SIZE);
+    }
+
+    #[test]
+    fn inserts_stop_when_the_page_is_full() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let mut inserted = 0;
+        while sp.insert_record(&[0; 100]).is_some() {
+            inserted += 1;
+        }
+        assert_eq!(inserted, (PAGE_SIZE - SLOT_DIR_OFFSET) / (100 + SLOT_SIZE));
+        assert!(sp.free_space() < 100 + SLOT_SIZE);
+    }
+
+    #[test]
+    fn deleted_slots_are_reused_and_live_ids_stay_put() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let a = sp.insert_record(b"first").unwrap();
+        let b = sp.insert_record(b"second").unwrap();
+        assert!(sp.delete_record(a));
+        assert!(!sp.delete_record(a));
+        assert!(sp.is_tombstone(a));
+        assert_eq!(sp.get_record(b), Some(&b"second"[..]));
+
+        assert_eq!(sp.insert_record(b"third"), Some(a));
+        assert_eq!(sp.get_record(a), Some(&b"third"[..]));
+    }
+
+    #[test]
+    fn a_page_that_was_never_formatted_has_no_room() {
+        let mut page = Page::new(1, PageType::NodeStore);
+        let mut sp = SlottedPage::new(&mut page);
+        assert!(!sp.is_formatted());
+        assert_eq!(sp.free_space(), 0);
+        assert_eq!(sp.available_space(), 0);
+        assert_eq!(sp.insert_record(b"x"), None);
+    }
+
+    #[test]
+    fn item_count_follows_inserts_and_deletes() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let a = sp.insert_record(b"a").unwrap();
+        sp.insert_record(b"b").unwrap();
+        assert_eq!(sp.page().get_item_count(), 2);
+        assert!(sp.delete_record(a));
+        assert!(!sp.delete_record(a));
+        assert_eq!(sp.page().get_item_count(), 1);
+    }
+
+    #[test]
+    fn vacuum_reclaims_alternating_deletes_and_keeps_live_slots() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let slots: Vec<SlotId> = (0..10u8).map(|i| sp.insert_record(&[i; 100]).unwrap()).collect();
+        for &slot in slots.iter().skip(1).step_by(2) {
+            assert!(sp.delete_record(slot));
+        }
+        // slot 9's record sat at the free space frontier and went back right away, 1, 3, 5 and 7 are holes
+        assert_eq!(sp.vacuum(), 400);
+        assert_eq!(sp.vacuum(), 0);
+
+        assert_eq!(sp.page().get_item_count(), 5);
+        assert_eq!(sp.slot_count(), 9);
+        for &slot in slots.iter().step_by(2) {
+            assert_eq!(sp.get_record(slot), Some(&[slot as u8; 100][..]));
+        }
+        for &slot in slots.iter().skip(1).step_by(2).take(4) {
+            assert!(sp.is_tombstone(slot));
+        }
+    }
+
+    #[test]
+    fn updates_that_shrink_or_keep_the_size_stay_put() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let slot = sp.insert_record(&[1; 40]).unwrap();
+        assert_eq!(sp.update_record(slot, &[2; 40]), UpdateResult::InPlace);
+        assert_eq!(sp.get_record(slot), Some(&[2; 40][..]));
+        assert_eq!(sp.update_record(slot, &[3; 10]), UpdateResult::Shrunk { freed: 30 });
+        assert_eq!(sp.get_record(slot), Some(&[3; 10][..]));
+        assert_eq!(sp.update_record(slot + 1, b"x"), UpdateResult::NotFound);
+    }
+
+    #[test]
+    fn a_grown_record_that_still_fits_keeps_its_slot() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let slot = sp.insert_record(&[1; 10]).unwrap();
+        let other = sp.insert_record(&[9; 10]).unwrap();
+        assert_eq!(sp.update_record(slot, &[4; 500]), UpdateResult::InPlace);
+        assert_eq!(sp.get_record(slot), Some(&[4; 500][..]));
+        assert_eq!(sp.get_record(other), Some(&[9; 10][..]));
+        assert_eq!(sp.record_count(), 2);
+    }
+
+    #[test]
+    fn a_record_too_big_for_the_page_has_to_relocate() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let slot = sp.insert_record(&[1; 100]).unwrap();
+        let filler = sp.available_space() - SLOT_SIZE;
+        sp.insert_record(&vec![2; filler]).unwrap();
+
+        let before = *sp.page().get_data();
+        assert_eq!(sp.update_record(slot, &[3; 101]), UpdateResult::Relocated);
+        assert_eq!(sp.page().get_data(), &before);
+        assert!(sp.set_forward(slot, 42, 7));
+        assert_eq!(sp.resolve_forward(slot), Some((42, 7)));
+        assert_eq!(sp.record_count(), 1);
+    }
+
+    #[test]
+    fn forwards_point_elsewhere_and_moved_records_point_home() {
+        let mut home = empty_page();
+        let mut target = Page::new(2, PageType::NodeStore);
+        SlottedPage::init(&mut target);
+        let mut home_sp = SlottedPage::new(&mut home);
+        let mut target_sp = SlottedPage::new(&mut target);
+
+        let slot = home_sp.insert_record(b"record").unwrap();
+        let moved = target_sp.insert_moved(1, slot, b"record").unwrap();
+        assert!(home_sp.set_forward(slot, 2, moved));
+
+        assert!(home_sp.is_forward(slot));
+        assert_eq!(home_sp.get_record(slot), None);
+        assert_eq!(home_sp.resolve_forward(slot), Some((2, moved)));
+        assert_eq!(home_sp.iter().count(), 0);
+        assert_eq!(target_sp.get_record(moved), Some(&b"record"[..]));
+        assert_eq!(target_sp.home_of(moved), Some((1, slot)));
+        assert_eq!(target_sp.resolve_forward(moved), None);
+    }
+
+    #[test]
+    fn iter_yields_the_live_records_in_slot_order() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        for i in 0..8u8 {
+            sp.insert_record(&[i; 10]).unwrap();
+        }
+        for slot in [0, 3, 4, 7] {
+            assert!(sp.delete_record(slot));
+        }
+
+        let live: Vec<(SlotId, Vec<u8>)> = sp.iter().map(|(slot, record)| (slot, record.to_vec())).collect();
+        assert_eq!(live, [1, 2, 5, 6].map(|i| (i, vec![i as u8; 10])));
+        assert_eq!(sp.iter().rev().map(|(slot, _)| slot).collect::<Vec<_>>(), vec![6, 5, 2, 1]);
+    }
+
+    #[test]
+    fn iter_skips_forwards_and_yields_nothing_on_an_empty_page() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        assert_eq!(sp.iter().count(), 0);
+
+        let a = sp.insert_record(&[1; FORWARD_LEN]).unwrap();
+        let b = sp.insert_record(b"b").unwrap();
+        assert!(sp.set_forward(a, 9, 0));
+        assert_eq!(sp.iter().collect::<Vec<_>>(), vec![(b, &b"b"[..])]);
+    }
+
+    fn second_page() -> Page {
+        let mut page = Page::new(2, PageType::NodeStore);
+        SlottedPage::init(&mut page);
+        page
+    }
+
+    #[test]
+    fn a_moved_record_is_tombstoned_here_and_readable_there() {
+        let (mut source, mut dest) = (empty_page(), second_page());
+        let mut from = SlottedPage::new(&mut source);
+        let mut to = SlottedPage::new(&mut dest);
+        let slot = from.insert_record(b"travels").unwrap();
+        let kept = from.insert_record(b"stays").unwrap();
+        to.insert_record(b"already here").unwrap();
+
+        let moved = from.move_record_to(slot, &mut to).unwrap();
+        assert_eq!(moved, 1);
+        assert!(from.is_tombstone(slot));
+        assert_eq!(from.get_record(slot), None);
+        assert_eq!(from.get_record(kept), Some(&b"stays"[..]));
+        assert_eq!(from.record_count(), 1);
+        assert_eq!(to.get_record(moved), Some(&b"travels"[..]));
+        assert_eq!(to.record_count(), 2);
+        assert_eq!(to.home_of(moved), None);
+    }
+
+    #[test]
+    fn moving_into_a_full_page_changes_neither_page() {
+        let (mut source, mut dest) = (empty_page(), second_page());
+        let mut from = SlottedPage::new(&mut source);
+        let mut to = SlottedPage::new(&mut dest);
+        let slot = from.insert_record(&[5; 64]).unwrap();
+        while to.insert_record(&[6; 32]).is_some() {}
+        let (before_from, before_to) = (*from.page().get_data(), *to.page().get_data());
+
+        assert_eq!(from.move_record_to(slot, &mut to), None);
+        assert_eq!(from.page().get_data(), &before_from);
+        assert_eq!(to.page().get_data(), &before_to);
+        assert_eq!(from.move_record_to(7, &mut to), None);
+    }
+
+    #[test]
+    fn a_record_moved_in_keeps_pointing_home_when_it_moves_on() {
+        let (mut source, mut dest) = (empty_page(), second_page());
+        let mut from = SlottedPage::new(&mut source);
+        let mut to = SlottedPage::new(&mut dest);
+        let slot = from.insert_moved(9, 4, b"forwarded").unwrap();
+
+        let moved = from.move_record_to(slot, &mut to).unwrap();
+        assert_eq!(to.home_of(moved), Some((9, 4)));
+        assert_eq!(to.get_record(moved), Some(&b"forwarded"[..]));
+    }
+
+    #[test]
+    fn raw_slots_flag_every_kind_of_entry() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let live = sp.insert_record(b"hello").unwrap();
+        let deleted = sp.insert_record(b"gone").unwrap();
+        let forwarded = sp.insert_record(b"moving").unwrap();
+        let moved_in = sp.insert_moved(9, 4, b"guest").unwrap();
+        assert!(sp.delete_record(deleted));
+        assert!(sp.set_forward(forwarded, 7, 2));
+
+        let slots: Vec<SlotEntry> = sp.raw_slots().collect();
+        let kinds: Vec<SlotKind> = slots.iter().map(|entry| entry.kind).collect();
+        assert_eq!(kinds, [SlotKind::Record, SlotKind::Tombstone, SlotKind::Forward, SlotKind::MovedIn]);
+        assert_eq!(slots.iter().map(|entry| entry.slot).collect::<Vec<_>>(), [live, deleted, forwarded, moved_in]);
+
+        let record = slots[live as usize];
+        assert_eq!(record.length, 5);
+        assert_eq!(&sp.page().get_data()[record.offset as usize..][..5], b"hello");
+        assert_eq!(slots[forwarded as usize].length as usize, FORWARD_LEN);
+        assert_eq!(slots[moved_in as usize].length as usize, FORWARD_LEN + 5);
+        // and backwards
+        assert_eq!(sp.raw_slots().rev().map(|entry| entry.slot).collect::<Vec<_>>(), [3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn raw_slots_of_an_empty_page_is_empty() {
+        let page = empty_page();
+        assert_eq!(SlottedPage::new(&page).raw_slots().count(), 0);
+    }
+
+    #[test]
+    fn deleting_the_newest_record_gives_its_bytes_back_at_once() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        sp.insert_record(&[1; 50]).unwrap();
+        let (free, pointer) = (sp.free_space(), sp.page().get_free_space_pointer());
+        let newest = sp.insert_record(&[2; 50]).unwrap();
+        assert!(sp.delete_record(newest));
+        assert_eq!(sp.free_space(), free);
+        assert_eq!(sp.page().get_free_space_pointer(), pointer);
+        assert_eq!(sp.slot_count(), 1);
+    }
+
+    #[test]
+    fn deleting_an_interior_record_leaves_a_tombstone() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let interior = sp.insert_record(&[1; 50]).unwrap();
+        sp.insert_record(&[2; 50]).unwrap();
+        let pointer = sp.page().get_free_space_pointer();
+        assert!(sp.delete_record(interior));
+        assert!(sp.is_tombstone(interior));
+        assert_eq!(sp.page().get_free_space_pointer(), pointer);
+        assert_eq!((sp.slot_count(), sp.record_count()), (2, 1));
+    }
+
+    #[test]
+    fn deleting_the_newest_record_also_reclaims_deleted_ones_below_it() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        let oldest = sp.insert_record(&[1; 50]).unwrap();
+        let pointer = sp.page().get_free_space_pointer();
+        let middle = sp.insert_record(&[2; 50]).unwrap();
+        let newest = sp.insert_record(&[3; 50]).unwrap();
+        assert!(sp.delete_record(middle));
+        assert!(sp.delete_record(newest));
+        assert_eq!(sp.page().get_free_space_pointer(), pointer);
+        assert_eq!(sp.slot_count(), 1);
+        assert_eq!(sp.get_record(oldest), Some(&[1; 50][..]));
+        // the last one going empties the page
+        assert!(sp.delete_record(oldest));
+        assert_eq!(sp.page().get_free_space_pointer(), PAGE_SIZE as u32);
+        assert_eq!(sp.slot_count(), 0);
+    }
+
+    #[test]
+    fn a_tombstone_holding_a_generation_stays_in_the_directory() {
+        let mut page = empty_page();
+        let mut sp = SlottedPage::new(&mut page);
+        sp.insert_record(&[1; 50]).unwrap();
+        let newest = sp.insert_record(&[2; 50]).unwrap();
+        assert!(sp.delete_record_with_generation(newest, 3));
+        assert_eq!(sp.slot_count(), 2);
+        assert_eq!(sp.generation(newest), 3);
+        // its bytes still come back
+        assert_eq!(sp.page().get_free_space_pointer(), PAGE_SIZE as u32 - 50);
+    }
+}
@@ -0,0 +1,84 @@
+/*
+* Counters for how the buffer pool is doing, mostly for picking a pool size.
+* All relaxed atomics: the numbers are only ever read as a rough picture, so there's no point paying for ordering
+* on the fetch path. A snapshot taken while other threads are working can be slightly out of sync between fields.
+*/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct BufferPoolStats {
+    hits: AtomicU64,                  // fetches that found the page already in a frame
+    misses: AtomicU64,                // fetches that had to read the page in from disk
+    evictions: AtomicU64,             // pages kicked out of a frame to make room
+    dirty_flushes: AtomicU64,         // dirty pages written back, by eviction or flushing
+    pool_exhausted_events: AtomicU64, // times a frame was needed but every one was pinned
+    syncs: AtomicU64,                 // fsyncs of the data file
+    replacer_passes: AtomicU64,       // times the pool went to the replacer for victims, see eviction_batch
+}
+
+// Plain copy of the counters at some point in time
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BufferPoolStatsSnapshot {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub dirty_flushes: u64,
+    pub pool_exhausted_events: u64,
+    pub syncs: u64,
+    pub replacer_passes: u64,
+}
+
+impl BufferPoolStatsSnapshot {
+    // fraction of fetches served from RAM, 0 if nothing has been fetched yet
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 { 0.0 } else { self.hits as f64 / total as f64 }
+    }
+}
+
+impl BufferPoolStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record_hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_eviction(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dirty_flush(&self) {
+        self.dirty_flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_pool_exhausted(&self) {
+        self.pool_exhausted_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_sync(&self) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_replacer_pass(&self) {
+        self.replacer_passes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> BufferPoolStatsSnapshot {
+        BufferPoolStatsSnapshot {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            dirty_flushes: self.dirty_flushes.load(Ordering::Relaxed),
+            pool_exhausted_events: self.pool_exhausted_events.load(Ordering::Relaxed),
+            syncs: self.syncs.load(Ordering::Relaxed),
+            replacer_passes: self.replacer_passes.load(Ordering::Relaxed),
+        }
+    }
+}
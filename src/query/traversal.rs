@@ -0,0 +1,123 @@
+/*
+* Graph traversals on top of the node and relationship stores.
+* Everything here only reads, and goes through the stores (and so the buffer pool) for every hop.
+*/
+
+use std::collections::HashSet;
+
+use crate::store::node_store::NodeId;
+use crate::store::relationship_store::{Direction, RelationshipStore};
+
+// Breadth first walk from start, following relationships in direction, up to max_depth hops out
+// with a non empty type_filter only relationships of those types are followed
+// returns every node reached in visiting order, start first, then all nodes at depth 1, then depth 2, ...
+// each node shows up once no matter how many paths lead to it, so cycles are fine
+// before walking a level the relationship pages of the whole frontier are prefetched
+pub fn bfs(
+    rels: &RelationshipStore,
+    start: NodeId,
+    max_depth: u32,
+    direction: Direction,
+    type_filter: &[u32],
+) -> Vec<NodeId> {
+    let mut visited = HashSet::from([start]);
+    let mut order = vec![start];
+    let mut frontier = vec![start];
+
+    for _ in 0..max_depth {
+        if frontier.is_empty() {
+            break;
+        }
+        rels.prefetch_chains(&frontier);
+
+        let mut next = Vec::new();
+        for &node in &frontier {
+            for rel in rels.relationships_of(node, direction, type_filter) {
+                let other = rel.other(node);
+                if visited.insert(other) {
+                    next.push(other);
+                }
+            }
+        }
+        order.extend_from_slice(&next);
+        frontier = next;
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::buffer_pool_manager::BufferPoolManager;
+    use crate::store::node_store::NodeStore;
+
+    // a -> b, a -> c, b -> d, c -> d, d -> a, and e off on its own
+    struct Graph {
+        rels: RelationshipStore,
+        a: NodeId,
+        b: NodeId,
+        c: NodeId,
+        d: NodeId,
+        e: NodeId,
+    }
+
+    fn graph() -> Graph {
+        let bpm = Arc::new(BufferPoolManager::new(16, MockDiskManager::new()).unwrap());
+        let nodes = Arc::new(NodeStore::new(bpm.clone()));
+        let [a, b, c, d, e] = [0; 5].map(|_| nodes.create_node(&[]).unwrap());
+        let rels = RelationshipStore::new(bpm, nodes);
+        for (start, end) in [(a, b), (a, c), (b, d), (c, d), (d, a)] {
+            rels.create_relationship(start, end, 0).unwrap();
+        }
+        Graph { rels, a, b, c, d, e }
+    }
+
+    fn set(nodes: &[NodeId]) -> HashSet<NodeId> {
+        nodes.iter().copied().collect()
+    }
+
+    #[test]
+    fn bfs_visits_level_by_level_and_each_node_once() {
+        let g = graph();
+        let order = bfs(&g.rels, g.a, 10, Direction::Outgoing, &[]);
+        // d -> a closes a cycle, a isn't visited again
+        assert_eq!(order.len(), 4);
+        assert_eq!(order[0], g.a);
+        assert_eq!(set(&order[1..3]), set(&[g.b, g.c]));
+        assert_eq!(order[3], g.d);
+    }
+
+    #[test]
+    fn bfs_stops_at_max_depth() {
+        let g = graph();
+        assert_eq!(bfs(&g.rels, g.a, 0, Direction::Outgoing, &[]), vec![g.a]);
+        let order = bfs(&g.rels, g.a, 1, Direction::Outgoing, &[]);
+        assert_eq!(set(&order), set(&[g.a, g.b, g.c]));
+    }
+
+    #[test]
+    fn bfs_from_a_node_without_relationships_is_just_that_node() {
+        let g = graph();
+        assert_eq!(bfs(&g.rels, g.e, 5, Direction::Both, &[]), vec![g.e]);
+    }
+
+    #[test]
+    fn bfs_follows_relationships_backwards_when_incoming() {
+        let g = graph();
+        let order = bfs(&g.rels, g.d, 1, Direction::Incoming, &[]);
+        assert_eq!(set(&order), set(&[g.d, g.b, g.c]));
+        let order = bfs(&g.rels, g.d, 1, Direction::Both, &[]);
+        assert_eq!(set(&order), set(&[g.d, g.b, g.c, g.a]));
+    }
+
+    #[test]
+    fn bfs_only_follows_the_filtered_types() {
+        let g = graph();
+        let extra = g.rels.create_relationship(g.a, g.e, 2).unwrap();
+        assert_eq!(g.rels.get_relationship(extra).unwrap().type_id, 2);
+        assert_eq!(bfs(&g.rels, g.a, 3, Direction::Outgoing, &[2]), vec![g.a, g.e]);
+        assert_eq!(bfs(&g.rels, g.a, 3, Direction::Outgoing, &[0]).len(), 4);
+    }
+}
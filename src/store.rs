@@ -1 +1,5 @@
-pub mod node_store;
\ No newline at end of file
+pub mod node_store;
+pub mod relationship_store;
+pub mod property_store;
+pub mod overflow;
+pub mod storage_stats;
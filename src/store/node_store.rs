@@ -0,0 +1,1151 @@
+/*
+* Node storage on top of the buffer pool.
+* Nodes are variable length (a node can have any number of labels) so they live in slotted pages of type NodeStore,
+* and a NodeId is the (page, slot) the record was created in plus the slot's generation. A node that grows too big
+* for its page (set_labels) moves to another one and leaves a forward behind in its home slot, so the id keeps
+* working (see slotted_page.rs).
+* Deleting a node leaves the next generation in its slot's tombstone, the node created there next gets it. Looking
+* a node up checks the id's generation against the record's, so a stale id comes up empty instead of finding the
+* new node. A NodeId only has 34 bits for the page, so nodes can't go on pages past 2^34 - 1, once the file is that
+* big create_node (and moving a node) fails instead of handing out a truncated id.
+*
+* Node record layout:
+* | first_rel: u64 | first_prop: u64 | label_mask: u64 | label_count: u16 | label: u32 ... | generation: u16 |
+* first_rel is the head of the node's relationship chain (see relationship_store.rs) and first_prop the head of its
+* property chain (see property_store.rs), u64::MAX when there is none. Records from before generations end after the
+* labels, they read as generation 0.
+* label_mask has bit (label % 64) set for every label, label scans check it straight off the page bytes and only
+* decode records that might match.
+*
+* A node whose record would come out bigger than MAX_INLINE_RECORD keeps its labels in an overflow chain instead
+* (see overflow.rs), stored as u32s, and label_count is OVERFLOW_LABELS followed by | head: u64 | count: u32 |.
+* label_mask stays in the record either way. get_node and friends read the chain transparently, RecordView can't
+* (it only has the page) so it says so through labels_overflowed.
+*/
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::index::bplus_tree::BPlusTree;
+use crate::paging::config::MAX_INLINE_RECORD;
+use crate::paging::buffer_pool_manager::{BufferPoolManager, PageFrameRef, PageTypeIter, WritePageGuard};
+use crate::paging::free_space_map::FreeSpaceMap;
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::PageId;
+use crate::paging::record::{DecodeError, Record, RecordReader};
+use crate::paging::slotted_page::{SlotId, SlottedPage, UpdateResult, FORWARD_LEN, MAX_GENERATION, SLOT_SIZE};
+use super::overflow::OverflowChain;
+use super::property_store::PropertyId;
+use super::relationship_store::RelationshipId;
+use super::storage_stats::{storage_stats, StorageStats};
+
+// marks an empty pointer field in a record
+pub(crate) const NO_ID: u64 = u64::MAX;
+
+// Location of a node record and the generation of its slot, | generation: 14 | page_id: 34 | slot: 16 | from the
+// high bits down. Ids from before generations have 0 up there, same as the first node in any slot
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub u64);
+
+pub(crate) const PAGE_BITS: u32 = 34;
+pub(crate) const PAGE_MASK: u64 = (1 << PAGE_BITS) - 1;
+
+impl NodeId {
+    // None if page_id doesn't fit in the 34 bits there are for it (see fits)
+    pub fn new(page_id: PageId, slot: SlotId, generation: u16) -> Option<Self> {
+        if !Self::fits(page_id) {
+            return None;
+        }
+        let generation = (generation & MAX_GENERATION) as u64;
+        Some(Self((generation << (PAGE_BITS + 16)) | (page_id << 16) | slot as u64))
+    }
+
+    // whether node ids can point into page_id, pages past 2^34 - 1 can't hold nodes
+    pub fn fits(page_id: PageId) -> bool {
+        page_id <= PAGE_MASK
+    }
+
+    pub fn page_id(&self) -> PageId {
+        (self.0 >> 16) & PAGE_MASK
+    }
+
+    pub fn generation(&self) -> u16 {
+        (self.0 >> (PAGE_BITS + 16)) as u16
+    }
+
+    pub fn slot(&self) -> SlotId {
+        (self.0 & 0xFFFF) as SlotId
+    }
+}
+
+/*
+* Set of label ids as a bitset, for asking for nodes that carry all of some labels.
+* mask() folds it down to the 64 bit summary node records carry, which can have false positives but no
+* false negatives.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelSet {
+    words: Vec<u64>,
+}
+
+impl LabelSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_labels(labels: &[u32]) -> Self {
+        let mut set = Self::new();
+        for &label in labels {
+            set.insert(label);
+        }
+        set
+    }
+
+    pub fn insert(&mut self, label: u32) {
+        let word = label as usize / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (label % 64);
+    }
+
+    pub fn contains(&self, label: u32) -> bool {
+        self.words.get(label as usize / 64).is_some_and(|word| word & (1 << (label % 64)) != 0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    // every label in self is also in other
+    pub fn is_subset(&self, other: &LabelSet) -> bool {
+        self.words.iter().enumerate().all(|(i, &word)| word & !other.words.get(i).copied().unwrap_or(0) == 0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            (0..64).filter(move |bit| word & (1 << bit) != 0).map(move |bit| (i * 64 + bit) as u32)
+        })
+    }
+
+    // bit (label % 64) for every label
+    pub fn mask(&self) -> u64 {
+        self.words.iter().fold(0, |mask, &word| mask | word)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub id: NodeId,
+    pub labels: Vec<u32>,
+    pub first_rel: Option<RelationshipId>,
+    pub first_prop: Option<PropertyId>,
+}
+
+pub struct NodeStore {
+    bpm: Arc<BufferPoolManager>,
+    // how much room each of our pages has left, new nodes go to the first page that fits
+    free_space: Arc<FreeSpaceMap>,
+}
+
+impl NodeStore {
+    pub fn new(bpm: Arc<BufferPoolManager>) -> Self {
+        let free_space = bpm.free_space_map(PageType::NodeStore);
+        Self { bpm, free_space }
+    }
+
+    // page and record counts for this store, see StorageStats
+    pub fn stats(&self) -> StorageStats {
+        storage_stats(&self.bpm, PageType::NodeStore)
+    }
+
+    // page_id read latched, None unless it's one of our pages (a node id that points at another kind of page
+    // is a bug somewhere, better to come up empty than to decode whatever is there)
+    fn fetch(&self, page_id: PageId) -> Option<PageFrameRef<'_>> {
+        let guard = self.bpm.fetch_page(page_id).ok()?;
+        guard.expect_type(PageType::NodeStore).ok()?;
+        Some(guard)
+    }
+
+    // same as fetch, write latched
+    fn fetch_write(&self, page_id: PageId) -> Option<WritePageGuard<'_>> {
+        let guard = self.bpm.fetch_page_write(page_id).ok()?;
+        guard.expect_type(PageType::NodeStore).ok()?;
+        Some(guard)
+    }
+
+    // Stores a new node with the given labels, None if the pool is out of frames
+    pub fn create_node(&self, labels: &[u32]) -> Option<NodeId> {
+        let record = self.encode(None, None, 0, labels)?;
+        let id = self.place(record.serialized_len(), |page| {
+            // a reused slot hands the new node the generation its last one left behind
+            let generation = page.next_generation();
+            let slot = page.insert(&NodeRecord { generation, ..record.clone() })?;
+            Some((slot, generation))
+        });
+        if id.is_none() {
+            self.free_overflow(&record);
+        }
+        id
+    }
+
+    // the record for a node with these fields, labels go to a new overflow chain if the record would come out
+    // bigger than MAX_INLINE_RECORD. None if that chain couldn't be written
+    fn encode(
+        &self,
+        first_rel: Option<RelationshipId>,
+        first_prop: Option<PropertyId>,
+        generation: u16,
+        labels: &[u32],
+    ) -> Option<NodeRecord> {
+        let labels = if LABELS + labels.len() * 4 + GENERATION_LEN <= MAX_INLINE_RECORD {
+            StoredLabels::Inline(labels.to_vec())
+        } else {
+            let bytes: Vec<u8> = labels.iter().flat_map(|label| label.to_le_bytes()).collect();
+            let head = OverflowChain::new(&self.bpm).write_blob(&bytes).ok()?;
+            StoredLabels::Overflow { head, count: labels.len() as u32, mask: LabelSet::from_labels(labels).mask() }
+        };
+        Some(NodeRecord { first_rel, first_prop, labels, generation })
+    }
+
+    // record's labels, read from its overflow chain if that's where they are
+    fn load_labels(&self, record: NodeRecord) -> Option<Vec<u32>> {
+        match record.labels {
+            StoredLabels::Inline(labels) => Some(labels),
+            StoredLabels::Overflow { head, count, .. } => {
+                let bytes = OverflowChain::new(&self.bpm).read_blob(head).ok()?;
+                if bytes.len() != count as usize * 4 {
+                    return None;
+                }
+                Some(bytes.chunks_exact(4).map(|label| u32::from_le_bytes(label.try_into().unwrap())).collect())
+            }
+        }
+    }
+
+    // gives back record's overflow chain, once the record itself is gone (or never made it into a page)
+    // the record is already gone by then, a chain that can't be freed goes to the pool's deferred errors
+    fn free_overflow(&self, record: &NodeRecord) {
+        if let StoredLabels::Overflow { head, .. } = record.labels
+            && let Err(e) = OverflowChain::new(&self.bpm).free_chain(head)
+        {
+            self.bpm.defer_error(e);
+        }
+    }
+
+    // the record at (not through) id, still undecoded labels and all
+    fn read_record(&self, at: NodeId) -> Option<NodeRecord> {
+        let guard = self.fetch(at.page_id())?;
+        SlottedPage::new(&*guard).get(at.slot())?.ok()
+    }
+
+    // create_node plus an index entry mapping key to the new node, both or neither: if the index insert fails
+    // the node is deleted again and this returns None. Like BPlusTree::insert an existing entry for key is replaced
+    pub fn create_node_indexed(&self, labels: &[u32], index: &BPlusTree, key: u64) -> Option<NodeId> {
+        let id = self.create_node(labels)?;
+        if index.insert(key, id).is_err() {
+            self.delete_node(id);
+            return None;
+        }
+        Some(id)
+    }
+
+    // Runs insert on the first page with room for len bytes (plus a slot), or on a new page if none has any
+    // insert says which slot it used and the generation of what it put there
+    fn place(&self, len: usize, insert: impl Fn(&mut SlottedPage<&mut Page>) -> Option<(SlotId, u16)>) -> Option<NodeId> {
+        if let Some(page_id) = self.free_space.find_page_with_room(len + SLOT_SIZE) {
+            let mut guard = self.fetch_write(page_id)?;
+            let mut page = SlottedPage::new(&mut *guard);
+            // someone else might have filled it up since we looked
+            let slot = insert(&mut page);
+            self.free_space.update(page_id, page.available_space());
+            if let Some((slot, generation)) = slot {
+                return NodeId::new(page_id, slot, generation);
+            }
+        }
+
+        // no page has room (or we don't have one yet), start a new one
+        let mut guard = self.bpm.allocate_page_of_type(PageType::NodeStore).ok()?;
+        let page_id = guard.page_id;
+        if !NodeId::fits(page_id) {
+            // the file grew past what node ids can address, hand the page back and refuse the node
+            drop(guard);
+            if let Err(e) = self.bpm.deallocate_page(page_id) {
+                self.bpm.defer_error(e);
+            }
+            return None;
+        }
+        let mut page = SlottedPage::init(&mut *guard);
+        let (slot, generation) = insert(&mut page)?;
+        self.free_space.update(page_id, page.available_space());
+        NodeId::new(page_id, slot, generation)
+    }
+
+    // where id's record actually sits: id itself, or wherever the forward in its slot points (with id's generation)
+    // forwards only go one hop, so this never needs to look further. None if the record there is from another
+    // generation, id is stale then
+    fn locate(&self, id: NodeId) -> Option<NodeId> {
+        let at = {
+            let guard = self.fetch(id.page_id())?;
+            let page = SlottedPage::new(&*guard);
+            match page.resolve_forward(id.slot()) {
+                Some((page_id, slot)) => NodeId::new(page_id, slot, id.generation())?,
+                None => return (record_generation(page.get_record(id.slot())?)? == id.generation()).then_some(id),
+            }
+        };
+        let guard = self.fetch(at.page_id())?;
+        let record = SlottedPage::new(&*guard).get_record(at.slot()).and_then(record_generation);
+        (record? == id.generation()).then_some(at)
+    }
+
+    pub fn get_node(&self, id: NodeId) -> Option<Node> {
+        let record = self.read_record(self.locate(id)?)?;
+        let (first_rel, first_prop) = (record.first_rel, record.first_prop);
+        Some(Node { id, labels: self.load_labels(record)?, first_rel, first_prop })
+    }
+
+    // Runs f on a view of the node without decoding it, None if there's no node at id
+    // the page stays pinned and read latched while f runs, so keep f short. Overflowed labels aren't in the view
+    pub fn with_view<T>(&self, id: NodeId, f: impl FnOnce(RecordView<'_>) -> T) -> Option<T> {
+        let at = self.locate(id)?;
+        let guard = self.fetch(at.page_id())?;
+        let page = SlottedPage::new(&*guard);
+        Some(f(RecordView::new(id, page.get_record(at.slot())?)?))
+    }
+
+    // Every node on page as a RecordView in slot order, for scans that only look at a few fields
+    // page is one of our pages borrowed from its guard, the views can't outlive it
+    pub fn views(page: &Page) -> impl DoubleEndedIterator<Item = RecordView<'_>> {
+        let page_id = page.get_page_id();
+        SlottedPage::new(page).records().filter_map(move |(slot, record)| {
+            // nodes that moved here are still known by their home slot
+            let (home_page, home_slot) = SlottedPage::new(page).home_of(slot).unwrap_or((page_id, slot));
+            RecordView::new(NodeId::new(home_page, home_slot, record_generation(record)?)?, record)
+        })
+    }
+
+    // Replaces node's labels, returns false if the node doesn't exist or there was no room for it anywhere
+    // if the record outgrows its page it moves to one with room and its home slot becomes a forward, id stays valid
+    // the old labels' overflow chain (if any) is freed once the new record is in, a new one is made if needed
+    pub fn set_labels(&self, id: NodeId, labels: &[u32]) -> bool {
+        let Some(at) = self.locate(id) else { return false };
+        let Some(old) = self.read_record(at) else { return false };
+        let Some(record) = self.encode(old.first_rel, old.first_prop, old.generation, labels) else { return false };
+        if self.replace_record(id, at, &record) {
+            self.free_overflow(&old);
+            true
+        } else {
+            self.free_overflow(&record);
+            false
+        }
+    }
+
+    // writes record over the node's current one at at, moving it to another page if it doesn't fit
+    fn replace_record(&self, id: NodeId, at: NodeId, record: &NodeRecord) -> bool {
+        let bytes = record.to_bytes();
+        {
+            let Some(mut guard) = self.fetch_write(at.page_id()) else { return false };
+            let mut page = SlottedPage::new(&mut *guard);
+            let result = page.update_record(at.slot(), &bytes);
+            self.free_space.update(at.page_id(), page.available_space());
+            match result {
+                UpdateResult::InPlace | UpdateResult::Shrunk { .. } => return true,
+                UpdateResult::NotFound => return false,
+                UpdateResult::Relocated => {}
+            }
+        }
+
+        let moved = self.place(FORWARD_LEN + bytes.len(), |page| {
+            Some((page.insert_moved(id.page_id(), id.slot(), &bytes)?, id.generation()))
+        });
+        let Some(moved) = moved else { return false };
+        // the forward at home gets repointed first, until then the old copy is still the node. If that fails the
+        // new copy goes again and the node stays as it was
+        let repointed = self.fetch_write(id.page_id()).is_some_and(|mut guard| {
+            let mut page = SlottedPage::new(&mut *guard);
+            let repointed = page.set_forward(id.slot(), moved.page_id(), moved.slot());
+            self.free_space.update(id.page_id(), page.available_space());
+            repointed
+        });
+        if !repointed {
+            self.delete_slot(moved, 0);
+            return false;
+        }
+        // a node that already moved once gets its old copy dropped
+        if at != id {
+            self.delete_slot(at, 0);
+        }
+        true
+    }
+
+    // points the head of node's relationship chain at rel, returns false if the node doesn't exist
+    pub fn set_first_rel(&self, id: NodeId, rel: Option<RelationshipId>) -> bool {
+        self.write_pointer(id, FIRST_REL, rel.map(|r| r.0))
+    }
+
+    // points the head of node's property chain at prop, returns false if the node doesn't exist
+    pub fn set_first_prop(&self, id: NodeId, prop: Option<PropertyId>) -> bool {
+        self.write_pointer(id, FIRST_PROP, prop.map(|p| p.0))
+    }
+
+    fn write_pointer(&self, id: NodeId, at: usize, value: Option<u64>) -> bool {
+        let Some(id) = self.locate(id) else { return false };
+        let Some(mut guard) = self.fetch_write(id.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        match page.get_record_mut(id.slot()) {
+            Some(record) => {
+                record[at..at + 8].copy_from_slice(&value.unwrap_or(NO_ID).to_le_bytes());
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Scans every node page for nodes carrying all of labels (an empty set matches everything)
+    // the label mask is checked on the raw record first, only nodes that pass get their labels decoded and compared,
+    // and only nodes that really match are decoded in full. Pages are copied out one at a time so nothing stays
+    // pinned between calls to next
+    pub fn scan_with_labels(&self, labels: &LabelSet) -> NodeScan<'_> {
+        let pages = self.bpm.iter_pages_of_type(PageType::NodeStore);
+        NodeScan { store: self, pages, wanted: labels.clone(), buffered: Vec::new() }
+    }
+
+    // Counts the nodes pred accepts, looking at each one through a RecordView so nothing gets decoded or copied
+    // pages whose header says they hold no records (item_count 0) are skipped without touching their slots. pred
+    // is opaque, so pages can't be ruled out by what's on them until the header carries some label summary
+    pub fn count_where(&self, pred: impl Fn(&RecordView<'_>) -> bool) -> u64 {
+        let mut count = 0;
+        for page in self.bpm.iter_pages_of_type(PageType::NodeStore) {
+            if SlottedPage::new(&*page).record_count() == 0 {
+                continue;
+            }
+            count += Self::views(&page).filter(|view| pred(view)).count() as u64;
+        }
+        count
+    }
+
+    // returns false if there was no node at id, a moved node loses both its record and the forward to it
+    pub fn delete_node(&self, id: NodeId) -> bool {
+        let Some(at) = self.locate(id) else { return false };
+        let record = self.read_record(at);
+        if at != id && !self.delete_slot(at, 0) {
+            return false;
+        }
+        let deleted = self.delete_slot(id, next_generation(id.generation()));
+        if let Some(record) = record {
+            self.free_overflow(&record);
+        }
+        deleted
+    }
+
+    // Deletes every node in ids and returns how many of them existed, each page gets fetched once for all of its
+    // nodes instead of once per node. Moved nodes are the exception, their generation is on the page they moved to
+    // so they go through delete_node afterwards. Like delete_node this doesn't look at relationships,
+    // RelationshipStore::delete_nodes detaches them first or refuses
+    pub fn delete_nodes(&self, ids: &[NodeId]) -> usize {
+        let mut by_page: BTreeMap<PageId, Vec<NodeId>> = BTreeMap::new();
+        for &id in ids {
+            by_page.entry(id.page_id()).or_default().push(id);
+        }
+
+        let mut deleted = 0;
+        let mut moved = Vec::new();
+        // overflow chains of deleted records, freed once no node page is latched anymore
+        let mut overflowed = Vec::new();
+        for (page_id, ids) in by_page {
+            let Some(mut guard) = self.fetch_write(page_id) else { continue };
+            let mut page = SlottedPage::new(&mut *guard);
+            for id in ids {
+                if page.is_forward(id.slot()) {
+                    moved.push(id);
+                    continue;
+                }
+                let Some(record) = page.get::<NodeRecord>(id.slot()).and_then(Result::ok) else { continue };
+                if record.generation != id.generation() {
+                    continue;
+                }
+                if page.delete_record_with_generation(id.slot(), next_generation(id.generation())) {
+                    deleted += 1;
+                    overflowed.push(record);
+                }
+            }
+            self.free_space.update(page_id, page.available_space());
+        }
+
+        for record in &overflowed {
+            self.free_overflow(record);
+        }
+        deleted + moved.into_iter().filter(|&id| self.delete_node(id)).count()
+    }
+
+    // generation is what the tombstone hands the slot's next node, 0 for slots nodes only ever moved into
+    fn delete_slot(&self, id: NodeId, generation: u16) -> bool {
+        let Some(mut guard) = self.fetch_write(id.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        let deleted = page.delete_record_with_generation(id.slot(), generation);
+        self.free_space.update(id.page_id(), page.available_space());
+        deleted
+    }
+}
+
+// offsets of the fixed fields inside a node record
+const FIRST_REL: usize = 0;
+const FIRST_PROP: usize = 8;
+const LABEL_MASK: usize = 16;
+const LABELS: usize = 26;
+// label_count of a node whose labels are in an overflow chain
+const OVERFLOW_LABELS: u16 = u16::MAX;
+// | head: u64 | count: u32 | after the OVERFLOW_LABELS marker
+const OVERFLOW_REF_LEN: usize = 12;
+const GENERATION_LEN: usize = 2;
+
+// what a slot's next generation is after deleting a node of this one, 0 is only ever a slot's first
+pub(crate) fn next_generation(generation: u16) -> u16 {
+    if generation >= MAX_GENERATION { 1 } else { generation + 1 }
+}
+
+// bytes of record up to the end of its labels, None if it's too short for the labels it says it has
+fn fields_len(record: &[u8]) -> Option<usize> {
+    let len = match u16::from_le_bytes(record.get(LABELS - 2..LABELS)?.try_into().unwrap()) {
+        OVERFLOW_LABELS => LABELS + OVERFLOW_REF_LEN,
+        count => LABELS + count as usize * 4,
+    };
+    (record.len() >= len).then_some(len)
+}
+
+// generation the record was created with, 0 for records that don't have one. None if it's not a valid record
+fn record_generation(record: &[u8]) -> Option<u16> {
+    let len = fields_len(record)?;
+    Some(record.get(len..len + GENERATION_LEN).map_or(0, |raw| u16::from_le_bytes(raw.try_into().unwrap())))
+}
+
+// a node as it's stored, the id comes from where the record sits (and its generation)
+#[derive(Clone)]
+struct NodeRecord {
+    first_rel: Option<RelationshipId>,
+    first_prop: Option<PropertyId>,
+    labels: StoredLabels,
+    generation: u16,
+}
+
+#[derive(Clone)]
+enum StoredLabels {
+    Inline(Vec<u32>),
+    Overflow { head: PageId, count: u32, mask: u64 },
+}
+
+impl Record for NodeRecord {
+    fn serialized_len(&self) -> usize {
+        match &self.labels {
+            StoredLabels::Inline(labels) => LABELS + labels.len() * 4 + GENERATION_LEN,
+            StoredLabels::Overflow { .. } => LABELS + OVERFLOW_REF_LEN + GENERATION_LEN,
+        }
+    }
+
+    fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.first_rel.map_or(NO_ID, |r| r.0).to_le_bytes());
+        buf.extend_from_slice(&self.first_prop.map_or(NO_ID, |p| p.0).to_le_bytes());
+        match &self.labels {
+            StoredLabels::Inline(labels) => {
+                buf.extend_from_slice(&LabelSet::from_labels(labels).mask().to_le_bytes());
+                buf.extend_from_slice(&(labels.len() as u16).to_le_bytes());
+                for label in labels {
+                    buf.extend_from_slice(&label.to_le_bytes());
+                }
+            }
+            StoredLabels::Overflow { head, count, mask } => {
+                buf.extend_from_slice(&mask.to_le_bytes());
+                buf.extend_from_slice(&OVERFLOW_LABELS.to_le_bytes());
+                buf.extend_from_slice(&head.to_le_bytes());
+                buf.extend_from_slice(&count.to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(&self.generation.to_le_bytes());
+    }
+
+    fn deserialize(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = RecordReader::new(buf);
+        let id = |raw: u64| (raw != NO_ID).then_some(raw);
+        let first_rel = id(reader.u64()?).map(RelationshipId);
+        let first_prop = id(reader.u64()?).map(PropertyId);
+        let mask = reader.u64()?;
+        let labels = match reader.u16()? {
+            OVERFLOW_LABELS => StoredLabels::Overflow { head: reader.u64()?, count: reader.u32()?, mask },
+            count => StoredLabels::Inline((0..count).map(|_| reader.u32()).collect::<Result<_, _>>()?),
+        };
+        let generation = match reader.rest() {
+            [low, high, ..] => u16::from_le_bytes([*low, *high]),
+            _ => 0,
+        };
+        Ok(Self { first_rel, first_prop, labels, generation })
+    }
+}
+
+/*
+* Zero copy view of a node record, reads the fields straight off the page bytes when asked instead of decoding the
+* whole record into a Node. Borrows from the page, so whatever guard pinned the page has to outlive the view and the
+* page can't be evicted or changed underneath it. Get them from NodeStore::views or NodeStore::with_view.
+*/
+#[derive(Debug, Copy, Clone)]
+pub struct RecordView<'a> {
+    id: NodeId,
+    record: &'a [u8],
+}
+
+impl<'a> RecordView<'a> {
+    // None if record is too short for the labels it says it has, the accessors can't fail after that
+    fn new(id: NodeId, record: &'a [u8]) -> Option<Self> {
+        fields_len(record)?;
+        Some(Self { id, record })
+    }
+
+    pub fn node_id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn first_rel(&self) -> Option<RelationshipId> {
+        self.pointer(FIRST_REL).map(RelationshipId)
+    }
+
+    pub fn first_prop(&self) -> Option<PropertyId> {
+        self.pointer(FIRST_PROP).map(PropertyId)
+    }
+
+    // the 64 bit label summary, see LabelSet::mask
+    pub fn label_mask(&self) -> u64 {
+        self.read_u64(LABEL_MASK)
+    }
+
+    // the labels are in an overflow chain rather than the record, labels() comes up empty then and has_labels only
+    // goes by the mask. NodeStore::get_node reads them
+    pub fn labels_overflowed(&self) -> bool {
+        self.raw_label_count() == OVERFLOW_LABELS
+    }
+
+    // right for overflowed labels too
+    pub fn label_count(&self) -> usize {
+        match self.raw_label_count() {
+            OVERFLOW_LABELS => u32::from_le_bytes(self.record[LABELS + 8..LABELS + 12].try_into().unwrap()) as usize,
+            count => count as usize,
+        }
+    }
+
+    // labels in the order they were stored, doesn't allocate. Nothing if they overflowed
+    pub fn labels(&self) -> impl Iterator<Item = u32> + 'a {
+        let inline = if self.labels_overflowed() { 0 } else { self.label_count() };
+        let labels = &self.record[LABELS..LABELS + inline * 4];
+        labels.chunks_exact(4).map(|label| u32::from_le_bytes(label.try_into().unwrap()))
+    }
+
+    fn raw_label_count(&self) -> u16 {
+        u16::from_le_bytes(self.record[LABELS - 2..LABELS].try_into().unwrap())
+    }
+
+    pub fn label_set(&self) -> LabelSet {
+        let mut set = LabelSet::new();
+        for label in self.labels() {
+            set.insert(label);
+        }
+        set
+    }
+
+    // node carries every label in wanted, checks the mask first and only walks the labels if it might
+    // with overflowed labels the mask is all there is to go by, so a yes might be wrong
+    pub fn has_labels(&self, wanted: &LabelSet) -> bool {
+        let mask = wanted.mask();
+        self.label_mask() & mask == mask && (self.labels_overflowed() || wanted.is_subset(&self.label_set()))
+    }
+
+    // copies the view out into an owned Node, without its labels if they overflowed
+    pub fn to_node(&self) -> Node {
+        Node { id: self.id, labels: self.labels().collect(), first_rel: self.first_rel(), first_prop: self.first_prop() }
+    }
+
+    fn pointer(&self, at: usize) -> Option<u64> {
+        let raw = self.read_u64(at);
+        (raw != NO_ID).then_some(raw)
+    }
+
+    fn read_u64(&self, at: usize) -> u64 {
+        u64::from_le_bytes(self.record[at..at + 8].try_into().unwrap())
+    }
+}
+
+// Iterator returned by NodeStore::scan_with_labels, in page then slot order
+pub struct NodeScan<'a> {
+    store: &'a NodeStore,
+    pages: PageTypeIter<'a>,
+    wanted: LabelSet,
+    buffered: Vec<ScanMatch>, // matches from the current page, reversed so pop gives slot order
+}
+
+enum ScanMatch {
+    Node(Node),
+    // passed the mask but its labels are in an overflow chain, read (and checked) once the page is let go
+    Overflowed(NodeId),
+}
+
+impl<'a> Iterator for NodeScan<'a> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        loop {
+            match self.buffered.pop() {
+                Some(ScanMatch::Node(node)) => return Some(node),
+                Some(ScanMatch::Overflowed(id)) => {
+                    let node = self.store.get_node(id);
+                    if let Some(node) = node.filter(|node| self.wanted.is_subset(&LabelSet::from_labels(&node.labels))) {
+                        return Some(node);
+                    }
+                    continue;
+                }
+                None => {}
+            }
+            let guard = self.pages.next()?;
+            let matches = NodeStore::views(&guard).rev().filter(|view| view.has_labels(&self.wanted));
+            self.buffered.extend(matches.map(|view| {
+                if view.labels_overflowed() { ScanMatch::Overflowed(view.node_id()) } else { ScanMatch::Node(view.to_node()) }
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::page_constants::PAGE_SIZE;
+
+    fn store() -> NodeStore {
+        NodeStore::new(Arc::new(BufferPoolManager::new(16, MockDiskManager::new()).unwrap()))
+    }
+
+    #[test]
+    fn created_nodes_read_back() {
+        let nodes = store();
+        let a = nodes.create_node(&[1, 2]).unwrap();
+        let b = nodes.create_node(&[]).unwrap();
+        assert_ne!(a, b);
+
+        let node = nodes.get_node(a).unwrap();
+        assert_eq!(node.id, a);
+        assert_eq!(node.labels, vec![1, 2]);
+        assert_eq!(node.first_rel, None);
+        assert_eq!(node.first_prop, None);
+        assert_eq!(nodes.get_node(b).unwrap().labels, Vec::<u32>::new());
+    }
+
+    #[test]
+    fn nodes_spill_onto_new_pages() {
+        let nodes = store();
+        let ids: Vec<NodeId> = (0..2000).map(|i| nodes.create_node(&[i]).unwrap()).collect();
+        assert!(ids.iter().any(|id| id.page_id() != ids[0].page_id()));
+        for (i, id) in ids.iter().enumerate() {
+            assert_eq!(nodes.get_node(*id).unwrap().labels, vec![i as u32]);
+        }
+    }
+
+    #[test]
+    fn deleted_nodes_are_gone() {
+        let nodes = store();
+        let a = nodes.create_node(&[1]).unwrap();
+        let b = nodes.create_node(&[2]).unwrap();
+        assert!(nodes.delete_node(a));
+        assert!(!nodes.delete_node(a));
+        assert_eq!(nodes.get_node(a), None);
+        assert_eq!(nodes.get_node(b).unwrap().labels, vec![2]);
+    }
+
+    #[test]
+    fn ids_that_point_at_other_page_types_find_nothing() {
+        let nodes = store();
+        let other = nodes.bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        assert_eq!(nodes.get_node(NodeId::new(other, 0, 0).unwrap()), None);
+    }
+
+    #[test]
+    fn count_items_of_type_counts_live_nodes() {
+        let nodes = store();
+        let ids: Vec<NodeId> = (0..1500).map(|i| nodes.create_node(&[i]).unwrap()).collect();
+        for &id in ids.iter().step_by(3) {
+            assert!(nodes.delete_node(id));
+        }
+        assert_eq!(nodes.bpm.count_items_of_type(PageType::NodeStore), 1000);
+        assert_eq!(nodes.bpm.count_items_of_type(PageType::Relationship), 0);
+    }
+
+    fn ids_of(scan: NodeScan<'_>) -> Vec<NodeId> {
+        scan.map(|node| node.id).collect()
+    }
+
+    #[test]
+    fn scan_with_labels_only_returns_nodes_with_every_label() {
+        let nodes = store();
+        let a = nodes.create_node(&[1, 2]).unwrap();
+        let b = nodes.create_node(&[2]).unwrap();
+        let c = nodes.create_node(&[1, 2, 3]).unwrap();
+        let d = nodes.create_node(&[]).unwrap();
+
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::from_labels(&[1, 2]))), vec![a, c]);
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::from_labels(&[2]))), vec![a, b, c]);
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::from_labels(&[4]))), vec![]);
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::new())), vec![a, b, c, d]);
+    }
+
+    #[test]
+    fn labels_sharing_a_mask_bit_are_still_told_apart() {
+        let nodes = store();
+        let low = nodes.create_node(&[1]).unwrap();
+        let high = nodes.create_node(&[65]).unwrap();
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::from_labels(&[65]))), vec![high]);
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::from_labels(&[1]))), vec![low]);
+    }
+
+    #[test]
+    fn nodes_failing_the_mask_arent_decoded() {
+        let nodes = store();
+        let id = nodes.create_node(&[3]).unwrap();
+        // the stored label now says 5 but the mask still only has bit 3, only decoding the labels would notice
+        {
+            let mut page = nodes.bpm.fetch_page_write(id.page_id()).unwrap();
+            let mut slotted = SlottedPage::new(&mut *page);
+            let record = slotted.get_record_mut(id.slot()).unwrap();
+            record[LABELS..LABELS + 4].copy_from_slice(&5u32.to_le_bytes());
+        }
+        assert_eq!(ids_of(nodes.scan_with_labels(&LabelSet::from_labels(&[5]))), vec![]);
+    }
+
+    #[test]
+    fn label_set_subsets_span_words() {
+        let small = LabelSet::from_labels(&[3, 130]);
+        let big = LabelSet::from_labels(&[3, 64, 130]);
+        assert!(small.is_subset(&big));
+        assert!(!big.is_subset(&small));
+        assert_eq!(big.iter().collect::<Vec<_>>(), vec![3, 64, 130]);
+        assert_eq!(small.mask(), (1 << 3) | (1 << 2));
+    }
+
+    // nodes until the first one's page is full, returns the first
+    fn node_on_a_full_page(nodes: &NodeStore) -> NodeId {
+        let first = nodes.create_node(&[1]).unwrap();
+        while nodes.create_node(&[1]).unwrap().page_id() == first.page_id() {}
+        first
+    }
+
+    #[test]
+    fn a_node_that_outgrows_its_page_is_still_found_through_its_id() {
+        let nodes = store();
+        let id = node_on_a_full_page(&nodes);
+        let labels: Vec<u32> = (0..200).collect();
+        assert!(nodes.set_labels(id, &labels));
+
+        let home = nodes.bpm.fetch_page(id.page_id()).unwrap();
+        let home = SlottedPage::new(&*home);
+        assert!(home.is_forward(id.slot()));
+        let (page_id, _) = home.resolve_forward(id.slot()).unwrap();
+        assert_ne!(page_id, id.page_id());
+        assert_eq!(nodes.get_node(id).unwrap().labels, labels);
+    }
+
+    #[test]
+    fn moving_a_moved_node_again_repoints_the_forward() {
+        let nodes = store();
+        let id = node_on_a_full_page(&nodes);
+        assert!(nodes.set_labels(id, &(0..PAGE_SIZE as u32 / 64).collect::<Vec<_>>()));
+        let first_move = nodes.locate(id).unwrap();
+
+        // fill the page it moved to so not even its own old bytes make room for it, then grow it once more
+        let room = || SlottedPage::new(&*nodes.bpm.fetch_page(first_move.page_id()).unwrap()).available_space();
+        for _ in 0..PAGE_SIZE {
+            if room() < PAGE_SIZE / 16 {
+                break;
+            }
+            nodes.create_node(&[2]).unwrap();
+        }
+        assert!(room() < PAGE_SIZE / 16);
+        let labels: Vec<u32> = (0..(MAX_INLINE_RECORD as u32 - 64) / 4).collect();
+        assert!(nodes.set_labels(id, &labels));
+
+        let second_move = nodes.locate(id).unwrap();
+        assert_ne!(second_move.page_id(), first_move.page_id());
+        let forward = SlottedPage::new(&*nodes.bpm.fetch_page(id.page_id()).unwrap()).resolve_forward(id.slot());
+        assert_eq!(forward, Some((second_move.page_id(), second_move.slot())));
+        assert_eq!(nodes.get_node(id).unwrap().labels, labels);
+        assert!(nodes.delete_node(id));
+        assert_eq!(nodes.get_node(id), None);
+    }
+
+    #[test]
+    fn a_view_reads_the_same_fields_as_get_node() {
+        let nodes = store();
+        let a = nodes.create_node(&[3, 70, 1]).unwrap();
+        let b = nodes.create_node(&[]).unwrap();
+        assert!(nodes.set_first_rel(a, Some(RelationshipId(12))));
+        assert!(nodes.set_first_prop(b, Some(PropertyId(34))));
+
+        for id in [a, b] {
+            let node = nodes.get_node(id).unwrap();
+            let view_node = nodes
+                .with_view(id, |view| {
+                    assert_eq!(view.node_id(), id);
+                    assert_eq!(view.first_rel(), node.first_rel);
+                    assert_eq!(view.first_prop(), node.first_prop);
+                    assert_eq!(view.label_count(), node.labels.len());
+                    assert!(!view.labels_overflowed());
+                    assert_eq!(view.label_mask(), LabelSet::from_labels(&node.labels).mask());
+                    assert!(view.has_labels(&LabelSet::from_labels(&node.labels)));
+                    view.to_node()
+                })
+                .unwrap();
+            assert_eq!(view_node, node);
+        }
+        assert!(nodes.delete_node(b));
+        assert_eq!(nodes.with_view(b, |view| view.node_id()), None);
+    }
+
+    #[test]
+    fn views_over_a_page_match_every_node_on_it() {
+        let nodes = store();
+        let ids: Vec<NodeId> = (0..20).map(|i| nodes.create_node(&[i, i * 2]).unwrap()).collect();
+        assert!(nodes.delete_node(ids[5]));
+
+        let guard = nodes.bpm.fetch_page(ids[0].page_id()).unwrap();
+        let from_views: Vec<Node> = NodeStore::views(&guard).map(|view| view.to_node()).collect();
+        drop(guard);
+        let expected: Vec<Node> =
+            ids.iter().filter(|&&id| id != ids[5]).map(|&id| nodes.get_node(id).unwrap()).collect();
+        assert_eq!(from_views, expected);
+    }
+
+    #[test]
+    fn views_of_moved_nodes_carry_their_home_id() {
+        let nodes = store();
+        let id = node_on_a_full_page(&nodes);
+        assert!(nodes.set_labels(id, &(0..200).collect::<Vec<_>>()));
+        let at = nodes.locate(id).unwrap();
+
+        let guard = nodes.bpm.fetch_page(at.page_id()).unwrap();
+        let view = NodeStore::views(&guard).find(|view| view.label_count() == 200).unwrap();
+        assert_eq!(view.node_id(), id);
+        assert_eq!(view.labels().collect::<Vec<_>>(), (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn a_view_of_overflowed_labels_only_has_the_count_and_mask() {
+        let nodes = store();
+        let labels: Vec<u32> = (0..MAX_INLINE_RECORD as u32).collect();
+        let id = nodes.create_node(&labels).unwrap();
+        nodes
+            .with_view(id, |view| {
+                assert!(view.labels_overflowed());
+                assert_eq!(view.label_count(), labels.len());
+                assert_eq!(view.labels().count(), 0);
+                assert!(view.has_labels(&LabelSet::from_labels(&[5, 900])));
+            })
+            .unwrap();
+        assert_eq!(nodes.get_node(id).unwrap().labels, labels);
+    }
+
+    #[test]
+    fn delete_nodes_removes_a_batch_across_pages() {
+        let nodes = store();
+        let ids: Vec<NodeId> = (0..1500).map(|i| nodes.create_node(&[i]).unwrap()).collect();
+        let pages: BTreeSet<PageId> = ids.iter().map(|id| id.page_id()).collect();
+        assert!(pages.len() > 2);
+
+        let doomed: Vec<NodeId> = ids.iter().copied().step_by(2).collect();
+        let per_page = |page_id| nodes.bpm.fetch_page(page_id).unwrap().get_item_count();
+        let before: Vec<u32> = pages.iter().map(|&p| per_page(p)).collect();
+        assert_eq!(nodes.delete_nodes(&doomed), doomed.len());
+
+        for (&page_id, before) in pages.iter().zip(before) {
+            let gone = doomed.iter().filter(|id| id.page_id() == page_id).count() as u32;
+            assert_eq!(per_page(page_id), before - gone);
+        }
+        assert_eq!(nodes.bpm.count_items_of_type(PageType::NodeStore), 750);
+        assert!(doomed.iter().all(|&id| nodes.get_node(id).is_none()));
+        assert!(ids.iter().skip(1).step_by(2).all(|&id| nodes.get_node(id).is_some()));
+    }
+
+    #[test]
+    fn delete_nodes_only_counts_nodes_that_existed() {
+        let nodes = store();
+        let a = nodes.create_node(&[1]).unwrap();
+        let b = nodes.create_node(&[2]).unwrap();
+        assert!(nodes.delete_node(b));
+        // b's slot has a new node of the next generation now, the old id must not delete it
+        let c = nodes.create_node(&[3]).unwrap();
+        assert_eq!(c.slot(), b.slot());
+
+        assert_eq!(nodes.delete_nodes(&[a, a, b]), 1);
+        assert_eq!(nodes.get_node(c).unwrap().labels, vec![3]);
+        assert_eq!(nodes.delete_nodes(&[]), 0);
+    }
+
+    #[test]
+    fn delete_nodes_follows_nodes_that_moved() {
+        let nodes = store();
+        let id = node_on_a_full_page(&nodes);
+        let labels: Vec<u32> = (0..500).collect();
+        assert!(nodes.set_labels(id, &labels[..200]));
+        let neighbour = nodes.create_node(&labels).unwrap();
+
+        assert_eq!(nodes.delete_nodes(&[id, neighbour]), 2);
+        assert_eq!(nodes.get_node(id), None);
+        assert_eq!(nodes.get_node(neighbour), None);
+    }
+
+    #[test]
+    fn a_node_id_into_another_kind_of_page_reads_as_nothing() {
+        let nodes = store();
+        let page_id = {
+            let mut page = nodes.bpm.allocate_page_of_type(PageType::Relationship).unwrap();
+            SlottedPage::init(&mut *page).insert_record(&[0; 64]).unwrap();
+            page.page_id
+        };
+        let wrong = NodeId::new(page_id, 0, 0).unwrap();
+        assert!(nodes.get_node(wrong).is_none());
+        assert!(!nodes.set_labels(wrong, &[1]));
+        assert_eq!(nodes.bpm.fetch_page(page_id).unwrap().page_type(), Ok(PageType::Relationship));
+    }
+
+    #[test]
+    fn create_node_indexed_leaves_both_the_record_and_the_entry() {
+        let nodes = store();
+        let index = BPlusTree::create(nodes.bpm.clone()).unwrap();
+        let id = nodes.create_node_indexed(&[4], &index, 17).unwrap();
+        assert_eq!(index.search(17), Some(id));
+        assert_eq!(nodes.get_node(id).unwrap().labels, vec![4]);
+
+        // a second node under the same key takes the entry over
+        let other = nodes.create_node_indexed(&[5], &index, 17).unwrap();
+        assert_eq!(index.search(17), Some(other));
+        assert!(nodes.get_node(id).is_some());
+    }
+
+    #[test]
+    fn create_node_indexed_leaves_neither_when_the_index_fails() {
+        let nodes = store();
+        // a tree whose root isn't a tree page, every insert into it fails
+        let root = nodes.bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        let index = BPlusTree::open(nodes.bpm.clone(), root);
+        let kept = nodes.create_node(&[1]).unwrap();
+
+        assert_eq!(nodes.create_node_indexed(&[2], &index, 17), None);
+        assert_eq!(index.search(17), None);
+        assert_eq!(nodes.bpm.count_items_of_type(PageType::NodeStore), 1);
+        assert!(nodes.get_node(kept).is_some());
+    }
+
+    #[test]
+    fn count_where_matches_a_naive_count() {
+        let nodes = store();
+        let ids: Vec<NodeId> = (0..600u32).map(|i| nodes.create_node(&[i % 5, 10 + i % 3]).unwrap()).collect();
+        assert!(ids.iter().map(|id| id.page_id()).collect::<BTreeSet<_>>().len() > 1);
+        for id in ids.iter().step_by(7) {
+            assert!(nodes.delete_node(*id));
+        }
+        let live: Vec<Node> = ids.iter().filter_map(|&id| nodes.get_node(id)).collect();
+
+        let naive = |wanted: u32| live.iter().filter(|node| node.labels.contains(&wanted)).count() as u64;
+        for wanted in [2, 11, 99] {
+            assert_eq!(nodes.count_where(|view| view.labels().any(|label| label == wanted)), naive(wanted));
+        }
+        assert_eq!(nodes.count_where(|_| true), live.len() as u64);
+        assert_eq!(nodes.count_where(|view| view.first_rel().is_some()), 0);
+    }
+
+    #[test]
+    fn count_where_skips_pages_with_no_records() {
+        let nodes = store();
+        let ids: Vec<NodeId> = (0..600).map(|_| nodes.create_node(&[1]).unwrap()).collect();
+        let first_page = ids[0].page_id();
+        let on_first: Vec<NodeId> = ids.iter().copied().filter(|id| id.page_id() == first_page).collect();
+        assert_eq!(nodes.delete_nodes(&on_first), on_first.len());
+
+        let seen = std::cell::Cell::new(0);
+        let count = nodes.count_where(|view| {
+            assert_ne!(view.node_id().page_id(), first_page);
+            seen.set(seen.get() + 1);
+            true
+        });
+        assert_eq!(count, (ids.len() - on_first.len()) as u64);
+        assert_eq!(seen.get(), count);
+    }
+
+    #[test]
+    fn labels_stay_inline_up_to_max_inline_record_and_overflow_past_it() {
+        let nodes = store();
+        // label count whose record comes out exactly MAX_INLINE_RECORD bytes
+        let at = (MAX_INLINE_RECORD - LABELS - GENERATION_LEN) / 4;
+        for (count, overflowed) in [(at - 1, false), (at, false), (at + 1, true)] {
+            let labels: Vec<u32> = (0..count as u32).collect();
+            let id = nodes.create_node(&labels).unwrap();
+            assert_eq!(nodes.with_view(id, |view| view.labels_overflowed()), Some(overflowed), "{} labels", count);
+            let stored = {
+                let page = nodes.bpm.fetch_page(id.page_id()).unwrap();
+                SlottedPage::new(&*page).get_record(id.slot()).unwrap().len()
+            };
+            if overflowed {
+                assert_eq!(stored, LABELS + OVERFLOW_REF_LEN + GENERATION_LEN);
+            } else {
+                assert_eq!(stored, LABELS + count * 4 + GENERATION_LEN);
+                assert!(stored <= MAX_INLINE_RECORD);
+            }
+            assert_eq!(nodes.get_node(id).unwrap().labels, labels);
+        }
+    }
+
+    #[test]
+    fn growing_labels_past_the_limit_moves_them_out_and_shrinking_brings_them_back() {
+        let nodes = store();
+        let id = nodes.create_node(&[1]).unwrap();
+        let many: Vec<u32> = (0..MAX_INLINE_RECORD as u32 / 2).collect();
+        assert!(nodes.set_labels(id, &many));
+        assert_eq!(nodes.with_view(id, |view| view.labels_overflowed()), Some(true));
+        assert_eq!(nodes.get_node(id).unwrap().labels, many);
+        assert!(nodes.bpm.iter_pages_of_type(PageType::Overflow).next().is_some());
+
+        assert!(nodes.set_labels(id, &[2, 3]));
+        assert_eq!(nodes.with_view(id, |view| view.labels_overflowed()), Some(false));
+        assert_eq!(nodes.get_node(id).unwrap().labels, vec![2, 3]);
+    }
+
+    #[test]
+    fn node_ids_round_trip_their_parts() {
+        let id = NodeId::new(PAGE_MASK, 513, 7).unwrap();
+        assert_eq!((id.page_id(), id.slot(), id.generation()), (PAGE_MASK, 513, 7));
+        assert_eq!(NodeId::new(PAGE_MASK + 1, 0, 0), None);
+        // the generation gets 14 bits
+        assert_eq!(NodeId::new(1, 0, MAX_GENERATION + 1).unwrap().generation(), 0);
+        assert_eq!(next_generation(MAX_GENERATION), 1);
+    }
+
+    #[test]
+    fn a_stale_id_stops_resolving_once_its_slot_is_reused() {
+        let nodes = store();
+        let old = nodes.create_node(&[1]).unwrap();
+        assert!(nodes.delete_node(old));
+        let new = nodes.create_node(&[2]).unwrap();
+        assert_eq!((new.page_id(), new.slot()), (old.page_id(), old.slot()));
+        assert_eq!(new.generation(), old.generation() + 1);
+
+        assert_eq!(nodes.get_node(old), None);
+        assert!(!nodes.set_labels(old, &[3]));
+        assert!(!nodes.delete_node(old));
+        assert_eq!(nodes.get_node(new).unwrap().labels, vec![2]);
+    }
+
+    #[test]
+    fn a_slot_keeps_counting_generations_through_many_reuses() {
+        let nodes = store();
+        let mut ids = vec![nodes.create_node(&[]).unwrap()];
+        for _ in 0..5 {
+            assert!(nodes.delete_node(*ids.last().unwrap()));
+            ids.push(nodes.create_node(&[]).unwrap());
+        }
+        let generations: Vec<u16> = ids.iter().map(|id| id.generation()).collect();
+        assert_eq!(generations, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(ids.iter().filter(|&&id| nodes.get_node(id).is_some()).count(), 1);
+    }
+}
@@ -0,0 +1,168 @@
+/*
+* Blobs too big for a record, spread over a singly linked chain of Overflow pages.
+* Whoever owns the blob keeps the head page id around, and frees the chain when the owner goes away.
+*
+* Overflow page layout:
+* | PageHeader | next_page: u64 | chunk_len: u32 | chunk ... |
+* next_page is NO_ID on the last page of the chain. An empty blob still gets one (empty) page so it has a head.
+*/
+
+use crate::paging::buffer_pool_manager::{BufferError, BufferPoolManager};
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::{PageId, HEADER_SIZE, PAGE_SIZE};
+use super::node_store::NO_ID;
+
+const NEXT: usize = HEADER_SIZE;
+const LEN: usize = HEADER_SIZE + 8;
+const DATA: usize = HEADER_SIZE + 12;
+
+// bytes of blob per overflow page
+pub const CHUNK_CAPACITY: usize = PAGE_SIZE - DATA;
+
+pub struct OverflowChain<'a> {
+    bpm: &'a BufferPoolManager,
+}
+
+impl<'a> OverflowChain<'a> {
+    pub fn new(bpm: &'a BufferPoolManager) -> Self {
+        Self { bpm }
+    }
+
+    // writes data across as many overflow pages as it takes and returns the first one
+    // written back to front so each page already knows its successor, if a page can't be allocated
+    // the part of the chain written so far is freed again
+    pub fn write_blob(&self, data: &[u8]) -> Result<PageId, BufferError> {
+        let mut chunks: Vec<&[u8]> = data.chunks(CHUNK_CAPACITY).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+
+        let mut next = NO_ID;
+        for chunk in chunks.into_iter().rev() {
+            let mut page = match self.bpm.allocate_page_of_type(PageType::Overflow) {
+                Ok(page) => page,
+                Err(e) => {
+                    // the allocation failure is the one worth returning, a chain that can't be freed is only leaked
+                    let _ = self.free_chain(next);
+                    return Err(e);
+                }
+            };
+            let page_id = page.page_id;
+            let data = page.get_data_mut();
+            data[NEXT..NEXT + 8].copy_from_slice(&next.to_le_bytes());
+            data[LEN..LEN + 4].copy_from_slice(&(chunk.len() as u32).to_le_bytes());
+            data[DATA..DATA + chunk.len()].copy_from_slice(chunk);
+            page.set_dirty(true);
+            next = page_id;
+        }
+        Ok(next)
+    }
+
+    // reads back the whole blob starting at head
+    pub fn read_blob(&self, head: PageId) -> Result<Vec<u8>, BufferError> {
+        let mut blob = Vec::new();
+        let mut next = head;
+        while next != NO_ID {
+            let page = self.bpm.fetch_page(next)?;
+            if !is_overflow_page(&page) {
+                return Err(not_overflow(next));
+            }
+            let len = page.read_u32_at(LEN as u32).map_err(|_| not_overflow(next))? as usize;
+            blob.extend_from_slice(page.read_at(DATA as u32, len).ok_or_else(|| not_overflow(next))?);
+            next = page.read_u64_at(NEXT as u32).map_err(|_| not_overflow(next))?;
+        }
+        Ok(blob)
+    }
+
+    // hands every page of the chain back to the buffer pool, stops at the first page that can't be read or
+    // freed (the rest of the chain stays allocated) and returns why. A page someone has pinned is skipped
+    pub fn free_chain(&self, head: PageId) -> Result<(), BufferError> {
+        let mut next = head;
+        while next != NO_ID {
+            let following = {
+                let page = self.bpm.fetch_page(next)?;
+                if !is_overflow_page(&page) {
+                    return Err(not_overflow(next));
+                }
+                page.read_u64_at(NEXT as u32).unwrap_or(NO_ID)
+            };
+            self.bpm.deallocate_page(next)?;
+            next = following;
+        }
+        Ok(())
+    }
+}
+
+// chains written before PageType::Overflow existed were tagged PropertyStore
+fn is_overflow_page(page: &Page) -> bool {
+    matches!(page.page_type(), Ok(PageType::Overflow | PageType::PropertyStore))
+}
+
+fn not_overflow(page_id: PageId) -> BufferError {
+    BufferError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("page {} is not a valid overflow page", page_id),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+
+    fn pool() -> BufferPoolManager {
+        BufferPoolManager::new(8, MockDiskManager::new()).unwrap()
+    }
+
+    // ids of the pages in the chain starting at head
+    fn chain(bpm: &BufferPoolManager, head: PageId) -> Vec<PageId> {
+        let mut pages = Vec::new();
+        let mut next = head;
+        while next != NO_ID {
+            pages.push(next);
+            next = bpm.fetch_page(next).unwrap().read_u64_at(NEXT as u32).unwrap();
+        }
+        pages
+    }
+
+    #[test]
+    fn a_blob_spanning_three_pages_reads_back_exactly() {
+        let bpm = pool();
+        let blob: Vec<u8> = (0..CHUNK_CAPACITY * 2 + 17).map(|i| (i % 251) as u8).collect();
+        let overflow = OverflowChain::new(&bpm);
+        let head = overflow.write_blob(&blob).unwrap();
+        assert_eq!(chain(&bpm, head).len(), 3);
+        assert_eq!(overflow.read_blob(head).unwrap(), blob);
+    }
+
+    #[test]
+    fn an_empty_blob_still_gets_a_page() {
+        let bpm = pool();
+        let overflow = OverflowChain::new(&bpm);
+        let head = overflow.write_blob(&[]).unwrap();
+        assert_eq!(chain(&bpm, head), vec![head]);
+        assert_eq!(overflow.read_blob(head).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn freed_chains_hand_their_pages_back() {
+        let bpm = pool();
+        let overflow = OverflowChain::new(&bpm);
+        let head = overflow.write_blob(&vec![1; CHUNK_CAPACITY * 3]).unwrap();
+        let pages: BTreeSet<PageId> = chain(&bpm, head).into_iter().collect();
+        overflow.free_chain(head).unwrap();
+        let reused: BTreeSet<PageId> =
+            (0..3).map(|_| bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id).collect();
+        assert_eq!(reused, pages);
+    }
+
+    #[test]
+    fn reading_a_page_that_isnt_overflow_fails() {
+        let bpm = pool();
+        let other = bpm.allocate_page_of_type(PageType::BTreeNode).unwrap().page_id;
+        let overflow = OverflowChain::new(&bpm);
+        assert!(overflow.read_blob(other).is_err());
+        assert!(overflow.free_chain(other).is_err());
+    }
+}
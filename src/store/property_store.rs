@@ -0,0 +1,709 @@
+/*
+* Property storage for nodes and relationships.
+* Each owner (node or relationship) points at the head of a singly linked chain of property records, one record
+* per key. Records live in slotted PropertyStore pages.
+*
+* Property record layout:
+* | key_id: u32 | next: u64 | tag: u8 | value ... |
+*
+* Ints, floats and bools are stored inline, Null is just the tag. Strings up to INLINE_STRING_MAX bytes are stored
+* inline too, anything longer is written to a chain of overflow pages and the record only holds (head page, length).
+*
+* Comparisons between values (PartialOrd, eval_predicate) go by these rules:
+* - Int and Float compare as numbers with each other, so Int(1) == Float(1.0)
+* - Bool compares with Bool (false < true) and Str with Str (byte order), any other mix of types is unordered
+* - Null is only equal to Null, but every predicate involving Null is false, like SQL. A missing property counts
+*   as Null in nodes_where
+*
+* See overflow.rs for the overflow page layout.
+*/
+
+use std::cmp::Ordering;
+use std::sync::{Arc, Mutex};
+
+use crate::paging::buffer_pool_manager::{BufferPoolManager, PageFrameRef, WritePageGuard};
+use crate::paging::config::MAX_INLINE_RECORD;
+use crate::paging::free_space_map::FreeSpaceMap;
+use crate::paging::page::PageType;
+use crate::paging::page_constants::PageId;
+use crate::paging::record::{DecodeError, Record, RecordReader};
+use crate::paging::slotted_page::{SlotId, SlottedPage, SLOT_SIZE};
+use super::node_store::{NodeId, NodeStore, NO_ID};
+use super::overflow::OverflowChain;
+use super::relationship_store::{RelationshipId, RelationshipStore};
+use super::storage_stats::{storage_stats, StorageStats};
+
+// strings longer than this go to overflow pages
+pub const INLINE_STRING_MAX: usize = 128;
+
+// offsets inside a property record
+const NEXT: usize = 4;
+const TAG: usize = 12;
+const VALUE: usize = 13;
+
+const TAG_INT: u8 = 0;
+const TAG_FLOAT: u8 = 1;
+const TAG_BOOL: u8 = 2;
+const TAG_SHORT_STR: u8 = 3;
+const TAG_LONG_STR: u8 = 4;
+const TAG_NULL: u8 = 5;
+
+// long strings going to overflow pages is what keeps property records under the limit
+const _: () = assert!(VALUE + INLINE_STRING_MAX <= MAX_INLINE_RECORD);
+
+
+// Location of a property record, same (page, slot) encoding as NodeId
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PropertyId(pub u64);
+
+impl PropertyId {
+    pub fn new(page_id: PageId, slot: SlotId) -> Self {
+        Self((page_id << 16) | slot as u64)
+    }
+
+    pub fn page_id(&self) -> PageId {
+        self.0 >> 16
+    }
+
+    pub fn slot(&self) -> SlotId {
+        (self.0 & 0xFFFF) as SlotId
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PropertyOwner {
+    Node(NodeId),
+    Relationship(RelationshipId),
+}
+
+#[derive(Debug, Clone)]
+pub enum PropertyValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    Null,
+}
+
+// comparison operators for eval_predicate
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl PropertyValue {
+    // self op other by the rules at the top of the file. Anything with Null is false, Ne is true for values
+    // that can't be compared (different types, NaN) and every other op false
+    pub fn eval_predicate(&self, op: CmpOp, other: &PropertyValue) -> bool {
+        if matches!(self, PropertyValue::Null) || matches!(other, PropertyValue::Null) {
+            return false;
+        }
+        let Some(ordering) = self.partial_cmp(other) else { return op == CmpOp::Ne };
+        match op {
+            CmpOp::Eq => ordering == Ordering::Equal,
+            CmpOp::Ne => ordering != Ordering::Equal,
+            CmpOp::Lt => ordering == Ordering::Less,
+            CmpOp::Le => ordering != Ordering::Greater,
+            CmpOp::Gt => ordering == Ordering::Greater,
+            CmpOp::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl PartialEq for PropertyValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for PropertyValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        use PropertyValue::*;
+        match (self, other) {
+            (Int(a), Int(b)) => Some(a.cmp(b)),
+            (Int(a), Float(b)) => cmp_int_float(*a, *b),
+            (Float(a), Int(b)) => cmp_int_float(*b, *a).map(Ordering::reverse),
+            (Float(a), Float(b)) => a.partial_cmp(b),
+            (Bool(a), Bool(b)) => Some(a.cmp(b)),
+            (Str(a), Str(b)) => Some(a.cmp(b)),
+            (Null, Null) => Some(Ordering::Equal),
+            _ => None,
+        }
+    }
+}
+
+// exact, an i64 doesn't always fit in an f64 so comparing a as f64 could call different ints equal to b
+fn cmp_int_float(a: i64, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        return None;
+    }
+    // 2^63 as f64, every float at or past it is bigger than any i64 (and -2^63 smaller or equal)
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if b >= LIMIT {
+        return Some(Ordering::Less);
+    }
+    if b < -LIMIT {
+        return Some(Ordering::Greater);
+    }
+    // b is in i64 range now, compare the integer parts and then the fraction
+    let whole = b.trunc();
+    match a.cmp(&(whole as i64)) {
+        Ordering::Equal => 0.0.partial_cmp(&(b - whole)),
+        ordering => Some(ordering),
+    }
+}
+
+// a property record as it sits in the page, long strings are still just a pointer to their overflow chain
+struct StoredProperty {
+    key_id: u32,
+    next: Option<PropertyId>,
+    value: StoredValue,
+}
+
+enum StoredValue {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    ShortStr(String),
+    LongStr { head: PageId, len: u32 },
+    Null,
+}
+
+// a decoded property record
+struct PropertyRecord {
+    key_id: u32,
+    next: Option<PropertyId>,
+    value: PropertyValue,
+    overflow_head: Option<PageId>,
+}
+
+pub struct PropertyStore {
+    bpm: Arc<BufferPoolManager>,
+    nodes: Arc<NodeStore>,
+    relationships: Arc<RelationshipStore>,
+    // serializes writers since set_property rewrites chains
+    write_lock: Mutex<()>,
+    // room left in each of our record pages (not the overflow pages)
+    free_space: Arc<FreeSpaceMap>,
+}
+
+impl PropertyStore {
+    pub fn new(bpm: Arc<BufferPoolManager>, nodes: Arc<NodeStore>, relationships: Arc<RelationshipStore>) -> Self {
+        let free_space = bpm.free_space_map(PageType::PropertyStore);
+        Self { bpm, nodes, relationships, write_lock: Mutex::new(()), free_space }
+    }
+
+    // page and record counts for this store, see StorageStats
+    pub fn stats(&self) -> StorageStats {
+        storage_stats(&self.bpm, PageType::PropertyStore)
+    }
+
+    // page_id read latched, None unless it's one of our pages (a property id that points at another kind of page
+    // is a bug somewhere, better to come up empty than to decode whatever is there)
+    fn fetch(&self, page_id: PageId) -> Option<PageFrameRef<'_>> {
+        let guard = self.bpm.fetch_page(page_id).ok()?;
+        guard.expect_type(PageType::PropertyStore).ok()?;
+        Some(guard)
+    }
+
+    // same as fetch, write latched
+    fn fetch_write(&self, page_id: PageId) -> Option<WritePageGuard<'_>> {
+        let guard = self.bpm.fetch_page_write(page_id).ok()?;
+        guard.expect_type(PageType::PropertyStore).ok()?;
+        Some(guard)
+    }
+
+    // Sets key_id on owner, replacing the old value if there was one
+    // returns false if the owner doesn't exist or the value couldn't be stored, the old value stays then
+    pub fn set_property(&self, owner: PropertyOwner, key_id: u32, value: PropertyValue) -> bool {
+        let _writer = self.write_lock.lock().unwrap();
+        let Some(head) = self.head(owner) else { return false };
+
+        // the old record for this key, if any, and the one in front of it
+        let mut old = None;
+        let mut prev: Option<PropertyId> = None;
+        let mut cursor = head;
+        while let Some(id) = cursor {
+            let Some(record) = self.read_record(id) else { return false };
+            if record.key_id == key_id {
+                old = Some((prev, id, record));
+                break;
+            }
+            prev = Some(id);
+            cursor = record.next;
+        }
+
+        // The new record goes at the front of the chain, written and linked in before the old one is touched so
+        // failing here leaves the chain as it was. If the old one was the head the new one takes its place
+        let next = match &old {
+            Some((None, _, record)) => record.next,
+            _ => head,
+        };
+        let Some(encoded) = self.encode_record(key_id, next, &value) else { return false };
+        let Some(id) = self.insert_record(&encoded) else { return false };
+        if !self.set_head(owner, Some(id)) {
+            if let Some(record) = self.read_record(id) {
+                self.delete_record(id, &record);
+            }
+            return false;
+        }
+
+        // If the old one can't be spliced out it stays linked behind the new one, which shadows it. Freeing it
+        // would leave prev pointing at a freed slot
+        if let Some((prev, old_id, record)) = old
+            && prev.is_none_or(|prev| self.set_next(prev, record.next))
+        {
+            self.delete_record(old_id, &record);
+        }
+        true
+    }
+
+    // RelationshipStore::delete_relationship, with the relationship's property chain freed once it's gone
+    // false (and nothing freed) if it couldn't be deleted, a stale id included
+    pub fn delete_relationship(&self, id: RelationshipId) -> bool {
+        let _writer = self.write_lock.lock().unwrap();
+        let Some(head) = self.head(PropertyOwner::Relationship(id)) else { return false };
+        if !self.relationships.delete_relationship(id) {
+            return false;
+        }
+        // a record that can't be read leaks the rest of the chain, the relationship is gone either way
+        let mut cursor = head;
+        while let Some(prop) = cursor {
+            let Some(record) = self.read_record(prop) else { break };
+            cursor = record.next;
+            self.delete_record(prop, &record);
+        }
+        true
+    }
+
+    pub fn get_property(&self, owner: PropertyOwner, key_id: u32) -> Option<PropertyValue> {
+        self.properties(owner).find(|(key, _)| *key == key_id).map(|(_, value)| value)
+    }
+
+    // every (key_id, value) on owner, most recently set first
+    pub fn properties(&self, owner: PropertyOwner) -> impl Iterator<Item = (u32, PropertyValue)> + '_ {
+        let next = self.head(owner).flatten();
+        PropertyIter { store: self, next }
+    }
+
+    // Every node whose key_id property satisfies `property op target` (see eval_predicate), in page and slot order.
+    // Nodes aren't decoded, their chain heads come off the raw records (RecordView), and in the chain only the
+    // record with key_id gets decoded. A long string is only read from its overflow pages when target is a
+    // string too. Nodes without the property count as Null and never match
+    pub fn nodes_where(&self, key_id: u32, op: CmpOp, target: &PropertyValue) -> Vec<NodeId> {
+        let mut matches = Vec::new();
+        if matches!(target, PropertyValue::Null) {
+            return matches;
+        }
+        for page in self.bpm.iter_pages_of_type(PageType::NodeStore) {
+            let heads: Vec<(NodeId, PropertyId)> =
+                NodeStore::views(&page).filter_map(|view| Some((view.node_id(), view.first_prop()?))).collect();
+            // let go of the node page before walking chains on other pages
+            drop(page);
+            for (node, head) in heads {
+                if self.find_stored(head, key_id).is_some_and(|stored| self.stored_matches(stored, op, target)) {
+                    matches.push(node);
+                }
+            }
+        }
+        matches
+    }
+
+    // key_id's record in the chain starting at head, only that one gets decoded
+    fn find_stored(&self, head: PropertyId, key_id: u32) -> Option<StoredProperty> {
+        let mut cursor = head;
+        loop {
+            let guard = self.fetch(cursor.page_id())?;
+            let page = SlottedPage::new(&*guard);
+            let record = page.get_record(cursor.slot())?;
+            if record.len() < VALUE {
+                return None;
+            }
+            if u32::from_le_bytes(record[..NEXT].try_into().unwrap()) == key_id {
+                return StoredProperty::deserialize(record).ok();
+            }
+            let next = u64::from_le_bytes(record[NEXT..TAG].try_into().unwrap());
+            if next == NO_ID {
+                return None;
+            }
+            cursor = PropertyId(next);
+        }
+    }
+
+    fn stored_matches(&self, stored: StoredProperty, op: CmpOp, target: &PropertyValue) -> bool {
+        let value = match stored.value {
+            StoredValue::Int(v) => PropertyValue::Int(v),
+            StoredValue::Float(v) => PropertyValue::Float(v),
+            StoredValue::Bool(v) => PropertyValue::Bool(v),
+            StoredValue::ShortStr(s) => PropertyValue::Str(s),
+            StoredValue::LongStr { head, len } if matches!(target, PropertyValue::Str(_)) => {
+                let Some(s) = self.read_long_str(head, len) else { return false };
+                PropertyValue::Str(s)
+            }
+            // against anything but a string only the type matters, no need to read the overflow pages
+            StoredValue::LongStr { .. } => PropertyValue::Str(String::new()),
+            StoredValue::Null => PropertyValue::Null,
+        };
+        value.eval_predicate(op, target)
+    }
+
+    // head of owner's chain, None if the owner doesn't exist
+    fn head(&self, owner: PropertyOwner) -> Option<Option<PropertyId>> {
+        match owner {
+            PropertyOwner::Node(id) => Some(self.nodes.get_node(id)?.first_prop),
+            PropertyOwner::Relationship(id) => Some(self.relationships.get_relationship(id)?.first_prop),
+        }
+    }
+
+    fn set_head(&self, owner: PropertyOwner, head: Option<PropertyId>) -> bool {
+        match owner {
+            PropertyOwner::Node(id) => self.nodes.set_first_prop(id, head),
+            PropertyOwner::Relationship(id) => self.relationships.set_first_prop(id, head),
+        }
+    }
+
+    fn read_record(&self, id: PropertyId) -> Option<PropertyRecord> {
+        let stored: StoredProperty = {
+            let guard = self.fetch(id.page_id())?;
+            let page = SlottedPage::new(&*guard);
+            page.get(id.slot())?.ok()?
+        };
+
+        let mut overflow_head = None;
+        let value = match stored.value {
+            StoredValue::Int(v) => PropertyValue::Int(v),
+            StoredValue::Float(v) => PropertyValue::Float(v),
+            StoredValue::Bool(v) => PropertyValue::Bool(v),
+            StoredValue::ShortStr(s) => PropertyValue::Str(s),
+            StoredValue::LongStr { head, len } => {
+                overflow_head = Some(head);
+                PropertyValue::Str(self.read_long_str(head, len)?)
+            }
+            StoredValue::Null => PropertyValue::Null,
+        };
+        Some(PropertyRecord { key_id: stored.key_id, next: stored.next, value, overflow_head })
+    }
+
+    fn read_long_str(&self, head: PageId, len: u32) -> Option<String> {
+        let data = OverflowChain::new(&self.bpm).read_blob(head).ok()?;
+        if data.len() != len as usize {
+            return None;
+        }
+        String::from_utf8(data).ok()
+    }
+
+    fn encode_record(&self, key_id: u32, next: Option<PropertyId>, value: &PropertyValue) -> Option<StoredProperty> {
+        let value = match value {
+            PropertyValue::Int(v) => StoredValue::Int(*v),
+            PropertyValue::Float(v) => StoredValue::Float(*v),
+            PropertyValue::Bool(v) => StoredValue::Bool(*v),
+            PropertyValue::Str(s) if s.len() <= INLINE_STRING_MAX => StoredValue::ShortStr(s.clone()),
+            PropertyValue::Str(s) => {
+                let head = OverflowChain::new(&self.bpm).write_blob(s.as_bytes()).ok()?;
+                StoredValue::LongStr { head, len: s.len() as u32 }
+            },
+            PropertyValue::Null => StoredValue::Null,
+        };
+        Some(StoredProperty { key_id, next, value })
+    }
+
+    fn insert_record(&self, record: &StoredProperty) -> Option<PropertyId> {
+        if let Some(page_id) = self.free_space.find_page_with_room(record.serialized_len() + SLOT_SIZE) {
+            let mut guard = self.fetch_write(page_id)?;
+            let mut page = SlottedPage::new(&mut *guard);
+            let slot = page.insert(record);
+            self.free_space.update(page_id, page.available_space());
+            if let Some(slot) = slot {
+                return Some(PropertyId::new(page_id, slot));
+            }
+        }
+
+        let mut guard = self.bpm.allocate_page_of_type(PageType::PropertyStore).ok()?;
+        let page_id = guard.page_id;
+        let mut page = SlottedPage::init(&mut *guard);
+        let slot = page.insert(record)?;
+        self.free_space.update(page_id, page.available_space());
+        Some(PropertyId::new(page_id, slot))
+    }
+
+    fn delete_record(&self, id: PropertyId, record: &PropertyRecord) {
+        // nowhere to return it to, see BufferPoolManager::take_deferred_errors
+        if let Some(head) = record.overflow_head
+            && let Err(e) = OverflowChain::new(&self.bpm).free_chain(head)
+        {
+            self.bpm.defer_error(e);
+        }
+        if let Some(mut guard) = self.fetch_write(id.page_id()) {
+            let mut page = SlottedPage::new(&mut *guard);
+            page.delete_record(id.slot());
+            self.free_space.update(id.page_id(), page.available_space());
+        }
+    }
+
+    fn set_next(&self, id: PropertyId, next: Option<PropertyId>) -> bool {
+        let Some(mut guard) = self.fetch_write(id.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        let Some(record) = page.get_record_mut(id.slot()) else { return false };
+        record[NEXT..TAG].copy_from_slice(&next.map_or(NO_ID, |p| p.0).to_le_bytes());
+        true
+    }
+}
+
+impl Record for StoredProperty {
+    fn serialized_len(&self) -> usize {
+        VALUE + match &self.value {
+            StoredValue::Int(_) | StoredValue::Float(_) => 8,
+            StoredValue::Bool(_) => 1,
+            StoredValue::Null => 0,
+            StoredValue::ShortStr(s) => s.len(),
+            StoredValue::LongStr { .. } => 12,
+        }
+    }
+
+    fn serialize(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.key_id.to_le_bytes());
+        buf.extend_from_slice(&self.next.map_or(NO_ID, |p| p.0).to_le_bytes());
+        match &self.value {
+            StoredValue::Int(v) => {
+                buf.push(TAG_INT);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            StoredValue::Float(v) => {
+                buf.push(TAG_FLOAT);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            StoredValue::Bool(v) => {
+                buf.push(TAG_BOOL);
+                buf.push(*v as u8);
+            }
+            StoredValue::ShortStr(s) => {
+                buf.push(TAG_SHORT_STR);
+                buf.extend_from_slice(s.as_bytes());
+            }
+            StoredValue::LongStr { head, len } => {
+                buf.push(TAG_LONG_STR);
+                buf.extend_from_slice(&head.to_le_bytes());
+                buf.extend_from_slice(&len.to_le_bytes());
+            }
+            StoredValue::Null => buf.push(TAG_NULL),
+        }
+    }
+
+    fn deserialize(buf: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = RecordReader::new(buf);
+        let key_id = reader.u32()?;
+        let next = reader.u64()?;
+        let value = match reader.u8()? {
+            TAG_INT => StoredValue::Int(reader.u64()? as i64),
+            TAG_FLOAT => StoredValue::Float(f64::from_bits(reader.u64()?)),
+            TAG_BOOL => StoredValue::Bool(reader.u8()? != 0),
+            TAG_SHORT_STR => {
+                let s = std::str::from_utf8(reader.rest()).map_err(|_| DecodeError::Invalid("property string is not utf8"))?;
+                StoredValue::ShortStr(s.to_string())
+            }
+            TAG_LONG_STR => StoredValue::LongStr { head: reader.u64()?, len: reader.u32()? },
+            TAG_NULL => StoredValue::Null,
+            _ => return Err(DecodeError::Invalid("unknown property tag")),
+        };
+        Ok(Self { key_id, next: (next != NO_ID).then_some(PropertyId(next)), value })
+    }
+}
+
+pub struct PropertyIter<'a> {
+    store: &'a PropertyStore,
+    next: Option<PropertyId>,
+}
+
+impl<'a> Iterator for PropertyIter<'a> {
+    type Item = (u32, PropertyValue);
+
+    fn next(&mut self) -> Option<(u32, PropertyValue)> {
+        let record = self.store.read_record(self.next?)?;
+        self.next = record.next;
+        Some((record.key_id, record.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+
+    struct Stores {
+        nodes: Arc<NodeStore>,
+        rels: Arc<RelationshipStore>,
+        props: PropertyStore,
+    }
+
+    fn stores() -> Stores {
+        let bpm = Arc::new(BufferPoolManager::new(16, MockDiskManager::new()).unwrap());
+        let nodes = Arc::new(NodeStore::new(bpm.clone()));
+        let rels = Arc::new(RelationshipStore::new(bpm.clone(), nodes.clone()));
+        let props = PropertyStore::new(bpm, nodes.clone(), rels.clone());
+        Stores { nodes, rels, props }
+    }
+
+    #[test]
+    fn properties_on_nodes_and_relationships_read_back() {
+        let s = stores();
+        let a = s.nodes.create_node(&[]).unwrap();
+        let b = s.nodes.create_node(&[]).unwrap();
+        let rel = s.rels.create_relationship(a, b, 1).unwrap();
+        let (node, edge) = (PropertyOwner::Node(a), PropertyOwner::Relationship(rel));
+
+        assert!(s.props.set_property(node, 1, PropertyValue::Int(42)));
+        assert!(s.props.set_property(node, 2, PropertyValue::Str("ada".into())));
+        assert!(s.props.set_property(edge, 1, PropertyValue::Float(0.5)));
+        assert!(s.props.set_property(edge, 3, PropertyValue::Bool(true)));
+
+        assert_eq!(s.props.get_property(node, 1), Some(PropertyValue::Int(42)));
+        assert_eq!(s.props.get_property(node, 2), Some(PropertyValue::Str("ada".into())));
+        assert_eq!(s.props.get_property(node, 3), None);
+        assert_eq!(s.props.get_property(edge, 1), Some(PropertyValue::Float(0.5)));
+        assert_eq!(s.props.get_property(edge, 3), Some(PropertyValue::Bool(true)));
+        assert_eq!(s.props.get_property(PropertyOwner::Node(b), 1), None);
+    }
+
+    #[test]
+    fn setting_a_key_again_replaces_its_value() {
+        let s = stores();
+        let owner = PropertyOwner::Node(s.nodes.create_node(&[]).unwrap());
+        s.props.set_property(owner, 1, PropertyValue::Int(1));
+        s.props.set_property(owner, 2, PropertyValue::Int(2));
+        s.props.set_property(owner, 1, PropertyValue::Null);
+
+        let mut props: Vec<(u32, PropertyValue)> = s.props.properties(owner).collect();
+        props.sort_by_key(|(key, _)| *key);
+        assert_eq!(props, vec![(1, PropertyValue::Null), (2, PropertyValue::Int(2))]);
+    }
+
+    #[test]
+    fn long_strings_go_through_overflow_pages() {
+        let s = stores();
+        let owner = PropertyOwner::Node(s.nodes.create_node(&[]).unwrap());
+        let long = "x".repeat(INLINE_STRING_MAX * 100);
+        assert!(s.props.set_property(owner, 1, PropertyValue::Str(long.clone())));
+        assert_eq!(s.props.get_property(owner, 1), Some(PropertyValue::Str(long)));
+        assert!(s.props.bpm.iter_pages_of_type(PageType::Overflow).next().is_some());
+    }
+
+    #[test]
+    fn owners_that_dont_exist_get_no_properties() {
+        let s = stores();
+        let gone = s.nodes.create_node(&[]).unwrap();
+        s.nodes.delete_node(gone);
+        assert!(!s.props.set_property(PropertyOwner::Node(gone), 1, PropertyValue::Int(1)));
+    }
+
+    #[test]
+    fn ints_and_floats_compare_by_value() {
+        use PropertyValue::*;
+        assert!(Int(1).eval_predicate(CmpOp::Eq, &Float(1.0)));
+        assert!(Float(1.5).eval_predicate(CmpOp::Gt, &Int(1)));
+        assert!(Int(2).eval_predicate(CmpOp::Gt, &Float(1.5)));
+        assert!(Int(-2).eval_predicate(CmpOp::Lt, &Float(-1.5)));
+        assert!(Int(1).eval_predicate(CmpOp::Le, &Float(1.0)));
+        // i64::MAX as f64 rounds up to 2^63, the exact comparison still tells them apart
+        assert!(Int(i64::MAX).eval_predicate(CmpOp::Lt, &Float(i64::MAX as f64)));
+        assert!(Int(i64::MIN).eval_predicate(CmpOp::Eq, &Float(i64::MIN as f64)));
+        assert!(Int(i64::MIN).eval_predicate(CmpOp::Gt, &Float(-1e300)));
+    }
+
+    #[test]
+    fn values_of_different_types_are_only_not_equal() {
+        use PropertyValue::*;
+        for (a, b) in [(Int(1), Str("1".into())), (Bool(true), Int(1)), (Float(f64::NAN), Float(f64::NAN))] {
+            assert!(a.eval_predicate(CmpOp::Ne, &b));
+            for op in [CmpOp::Eq, CmpOp::Lt, CmpOp::Le, CmpOp::Gt, CmpOp::Ge] {
+                assert!(!a.eval_predicate(op, &b), "{:?} {:?} {:?}", a, op, b);
+            }
+        }
+        assert!(Int(1).eval_predicate(CmpOp::Ne, &Float(f64::NAN)));
+        assert!(Str("a".into()).eval_predicate(CmpOp::Lt, &Str("b".into())));
+        assert!(Bool(false).eval_predicate(CmpOp::Lt, &Bool(true)));
+    }
+
+    #[test]
+    fn anything_compared_with_null_is_false() {
+        use PropertyValue::*;
+        for op in [CmpOp::Eq, CmpOp::Ne, CmpOp::Lt, CmpOp::Le, CmpOp::Gt, CmpOp::Ge] {
+            assert!(!Null.eval_predicate(op, &Null));
+            assert!(!Null.eval_predicate(op, &Int(1)));
+            assert!(!Int(1).eval_predicate(op, &Null));
+        }
+        // equality of the values themselves is a different thing, a stored Null reads back equal to Null
+        assert_eq!(Null, Null);
+    }
+
+    #[test]
+    fn nodes_where_filters_on_typed_values() {
+        use PropertyValue::*;
+        let s = stores();
+        let nodes: Vec<NodeId> = (0..6).map(|_| s.nodes.create_node(&[]).unwrap()).collect();
+        let values = [Int(1), Float(2.5), Int(3), Str("x".into()), Null];
+        for (&node, value) in nodes.iter().zip(values) {
+            assert!(s.props.set_property(PropertyOwner::Node(node), 7, value));
+            assert!(s.props.set_property(PropertyOwner::Node(node), 8, Int(100)));
+        }
+        // nodes[5] has no key 7 at all
+        assert_eq!(s.props.nodes_where(7, CmpOp::Gt, &Float(1.0)), vec![nodes[1], nodes[2]]);
+        assert_eq!(s.props.nodes_where(7, CmpOp::Eq, &Float(3.0)), vec![nodes[2]]);
+        assert_eq!(s.props.nodes_where(7, CmpOp::Eq, &Str("x".into())), vec![nodes[3]]);
+        assert_eq!(s.props.nodes_where(7, CmpOp::Ne, &Int(1)), vec![nodes[1], nodes[2], nodes[3]]);
+        assert!(s.props.nodes_where(7, CmpOp::Eq, &Null).is_empty());
+        assert_eq!(s.props.get_property(PropertyOwner::Node(nodes[1]), 7), Some(Float(2.5)));
+    }
+
+    #[test]
+    fn strings_go_to_overflow_pages_just_past_the_inline_limit() {
+        let s = stores();
+        let owner = PropertyOwner::Node(s.nodes.create_node(&[]).unwrap());
+        let overflow_pages = || s.props.bpm.iter_pages_of_type(PageType::Overflow).count();
+        for (len, key) in [(INLINE_STRING_MAX - 1, 1), (INLINE_STRING_MAX, 2)] {
+            assert!(s.props.set_property(owner, key, PropertyValue::Str("y".repeat(len))));
+            assert_eq!(overflow_pages(), 0, "{} bytes", len);
+        }
+        assert!(s.props.set_property(owner, 3, PropertyValue::Str("y".repeat(INLINE_STRING_MAX + 1))));
+        assert_eq!(overflow_pages(), 1);
+        for (len, key) in [(INLINE_STRING_MAX - 1, 1), (INLINE_STRING_MAX, 2), (INLINE_STRING_MAX + 1, 3)] {
+            assert_eq!(s.props.get_property(owner, key), Some(PropertyValue::Str("y".repeat(len))));
+        }
+    }
+
+    #[test]
+    fn deleting_a_relationship_frees_its_properties() {
+        let s = stores();
+        let [a, b] = [(); 2].map(|_| s.nodes.create_node(&[]).unwrap());
+        let rel = s.rels.create_relationship(a, b, 1).unwrap();
+        let owner = PropertyOwner::Relationship(rel);
+        assert!(s.props.set_property(owner, 1, PropertyValue::Int(1)));
+        assert!(s.props.set_property(owner, 2, PropertyValue::Str("y".repeat(INLINE_STRING_MAX * 10))));
+
+        assert!(s.props.delete_relationship(rel));
+        assert!(s.rels.get_relationship(rel).is_none());
+        assert_eq!(s.props.bpm.count_items_of_type(PageType::PropertyStore), 0);
+        assert!(!s.props.delete_relationship(rel));
+    }
+
+    #[test]
+    fn a_stale_relationship_id_leaves_the_new_owners_properties_alone() {
+        let s = stores();
+        let [a, b] = [(); 2].map(|_| s.nodes.create_node(&[]).unwrap());
+        let old = s.rels.create_relationship(a, b, 1).unwrap();
+        assert!(s.props.delete_relationship(old));
+        // the same edge again, in the same slot
+        let new = s.rels.create_relationship(a, b, 1).unwrap();
+        assert_eq!((new.page_id(), new.slot()), (old.page_id(), old.slot()));
+        assert_ne!(new, old);
+        assert!(s.props.set_property(PropertyOwner::Relationship(new), 1, PropertyValue::Int(5)));
+
+        assert!(!s.props.delete_relationship(old));
+        assert_eq!(s.props.get_property(PropertyOwner::Relationship(new), 1), Some(PropertyValue::Int(5)));
+        assert!(s.rels.get_relationship(new).is_some());
+    }
+}
@@ -0,0 +1,1032 @@
+/*
+* Relationship storage, modeled after Neo4j's relationship store.
+* Every relationship sits in two doubly linked lists at once: the chain of its start node and the chain of its
+* end node. A node only stores the head of its chain (Node::first_rel), so walking a node's edges never has
+* to look at relationships that don't touch it.
+* Each chain keeps relationships of the same type next to each other: a new relationship goes in front of the first
+* one of its type already in the chain, or onto the head if there is none yet. That costs a walk of the chain on
+* create, but lets a walk filtered by type stop as soon as it has been through the groups it asked for.
+* A self loop (start == end) is only linked into the start chain, its end pointers stay empty.
+* Like a NodeId, a RelationshipId carries the generation of its slot. Deleting a relationship leaves the next one in
+* the tombstone, so a stale id doesn't find (or delete) whatever relationship got its slot since.
+*
+* Relationship record layout (fixed size):
+* | start_node: u64 | end_node: u64 | type_id: u32 | start_prev: u64 | start_next: u64 | end_prev: u64 | end_next: u64 |
+* | first_prop: u64 | generation: u16 |
+* Records from before generations end after first_prop, they read as generation 0.
+*/
+
+use std::sync::{Arc, Mutex};
+
+use crate::paging::buffer_pool_manager::{BufferPoolManager, PageFrameRef, WritePageGuard};
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::PageId;
+use crate::paging::record::{DecodeError, Record, RecordReader};
+use crate::paging::slotted_page::{SlotId, SlottedPage};
+use super::node_store::{next_generation, NodeId, NodeStore, NO_ID, PAGE_BITS, PAGE_MASK};
+use super::property_store::PropertyId;
+use super::storage_stats::{storage_stats, StorageStats};
+
+const RECORD_SIZE: usize = 62;
+// size of a record from before generations
+const OLD_RECORD_SIZE: usize = 60;
+
+// offsets of the fields that get patched in place, the rest only goes through RelationshipRecord
+const START_NODE: usize = 0;
+const START_PREV: usize = 20;
+const START_NEXT: usize = 28;
+const END_PREV: usize = 36;
+const END_NEXT: usize = 44;
+const FIRST_PROP: usize = 52;
+const GENERATION: usize = 60;
+
+// Location of a relationship record and the generation of its slot, same | generation | page_id | slot | encoding
+// as NodeId
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RelationshipId(pub u64);
+
+impl RelationshipId {
+    // None if page_id doesn't fit, see NodeId::fits
+    pub fn new(page_id: PageId, slot: SlotId, generation: u16) -> Option<Self> {
+        NodeId::new(page_id, slot, generation).map(|id| Self(id.0))
+    }
+
+    pub fn page_id(&self) -> PageId {
+        (self.0 >> 16) & PAGE_MASK
+    }
+
+    pub fn generation(&self) -> u16 {
+        (self.0 >> (PAGE_BITS + 16)) as u16
+    }
+
+    pub fn slot(&self) -> SlotId {
+        (self.0 & 0xFFFF) as SlotId
+    }
+}
+
+// Which of a node's relationships to follow, relative to that node
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    Outgoing, // node is the start
+    Incoming, // node is the end
+    Both,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relationship {
+    pub id: RelationshipId,
+    pub start_node: NodeId,
+    pub end_node: NodeId,
+    pub type_id: u32,
+    pub start_prev: Option<RelationshipId>,
+    pub start_next: Option<RelationshipId>,
+    pub end_prev: Option<RelationshipId>,
+    pub end_next: Option<RelationshipId>,
+    pub first_prop: Option<PropertyId>,
+}
+
+impl Relationship {
+    // next relationship in node's chain, node has to be one of the two endpoints
+    pub fn next_for(&self, node: NodeId) -> Option<RelationshipId> {
+        if self.start_node == node { self.start_next } else { self.end_next }
+    }
+
+    // same for the previous one, None at the head of the chain
+    pub fn prev_for(&self, node: NodeId) -> Option<RelationshipId> {
+        if self.start_node == node { self.start_prev } else { self.end_prev }
+    }
+
+    // whether this relationship leaves or enters node in the given direction, a self loop does both
+    pub fn goes(&self, node: NodeId, direction: Direction) -> bool {
+        match direction {
+            Direction::Outgoing => self.start_node == node,
+            Direction::Incoming => self.end_node == node,
+            Direction::Both => self.start_node == node || self.end_node == node,
+        }
+    }
+
+    // the endpoint that isn't node, node itself for a self loop
+    pub fn other(&self, node: NodeId) -> NodeId {
+        if self.start_node == node { self.end_node } else { self.start_node }
+    }
+}
+
+// What RelationshipStore::delete_nodes does about nodes that still have relationships
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DetachMode {
+    Refuse, // fail without deleting anything
+    Detach, // delete their relationships along with them, taking them out of the other endpoints' chains
+}
+
+// delete_nodes under DetachMode::Refuse hit a node that still has relationships
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HasRelationships(pub NodeId);
+
+impl std::fmt::Display for HasRelationships {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "node {} still has relationships", self.0.0)
+    }
+}
+
+impl std::error::Error for HasRelationships {}
+
+pub struct RelationshipStore {
+    bpm: Arc<BufferPoolManager>,
+    nodes: Arc<NodeStore>,
+    // page we last inserted into, also serializes relationship creation since it rewrites several chains
+    current_page: Mutex<Option<PageId>>,
+}
+
+impl RelationshipStore {
+    pub fn new(bpm: Arc<BufferPoolManager>, nodes: Arc<NodeStore>) -> Self {
+        Self { bpm, nodes, current_page: Mutex::new(None) }
+    }
+
+    // page and record counts for this store, see StorageStats
+    pub fn stats(&self) -> StorageStats {
+        storage_stats(&self.bpm, PageType::Relationship)
+    }
+
+    // page_id read latched, None unless it's one of our pages (a relationship id that points at another kind of page
+    // is a bug somewhere, better to come up empty than to decode whatever is there)
+    fn fetch(&self, page_id: PageId) -> Option<PageFrameRef<'_>> {
+        let guard = self.bpm.fetch_page(page_id).ok()?;
+        guard.expect_type(PageType::Relationship).ok()?;
+        Some(guard)
+    }
+
+    // same as fetch, write latched
+    fn fetch_write(&self, page_id: PageId) -> Option<WritePageGuard<'_>> {
+        let guard = self.bpm.fetch_page_write(page_id).ok()?;
+        guard.expect_type(PageType::Relationship).ok()?;
+        Some(guard)
+    }
+
+    // Creates start -[type_id]-> end and links it into both nodes' chains
+    // None if either node doesn't exist or the pool is out of frames, or if a chain couldn't be rewritten. Both chains
+    // are put back the way they were then and the record is freed again
+    pub fn create_relationship(&self, start: NodeId, end: NodeId, type_id: u32) -> Option<RelationshipId> {
+        let mut current_page = self.current_page.lock().unwrap();
+
+        let (start_prev, start_next) = self.group_position(start, type_id)?;
+        let (end_prev, end_next) = if start == end { (None, None) } else { self.group_position(end, type_id)? };
+
+        let record = RelationshipRecord {
+            start_node: start,
+            end_node: end,
+            type_id,
+            start_prev,
+            start_next,
+            end_prev,
+            end_next,
+            first_prop: None,
+            generation: 0, // insert_record fills it in
+        };
+        let id = self.insert_record(&mut current_page, &record)?;
+
+        if !self.link(start, id, start_prev, start_next) {
+            self.delete_record(id);
+            return None;
+        }
+        if start != end && !self.link(end, id, end_prev, end_next) {
+            // if start's chain can't be put back either the record stays, rather than leave it pointing at a freed slot
+            if self.unlink(start, &record.with_id(id)) {
+                self.delete_record(id);
+            }
+            return None;
+        }
+        Some(id)
+    }
+
+    pub fn get_relationship(&self, id: RelationshipId) -> Option<Relationship> {
+        let guard = self.fetch(id.page_id())?;
+        let page = SlottedPage::new(&*guard);
+        let record = page.get::<RelationshipRecord>(id.slot())?.ok()?;
+        // a different generation means the slot has been reused since id was handed out
+        (record.generation == id.generation()).then(|| record.with_id(id))
+    }
+
+    // Whether start -[type_id]-> end exists, without walking either chain all the way. The edge would be in both
+    // chains, so this walks them a step at a time each and gives up as soon as one of them runs out or goes past
+    // its type_id group, which bounds it by the shorter chain (twice over) no matter how dense the other node is
+    pub fn has_edge(&self, start: NodeId, end: NodeId, type_id: u32) -> bool {
+        let head = |node| self.nodes.get_node(node).and_then(|n| n.first_rel);
+        let mut walks = [EdgeSearch::new(start, head(start)), EdgeSearch::new(end, head(end))];
+        loop {
+            for walk in &mut walks {
+                if let Some(found) = walk.step(self, start, end, type_id) {
+                    return found;
+                }
+            }
+        }
+    }
+
+    // Walks node's relationship chain, keeping only the relationships that go the given direction and,
+    // unless type_filter is empty, have one of its type_ids
+    // both directions share the one chain, the start node at the front of each record says which side node is on
+    pub fn relationships_of<'a>(
+        &'a self,
+        node: NodeId,
+        direction: Direction,
+        type_filter: &[u32],
+    ) -> RelationshipIter<'a> {
+        let next = self.nodes.get_node(node).and_then(|n| n.first_rel);
+        let mut types = type_filter.to_vec();
+        types.sort_unstable();
+        types.dedup();
+        let groups_left = types.len();
+        RelationshipIter { store: self, node, direction, next, types, groups_left, last_type: None }
+    }
+
+    // loads the pages holding the heads of each node's relationship chain ahead of walking them
+    // only a hint, see BufferPoolManager::prefetch
+    pub fn prefetch_chains(&self, nodes: &[NodeId]) {
+        let mut pages: Vec<PageId> =
+            nodes.iter().filter_map(|&n| self.nodes.get_node(n)?.first_rel).map(|rel| rel.page_id()).collect();
+        pages.sort_unstable();
+        pages.dedup();
+        self.bpm.prefetch(&pages);
+    }
+
+    // points the head of rel's property chain at prop, returns false if the relationship doesn't exist
+    pub fn set_first_prop(&self, rel: RelationshipId, prop: Option<PropertyId>) -> bool {
+        let Some(mut guard) = self.fetch_write(rel.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        let Some(record) = record_of(&mut page, rel) else { return false };
+        write_id(record, FIRST_PROP, prop.map(|p| p.0));
+        true
+    }
+
+    // Splices id out of its start node's and end node's chains, moving a node's first_rel on to the next one if
+    // this was the head, then frees the record. false if there's no relationship at id (a stale id included) or one
+    // of the chains couldn't be rewritten, the record is kept then so no chain is left pointing at a freed slot
+    // its property chain isn't freed here, PropertyStore::delete_relationship does both
+    pub fn delete_relationship(&self, id: RelationshipId) -> bool {
+        // rewrites chains like create_relationship does, so it takes the same lock
+        let _current_page = self.current_page.lock().unwrap();
+        let Some(rel) = self.get_relationship(id) else { return false };
+        self.remove(id, &rel, rel.start_node)
+    }
+
+    // delete_relationship without the lock, unlinking from first's chain before the other end's
+    fn remove(&self, id: RelationshipId, rel: &Relationship, first: NodeId) -> bool {
+        let other = rel.other(first);
+        let unlinked = self.unlink(first, rel) && (other == first || self.unlink(other, rel));
+        unlinked && self.delete_record(id)
+    }
+
+    // NodeStore::delete_nodes for nodes that might still be connected, see DetachMode for what happens to their
+    // relationships. Returns how many nodes were deleted
+    // under Detach a relationship that can't be unlinked stops it with HasRelationships for its node, the ones
+    // deleted up to there stay deleted but no node is
+    // the relationships' property chains aren't freed, same as delete_node leaves the node's
+    pub fn delete_nodes(&self, ids: &[NodeId], mode: DetachMode) -> Result<usize, HasRelationships> {
+        // rewrites chains like create_relationship does, so it takes the same lock
+        let _current_page = self.current_page.lock().unwrap();
+        match mode {
+            DetachMode::Refuse => {
+                let connected = ids.iter().find(|&&id| self.nodes.get_node(id).is_some_and(|n| n.first_rel.is_some()));
+                if let Some(&id) = connected {
+                    return Err(HasRelationships(id));
+                }
+            }
+            DetachMode::Detach => {
+                if let Some(&id) = ids.iter().find(|&&id| !self.detach(id)) {
+                    return Err(HasRelationships(id));
+                }
+            }
+        }
+        Ok(self.nodes.delete_nodes(ids))
+    }
+
+    // deletes every relationship of node from the head of its chain on, false if one couldn't be. The chain
+    // stays intact from there, so the node can be kept and still only points at live records
+    fn detach(&self, node: NodeId) -> bool {
+        while let Some(id) = self.nodes.get_node(node).and_then(|n| n.first_rel) {
+            let Some(rel) = self.get_relationship(id) else { return false };
+            // the other end first, if its chain can't be rewritten node's is still whole
+            if !self.remove(id, &rel, rel.other(node)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    // the tombstone hands the slot's next relationship the generation after id's
+    fn delete_record(&self, id: RelationshipId) -> bool {
+        let Some(mut guard) = self.fetch_write(id.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        if record_of(&mut page, id).is_none() {
+            return false;
+        }
+        page.delete_record_with_generation(id.slot(), next_generation(id.generation()))
+    }
+
+    fn insert_record(&self, current_page: &mut Option<PageId>, record: &RelationshipRecord) -> Option<RelationshipId> {
+        if let Some(page_id) = *current_page {
+            let mut guard = self.fetch_write(page_id)?;
+            if let Some((slot, generation)) = Self::insert_into(&mut SlottedPage::new(&mut *guard), record) {
+                return RelationshipId::new(page_id, slot, generation);
+            }
+        }
+
+        let mut guard = self.bpm.allocate_page_of_type(PageType::Relationship).ok()?;
+        let page_id = guard.page_id;
+        if !NodeId::fits(page_id) {
+            // past what relationship ids can address, same as NodeStore::place
+            drop(guard);
+            if let Err(e) = self.bpm.deallocate_page(page_id) {
+                self.bpm.defer_error(e);
+            }
+            return None;
+        }
+        let (slot, generation) = Self::insert_into(&mut SlottedPage::init(&mut *guard), record)?;
+        *current_page = Some(page_id);
+        RelationshipId::new(page_id, slot, generation)
+    }
+
+    // inserts record with the generation a reused slot hands it, see NodeStore::create_node
+    fn insert_into(page: &mut SlottedPage<&mut Page>, record: &RelationshipRecord) -> Option<(SlotId, u16)> {
+        let generation = page.next_generation();
+        let slot = page.insert(&RelationshipRecord { generation, ..record.clone() })?;
+        Some((slot, generation))
+    }
+
+    // (prev, next) for a new relationship of type_id in node's chain: right in front of the first one of the
+    // same type, or at the head if node has none of that type yet. None if node doesn't exist
+    fn group_position(&self, node: NodeId, type_id: u32) -> Option<(Option<RelationshipId>, Option<RelationshipId>)> {
+        let head = self.nodes.get_node(node)?.first_rel;
+        let mut prev = None;
+        let mut next = head;
+        while let Some(id) = next {
+            let Some(rel) = self.get_relationship(id) else { break };
+            if rel.type_id == type_id {
+                return Some((prev, Some(id)));
+            }
+            prev = Some(id);
+            next = rel.next_for(node);
+        }
+        Some((None, head))
+    }
+
+    // splices rel into node's chain between prev and next. false if a pointer couldn't be rewritten, the one
+    // already rewritten is put back then
+    fn link(&self, node: NodeId, rel: RelationshipId, prev: Option<RelationshipId>, next: Option<RelationshipId>) -> bool {
+        // the back pointer first, rel isn't reachable from the head until the forward side is done
+        if next.is_some_and(|next| !self.set_prev_for(next, node, Some(rel))) {
+            return false;
+        }
+        let linked = match prev {
+            Some(prev) => self.set_next_for(prev, node, Some(rel)),
+            None => self.nodes.set_first_rel(node, Some(rel)),
+        };
+        if !linked && let Some(next) = next {
+            self.set_prev_for(next, node, prev);
+        }
+        linked
+    }
+
+    // splices rel out of node's chain, the other way around from link. false if a pointer couldn't be rewritten
+    fn unlink(&self, node: NodeId, rel: &Relationship) -> bool {
+        let (prev, next) = (rel.prev_for(node), rel.next_for(node));
+        // the forward side first, if that fails the chain hasn't been touched yet
+        let skipped = match prev {
+            Some(prev) => self.set_next_for(prev, node, next),
+            None => self.nodes.set_first_rel(node, next),
+        };
+        skipped && next.is_none_or(|next| self.set_prev_for(next, node, prev))
+    }
+
+    // sets the prev pointer of rel on node's chain
+    fn set_prev_for(&self, rel: RelationshipId, node: NodeId, prev: Option<RelationshipId>) -> bool {
+        let Some(mut guard) = self.fetch_write(rel.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        let Some(record) = record_of(&mut page, rel) else { return false };
+        let field = if read_id(record, START_NODE) == Some(node.0) { START_PREV } else { END_PREV };
+        write_id(record, field, prev.map(|r| r.0));
+        true
+    }
+
+    // sets the next pointer of rel on node's chain
+    fn set_next_for(&self, rel: RelationshipId, node: NodeId, next: Option<RelationshipId>) -> bool {
+        let Some(mut guard) = self.fetch_write(rel.page_id()) else { return false };
+        let mut page = SlottedPage::new(&mut *guard);
+        let Some(record) = record_of(&mut page, rel) else { return false };
+        let field = if read_id(record, START_NODE) == Some(node.0) { START_NEXT } else { END_NEXT };
+        write_id(record, field, next.map(|r| r.0));
+        true
+    }
+}
+
+// one side of has_edge
+struct EdgeSearch {
+    node: NodeId,
+    next: Option<RelationshipId>,
+    in_group: bool, // walked into the type_id group, leaving it means the edge isn't in this chain
+}
+
+impl EdgeSearch {
+    fn new(node: NodeId, head: Option<RelationshipId>) -> Self {
+        Self { node, next: head, in_group: false }
+    }
+
+    // looks at the next relationship in the chain, Some once the answer is known
+    fn step(&mut self, store: &RelationshipStore, start: NodeId, end: NodeId, type_id: u32) -> Option<bool> {
+        let Some(rel) = self.next.and_then(|id| store.get_relationship(id)) else { return Some(false) };
+        self.next = rel.next_for(self.node);
+        if rel.type_id != type_id {
+            return self.in_group.then_some(false);
+        }
+        if rel.start_node == start && rel.end_node == end {
+            return Some(true);
+        }
+        self.in_group = true;
+        None
+    }
+}
+
+pub struct RelationshipIter<'a> {
+    store: &'a RelationshipStore,
+    node: NodeId,
+    direction: Direction,
+    next: Option<RelationshipId>,
+    types: Vec<u32>, // sorted, empty means every type
+    groups_left: usize, // wanted type groups not walked past yet
+    last_type: Option<u32>,
+}
+
+impl<'a> RelationshipIter<'a> {
+    fn wants(&self, type_id: u32) -> bool {
+        self.types.is_empty() || self.types.binary_search(&type_id).is_ok()
+    }
+}
+
+impl<'a> Iterator for RelationshipIter<'a> {
+    type Item = Relationship;
+
+    fn next(&mut self) -> Option<Relationship> {
+        loop {
+            let rel = self.store.get_relationship(self.next?)?;
+            self.next = rel.next_for(self.node);
+
+            // types are grouped, once the walk leaves the last wanted group nothing further down can match
+            if let Some(last) = self.last_type.replace(rel.type_id)
+                && last != rel.type_id
+                && !self.types.is_empty()
+                && self.wants(last)
+            {
+                self.groups_left -= 1;
+                if self.groups_left == 0 {
+                    self.next = None;
+                    return None;
+                }
+            }
+
+            if self.wants(rel.type_id) && rel.goes(self.node, self.direction) {
+                return Some(rel);
+            }
+        }
+    }
+}
+
+fn write_id(record: &mut [u8], at: usize, id: Option<u64>) {
+    record[at..at + 8].copy_from_slice(&id.unwrap_or(NO_ID).to_le_bytes());
+}
+
+fn read_id(record: &[u8], at: usize) -> Option<u64> {
+    let id = u64::from_le_bytes(record[at..at + 8].try_into().unwrap());
+    (id != NO_ID).then_some(id)
+}
+
+// id's record to patch in place, None if the slot is empty or has been reused since id was handed out
+fn record_of<'p>(page: &'p mut SlottedPage<&mut Page>, id: RelationshipId) -> Option<&'p mut [u8]> {
+    let record = page.get_record_mut(id.slot())?;
+    (record_generation(record)? == id.generation()).then_some(record)
+}
+
+// generation the record was created with, 0 for records that don't have one. None if it's not a relationship record
+fn record_generation(record: &[u8]) -> Option<u16> {
+    match record.len() {
+        OLD_RECORD_SIZE => Some(0),
+        RECORD_SIZE => Some(u16::from_le_bytes([record[GENERATION], record[GENERATION + 1]])),
+        _ => None,
+    }
+}
+
+// a relationship as it's stored, the id comes from where the record sits (and its generation)
+#[derive(Clone)]
+struct RelationshipRecord {
+    start_node: NodeId,
+    end_node: NodeId,
+    type_id: u32,
+    start_prev: Option<RelationshipId>,
+    start_next: Option<RelationshipId>,
+    end_prev: Option<RelationshipId>,
+    end_next: Option<RelationshipId>,
+    first_prop: Option<PropertyId>,
+    generation: u16,
+}
+
+impl RelationshipRecord {
+    fn with_id(self, id: RelationshipId) -> Relationship {
+        Relationship {
+            id,
+            start_node: self.start_node,
+            end_node: self.end_node,
+            type_id: self.type_id,
+            start_prev: self.start_prev,
+            start_next: self.start_next,
+            end_prev: self.end_prev,
+            end_next: self.end_next,
+            first_prop: self.first_prop,
+        }
+    }
+}
+
+impl Record for RelationshipRecord {
+    fn serialized_len(&self) -> usize {
+        RECORD_SIZE
+    }
+
+    fn serialize(&self, buf: &mut Vec<u8>) {
+        let rel = |r: Option<RelationshipId>| r.map_or(NO_ID, |r| r.0).to_le_bytes();
+        buf.extend_from_slice(&self.start_node.0.to_le_bytes());
+        buf.extend_from_slice(&self.end_node.0.to_le_bytes());
+        buf.extend_from_slice(&self.type_id.to_le_bytes());
+        buf.extend_from_slice(&rel(self.start_prev));
+        buf.extend_from_slice(&rel(self.start_next));
+        buf.extend_from_slice(&rel(self.end_prev));
+        buf.extend_from_slice(&rel(self.end_next));
+        buf.extend_from_slice(&self.first_prop.map_or(NO_ID, |p| p.0).to_le_bytes());
+        buf.extend_from_slice(&self.generation.to_le_bytes());
+    }
+
+    fn deserialize(buf: &[u8]) -> Result<Self, DecodeError> {
+        let Some(generation) = record_generation(buf) else {
+            return Err(DecodeError::Invalid("relationship record has the wrong size"));
+        };
+        let mut reader = RecordReader::new(buf);
+        let id = |raw: u64| (raw != NO_ID).then_some(raw);
+        let node = |raw: u64| id(raw).map(NodeId).ok_or(DecodeError::Invalid("relationship without an endpoint"));
+        Ok(Self {
+            start_node: node(reader.u64()?)?,
+            end_node: node(reader.u64()?)?,
+            type_id: reader.u32()?,
+            start_prev: id(reader.u64()?).map(RelationshipId),
+            start_next: id(reader.u64()?).map(RelationshipId),
+            end_prev: id(reader.u64()?).map(RelationshipId),
+            end_next: id(reader.u64()?).map(RelationshipId),
+            first_prop: id(reader.u64()?).map(PropertyId),
+            generation,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::buffer_pool_manager::FrameMeta;
+    use crate::paging::page_constants::FrameId;
+    use crate::paging::replacement::Replacer;
+
+    fn stores() -> (Arc<NodeStore>, RelationshipStore) {
+        let bpm = Arc::new(BufferPoolManager::new(16, MockDiskManager::new()).unwrap());
+        let nodes = Arc::new(NodeStore::new(bpm.clone()));
+        (nodes.clone(), RelationshipStore::new(bpm, nodes))
+    }
+
+    fn ids_of(rels: impl Iterator<Item = Relationship>) -> Vec<RelationshipId> {
+        let mut ids: Vec<_> = rels.map(|r| r.id).collect();
+        ids.sort();
+        ids
+    }
+
+    fn sorted<const N: usize>(mut ids: [RelationshipId; N]) -> Vec<RelationshipId> {
+        ids.sort();
+        ids.to_vec()
+    }
+
+    #[test]
+    fn relationships_show_up_in_both_endpoints_chains() {
+        let (nodes, rels) = stores();
+        let [a, b, c] = [(); 3].map(|_| nodes.create_node(&[]).unwrap());
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        let ac = rels.create_relationship(a, c, 1).unwrap();
+        let cb = rels.create_relationship(c, b, 1).unwrap();
+
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Both, &[])), sorted([ab, ac]));
+        assert_eq!(ids_of(rels.relationships_of(b, Direction::Both, &[])), sorted([ab, cb]));
+        assert_eq!(ids_of(rels.relationships_of(c, Direction::Both, &[])), sorted([ac, cb]));
+
+        let rel = rels.get_relationship(ab).unwrap();
+        assert_eq!((rel.start_node, rel.end_node, rel.type_id), (a, b, 1));
+    }
+
+    #[test]
+    fn chains_link_both_ways() {
+        let (nodes, rels) = stores();
+        let a = nodes.create_node(&[]).unwrap();
+        let others: Vec<NodeId> = (0..3).map(|_| nodes.create_node(&[]).unwrap()).collect();
+        for &other in &others {
+            rels.create_relationship(a, other, 1).unwrap();
+        }
+
+        let chain: Vec<Relationship> = rels.relationships_of(a, Direction::Both, &[]).collect();
+        assert_eq!(chain.len(), 3);
+        assert_eq!(chain[0].prev_for(a), None);
+        for pair in chain.windows(2) {
+            assert_eq!(pair[0].next_for(a), Some(pair[1].id));
+            assert_eq!(pair[1].prev_for(a), Some(pair[0].id));
+        }
+        assert_eq!(chain[2].next_for(a), None);
+    }
+
+    #[test]
+    fn a_self_loop_is_only_in_one_chain() {
+        let (nodes, rels) = stores();
+        let a = nodes.create_node(&[]).unwrap();
+        let id = rels.create_relationship(a, a, 1).unwrap();
+        let found: Vec<Relationship> = rels.relationships_of(a, Direction::Both, &[]).collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, id);
+        assert_eq!((found[0].end_prev, found[0].end_next), (None, None));
+    }
+
+    #[test]
+    fn relationships_need_both_endpoints() {
+        let (nodes, rels) = stores();
+        let a = nodes.create_node(&[]).unwrap();
+        let gone = nodes.create_node(&[]).unwrap();
+        nodes.delete_node(gone);
+        assert_eq!(rels.create_relationship(a, gone, 1), None);
+        assert_eq!(nodes.get_node(a).unwrap().first_rel, None);
+    }
+
+    #[test]
+    fn direction_picks_the_side_of_the_relationship() {
+        let (nodes, rels) = stores();
+        let [a, b, c] = [(); 3].map(|_| nodes.create_node(&[]).unwrap());
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        let ca = rels.create_relationship(c, a, 1).unwrap();
+        let bc = rels.create_relationship(b, c, 1).unwrap();
+        let aa = rels.create_relationship(a, a, 1).unwrap();
+
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Outgoing, &[])), sorted([ab, aa]));
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Incoming, &[])), sorted([ca, aa]));
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Both, &[])), sorted([ab, ca, aa]));
+        assert_eq!(ids_of(rels.relationships_of(b, Direction::Outgoing, &[])), sorted([bc]));
+        assert_eq!(ids_of(rels.relationships_of(b, Direction::Incoming, &[])), sorted([ab]));
+
+        let rel = rels.get_relationship(ca).unwrap();
+        assert!(rel.goes(c, Direction::Outgoing) && !rel.goes(c, Direction::Incoming));
+        assert!(rel.goes(a, Direction::Incoming) && rel.goes(a, Direction::Both));
+    }
+
+    #[test]
+    fn type_filter_keeps_only_those_types() {
+        let (nodes, rels) = stores();
+        let [a, b] = [(); 2].map(|_| nodes.create_node(&[]).unwrap());
+        let mut by_type: BTreeMap<u32, Vec<RelationshipId>> = BTreeMap::new();
+        // interleaved on purpose, the chain groups them by type anyway
+        for type_id in [1, 2, 3, 1, 3, 2, 1] {
+            by_type.entry(type_id).or_default().push(rels.create_relationship(a, b, type_id).unwrap());
+        }
+        let of_types = |types: &[u32]| {
+            let mut ids: Vec<RelationshipId> = types.iter().flat_map(|t| by_type[t].clone()).collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Both, &[1])), of_types(&[1]));
+        assert_eq!(ids_of(rels.relationships_of(b, Direction::Incoming, &[2])), of_types(&[2]));
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Outgoing, &[3, 1, 3])), of_types(&[1, 3]));
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Incoming, &[1])), vec![]);
+        assert_eq!(ids_of(rels.relationships_of(a, Direction::Both, &[9])), vec![]);
+        assert_eq!(rels.relationships_of(a, Direction::Both, &[]).count(), 7);
+    }
+
+    #[test]
+    fn chains_keep_each_type_together() {
+        let (nodes, rels) = stores();
+        let [a, b, c] = [(); 3].map(|_| nodes.create_node(&[]).unwrap());
+        for (end, type_id) in [(b, 5), (c, 7), (b, 7), (c, 5), (b, 6)] {
+            rels.create_relationship(a, end, type_id).unwrap();
+        }
+        let types: Vec<u32> = rels.relationships_of(a, Direction::Both, &[]).map(|r| r.type_id).collect();
+        let mut groups = types.clone();
+        groups.dedup();
+        assert_eq!(groups.len(), 3, "{:?}", types);
+    }
+
+    #[test]
+    fn delete_nodes_refuses_connected_nodes_and_deletes_nothing() {
+        let (nodes, rels) = stores();
+        let [a, b, lonely] = [(); 3].map(|_| nodes.create_node(&[]).unwrap());
+        rels.create_relationship(a, b, 1).unwrap();
+
+        assert_eq!(rels.delete_nodes(&[lonely, b], DetachMode::Refuse), Err(HasRelationships(b)));
+        assert!(nodes.get_node(lonely).is_some());
+        assert!(nodes.get_node(b).is_some());
+        assert_eq!(rels.delete_nodes(&[lonely], DetachMode::Refuse), Ok(1));
+    }
+
+    #[test]
+    fn delete_nodes_can_detach_first() {
+        let (nodes, rels) = stores();
+        let [a, b, c] = [(); 3].map(|_| nodes.create_node(&[]).unwrap());
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        let ca = rels.create_relationship(c, a, 2).unwrap();
+        let bc = rels.create_relationship(b, c, 1).unwrap();
+        rels.create_relationship(a, a, 3).unwrap();
+
+        assert_eq!(rels.delete_nodes(&[a], DetachMode::Detach), Ok(1));
+        assert_eq!(nodes.get_node(a), None);
+        assert_eq!(rels.get_relationship(ab), None);
+        assert_eq!(rels.get_relationship(ca), None);
+        assert_eq!(ids_of(rels.relationships_of(b, Direction::Both, &[])), vec![bc]);
+        assert_eq!(ids_of(rels.relationships_of(c, Direction::Both, &[])), vec![bc]);
+    }
+
+    #[test]
+    fn has_edge_checks_direction_and_type() {
+        let (nodes, rels) = stores();
+        let [a, b, c] = [(); 3].map(|_| nodes.create_node(&[]).unwrap());
+        rels.create_relationship(a, b, 1).unwrap();
+        rels.create_relationship(b, c, 2).unwrap();
+        rels.create_relationship(c, c, 3).unwrap();
+
+        assert!(rels.has_edge(a, b, 1));
+        assert!(!rels.has_edge(b, a, 1));
+        assert!(!rels.has_edge(a, b, 2));
+        assert!(rels.has_edge(b, c, 2));
+        assert!(!rels.has_edge(a, c, 1));
+        assert!(rels.has_edge(c, c, 3));
+        assert!(!rels.has_edge(a, a, 1));
+    }
+
+    #[test]
+    fn has_edge_between_a_dense_and_a_sparse_node_stops_early() {
+        let (nodes, rels) = stores();
+        let hub = nodes.create_node(&[]).unwrap();
+        let leaf = nodes.create_node(&[]).unwrap();
+        for type_id in 0..4 {
+            for _ in 0..200 {
+                let other = nodes.create_node(&[]).unwrap();
+                rels.create_relationship(hub, other, type_id).unwrap();
+            }
+        }
+        rels.create_relationship(hub, leaf, 2).unwrap();
+        let unrelated = nodes.create_node(&[]).unwrap();
+
+        let fetches = || {
+            let stats = rels.bpm.stats();
+            stats.hits + stats.misses
+        };
+        let cases = [(hub, leaf, 2, true), (hub, leaf, 1, false), (leaf, hub, 2, false), (hub, unrelated, 0, false)];
+        for (start, end, type_id, expected) in cases {
+            let before = fetches();
+            assert_eq!(rels.has_edge(start, end, type_id), expected);
+            // the hub's chain is 801 records long, the answer never needs more than a few steps of it
+            assert!(fetches() - before < 20, "{} fetches", fetches() - before);
+        }
+    }
+
+    #[test]
+    fn creating_relationships_both_ways_from_two_threads_doesnt_deadlock() {
+        let (nodes, rels) = stores();
+        let [a, b] = [(); 2].map(|_| nodes.create_node(&[]).unwrap());
+        let (done, finished) = std::sync::mpsc::channel();
+
+        std::thread::scope(|s| {
+            for (start, end) in [(a, b), (b, a)] {
+                let (rels, done) = (&rels, done.clone());
+                s.spawn(move || {
+                    for _ in 0..300 {
+                        rels.create_relationship(start, end, 1).unwrap();
+                    }
+                    done.send(()).unwrap();
+                });
+            }
+            for _ in 0..2 {
+                finished.recv_timeout(std::time::Duration::from_secs(30)).expect("deadlocked");
+            }
+        });
+        assert_eq!(rels.relationships_of(a, Direction::Outgoing, &[]).count(), 300);
+        assert_eq!(rels.relationships_of(a, Direction::Incoming, &[]).count(), 300);
+        assert_eq!(rels.relationships_of(b, Direction::Both, &[]).count(), 600);
+    }
+
+    #[test]
+    fn a_relationship_id_into_a_node_page_reads_as_nothing() {
+        let (nodes, rels) = stores();
+        let a = nodes.create_node(&[]).unwrap();
+        let b = nodes.create_node(&[]).unwrap();
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+
+        let wrong = RelationshipId::new(a.page_id(), a.slot(), a.generation()).unwrap();
+        assert!(rels.get_relationship(wrong).is_none());
+        assert!(!rels.delete_relationship(wrong));
+        // neither the node page nor the real relationship was touched
+        assert_eq!(nodes.get_node(a).unwrap().first_rel, Some(ab));
+        assert!(rels.get_relationship(ab).is_some());
+    }
+
+    // node's chain walked from its head: links agree both ways, and it holds exactly expected
+    fn assert_chain(nodes: &NodeStore, rels: &RelationshipStore, node: NodeId, expected: &[RelationshipId]) {
+        let chain: Vec<Relationship> = rels.relationships_of(node, Direction::Both, &[]).collect();
+        assert_eq!(nodes.get_node(node).unwrap().first_rel, chain.first().map(|rel| rel.id));
+        assert_eq!(chain.first().and_then(|rel| rel.prev_for(node)), None);
+        assert_eq!(chain.last().and_then(|rel| rel.next_for(node)), None);
+        for pair in chain.windows(2) {
+            assert_eq!(pair[0].next_for(node), Some(pair[1].id));
+            assert_eq!(pair[1].prev_for(node), Some(pair[0].id));
+        }
+        let mut expected = expected.to_vec();
+        expected.sort();
+        assert_eq!(ids_of(chain.into_iter()), expected);
+    }
+
+    // a with three relationships to b, c and d in chain order
+    fn fan_out(nodes: &NodeStore, rels: &RelationshipStore) -> (NodeId, Vec<NodeId>, Vec<RelationshipId>) {
+        let a = nodes.create_node(&[]).unwrap();
+        let others: Vec<NodeId> = (0..3).map(|_| nodes.create_node(&[]).unwrap()).collect();
+        for &other in &others {
+            rels.create_relationship(a, other, 1).unwrap();
+        }
+        let chain: Vec<RelationshipId> = rels.relationships_of(a, Direction::Both, &[]).map(|rel| rel.id).collect();
+        (a, others, chain)
+    }
+
+    fn end_of(rels: &RelationshipStore, id: RelationshipId) -> NodeId {
+        rels.get_relationship(id).unwrap().end_node
+    }
+
+    #[test]
+    fn deleting_the_head_moves_first_rel_on() {
+        let (nodes, rels) = stores();
+        let (a, _, chain) = fan_out(&nodes, &rels);
+        let end = end_of(&rels, chain[0]);
+        assert!(rels.delete_relationship(chain[0]));
+        assert_chain(&nodes, &rels, a, &chain[1..]);
+        assert_chain(&nodes, &rels, end, &[]);
+        assert!(rels.get_relationship(chain[0]).is_none());
+    }
+
+    #[test]
+    fn deleting_a_middle_relationship_joins_its_neighbours() {
+        let (nodes, rels) = stores();
+        let (a, _, chain) = fan_out(&nodes, &rels);
+        let end = end_of(&rels, chain[1]);
+        // the end node has another relationship, so its chain has to be spliced too
+        let other = rels.create_relationship(end, a, 2).unwrap();
+        assert!(rels.delete_relationship(chain[1]));
+        assert_chain(&nodes, &rels, a, &[chain[0], chain[2], other]);
+        assert_chain(&nodes, &rels, end, &[other]);
+    }
+
+    #[test]
+    fn deleting_the_only_relationship_empties_both_chains() {
+        let (nodes, rels) = stores();
+        let [a, b] = [(); 2].map(|_| nodes.create_node(&[]).unwrap());
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        assert!(rels.delete_relationship(ab));
+        assert_chain(&nodes, &rels, a, &[]);
+        assert_chain(&nodes, &rels, b, &[]);
+        assert!(!rels.delete_relationship(ab));
+    }
+
+    #[test]
+    fn a_self_loop_is_only_unlinked_once() {
+        let (nodes, rels) = stores();
+        let a = nodes.create_node(&[]).unwrap();
+        let b = nodes.create_node(&[]).unwrap();
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        let aa = rels.create_relationship(a, a, 1).unwrap();
+        assert!(rels.delete_relationship(aa));
+        assert_chain(&nodes, &rels, a, &[ab]);
+    }
+
+    #[test]
+    fn a_stale_id_doesnt_delete_the_relationship_that_reused_its_slot() {
+        let (nodes, rels) = stores();
+        let [a, b, c, d] = [(); 4].map(|_| nodes.create_node(&[]).unwrap());
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        assert!(rels.delete_relationship(ab));
+        let cd = rels.create_relationship(c, d, 1).unwrap();
+        assert_eq!((cd.page_id(), cd.slot()), (ab.page_id(), ab.slot()));
+        assert_eq!(cd.generation(), ab.generation() + 1);
+
+        assert!(rels.get_relationship(ab).is_none());
+        assert!(!rels.delete_relationship(ab));
+        assert_chain(&nodes, &rels, c, &[cd]);
+        assert_chain(&nodes, &rels, d, &[cd]);
+        assert!(rels.delete_relationship(cd));
+    }
+
+    #[test]
+    fn a_stale_id_doesnt_delete_a_same_endpoint_relationship_in_its_slot() {
+        let (nodes, rels) = stores();
+        let [a, b] = [(); 2].map(|_| nodes.create_node(&[]).unwrap());
+        let first = rels.create_relationship(a, b, 1).unwrap();
+        assert!(rels.delete_relationship(first));
+        // another type between the same two nodes, then the very same edge again
+        let other_type = rels.create_relationship(a, b, 2).unwrap();
+        assert!(!rels.delete_relationship(first));
+        assert!(rels.delete_relationship(other_type));
+        let again = rels.create_relationship(a, b, 1).unwrap();
+        assert_eq!((again.page_id(), again.slot()), (first.page_id(), first.slot()));
+
+        for stale in [first, other_type] {
+            assert!(rels.get_relationship(stale).is_none());
+            assert!(!rels.delete_relationship(stale));
+            assert!(!rels.set_first_prop(stale, None));
+        }
+        assert_chain(&nodes, &rels, a, &[again]);
+        assert_chain(&nodes, &rels, b, &[again]);
+        assert_eq!(rels.get_relationship(again).unwrap().type_id, 1);
+    }
+
+    #[test]
+    fn relationship_records_from_before_generations_read_as_generation_zero() {
+        let (nodes, rels) = stores();
+        let [a, b] = [(); 2].map(|_| nodes.create_node(&[]).unwrap());
+        let id = rels.create_relationship(a, b, 1).unwrap();
+        assert_eq!(id.generation(), 0);
+        {
+            let mut guard = rels.bpm.fetch_page_write(id.page_id()).unwrap();
+            let mut page = SlottedPage::new(&mut *guard);
+            let old = page.get_record(id.slot()).unwrap()[..OLD_RECORD_SIZE].to_vec();
+            assert!(page.delete_record(id.slot()));
+            assert_eq!(page.insert_record(&old), Some(id.slot()));
+        }
+        let rel = rels.get_relationship(id).unwrap();
+        assert_eq!((rel.start_node, rel.end_node, rel.type_id), (a, b, 1));
+        assert!(rels.delete_relationship(id));
+        assert_eq!(rels.create_relationship(a, b, 1).unwrap().generation(), 1);
+    }
+
+    // page EvictOnly may evict, any while it's None
+    type EvictTarget = Arc<Mutex<Option<PageId>>>;
+
+    // only ever evicts the page it's told to
+    struct EvictOnly(EvictTarget);
+
+    impl Replacer for EvictOnly {
+        fn victim(&mut self, frames: &mut [FrameMeta]) -> Option<FrameId> {
+            let only = *self.0.lock().unwrap();
+            frames.iter().position(|f| !f.is_pinned() && only.is_none_or(|page_id| f.page_id == Some(page_id)))
+        }
+        fn record_access(&mut self, _frame: FrameId) {}
+        fn set_evictable(&mut self, _frame: FrameId, _evictable: bool) {}
+    }
+
+    // a three frame pool holding nodes a and c on one page and b on another, with a -> c as a's only relationship
+    // the relationship store is a new one, so its next relationship goes on a new page and costs a frame
+    fn tight_graph() -> (EvictTarget, Arc<NodeStore>, RelationshipStore, [NodeId; 3]) {
+        let evict = Arc::new(Mutex::new(None));
+        let replacer = Box::new(EvictOnly(evict.clone()));
+        let bpm = Arc::new(BufferPoolManager::with_replacer(3, MockDiskManager::new(), replacer).unwrap());
+        let nodes = Arc::new(NodeStore::new(bpm.clone()));
+        let a = nodes.create_node(&[]).unwrap();
+        let c = nodes.create_node(&[]).unwrap();
+        let b = std::iter::repeat_with(|| nodes.create_node(&[]).unwrap()).find(|n| n.page_id() != a.page_id()).unwrap();
+        RelationshipStore::new(bpm.clone(), nodes.clone()).create_relationship(a, c, 1).unwrap();
+        (evict, nodes.clone(), RelationshipStore::new(bpm, nodes), [a, b, c])
+    }
+
+    #[test]
+    fn a_failed_link_on_the_start_chain_puts_it_back_and_frees_the_record() {
+        let (evict, nodes, rels, [a, b, c]) = tight_graph();
+        let ac = nodes.get_node(a).unwrap().first_rel.unwrap();
+        // the new relationship page takes a's frame, which can't be had back for linking a
+        *evict.lock().unwrap() = Some(a.page_id());
+        assert_eq!(rels.create_relationship(a, b, 1), None);
+        *evict.lock().unwrap() = None;
+
+        assert_eq!(rels.stats().total_records, 1);
+        assert_chain(&nodes, &rels, a, &[ac]);
+        assert_chain(&nodes, &rels, c, &[ac]);
+        assert_chain(&nodes, &rels, b, &[]);
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        assert_chain(&nodes, &rels, a, &[ab, ac]);
+    }
+
+    #[test]
+    fn a_failed_link_on_the_end_chain_unlinks_the_start_again() {
+        let (evict, nodes, rels, [a, b, c]) = tight_graph();
+        let ac = nodes.get_node(a).unwrap().first_rel.unwrap();
+        // b's frame this time, a's chain has been linked by the time b's can't be
+        *evict.lock().unwrap() = Some(b.page_id());
+        assert_eq!(rels.create_relationship(a, b, 1), None);
+        *evict.lock().unwrap() = None;
+
+        assert_eq!(rels.stats().total_records, 1);
+        assert_chain(&nodes, &rels, a, &[ac]);
+        assert_chain(&nodes, &rels, c, &[ac]);
+        assert_chain(&nodes, &rels, b, &[]);
+        let ab = rels.create_relationship(a, b, 1).unwrap();
+        assert_chain(&nodes, &rels, b, &[ab]);
+    }
+}
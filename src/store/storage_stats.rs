@@ -0,0 +1,95 @@
+/*
+* Size and fill numbers for one of the stores, for query planning and capacity reports.
+* Computed from the page headers (item_count, free_space_pointer) and slot counts only, no record gets decoded,
+* so it costs one page read per page of the store. Bytes held by deleted records still count as used until the
+* page gets compacted, so right after a lot of deletes avg_record_size reads high.
+*/
+
+use crate::paging::buffer_pool_manager::BufferPoolManager;
+use crate::paging::page::PageType;
+use crate::paging::page_constants::{HEADER_SIZE, PAGE_SIZE};
+use crate::paging::slotted_page::SlottedPage;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct StorageStats {
+    pub total_pages: u64,
+    pub total_records: u64,
+    pub avg_record_size: f64, // bytes of record data per record, 0 without records
+    pub avg_fill_factor: f64, // share of each page's usable space (everything after the header) taken, 0..=1
+}
+
+impl StorageStats {
+    // rough records per page, 0 for an empty store
+    pub fn records_per_page(&self) -> f64 {
+        if self.total_pages == 0 { 0.0 } else { self.total_records as f64 / self.total_pages as f64 }
+    }
+}
+
+// StorageStats over every slotted page of page_type
+pub(crate) fn storage_stats(bpm: &BufferPoolManager, page_type: PageType) -> StorageStats {
+    let usable = (PAGE_SIZE - HEADER_SIZE) as f64;
+    let (mut pages, mut records, mut record_bytes, mut fill) = (0u64, 0u64, 0u64, 0.0);
+    for guard in bpm.iter_pages_of_type(page_type) {
+        let page = SlottedPage::new(&*guard);
+        pages += 1;
+        records += page.record_count() as u64;
+        record_bytes += (PAGE_SIZE - guard.get_free_space_pointer() as usize) as u64;
+        fill += 1.0 - page.free_space() as f64 / usable;
+    }
+
+    StorageStats {
+        total_pages: pages,
+        total_records: records,
+        avg_record_size: if records == 0 { 0.0 } else { record_bytes as f64 / records as f64 },
+        avg_fill_factor: if pages == 0 { 0.0 } else { fill / pages as f64 },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::slotted_page::SLOT_SIZE;
+
+    // a page_type page holding count records of len bytes each
+    fn page_of(bpm: &BufferPoolManager, page_type: PageType, count: usize, len: usize) {
+        let page_id = bpm.allocate_page_of_type(page_type).unwrap().page_id;
+        let mut guard = bpm.fetch_page_write(page_id).unwrap();
+        let mut page = SlottedPage::new(&mut *guard);
+        for i in 0..count {
+            page.insert_record(&vec![i as u8; len]).unwrap();
+        }
+    }
+
+    #[test]
+    fn stats_of_a_known_dataset() {
+        let bpm = BufferPoolManager::new(8, MockDiskManager::new()).unwrap();
+        page_of(&bpm, PageType::NodeStore, 10, 20);
+        page_of(&bpm, PageType::NodeStore, 30, 20);
+        page_of(&bpm, PageType::Relationship, 50, 40);
+
+        let stats = storage_stats(&bpm, PageType::NodeStore);
+        assert_eq!((stats.total_pages, stats.total_records), (2, 40));
+        assert!((stats.avg_record_size - 20.0).abs() < 1e-9);
+        assert!((stats.records_per_page() - 20.0).abs() < 1e-9);
+        // each page's directory (2 bytes of slot count and a slot per record) and records are what's taken
+        let taken = |n: usize| (2 + n * (SLOT_SIZE + 20)) as f64 / (PAGE_SIZE - HEADER_SIZE) as f64;
+        assert!((stats.avg_fill_factor - (taken(10) + taken(30)) / 2.0).abs() < 1e-9);
+
+        let rels = storage_stats(&bpm, PageType::Relationship);
+        assert_eq!((rels.total_pages, rels.total_records), (1, 50));
+        assert!((rels.avg_record_size - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn an_empty_store_has_all_zero_stats() {
+        let bpm = BufferPoolManager::new(4, MockDiskManager::new()).unwrap();
+        let stats = storage_stats(&bpm, PageType::PropertyStore);
+        assert_eq!(stats, StorageStats::default());
+        assert_eq!(stats.records_per_page(), 0.0);
+
+        page_of(&bpm, PageType::PropertyStore, 0, 0);
+        let stats = storage_stats(&bpm, PageType::PropertyStore);
+        assert_eq!((stats.total_pages, stats.total_records, stats.avg_record_size), (1, 0, 0.0));
+    }
+}
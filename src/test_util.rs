@@ -0,0 +1,50 @@
+/*
+* Shared bits for the unit tests.
+* TempPath is a path under the system temp directory that's unique to the test (and the test process) and gets
+* deleted again when it's dropped, together with anything next to it that has the same stem (log files, segment
+* directories, the .checkpoint file a log swap leaves behind).
+*/
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct TempPath {
+    path: PathBuf,
+}
+
+impl TempPath {
+    // name only has to tell tests apart when something is left behind, uniqueness comes from the counter
+    pub(crate) fn new(name: &str) -> Self {
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("ggdb_{}_{}_{}", name, std::process::id(), n));
+        let temp = Self { path };
+        temp.remove();
+        temp
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the path with extension added on, for files that go next to the main one
+    pub(crate) fn with_extension(&self, extension: &str) -> PathBuf {
+        self.path.with_extension(extension)
+    }
+
+    fn remove(&self) {
+        let _ = fs::remove_dir_all(&self.path);
+        let _ = fs::remove_file(&self.path);
+        for extension in ["log", "checkpoint", "dump"] {
+            let _ = fs::remove_file(self.with_extension(extension));
+        }
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
@@ -0,0 +1,3 @@
+pub mod log_device;
+pub mod log_manager;
+pub mod transaction;
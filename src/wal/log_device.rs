@@ -0,0 +1,106 @@
+/*
+* Where the write ahead log keeps its bytes.
+* LogDevice is the interface, LogManager holds a Box<dyn LogDevice> the way the buffer pool holds its DiskManager.
+* FileLogDevice is the real one backed by the log file, MockDiskManager is one too so tests can have the log fail
+* or tear its writes the same way they do it to pages.
+* The log is one append only byte stream, the only things that ever rewrite it are cutting off a partial record
+* when it's opened (truncate) and checkpoints (replace).
+*/
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub trait LogDevice: Send + Sync {
+    // every byte of the log, from the start
+    fn read_all(&self) -> io::Result<Vec<u8>>;
+
+    // adds bytes at the end, they're only durable after sync
+    fn append(&self, bytes: &[u8]) -> io::Result<()>;
+
+    // Durability barrier: blocks until everything appended so far is on stable storage
+    fn sync(&self) -> io::Result<()>;
+
+    // cuts the log down to its first len bytes
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    // Swaps the whole log for bytes, durably. A crash in the middle leaves either the old log or the new one
+    fn replace(&self, bytes: &[u8]) -> io::Result<()>;
+}
+
+// so a test can hand the log a device and still look at it afterwards
+impl<D: LogDevice + ?Sized> LogDevice for Arc<D> {
+    fn read_all(&self) -> io::Result<Vec<u8>> {
+        (**self).read_all()
+    }
+
+    fn append(&self, bytes: &[u8]) -> io::Result<()> {
+        (**self).append(bytes)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        (**self).sync()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        (**self).truncate(len)
+    }
+
+    fn replace(&self, bytes: &[u8]) -> io::Result<()> {
+        (**self).replace(bytes)
+    }
+}
+
+pub struct FileLogDevice {
+    path: PathBuf, // replace writes the new log next to it and renames it over
+    file: Mutex<File>,
+}
+
+impl FileLogDevice {
+    // opens the log at path, creating an empty one if there isn't one yet
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = open_log_file(&path)?;
+        Ok(Self { path, file: Mutex::new(file) })
+    }
+}
+
+impl LogDevice for FileLogDevice {
+    fn read_all(&self) -> io::Result<Vec<u8>> {
+        let mut file = self.file.lock().unwrap();
+        let mut bytes = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn append(&self, bytes: &[u8]) -> io::Result<()> {
+        self.file.lock().unwrap().write_all(bytes)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.file.lock().unwrap().sync_data()
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.file.lock().unwrap().set_len(len)
+    }
+
+    fn replace(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let tmp = self.path.with_extension("checkpoint");
+        let mut new = File::create(&tmp)?;
+        new.write_all(bytes)?;
+        new.sync_all()?;
+        drop(new);
+        fs::rename(&tmp, &self.path)?;
+        *file = open_log_file(&self.path)?;
+        Ok(())
+    }
+}
+
+// append mode, so writes always land at the end whatever read_all did to the position
+fn open_log_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new().read(true).append(true).create(true).open(path)
+}
@@ -0,0 +1,960 @@
+/*
+* Write ahead log.
+* Every change made through a WritePageGuard becomes an Update record with the bytes it replaced (before image) and
+* the bytes it wrote (after image). The record's LSN goes into the page header, and the WAL rule is enforced by the
+* buffer pool: a page only goes to disk after the log has been made durable up to that page's LSN.
+*
+* LSNs are just a counter starting at 1, 0 means a page was never logged.
+* The bytes live on a LogDevice (see log_device), the log file unless the log was opened with_device.
+* Records are appended to an in memory buffer and only written out by commit_sync/flush_log_up_to.
+* Those do group commit: whoever finds no write in progress becomes the leader, takes everything buffered so far and
+* writes + fsyncs it with no lock held, committers that show up meanwhile append their records and wait. When the
+* leader is done the next waiter whose LSN still isn't covered takes over and syncs the whole batch that piled up,
+* so under concurrency one fsync ends up covering many commits.
+*
+* A guard that changed several separate spans of a page logs a Delta instead, one run of before/after bytes per
+* span, so the unchanged bytes between them stay out of the log. Redo and rollback treat it like that many Updates
+* to the same page under one LSN. Which one the pool writes is up to BufferPoolConfig::log_granularity.
+*
+* Recovery is redo only (the repeating history half of ARIES): every durable record whose LSN is newer than the LSN
+* of the page on disk gets its after image applied again. A crash halfway through writing the log leaves a partial
+* record at the end, that one fails its length/crc check and everything from there on is ignored.
+* There is no undo pass and no commit record, so a transaction that was still running at the crash isn't rolled
+* back: its durable records are replayed like everyone else's (see RecoveryPolicy).
+* With OnCorruption::AttemptWalRedo a page that fails its checksum gets rebuilt from the log instead, see
+* rebuild_page.
+*
+* Allocating and freeing pages also get a record of their own (AllocPage / FreePage) next to the Update for the new
+* header, the free page list and next_page_id are only saved to the superblock on flush_all so they'd be stale after
+* a crash otherwise. Recovery plays them back over the list it found on disk, see redo_free_list. They're never
+* part of a transaction, rollback leaves them alone.
+*
+* Records made inside a Transaction carry its id, rollback walks them backwards putting the before images back.
+* The undo itself goes through the normal logged write path, so redo after a crash repeats the undo as well.
+* txn_id 0 means the change wasn't part of a transaction. For every running transaction the log also remembers the
+* newest LSN it wrote to each page, optimistic transactions tell their own changes apart from others' with it.
+*
+* checkpoint() flushes every dirty page, so older records aren't needed for redo anymore. The log gets rewritten
+* without them (keeping whatever running transactions still need for rollback) and ends with a Checkpoint record,
+* recovery skips everything older than the last checkpoint's oldest_active_lsn.
+*
+* On disk record layout:
+* | len: u32 | crc: u32 | kind: u8 | lsn: u64 | ... |
+* Update:     ... | txn_id: u64 | page_id: u64 | offset: u32 | before_len: u32 | after_len: u32 | before | after |
+* Delta:      ... | txn_id: u64 | page_id: u64 | run_count: u32 | run ... |
+*   run:      | offset: u32 | len: u32 | before | after |   (before and after are both len bytes)
+* Checkpoint: ... | oldest_active_lsn: u64 |
+* AllocPage:  ... | page_id: u64 | page_type: u16 |
+* FreePage:   ... | page_id: u64 |
+* len counts everything after itself, crc covers everything after the crc field.
+*/
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+use super::log_device::{FileLogDevice, LogDevice};
+
+use crate::paging::buffer_pool_manager::{BufferError, BufferPoolManager};
+use crate::paging::checksum::crc32;
+use crate::paging::page::{Page, PageType};
+use crate::paging::page_constants::{PageId, PAGE_SIZE};
+
+pub type Lsn = u64;
+pub type TxnId = u64;
+
+// txn_id of changes made outside of any transaction
+pub const NO_TXN: TxnId = 0;
+
+const LEN_SIZE: usize = 4;
+const CRC_SIZE: usize = 4;
+
+const KIND_UPDATE: u8 = 0;
+const KIND_CHECKPOINT: u8 = 1;
+const KIND_ALLOC_PAGE: u8 = 2;
+const KIND_FREE_PAGE: u8 = 3;
+const KIND_DELTA: u8 = 4;
+
+// one changed span of a Delta record, before and after are the same length
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaRun {
+    pub offset: u32,
+    pub before: Vec<u8>,
+    pub after: Vec<u8>,
+}
+
+// (offset, before, after) of one span a change record wrote
+type Change<'a> = (u32, &'a [u8], &'a [u8]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogRecord {
+    // bytes offset..offset + len of page_id changed from before_image to after_image
+    Update {
+        lsn: Lsn,
+        txn_id: TxnId,
+        page_id: PageId,
+        offset: u32,
+        before_image: Vec<u8>,
+        after_image: Vec<u8>,
+    },
+    // all pages were flushed, records older than oldest_active_lsn aren't needed for redo or rollback
+    Checkpoint { lsn: Lsn, oldest_active_lsn: Lsn },
+    // page_id was handed out as a new page of page_type, off the free list or past the end of the file
+    AllocPage { lsn: Lsn, page_id: PageId, page_type: PageType },
+    // page_id was deallocated and went on the free list
+    FreePage { lsn: Lsn, page_id: PageId },
+    // several separate spans of page_id changed at once, an Update per run with a shared LSN
+    Delta { lsn: Lsn, txn_id: TxnId, page_id: PageId, runs: Vec<DeltaRun> },
+}
+
+impl LogRecord {
+    pub fn lsn(&self) -> Lsn {
+        match self {
+            LogRecord::Update { lsn, .. }
+            | LogRecord::Checkpoint { lsn, .. }
+            | LogRecord::AllocPage { lsn, .. }
+            | LogRecord::FreePage { lsn, .. }
+            | LogRecord::Delta { lsn, .. } => *lsn,
+        }
+    }
+
+    // transaction of a page change (Update / Delta), None for the other kinds
+    pub fn txn_id(&self) -> Option<TxnId> {
+        match self {
+            LogRecord::Update { txn_id, .. } | LogRecord::Delta { txn_id, .. } => Some(*txn_id),
+            _ => None,
+        }
+    }
+
+    // the page an Update / Delta touched and every span it wrote there, None for the other kinds
+    fn changes(&self) -> Option<(PageId, Vec<Change<'_>>)> {
+        match self {
+            LogRecord::Update { page_id, offset, before_image, after_image, .. } => {
+                Some((*page_id, vec![(*offset, before_image.as_slice(), after_image.as_slice())]))
+            }
+            LogRecord::Delta { page_id, runs, .. } => {
+                Some((*page_id, runs.iter().map(|r| (r.offset, r.before.as_slice(), r.after.as_slice())).collect()))
+            }
+            _ => None,
+        }
+    }
+}
+
+// first and last LSN a running transaction has written, 0 while it hasn't written anything
+#[derive(Debug, Clone, Default)]
+struct TxnLsns {
+    first: Lsn,
+    last: Lsn,
+    pages: HashMap<PageId, Lsn>, // newest LSN it wrote to each page it changed, see Transaction::validate
+}
+
+struct LogState {
+    buffer: Vec<u8>,  // encoded records that haven't been written out yet
+    next_lsn: Lsn,
+    flushed_lsn: Lsn, // everything up to and including this is durable
+    syncing: bool,    // a group commit leader is writing out a batch right now
+    syncs: u64,       // fsyncs of the log so far
+    next_txn_id: TxnId,
+    active_txns: HashMap<TxnId, TxnLsns>,
+}
+
+pub struct LogManager {
+    state: Mutex<LogState>,
+    // only touched by the group commit leader or with state held and no leader around, see commit_sync
+    device: Box<dyn LogDevice>,
+    synced: Condvar, // a leader finished its batch
+}
+
+impl LogManager {
+    // Opens the log file at path (creating it if needed), see with_device
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_device(FileLogDevice::open(path)?)
+    }
+
+    // Opens the log kept on device and picks up numbering after the last complete record
+    // a partial record at the end (crash in the middle of a write) is cut off
+    pub fn with_device(device: impl LogDevice + 'static) -> io::Result<Self> {
+        let bytes = device.read_all()?;
+        let (records, valid_len) = decode_records(&bytes);
+        if valid_len < bytes.len() {
+            device.truncate(valid_len as u64)?;
+        }
+        let last_lsn = records.last().map_or(0, |r| r.lsn());
+        let last_txn = records.iter().filter_map(LogRecord::txn_id).max().unwrap_or(NO_TXN);
+
+        let state = LogState {
+            buffer: Vec::new(),
+            next_lsn: last_lsn + 1,
+            flushed_lsn: last_lsn,
+            syncing: false,
+            syncs: 0,
+            next_txn_id: last_txn + 1,
+            active_txns: HashMap::new(),
+        };
+        Ok(Self { state: Mutex::new(state), device: Box::new(device), synced: Condvar::new() })
+    }
+
+    // Adds a record for a change to page_id and returns its LSN, it isn't durable until flush_log_up_to
+    pub fn append(&self, txn_id: TxnId, page_id: PageId, offset: u32, before_image: &[u8], after_image: &[u8]) -> Lsn {
+        self.append_record(txn_id, Some(page_id), |lsn| LogRecord::Update {
+            lsn,
+            txn_id,
+            page_id,
+            offset,
+            before_image: before_image.to_vec(),
+            after_image: after_image.to_vec(),
+        })
+    }
+
+    // Same as append for a change made of several separate runs, logged as one Delta record
+    pub fn append_delta(&self, txn_id: TxnId, page_id: PageId, runs: Vec<DeltaRun>) -> Lsn {
+        self.append_record(txn_id, Some(page_id), |lsn| LogRecord::Delta { lsn, txn_id, page_id, runs })
+    }
+
+    // Adds an AllocPage record, the buffer pool logs one for every page it hands out
+    pub fn log_alloc(&self, page_id: PageId, page_type: PageType) -> Lsn {
+        self.append_record(NO_TXN, None, |lsn| LogRecord::AllocPage { lsn, page_id, page_type })
+    }
+
+    // Adds a FreePage record, the buffer pool logs one for every page it deallocates
+    pub fn log_free(&self, page_id: PageId) -> Lsn {
+        self.append_record(NO_TXN, None, |lsn| LogRecord::FreePage { lsn, page_id })
+    }
+
+    // hands record its LSN and buffers it, counting it towards txn_id if that's a running transaction
+    fn append_record(&self, txn_id: TxnId, page_id: Option<PageId>, record: impl FnOnce(Lsn) -> LogRecord) -> Lsn {
+        let mut state = self.state.lock().unwrap();
+        let lsn = state.next_lsn;
+        state.next_lsn += 1;
+        if let Some(txn) = state.active_txns.get_mut(&txn_id) {
+            if txn.first == 0 {
+                txn.first = lsn;
+            }
+            txn.last = lsn;
+            if let Some(page_id) = page_id {
+                txn.pages.insert(page_id, lsn);
+            }
+        }
+        encode_record(&record(lsn), &mut state.buffer);
+        lsn
+    }
+
+    // Makes sure every record up to lsn is on stable storage, what the buffer pool calls before writing a page back
+    pub fn flush_log_up_to(&self, lsn: Lsn) -> io::Result<()> {
+        self.commit_sync(lsn)
+    }
+
+    // Blocks until every record up to lsn is durable, sharing the fsync with whoever else is committing (see the
+    // top of the file). LSNs that haven't been handed out yet are treated as the last one that has
+    pub fn commit_sync(&self, lsn: Lsn) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let lsn = lsn.min(state.next_lsn - 1);
+        loop {
+            if lsn <= state.flushed_lsn {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state).unwrap();
+        }
+
+        // we're the leader, the batch is everything buffered up to now. The buffer can't be written partially
+        // without tracking record boundaries, so it always goes out whole
+        state.syncing = true;
+        let mut batch = std::mem::take(&mut state.buffer);
+        let batch_end = state.next_lsn - 1;
+        drop(state);
+
+        let result = self.write_batch(&batch);
+
+        let mut state = self.state.lock().unwrap();
+        state.syncing = false;
+        state.syncs += 1;
+        match &result {
+            Ok(()) => state.flushed_lsn = state.flushed_lsn.max(batch_end),
+            Err(_) => {
+                // put the batch back in front of whatever got appended meanwhile so the next leader retries it
+                batch.append(&mut state.buffer);
+                state.buffer = batch;
+            }
+        }
+        self.synced.notify_all();
+        result
+    }
+
+    fn write_batch(&self, batch: &[u8]) -> io::Result<()> {
+        self.device.append(batch)?;
+        self.device.sync()
+    }
+
+    // takes state once no group commit leader is writing, so the device can be used directly
+    fn lock_idle(&self) -> MutexGuard<'_, LogState> {
+        let state = self.state.lock().unwrap();
+        self.synced.wait_while(state, |state| state.syncing).unwrap()
+    }
+
+    // writes out the whole buffer with state already held, check lock_idle for why that's safe
+    fn flush_locked(&self, state: &mut LogState) -> io::Result<()> {
+        self.write_batch(&state.buffer)?;
+        state.syncs += 1;
+        state.buffer.clear();
+        state.flushed_lsn = state.next_lsn - 1;
+        Ok(())
+    }
+
+    fn read_all(&self) -> io::Result<Vec<LogRecord>> {
+        Ok(decode_records(&self.device.read_all()?).0)
+    }
+
+    // how many times the log has been fsynced, fewer than the number of commits when group commit kicks in
+    pub fn sync_count(&self) -> u64 {
+        self.state.lock().unwrap().syncs
+    }
+
+    // highest LSN that is known to be durable
+    pub fn flushed_lsn(&self) -> Lsn {
+        self.state.lock().unwrap().flushed_lsn
+    }
+
+    // LSN the next append will get
+    pub fn next_lsn(&self) -> Lsn {
+        self.state.lock().unwrap().next_lsn
+    }
+
+    // hands out a new transaction id and starts tracking it
+    pub fn begin_txn(&self) -> TxnId {
+        let mut state = self.state.lock().unwrap();
+        let txn_id = state.next_txn_id;
+        state.next_txn_id += 1;
+        state.active_txns.insert(txn_id, TxnLsns::default());
+        txn_id
+    }
+
+    // newest LSN running transaction txn_id stamped into page_id, None if it hasn't changed the page
+    pub(crate) fn txn_page_lsn(&self, txn_id: TxnId, page_id: PageId) -> Option<Lsn> {
+        self.state.lock().unwrap().active_txns.get(&txn_id)?.pages.get(&page_id).copied()
+    }
+
+    // some running transaction other than txn_id that has changed page_id, its changes can still be rolled back
+    pub(crate) fn other_writer(&self, txn_id: TxnId, page_id: PageId) -> Option<TxnId> {
+        let state = self.state.lock().unwrap();
+        state.active_txns.iter().find(|(id, t)| **id != txn_id && t.pages.contains_key(&page_id)).map(|(id, _)| *id)
+    }
+
+    // whether some running transaction has changed page_id, rolling it back would write its before images there
+    pub(crate) fn has_uncommitted_changes(&self, page_id: PageId) -> bool {
+        self.state.lock().unwrap().active_txns.values().any(|t| t.pages.contains_key(&page_id))
+    }
+
+    // Makes txn_id's changes durable and stops tracking it
+    pub fn commit(&self, txn_id: TxnId) -> io::Result<()> {
+        let last = self.state.lock().unwrap().active_txns.get(&txn_id).map(|t| t.last);
+        if let Some(last) = last {
+            self.commit_sync(last)?;
+        }
+        self.state.lock().unwrap().active_txns.remove(&txn_id);
+        Ok(())
+    }
+
+    // Undoes every change txn_id made, newest first, by writing the before images back through bpm
+    pub fn rollback(&self, bpm: &BufferPoolManager, txn_id: TxnId) -> io::Result<()> {
+        // the transaction's records might still be in the buffer, records() only sees durable ones
+        let last = self.state.lock().unwrap().active_txns.get(&txn_id).map_or(Lsn::MAX, |t| t.last);
+        self.flush_log_up_to(last)?;
+
+        for record in self.records()?.iter().rev() {
+            if record.txn_id() != Some(txn_id) {
+                continue;
+            }
+            let Some((page_id, changes)) = record.changes() else { continue };
+            let mut page = bpm.fetch_page_write(page_id).map_err(into_io)?;
+            for (offset, before, _) in changes.into_iter().rev() {
+                page.apply_delta(offset, before).map_err(|_| past_end(record))?;
+            }
+        }
+        self.state.lock().unwrap().active_txns.remove(&txn_id);
+        Ok(())
+    }
+
+    // Redo pass, replays every logged change the data file is missing, committed or not
+    // BufferPoolManager::with_config runs this when the pool is opened with a log file
+    pub fn recover(&self, bpm: &BufferPoolManager) -> io::Result<()> {
+        let records = self.records()?;
+        let start = redo_start(&records);
+
+        for record in records.iter().filter(|r| r.lsn() >= start) {
+            let Some((page_id, changes)) = record.changes() else { continue };
+            bpm.extend_to(page_id);
+            let mut page = bpm.fetch_page_write_unlogged(page_id).map_err(into_io)?;
+            if page.get_lsn() >= record.lsn() {
+                continue; // the page on disk already has this change
+            }
+            if page.page_type() == Ok(PageType::Quarantined) {
+                continue; // stand in for a corrupt page, replaying onto it would write over the bad copy on disk
+            }
+            for (offset, _, after) in changes {
+                page.apply_delta(offset, after).map_err(|_| past_end(record))?;
+            }
+            page.set_lsn(record.lsn());
+        }
+        Ok(())
+    }
+
+    // Rebuilds page_id from nothing out of the log, for a page whose copy on disk is corrupt (OnCorruption).
+    // Replays every change to it starting at its last AllocPage or full page image, None when the log doesn't
+    // have either anymore (the page is older than what checkpoints kept)
+    pub(crate) fn rebuild_page(&self, page_id: PageId) -> io::Result<Option<Page>> {
+        self.flush_log_up_to(Lsn::MAX)?;
+        let records = self.records()?;
+        let start = records.iter().rposition(|r| match r {
+            LogRecord::AllocPage { page_id: id, .. } => *id == page_id,
+            LogRecord::Update { page_id: id, offset: 0, after_image, .. } => {
+                *id == page_id && after_image.len() == PAGE_SIZE
+            }
+            _ => false,
+        });
+        let Some(start) = start else { return Ok(None) };
+
+        // starts out as a never written page would read, the AllocPage or full image puts a real header in
+        let mut page = Page::from_bytes([0; PAGE_SIZE]).expect("an all zero page passes validate");
+        for record in &records[start..] {
+            let Some((id, changes)) = record.changes() else { continue };
+            if id != page_id {
+                continue;
+            }
+            for (offset, _, after) in changes {
+                page.apply_delta(offset, after).map_err(|_| past_end(record))?;
+            }
+            page.set_lsn(record.lsn());
+        }
+        Ok(Some(page))
+    }
+
+    // Plays the AllocPage / FreePage records recovery would redo over free_pages (the free page list as the
+    // superblock had it, top of the stack last), so it ends up as it was at the crash. A page's last record wins:
+    // freed pages get pushed if they aren't on the list yet and allocated ones taken off wherever they are.
+    // Also makes sure next_page_id covers every page that was ever handed out
+    pub(crate) fn redo_free_list(&self, bpm: &BufferPoolManager, free_pages: &mut Vec<PageId>) -> io::Result<()> {
+        let records = self.records()?;
+        let start = redo_start(&records);
+        for record in records.iter().filter(|r| r.lsn() >= start) {
+            match record {
+                LogRecord::AllocPage { page_id, .. } => {
+                    bpm.extend_to(*page_id);
+                    free_pages.retain(|&free| free != *page_id);
+                }
+                LogRecord::FreePage { page_id, .. } => {
+                    bpm.extend_to(*page_id);
+                    if !free_pages.contains(page_id) {
+                        free_pages.push(*page_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes every dirty page in bpm and shrinks the log down to what running transactions still need
+    // returns the LSN of the checkpoint record
+    pub fn checkpoint(&self, bpm: &BufferPoolManager) -> io::Result<Lsn> {
+        // anything logged before this point is on disk once the data file is synced, so the log can let go of it
+        let begin = self.next_lsn();
+        bpm.flush_all_and_sync()?;
+
+        let mut state = self.lock_idle();
+        self.flush_locked(&mut state)?;
+        let oldest_active_lsn = state
+            .active_txns
+            .values()
+            .filter(|t| t.first != 0)
+            .map(|t| t.first)
+            .fold(begin, Lsn::min);
+
+        let lsn = state.next_lsn;
+        state.next_lsn += 1;
+
+        let mut bytes = Vec::new();
+        for record in self.read_all()?.iter().filter(|r| r.lsn() >= oldest_active_lsn) {
+            encode_record(record, &mut bytes);
+        }
+        encode_record(&LogRecord::Checkpoint { lsn, oldest_active_lsn }, &mut bytes);
+
+        // swapped in whole, so a crash leaves one or the other intact
+        self.device.replace(&bytes)?;
+        state.flushed_lsn = lsn;
+        Ok(lsn)
+    }
+
+    // every durable record in LSN order (records still sitting in the buffer aren't included)
+    pub fn records(&self) -> io::Result<Vec<LogRecord>> {
+        let _state = self.lock_idle();
+        self.read_all()
+    }
+}
+
+// everything before the last checkpoint's oldest_active_lsn already made it to the data file
+fn redo_start(records: &[LogRecord]) -> Lsn {
+    records
+        .iter()
+        .rev()
+        .find_map(|r| match r {
+            LogRecord::Checkpoint { oldest_active_lsn, .. } => Some(*oldest_active_lsn),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+fn encode_record(record: &LogRecord, out: &mut Vec<u8>) {
+    let mut body = Vec::new();
+    match record {
+        LogRecord::Update { lsn, txn_id, page_id, offset, before_image, after_image } => {
+            body.push(KIND_UPDATE);
+            body.extend_from_slice(&lsn.to_le_bytes());
+            body.extend_from_slice(&txn_id.to_le_bytes());
+            body.extend_from_slice(&page_id.to_le_bytes());
+            body.extend_from_slice(&offset.to_le_bytes());
+            body.extend_from_slice(&(before_image.len() as u32).to_le_bytes());
+            body.extend_from_slice(&(after_image.len() as u32).to_le_bytes());
+            body.extend_from_slice(before_image);
+            body.extend_from_slice(after_image);
+        }
+        LogRecord::Checkpoint { lsn, oldest_active_lsn } => {
+            body.push(KIND_CHECKPOINT);
+            body.extend_from_slice(&lsn.to_le_bytes());
+            body.extend_from_slice(&oldest_active_lsn.to_le_bytes());
+        }
+        LogRecord::AllocPage { lsn, page_id, page_type } => {
+            body.push(KIND_ALLOC_PAGE);
+            body.extend_from_slice(&lsn.to_le_bytes());
+            body.extend_from_slice(&page_id.to_le_bytes());
+            body.extend_from_slice(&(*page_type as u16).to_le_bytes());
+        }
+        LogRecord::FreePage { lsn, page_id } => {
+            body.push(KIND_FREE_PAGE);
+            body.extend_from_slice(&lsn.to_le_bytes());
+            body.extend_from_slice(&page_id.to_le_bytes());
+        }
+        LogRecord::Delta { lsn, txn_id, page_id, runs } => {
+            body.push(KIND_DELTA);
+            body.extend_from_slice(&lsn.to_le_bytes());
+            body.extend_from_slice(&txn_id.to_le_bytes());
+            body.extend_from_slice(&page_id.to_le_bytes());
+            body.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+            for run in runs {
+                body.extend_from_slice(&run.offset.to_le_bytes());
+                body.extend_from_slice(&(run.after.len() as u32).to_le_bytes());
+                body.extend_from_slice(&run.before);
+                body.extend_from_slice(&run.after);
+            }
+        }
+    }
+
+    out.extend_from_slice(&((CRC_SIZE + body.len()) as u32).to_le_bytes());
+    out.extend_from_slice(&crc32(&[&body]).to_le_bytes());
+    out.extend_from_slice(&body);
+}
+
+// decodes records until the end of bytes or the first one that is cut off or fails its crc
+// returns the records and how many bytes of valid log there were
+fn decode_records(bytes: &[u8]) -> (Vec<LogRecord>, usize) {
+    let mut records = Vec::new();
+    let mut at = 0;
+    while let Some((record, len)) = decode_record(&bytes[at..]) {
+        records.push(record);
+        at += len;
+    }
+    (records, at)
+}
+
+fn decode_record(bytes: &[u8]) -> Option<(LogRecord, usize)> {
+    let u32_at = |b: &[u8], at: usize| Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().unwrap()));
+    let u64_at = |b: &[u8], at: usize| Some(u64::from_le_bytes(b.get(at..at + 8)?.try_into().unwrap()));
+
+    let len = u32_at(bytes, 0)? as usize;
+    let record = bytes.get(LEN_SIZE..LEN_SIZE + len)?;
+    let body = record.get(CRC_SIZE..)?;
+    if crc32(&[body]) != u32_at(record, 0)? {
+        return None;
+    }
+
+    let lsn = u64_at(body, 1)?;
+    let record = match *body.first()? {
+        KIND_UPDATE => {
+            let before_len = u32_at(body, 29)? as usize;
+            let after_len = u32_at(body, 33)? as usize;
+            let images = body.get(37..)?;
+            if images.len() != before_len + after_len {
+                return None;
+            }
+            LogRecord::Update {
+                lsn,
+                txn_id: u64_at(body, 9)?,
+                page_id: u64_at(body, 17)?,
+                offset: u32_at(body, 25)?,
+                before_image: images[..before_len].to_vec(),
+                after_image: images[before_len..].to_vec(),
+            }
+        }
+        KIND_CHECKPOINT => LogRecord::Checkpoint { lsn, oldest_active_lsn: u64_at(body, 9)? },
+        KIND_ALLOC_PAGE => {
+            let page_type = u16::from_le_bytes(body.get(17..19)?.try_into().unwrap());
+            LogRecord::AllocPage { lsn, page_id: u64_at(body, 9)?, page_type: PageType::try_from(page_type).ok()? }
+        }
+        KIND_FREE_PAGE => LogRecord::FreePage { lsn, page_id: u64_at(body, 9)? },
+        KIND_DELTA => {
+            let count = u32_at(body, 25)? as usize;
+            let mut runs = Vec::new();
+            let mut at = 29;
+            for _ in 0..count {
+                let offset = u32_at(body, at)?;
+                let len = u32_at(body, at + 4)? as usize;
+                let images = body.get(at + 8..at + 8 + 2 * len)?;
+                runs.push(DeltaRun { offset, before: images[..len].to_vec(), after: images[len..].to_vec() });
+                at += 8 + 2 * len;
+            }
+            if at != body.len() {
+                return None;
+            }
+            LogRecord::Delta { lsn, txn_id: u64_at(body, 9)?, page_id: u64_at(body, 17)?, runs }
+        }
+        _ => return None,
+    };
+    Some((record, LEN_SIZE + len))
+}
+
+pub(crate) fn into_io(e: BufferError) -> io::Error {
+    match e {
+        BufferError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+fn past_end(record: &LogRecord) -> io::Error {
+    let page_id = match record {
+        LogRecord::Update { page_id, .. }
+        | LogRecord::AllocPage { page_id, .. }
+        | LogRecord::FreePage { page_id, .. }
+        | LogRecord::Delta { page_id, .. } => *page_id,
+        LogRecord::Checkpoint { .. } => 0,
+    };
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("log record {} points past the end of page {}", record.lsn(), page_id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::page_constants::HEADER_SIZE;
+
+    #[test]
+    fn records_get_increasing_lsns_and_are_durable_once_flushed() {
+        let device = Arc::new(MockDiskManager::new());
+        let log = LogManager::with_device(device.clone()).unwrap();
+        let first = log.append(NO_TXN, 1, 40, b"old", b"new");
+        let second = log.append(NO_TXN, 2, 40, b"a", b"b");
+        assert_eq!((first, second), (1, 2));
+        assert!(log.records().unwrap().is_empty());
+
+        log.flush_log_up_to(second).unwrap();
+        assert_eq!(log.flushed_lsn(), 2);
+        let records = log.records().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            LogRecord::Update {
+                lsn: 1,
+                txn_id: NO_TXN,
+                page_id: 1,
+                offset: 40,
+                before_image: b"old".to_vec(),
+                after_image: b"new".to_vec(),
+            }
+        );
+    }
+
+    #[test]
+    fn reopening_picks_up_after_the_last_record() {
+        let device = Arc::new(MockDiskManager::new());
+        let log = LogManager::with_device(device.clone()).unwrap();
+        log.append(NO_TXN, 1, 40, b"x", b"y");
+        log.append(NO_TXN, 1, 40, b"y", b"z");
+        log.flush_log_up_to(Lsn::MAX).unwrap();
+        drop(log);
+
+        let log = LogManager::with_device(device).unwrap();
+        assert_eq!(log.next_lsn(), 3);
+        assert_eq!(log.flushed_lsn(), 2);
+    }
+
+    #[test]
+    fn a_torn_record_at_the_end_is_cut_off() {
+        let device = Arc::new(MockDiskManager::new());
+        let log = LogManager::with_device(device.clone()).unwrap();
+        log.append(NO_TXN, 1, 40, b"x", b"y");
+        log.flush_log_up_to(Lsn::MAX).unwrap();
+        log.append(NO_TXN, 1, 40, b"y", b"z");
+        device.tear_next_write(10);
+        log.flush_log_up_to(Lsn::MAX).unwrap();
+        drop(log);
+
+        let log = LogManager::with_device(device.clone()).unwrap();
+        assert_eq!(log.records().unwrap().len(), 1);
+        assert_eq!(log.next_lsn(), 2);
+    }
+
+    #[test]
+    fn pages_only_reach_disk_after_their_log_records() {
+        let disk = Arc::new(MockDiskManager::new());
+        let bpm = BufferPoolManager::with_log(4, disk.clone(), LogManager::with_device(MockDiskManager::new()).unwrap())
+            .unwrap();
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 5).unwrap();
+        let lsn = bpm.fetch_page(page_id).unwrap().get_lsn();
+        assert!(lsn > 0);
+
+        let log = bpm.log_manager().unwrap();
+        assert!(log.flushed_lsn() < lsn);
+        bpm.flush_page(page_id).unwrap();
+        assert!(log.flushed_lsn() >= lsn);
+        assert_eq!(Page::from_bytes(disk.raw_page(page_id).unwrap()).unwrap().get_lsn(), lsn);
+    }
+
+    // pool over disk logging to log, recovering from whatever log already holds
+    fn logged_pool(disk: &Arc<MockDiskManager>, log: &Arc<MockDiskManager>) -> BufferPoolManager {
+        BufferPoolManager::with_log(4, disk.clone(), LogManager::with_device(log.clone()).unwrap()).unwrap()
+    }
+
+    // the pool goes away without writing anything back, like the process dying
+    fn crash(bpm: BufferPoolManager) {
+        std::mem::forget(bpm);
+    }
+
+    #[test]
+    fn redo_brings_back_logged_changes_the_data_file_missed() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        bpm.flush_all().unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 77).unwrap();
+        bpm.log_manager().unwrap().flush_log_up_to(Lsn::MAX).unwrap();
+        crash(bpm);
+
+        let on_disk = Page::from_bytes(disk.raw_page(page_id).unwrap()).unwrap();
+        assert_eq!(on_disk.read_u32_at(HEADER_SIZE as u32), Ok(0));
+
+        let bpm = logged_pool(&disk, &log);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(77));
+    }
+
+    #[test]
+    fn changes_that_never_made_it_into_the_log_are_lost() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        bpm.flush_all().unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 77).unwrap();
+        crash(bpm);
+
+        let bpm = logged_pool(&disk, &log);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(0));
+    }
+
+    #[test]
+    fn redo_skips_records_the_page_already_has() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 1).unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 2).unwrap();
+        bpm.flush_all().unwrap();
+        // written straight to disk behind the log's back, replaying the records above would undo it
+        let mut page = Page::from_bytes(disk.raw_page(page_id).unwrap()).unwrap();
+        page.write_u32_at(HEADER_SIZE as u32 + 4, 9).unwrap();
+        let mut bytes = page.to_owned_bytes();
+        crate::paging::page::stamp_checksum(&mut bytes);
+        disk.set_raw_page(page_id, bytes);
+        crash(bpm);
+
+        let bpm = logged_pool(&disk, &log);
+        let page = bpm.fetch_page(page_id).unwrap();
+        assert_eq!(page.read_u32_at(HEADER_SIZE as u32), Ok(2));
+        assert_eq!(page.read_u32_at(HEADER_SIZE as u32 + 4), Ok(9));
+    }
+
+    #[test]
+    fn checkpoint_drops_records_that_are_on_disk() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        for i in 0..10 {
+            bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, i).unwrap();
+        }
+        let manager = bpm.log_manager().unwrap();
+        manager.flush_log_up_to(Lsn::MAX).unwrap();
+        let before = log.read_all().unwrap().len();
+
+        let lsn = manager.checkpoint(&bpm).unwrap();
+        let records = manager.records().unwrap();
+        // flush_all saving the superblock logs a change of its own, only that can be left
+        assert!(matches!(records.last(), Some(LogRecord::Checkpoint { lsn: l, .. }) if *l == lsn));
+        assert!(records.iter().all(|r| r.changes().is_none_or(|(id, _)| id != page_id)));
+        assert!(log.read_all().unwrap().len() < before);
+        assert!(bpm.dirty_page_ids().is_empty());
+    }
+
+    #[test]
+    fn checkpoint_keeps_what_running_transactions_need() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        let txn = bpm.begin_txn().unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 5).unwrap();
+
+        bpm.log_manager().unwrap().checkpoint(&bpm).unwrap();
+        txn.abort().unwrap();
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(0));
+    }
+
+    #[test]
+    fn recovery_after_a_checkpoint_replays_what_came_later() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        bpm.log_manager().unwrap().checkpoint(&bpm).unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(HEADER_SIZE as u32, 8).unwrap();
+        bpm.log_manager().unwrap().flush_log_up_to(Lsn::MAX).unwrap();
+        crash(bpm);
+
+        let bpm = logged_pool(&disk, &log);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(HEADER_SIZE as u32), Ok(8));
+    }
+
+    // a log device whose fsync takes a while, so committers pile up behind it like on a real disk
+    struct SlowSync(MockDiskManager);
+
+    impl LogDevice for SlowSync {
+        fn read_all(&self) -> io::Result<Vec<u8>> {
+            self.0.read_all()
+        }
+        fn append(&self, bytes: &[u8]) -> io::Result<()> {
+            self.0.append(bytes)
+        }
+        fn sync(&self) -> io::Result<()> {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            LogDevice::sync(&self.0)
+        }
+        fn truncate(&self, len: u64) -> io::Result<()> {
+            self.0.truncate(len)
+        }
+        fn replace(&self, bytes: &[u8]) -> io::Result<()> {
+            self.0.replace(bytes)
+        }
+    }
+
+    #[test]
+    fn concurrent_commits_share_syncs_and_only_return_once_durable() {
+        let log = Arc::new(LogManager::with_device(SlowSync(MockDiskManager::new())).unwrap());
+        let (threads, commits) = (16, 20);
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let log = log.clone();
+                std::thread::spawn(move || {
+                    for i in 0..commits {
+                        let lsn = log.append(NO_TXN, t, i, &[0], &[1]);
+                        log.commit_sync(lsn).unwrap();
+                        assert!(log.flushed_lsn() >= lsn);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(log.flushed_lsn(), (threads * commits as u64) as Lsn);
+        assert!(log.sync_count() < threads * commits as u64, "{} syncs", log.sync_count());
+        assert_eq!(log.read_all().unwrap().len(), (threads * commits as u64) as usize);
+    }
+
+    #[test]
+    fn commit_sync_of_an_already_durable_lsn_doesnt_sync_again() {
+        let log = LogManager::with_device(MockDiskManager::new()).unwrap();
+        let lsn = log.append(NO_TXN, 1, 0, &[0], &[1]);
+        log.commit_sync(lsn).unwrap();
+        let syncs = log.sync_count();
+        log.commit_sync(lsn).unwrap();
+        log.commit_sync(lsn + 100).unwrap();
+        assert_eq!(log.sync_count(), syncs);
+    }
+
+    #[test]
+    fn allocating_and_freeing_pages_get_records_of_their_own() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let page_id = bpm.allocate_page_of_type(PageType::Relationship).unwrap().page_id;
+        assert!(bpm.deallocate_page(page_id).unwrap());
+        let log = bpm.log_manager().unwrap();
+        log.flush_log_up_to(Lsn::MAX).unwrap();
+
+        let records = log.records().unwrap();
+        assert!(records.iter().any(|r| matches!(
+            r,
+            LogRecord::AllocPage { page_id: id, page_type: PageType::Relationship, .. } if *id == page_id
+        )));
+        assert!(records.iter().any(|r| matches!(r, LogRecord::FreePage { page_id: id, .. } if *id == page_id)));
+    }
+
+    #[test]
+    fn a_crash_right_after_an_allocation_doesnt_hand_the_page_out_twice() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let ids: Vec<PageId> = (0..3).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        assert!(bpm.deallocate_page(ids[0]).unwrap());
+        assert!(bpm.deallocate_page(ids[1]).unwrap());
+        bpm.flush_all().unwrap();
+
+        // the superblock still has both on the free list, only the log knows one of them went out again
+        let taken = bpm.allocate_page().unwrap().page_id;
+        assert_eq!(taken, ids[1]);
+        bpm.log_manager().unwrap().flush_log_up_to(Lsn::MAX).unwrap();
+        crash(bpm);
+
+        let bpm = logged_pool(&disk, &log);
+        let next: Vec<PageId> = (0..2).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        assert_eq!(next, vec![ids[0], ids[2] + 1]);
+    }
+
+    #[test]
+    fn a_crash_after_freeing_a_page_still_puts_it_on_the_list() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        let ids: Vec<PageId> = (0..2).map(|_| bpm.allocate_page().unwrap().page_id).collect();
+        bpm.flush_all().unwrap();
+        assert!(bpm.deallocate_page(ids[0]).unwrap());
+        bpm.log_manager().unwrap().flush_log_up_to(Lsn::MAX).unwrap();
+        crash(bpm);
+
+        let bpm = logged_pool(&disk, &log);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[0]);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, ids[1] + 1);
+    }
+
+    #[test]
+    fn pages_allocated_after_the_last_flush_all_arent_handed_out_again() {
+        let (disk, log) = (Arc::new(MockDiskManager::new()), Arc::new(MockDiskManager::new()));
+        let bpm = logged_pool(&disk, &log);
+        bpm.flush_all().unwrap();
+        let page_id = bpm.allocate_page().unwrap().page_id;
+        bpm.log_manager().unwrap().flush_log_up_to(Lsn::MAX).unwrap();
+        crash(bpm);
+
+        let bpm = logged_pool(&disk, &log);
+        assert_eq!(bpm.allocate_page().unwrap().page_id, page_id + 1);
+    }
+}
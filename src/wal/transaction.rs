@@ -0,0 +1,279 @@
+/*
+* Minimal transaction handle.
+* Pages modified through Transaction::fetch_page_write get logged under the transaction's id, so abort can undo
+* them from the log. Only undo on abort is covered, there is no locking between transactions yet and
+* recovery doesn't roll back transactions that never finished before a crash.
+* A transaction that is dropped without commit or abort gets aborted, if that fails the error is kept for
+* BufferPoolManager::take_deferred_errors.
+*
+* Optimistic transactions (BufferPoolManager::begin_optimistic_txn) check for conflicts instead. The page LSN works
+* as the page's version, every logged change stamps a new one. The first read of a page through fetch_page_read
+* remembers its LSN (the read set), and commit validates that none of them moved since, other than by the
+* transaction's own writes. A page another running transaction has changed can't be read or written at all, those
+* changes might still get rolled back. Conflicts come back as BufferError::Conflict, and commit aborts.
+* Pages written by plain writes or plain transactions still count as changed, they just don't get checked the
+* other way around.
+*/
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+use crate::paging::buffer_pool_manager::{BufferError, BufferPoolManager, PageFrameRef, WritePageGuard};
+use crate::paging::config::SyncMode;
+use crate::paging::page_constants::PageId;
+use super::log_manager::{into_io, LogManager, Lsn, TxnId};
+
+pub struct Transaction<'a> {
+    bpm: &'a BufferPoolManager,
+    log: &'a LogManager,
+    id: TxnId,
+    finished: bool, // committed or aborted, Drop has nothing left to do
+    optimistic: bool,
+    read_set: Mutex<HashMap<PageId, Lsn>>, // page LSN from when each page was first read
+}
+
+impl<'a> Transaction<'a> {
+    // use BufferPoolManager::begin_txn / begin_optimistic_txn
+    pub(crate) fn new(bpm: &'a BufferPoolManager, log: &'a LogManager, optimistic: bool) -> Self {
+        Self { bpm, log, id: log.begin_txn(), finished: false, optimistic, read_set: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn is_optimistic(&self) -> bool {
+        self.optimistic
+    }
+
+    pub fn id(&self) -> TxnId {
+        self.id
+    }
+
+    // same as BufferPoolManager::fetch_page, the page goes into the read set the first time
+    pub fn fetch_page_read(&self, page_id: PageId) -> Result<PageFrameRef<'a>, BufferError> {
+        let page = self.bpm.fetch_page(page_id)?;
+        self.check_others(page_id)?;
+        self.read_set.lock().unwrap().entry(page_id).or_insert(page.get_lsn());
+        Ok(page)
+    }
+
+    // same as BufferPoolManager::fetch_page_write, but the changes belong to this transaction
+    // optimistic ones fail with Conflict if the page changed since they read it
+    pub fn fetch_page_write(&self, page_id: PageId) -> Result<WritePageGuard<'a>, BufferError> {
+        let page = self.bpm.fetch_page_write_in(page_id, self.id)?;
+        // checked under the latch, whoever gets it second sees the first one's change
+        self.check_others(page_id)?;
+        if self.optimistic && !self.unchanged(page_id, page.get_lsn()) {
+            return Err(BufferError::Conflict(page_id));
+        }
+        Ok(page)
+    }
+
+    /*
+    * Checks that no page in the read set has changed since it was read, except by this transaction.
+    * Plain transactions can call it too, it just isn't done for them on commit. Pages changed between this and
+    * the end of commit go unnoticed, validate and commit aren't atomic.
+    */
+    pub fn validate(&self) -> Result<(), BufferError> {
+        let pages: Vec<PageId> = self.read_set.lock().unwrap().keys().copied().collect();
+        for page_id in pages {
+            let lsn = self.bpm.fetch_page(page_id)?.get_lsn();
+            if !self.unchanged(page_id, lsn) {
+                return Err(BufferError::Conflict(page_id));
+            }
+        }
+        Ok(())
+    }
+
+    // page_id's LSN is still the one it was read with, or the last one this transaction wrote itself
+    // pages that were never read are always unchanged
+    fn unchanged(&self, page_id: PageId, lsn: Lsn) -> bool {
+        match self.read_set.lock().unwrap().get(&page_id) {
+            Some(&read_lsn) if read_lsn != lsn => self.log.txn_page_lsn(self.id, page_id) == Some(lsn),
+            _ => true,
+        }
+    }
+
+    // optimistic transactions stay away from pages other running transactions have changed
+    fn check_others(&self, page_id: PageId) -> Result<(), BufferError> {
+        if self.optimistic && self.log.other_writer(self.id, page_id).is_some() {
+            return Err(BufferError::Conflict(page_id));
+        }
+        Ok(())
+    }
+
+    // flushes the log so the transaction's changes survive a crash, under RecoveryPolicy::NoStealForce
+    // the changed pages themselves get written to the data file too. With SyncMode::PerCommit the data file
+    // is synced before this returns. An optimistic transaction gets validated first and aborted on a conflict, the error is the Conflict
+    pub fn commit(mut self) -> io::Result<()> {
+        if self.optimistic
+            && let Err(e) = self.validate()
+        {
+            self.abort()?;
+            return Err(into_io(e));
+        }
+        // if the log can't be made durable the transaction isn't committed, Drop rolls it back and releases it
+        self.log.commit(self.id)?;
+        self.finished = true;
+        self.bpm.end_txn(self.id, true)?;
+        if self.bpm.sync_mode() == SyncMode::PerCommit {
+            self.bpm.sync()?;
+        }
+        Ok(())
+    }
+
+    // puts every page the transaction touched back the way it was
+    pub fn abort(mut self) -> io::Result<()> {
+        self.finished = true;
+        let result = self.log.rollback(self.bpm, self.id);
+        self.bpm.end_txn(self.id, false)?;
+        result
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        // nobody to return these to, they wait in the pool for take_deferred_errors
+        if let Err(e) = self.log.rollback(self.bpm, self.id) {
+            let e = io::Error::new(e.kind(), format!("failed to roll back transaction {}: {e}", self.id));
+            self.bpm.defer_error(e.into());
+        }
+        if let Err(e) = self.bpm.end_txn(self.id, false) {
+            let e = io::Error::new(e.kind(), format!("failed to release pages of transaction {}: {e}", self.id));
+            self.bpm.defer_error(e.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::file_manager::mock_disk_manager::MockDiskManager;
+    use crate::paging::page::PageType;
+    use crate::paging::page_constants::HEADER_SIZE;
+
+    const AT: u32 = HEADER_SIZE as u32;
+
+    // a logged pool with one page holding 1 at AT
+    fn setup() -> (BufferPoolManager, PageId) {
+        let log = LogManager::with_device(MockDiskManager::new()).unwrap();
+        let bpm = BufferPoolManager::with_log(4, Arc::new(MockDiskManager::new()), log).unwrap();
+        let page_id = {
+            let mut page = bpm.allocate_page_of_type(PageType::Overflow).unwrap();
+            page.write_u32_at(AT, 1).unwrap();
+            page.page_id
+        };
+        (bpm, page_id)
+    }
+
+    fn value(bpm: &BufferPoolManager, page_id: PageId) -> u32 {
+        bpm.fetch_page(page_id).unwrap().read_u32_at(AT).unwrap()
+    }
+
+    #[test]
+    fn abort_puts_the_before_images_back() {
+        let (bpm, page_id) = setup();
+        let txn = bpm.begin_txn().unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(AT, 2).unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(AT + 4, 3).unwrap();
+        assert_eq!(value(&bpm, page_id), 2);
+
+        txn.abort().unwrap();
+        assert_eq!(value(&bpm, page_id), 1);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(AT + 4), Ok(0));
+    }
+
+    #[test]
+    fn committed_changes_stay_and_are_logged_under_the_transaction() {
+        let (bpm, page_id) = setup();
+        let txn = bpm.begin_txn().unwrap();
+        let id = txn.id();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(AT, 2).unwrap();
+        txn.commit().unwrap();
+
+        assert_eq!(value(&bpm, page_id), 2);
+        let records = bpm.log_manager().unwrap().records().unwrap();
+        assert!(records.iter().any(|r| r.txn_id() == Some(id)));
+    }
+
+    #[test]
+    fn dropping_a_transaction_rolls_it_back() {
+        let (bpm, page_id) = setup();
+        {
+            let txn = bpm.begin_txn().unwrap();
+            txn.fetch_page_write(page_id).unwrap().write_u32_at(AT, 2).unwrap();
+        }
+        assert_eq!(value(&bpm, page_id), 1);
+        assert!(bpm.take_deferred_errors().is_empty());
+    }
+
+    #[test]
+    fn rollback_leaves_other_writers_alone() {
+        let (bpm, page_id) = setup();
+        let txn = bpm.begin_txn().unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(AT, 2).unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(AT + 8, 5).unwrap();
+        txn.abort().unwrap();
+
+        assert_eq!(value(&bpm, page_id), 1);
+        assert_eq!(bpm.fetch_page(page_id).unwrap().read_u32_at(AT + 8), Ok(5));
+    }
+
+    #[test]
+    fn of_two_optimistic_read_modify_writes_exactly_one_commits() {
+        let (bpm, page_id) = setup();
+        let (first, second) = (bpm.begin_optimistic_txn().unwrap(), bpm.begin_optimistic_txn().unwrap());
+        let seen = [&first, &second].map(|txn| txn.fetch_page_read(page_id).unwrap().read_u32_at(AT).unwrap());
+        assert_eq!(seen, [1, 1]);
+
+        first.fetch_page_write(page_id).unwrap().write_u32_at(AT, seen[0] + 1).unwrap();
+        first.commit().unwrap();
+        assert!(matches!(second.fetch_page_write(page_id), Err(BufferError::Conflict(id)) if id == page_id));
+        assert!(second.commit().is_err());
+        assert_eq!(value(&bpm, page_id), 2);
+    }
+
+    #[test]
+    fn a_page_changed_by_another_running_optimistic_transaction_is_off_limits() {
+        let (bpm, page_id) = setup();
+        let (first, second) = (bpm.begin_optimistic_txn().unwrap(), bpm.begin_optimistic_txn().unwrap());
+        first.fetch_page_write(page_id).unwrap().write_u32_at(AT, 2).unwrap();
+        assert!(matches!(second.fetch_page_read(page_id), Err(BufferError::Conflict(_))));
+        assert!(matches!(second.fetch_page_write(page_id), Err(BufferError::Conflict(_))));
+
+        // once the first one is gone the page is fair game again
+        first.abort().unwrap();
+        assert_eq!(second.fetch_page_read(page_id).unwrap().read_u32_at(AT), Ok(1));
+        second.commit().unwrap();
+    }
+
+    #[test]
+    fn an_optimistic_transactions_own_writes_dont_conflict() {
+        let (bpm, page_id) = setup();
+        let txn = bpm.begin_optimistic_txn().unwrap();
+        assert!(txn.is_optimistic());
+        let read = txn.fetch_page_read(page_id).unwrap().read_u32_at(AT).unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(AT, read + 1).unwrap();
+        txn.fetch_page_write(page_id).unwrap().write_u32_at(AT + 4, 9).unwrap();
+        txn.validate().unwrap();
+        txn.commit().unwrap();
+        assert_eq!(value(&bpm, page_id), 2);
+    }
+
+    #[test]
+    fn a_plain_write_after_the_read_fails_validation_and_rolls_back() {
+        let (bpm, page_id) = setup();
+        let other = bpm.allocate_page_of_type(PageType::Overflow).unwrap().page_id;
+        let txn = bpm.begin_optimistic_txn().unwrap();
+        txn.fetch_page_read(page_id).unwrap();
+        txn.fetch_page_write(other).unwrap().write_u32_at(AT, 7).unwrap();
+        bpm.fetch_page_write(page_id).unwrap().write_u32_at(AT, 5).unwrap();
+
+        assert!(matches!(txn.validate(), Err(BufferError::Conflict(id)) if id == page_id));
+        assert!(txn.commit().is_err());
+        assert_eq!(bpm.fetch_page(other).unwrap().read_u32_at(AT), Ok(0));
+        assert_eq!(value(&bpm, page_id), 5);
+    }
+}